@@ -0,0 +1,171 @@
+//! Throughput of the work-stealing `ThreadPool` against the previous design,
+//! where every worker waited on one `Arc<Mutex<mpsc::Receiver<Job>>>`.
+//!
+//! Run with `cargo bench -p echo`.
+
+use echo::ThreadPool;
+
+use std::{
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        mpsc, Arc, Mutex,
+    },
+    thread,
+    time::{Duration, Instant},
+};
+
+type Job = Box<dyn FnOnce() + Send + 'static>;
+type Execute = Arc<dyn Fn(Job) + Send + Sync>;
+
+/// The pool as it was before the work-stealing scheduler.
+struct MutexPool {
+    workers: Vec<thread::JoinHandle<()>>,
+    sender: Option<mpsc::Sender<Job>>,
+}
+
+impl MutexPool {
+    fn new(size: usize) -> MutexPool {
+        let (sender, receiver) = mpsc::channel::<Job>();
+        let receiver = Arc::new(Mutex::new(receiver));
+
+        let workers = (0..size)
+            .map(|_| {
+                let receiver = Arc::clone(&receiver);
+                thread::spawn(move || loop {
+                    let message = receiver.lock().unwrap().recv();
+                    match message {
+                        Ok(job) => job(),
+                        Err(_) => break,
+                    }
+                })
+            })
+            .collect();
+
+        MutexPool {
+            workers,
+            sender: Some(sender),
+        }
+    }
+
+    fn execute<F>(&self, f: F)
+    where
+        F: FnOnce() + Send + 'static,
+    {
+        self.sender.as_ref().unwrap().send(Box::new(f)).unwrap();
+    }
+}
+
+impl Drop for MutexPool {
+    fn drop(&mut self) {
+        drop(self.sender.take());
+        for worker in self.workers.drain(..) {
+            worker.join().unwrap();
+        }
+    }
+}
+
+/// Submit `jobs` tiny jobs from the calling thread and wait for all of them.
+fn short_jobs(execute: &dyn Fn(Job), jobs: usize) -> Duration {
+    let done = Arc::new(AtomicUsize::new(0));
+    let (tx, rx) = mpsc::channel();
+
+    let start = Instant::now();
+    for _ in 0..jobs {
+        let done = Arc::clone(&done);
+        let tx = tx.clone();
+        execute(Box::new(move || {
+            if done.fetch_add(1, Ordering::Relaxed) + 1 == jobs {
+                tx.send(()).unwrap();
+            }
+        }));
+    }
+    rx.recv().unwrap();
+    start.elapsed()
+}
+
+/// Submit `batches` jobs that each fan out `fanout` tiny jobs of their own.
+fn nested_jobs(execute: &Execute, batches: usize, fanout: usize) -> Duration {
+    let total = batches * fanout;
+    let done = Arc::new(AtomicUsize::new(0));
+    let (tx, rx) = mpsc::channel();
+
+    let start = Instant::now();
+    for _ in 0..batches {
+        let inner = Arc::clone(execute);
+        let done = Arc::clone(&done);
+        let tx = tx.clone();
+        execute(Box::new(move || {
+            for _ in 0..fanout {
+                let done = Arc::clone(&done);
+                let tx = tx.clone();
+                inner(Box::new(move || {
+                    if done.fetch_add(1, Ordering::Relaxed) + 1 == total {
+                        tx.send(()).unwrap();
+                    }
+                }));
+            }
+        }));
+    }
+    rx.recv().unwrap();
+    start.elapsed()
+}
+
+/// Drop a pool once the workers are done with their temporary references, so
+/// that it is never dropped (and joined) from one of its own workers.
+fn shutdown<T>(pool: Arc<T>) {
+    while Arc::strong_count(&pool) > 1 {
+        thread::yield_now();
+    }
+    drop(pool);
+}
+
+fn report(name: &str, jobs: usize, elapsed: Duration) {
+    let rate = jobs as f64 / elapsed.as_secs_f64();
+    println!("{name:<32} {jobs:>9} jobs {elapsed:>12.2?} {rate:>14.0} jobs/s");
+}
+
+fn main() {
+    let size = thread::available_parallelism().map_or(4, |n| n.get());
+    let jobs = 1_000_000;
+    let (batches, fanout) = (10_000, 100);
+
+    println!("{size} workers");
+
+    for _ in 0..3 {
+        let pool = Arc::new(MutexPool::new(size));
+        let execute: Execute = {
+            let pool = Arc::downgrade(&pool);
+            Arc::new(move |job| pool.upgrade().unwrap().execute(job))
+        };
+        report(
+            "mutex receiver, short jobs",
+            jobs,
+            short_jobs(&*execute, jobs),
+        );
+        report(
+            "mutex receiver, nested jobs",
+            batches * fanout,
+            nested_jobs(&execute, batches, fanout),
+        );
+        drop(execute);
+        shutdown(pool);
+
+        let pool = Arc::new(ThreadPool::new(size));
+        let execute: Execute = {
+            let pool = Arc::downgrade(&pool);
+            Arc::new(move |job| pool.upgrade().unwrap().execute(job).unwrap())
+        };
+        report(
+            "work stealing, short jobs",
+            jobs,
+            short_jobs(&*execute, jobs),
+        );
+        report(
+            "work stealing, nested jobs",
+            batches * fanout,
+            nested_jobs(&execute, batches, fanout),
+        );
+        drop(execute);
+        shutdown(pool);
+    }
+}
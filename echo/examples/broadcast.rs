@@ -0,0 +1,102 @@
+//! A chat room: every message sent on `/ws` goes to everyone connected.
+//!
+//! Run it with `cargo run --example broadcast` and open
+//! http://127.0.0.1:7879 in a few browser tabs.
+
+use std::{
+    net::TcpListener,
+    sync::{Arc, Mutex},
+};
+
+use echo::{
+    server::Backend,
+    websocket::{self, Message, Sender, WebSocket},
+    Request, Response, Server,
+};
+
+const PAGE: &str = r#"<!DOCTYPE html>
+<html lang="en">
+<head><meta charset="utf-8"><title>Broadcast</title></head>
+<body>
+  <ul id="messages"></ul>
+  <form id="form"><input id="input" autocomplete="off" autofocus></form>
+  <script>
+    const socket = new WebSocket(`ws://${location.host}/ws`);
+    socket.onmessage = (event) => {
+      const item = document.createElement("li");
+      item.textContent = event.data;
+      document.getElementById("messages").append(item);
+    };
+    document.getElementById("form").onsubmit = (event) => {
+      event.preventDefault();
+      const input = document.getElementById("input");
+      socket.send(input.value);
+      input.value = "";
+    };
+  </script>
+</body>
+</html>
+"#;
+
+/// The senders of everyone connected.
+#[derive(Default, Clone)]
+struct Room {
+    members: Arc<Mutex<Vec<Sender>>>,
+}
+
+impl Room {
+    fn join(&self, socket: WebSocket) {
+        let name = socket
+            .peer_addr()
+            .map_or_else(|_| "someone".to_string(), |addr| addr.to_string());
+        self.members.lock().unwrap().push(socket.sender());
+        self.broadcast(&format!("{name} joined"));
+
+        self.listen(socket, &name);
+        self.broadcast(&format!("{name} left"));
+    }
+
+    fn listen(&self, mut socket: WebSocket, name: &str) {
+        while let Ok(message) = socket.recv() {
+            match message {
+                Message::Text(text) => self.broadcast(&format!("{name}: {text}")),
+                Message::Close(_) => return,
+                _ => {}
+            }
+        }
+    }
+
+    /// Send `text` to every member, dropping those whose connection is
+    /// gone.
+    fn broadcast(&self, text: &str) {
+        let message = Message::Text(text.to_string());
+        self.members
+            .lock()
+            .unwrap()
+            .retain(|member| member.send(&message).is_ok());
+    }
+}
+
+fn main() {
+    let room = Room::default();
+
+    #[cfg(target_os = "linux")]
+    let backend = Backend::EventLoop;
+    #[cfg(not(target_os = "linux"))]
+    let backend = Backend::Threaded;
+
+    let server = Server::builder()
+        .backend(backend)
+        .build(move |request: &Request| match request.path() {
+            "/ws" => {
+                let room = room.clone();
+                websocket::upgrade(request, move |socket| room.join(socket))
+            }
+            "/" => Response::html(200, PAGE),
+            _ => Response::text(404, "Not Found\n"),
+        });
+
+    let listener = TcpListener::bind("127.0.0.1:7879").unwrap();
+    println!("Listening on http://127.0.0.1:7879");
+    server.serve(listener).unwrap();
+}
@@ -0,0 +1,637 @@
+//! Access logging in the formats log analyzers understand.
+//!
+//! Entries are formatted on the thread that served the request and handed to
+//! a background thread, which does the (buffered) writing and the rotation.
+//! Entries it can't keep up with are dropped, not queued without end.
+
+use std::{
+    fmt::{self, Write as _},
+    fs::{self, File, OpenOptions},
+    io::{self, BufWriter, Write},
+    net::IpAddr,
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        mpsc::{self, Receiver, Sender, SyncSender, TrySendError},
+        Arc,
+    },
+    thread::{self, JoinHandle},
+    time::{Duration, SystemTime},
+};
+
+use crate::{
+    date::DateTime,
+    http::{Body, Request},
+};
+
+/// The layout of a log line.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Format {
+    /// `host - user [time] "request line" status bytes micros`: the Common
+    /// Log Format, followed by the response time in microseconds.
+    #[default]
+    Common,
+    /// The Common Log Format with the `Referer` and `User-Agent` headers
+    /// added before the response time.
+    Combined,
+    /// One JSON object per line.
+    Json,
+}
+
+/// What the log knows about a served request.
+#[derive(Debug, Clone)]
+pub struct Entry<'a> {
+    /// The client's address, if known.
+    pub peer: Option<IpAddr>,
+    /// When the request was received.
+    pub time: SystemTime,
+    pub request: &'a Request,
+    pub status: u16,
+    /// The size of the response body.
+    pub bytes: usize,
+    /// How long it took to produce the response.
+    pub duration: Duration,
+}
+
+impl Format {
+    /// Format an entry as a line, without the trailing newline.
+    pub fn format(&self, entry: &Entry<'_>) -> String {
+        let request = entry.request;
+        let host = match entry.peer {
+            Some(peer) => peer.to_string(),
+            None => "-".to_string(),
+        };
+        let micros = entry.duration.as_micros();
+
+        match self {
+            Format::Common | Format::Combined => {
+                let bytes = match entry.bytes {
+                    0 => "-".to_string(),
+                    n => n.to_string(),
+                };
+                let mut line = format!(
+                    "{host} - - [{}] \"{}\" {} {bytes}",
+                    DateTime::from_system_time(entry.time).clf(),
+                    quoted(&request_line(request)),
+                    entry.status,
+                );
+                if *self == Format::Combined {
+                    let header = |name| quoted(request.header(name).unwrap_or("-"));
+                    let _ = write!(
+                        line,
+                        " \"{}\" \"{}\"",
+                        header("Referer"),
+                        header("User-Agent")
+                    );
+                }
+                let _ = write!(line, " {micros}");
+                line
+            }
+            Format::Json => {
+                let mut line = String::from("{");
+                let mut field = |name: &str, value: &dyn fmt::Display, string: bool| {
+                    if line.len() > 1 {
+                        line.push(',');
+                    }
+                    if string {
+                        let _ = write!(line, "\"{name}\":\"{}\"", json_escape(&value.to_string()));
+                    } else {
+                        let _ = write!(line, "\"{name}\":{value}");
+                    }
+                };
+
+                let time = DateTime::from_system_time(entry.time).rfc3339();
+                field("time", &time, true);
+                field("remote_addr", &host, true);
+                field("method", &request.method, true);
+                field("target", &request.target, true);
+                field("version", &request.version, true);
+                field("status", &entry.status, false);
+                field("bytes", &entry.bytes, false);
+                field("duration_us", &micros, false);
+                for (key, name) in [("referer", "Referer"), ("user_agent", "User-Agent")] {
+                    if let Some(value) = request.header(name) {
+                        field(key, &value, true);
+                    }
+                }
+
+                line.push('}');
+                line
+            }
+        }
+    }
+}
+
+fn request_line(request: &Request) -> String {
+    format!("{} {} {}", request.method, request.target, request.version)
+}
+
+/// Escape quotes, backslashes and control characters the way Apache does,
+/// so that every entry stays on one line and fields can't be forged.
+fn quoted(s: &str) -> String {
+    let mut escaped = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '"' | '\\' => {
+                escaped.push('\\');
+                escaped.push(c);
+            }
+            c if c.is_control() => {
+                let _ = write!(escaped, "\\x{:02x}", c as u32);
+            }
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+fn json_escape(s: &str) -> String {
+    let mut escaped = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '"' => escaped.push_str("\\\""),
+            '\\' => escaped.push_str("\\\\"),
+            '\n' => escaped.push_str("\\n"),
+            '\r' => escaped.push_str("\\r"),
+            '\t' => escaped.push_str("\\t"),
+            c if c.is_control() => {
+                let _ = write!(escaped, "\\u{:04x}", c as u32);
+            }
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+enum Message {
+    Line(String),
+    /// Write out everything received so far, then answer.
+    Flush(Sender<()>),
+}
+
+/// Where the writer thread puts the lines.
+enum Output {
+    Stdout,
+    File(Rotating),
+}
+
+/// A log file that's renamed to `<path>.1` once it reaches `max_size`, with
+/// older files shifted to `<path>.2` and so on, up to `max_files`.
+struct Rotating {
+    path: PathBuf,
+    file: BufWriter<File>,
+    size: u64,
+    max_size: Option<u64>,
+    max_files: usize,
+}
+
+impl Rotating {
+    fn open(path: PathBuf, max_size: Option<u64>, max_files: usize) -> io::Result<Rotating> {
+        if let Some(dir) = path.parent().filter(|dir| !dir.as_os_str().is_empty()) {
+            fs::create_dir_all(dir)?;
+        }
+        let file = OpenOptions::new().create(true).append(true).open(&path)?;
+        let size = file.metadata()?.len();
+        Ok(Rotating {
+            path,
+            file: BufWriter::new(file),
+            size,
+            max_size,
+            max_files,
+        })
+    }
+
+    fn write_line(&mut self, line: &[u8]) -> io::Result<()> {
+        let len = line.len() as u64 + 1;
+        if self
+            .max_size
+            .is_some_and(|max| self.size > 0 && self.size + len > max)
+        {
+            self.rotate()?;
+        }
+
+        self.file.write_all(line)?;
+        self.file.write_all(b"\n")?;
+        self.size += len;
+        Ok(())
+    }
+
+    fn rotate(&mut self) -> io::Result<()> {
+        self.file.flush()?;
+
+        let rotated = |n: usize| {
+            let mut name = self.path.clone().into_os_string();
+            name.push(format!(".{n}"));
+            PathBuf::from(name)
+        };
+        if self.max_files == 0 {
+            fs::remove_file(&self.path)?;
+        } else {
+            for n in (1..self.max_files).rev() {
+                let from = rotated(n);
+                if from.exists() {
+                    fs::rename(from, rotated(n + 1))?;
+                }
+            }
+            fs::rename(&self.path, rotated(1))?;
+        }
+
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)?;
+        self.file = BufWriter::new(file);
+        self.size = 0;
+        Ok(())
+    }
+}
+
+impl Output {
+    fn write_line(&mut self, line: &str) -> io::Result<()> {
+        match self {
+            Output::Stdout => writeln!(io::stdout().lock(), "{line}"),
+            Output::File(file) => file.write_line(line.as_bytes()),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            Output::Stdout => io::stdout().flush(),
+            Output::File(file) => file.file.flush(),
+        }
+    }
+}
+
+/// Configures and creates an `AccessLog`.
+///
+/// ```no_run
+/// use echo::access_log::{AccessLog, Format};
+///
+/// let log = AccessLog::builder()
+///     .format(Format::Combined)
+///     .path("access.log")
+///     .max_size(10 * 1024 * 1024)
+///     .max_files(5)
+///     .build()
+///     .unwrap();
+/// ```
+#[derive(Debug)]
+pub struct Builder {
+    format: Format,
+    path: Option<PathBuf>,
+    max_size: Option<u64>,
+    max_files: usize,
+    max_queued: usize,
+}
+
+impl Builder {
+    /// Create a builder for a Common Log Format log on standard output.
+    pub fn new() -> Builder {
+        Builder {
+            format: Format::default(),
+            path: None,
+            max_size: None,
+            max_files: 5,
+            max_queued: 8192,
+        }
+    }
+
+    pub fn format(mut self, format: Format) -> Builder {
+        self.format = format;
+        self
+    }
+
+    /// Append to the file at `path` instead of writing to standard output,
+    /// creating its directory if need be.
+    pub fn path(mut self, path: impl AsRef<Path>) -> Builder {
+        self.path = Some(path.as_ref().to_path_buf());
+        self
+    }
+
+    /// Rotate the log file once it would grow past `bytes`. By default it
+    /// grows forever.
+    pub fn max_size(mut self, bytes: u64) -> Builder {
+        self.max_size = Some(bytes);
+        self
+    }
+
+    /// How many rotated files to keep. Defaults to 5.
+    pub fn max_files(mut self, count: usize) -> Builder {
+        self.max_files = count;
+        self
+    }
+
+    /// How many lines may wait for the writer. Past that, as when the disk
+    /// can't keep up, lines are dropped rather than held in memory, and
+    /// counted in `AccessLog::dropped`. Defaults to 8192.
+    pub fn max_queued(mut self, lines: usize) -> Builder {
+        self.max_queued = lines;
+        self
+    }
+
+    /// Open the log file and start the writer thread.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the log file can't be opened.
+    pub fn build(self) -> io::Result<AccessLog> {
+        let mut output = match self.path {
+            Some(path) => Output::File(Rotating::open(path, self.max_size, self.max_files)?),
+            None => Output::Stdout,
+        };
+
+        let (sender, receiver) = mpsc::sync_channel(self.max_queued);
+        let thread = thread::Builder::new()
+            .name("echo-access-log".to_string())
+            .spawn(move || write_lines(&receiver, &mut output))?;
+
+        Ok(AccessLog {
+            format: self.format,
+            queue: Some(Queue {
+                sender,
+                dropped: Arc::new(AtomicU64::new(0)),
+            }),
+            thread: Some(thread),
+        })
+    }
+}
+
+impl Default for Builder {
+    fn default() -> Builder {
+        Builder::new()
+    }
+}
+
+/// An access log, written to by a background thread.
+///
+/// Dropping the log writes out the entries that are still queued.
+pub struct AccessLog {
+    format: Format,
+    queue: Option<Queue>,
+    thread: Option<JoinHandle<()>>,
+}
+
+/// The writer thread's queue of messages.
+#[derive(Clone)]
+struct Queue {
+    sender: SyncSender<Message>,
+    /// Lines dropped because the queue was full.
+    dropped: Arc<AtomicU64>,
+}
+
+impl Queue {
+    /// Queue `line`, or drop it if the writer is too far behind.
+    fn line(&self, line: String) {
+        // The writer only stops once the sender is dropped.
+        if let Err(TrySendError::Full(_)) = self.sender.try_send(Message::Line(line)) {
+            self.dropped.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+}
+
+impl AccessLog {
+    pub fn builder() -> Builder {
+        Builder::new()
+    }
+
+    pub fn format(&self) -> Format {
+        self.format
+    }
+
+    /// Queue an entry for writing.
+    pub fn log(&self, entry: &Entry<'_>) {
+        if let Some(queue) = &self.queue {
+            queue.line(self.format.format(entry));
+        }
+    }
+
+    /// How many entries were dropped because the writer was too far behind.
+    pub fn dropped(&self) -> u64 {
+        self.queue
+            .as_ref()
+            .map_or(0, |queue| queue.dropped.load(Ordering::Relaxed))
+    }
+
+    /// Keep `entry` to log once its body is sent, when the size of that is
+    /// known.
+    pub(crate) fn defer(&self, entry: &Entry<'_>) -> Deferred {
+        let request = entry.request;
+        Deferred {
+            format: self.format,
+            queue: self.queue.clone(),
+            peer: entry.peer,
+            time: entry.time,
+            // The body is no part of the line.
+            request: Request {
+                method: request.method.clone(),
+                target: request.target.clone(),
+                version: request.version,
+                headers: request.headers.clone(),
+                body: Body::default(),
+                peer: request.peer,
+            },
+            status: entry.status,
+            duration: entry.duration,
+        }
+    }
+
+    /// Wait until every entry logged so far has been written.
+    pub fn flush(&self) {
+        if let Some(queue) = &self.queue {
+            let (sender, receiver) = mpsc::channel();
+            if queue.sender.send(Message::Flush(sender)).is_ok() {
+                let _ = receiver.recv();
+            }
+        }
+    }
+}
+
+/// An entry waiting for the size of its response body, from `defer`.
+pub(crate) struct Deferred {
+    format: Format,
+    queue: Option<Queue>,
+    peer: Option<IpAddr>,
+    time: SystemTime,
+    request: Request,
+    status: u16,
+    duration: Duration,
+}
+
+impl Deferred {
+    /// Log the entry, now that `bytes` of the body were sent.
+    pub(crate) fn log(self, bytes: usize) {
+        let line = self.format.format(&Entry {
+            peer: self.peer,
+            time: self.time,
+            request: &self.request,
+            status: self.status,
+            bytes,
+            duration: self.duration,
+        });
+        if let Some(queue) = &self.queue {
+            queue.line(line);
+        }
+    }
+}
+
+impl fmt::Debug for AccessLog {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("AccessLog")
+            .field("format", &self.format)
+            .finish_non_exhaustive()
+    }
+}
+
+impl Drop for AccessLog {
+    fn drop(&mut self) {
+        drop(self.queue.take());
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+/// The writer thread: write lines as they come, and flush whenever the queue
+/// runs dry, so that bursts are written in one go.
+fn write_lines(receiver: &Receiver<Message>, output: &mut Output) {
+    let report = |result: io::Result<()>| {
+        if let Err(e) = result {
+            eprintln!("Failed to write the access log: {e}");
+        }
+    };
+
+    while let Ok(mut message) = receiver.recv() {
+        loop {
+            match message {
+                Message::Line(line) => report(output.write_line(&line)),
+                Message::Flush(done) => {
+                    report(output.flush());
+                    let _ = done.send(());
+                }
+            }
+            match receiver.try_recv() {
+                Ok(next) => message = next,
+                Err(_) => break,
+            }
+        }
+        report(output.flush());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::TempDir;
+    use std::time::UNIX_EPOCH;
+
+    fn request() -> Request {
+        let mut request = Request::new("GET", "/apache_pb.gif");
+        request.version = crate::http::Version::Http10;
+        request
+            .headers
+            .append("Referer", "http://www.example.com/start.html");
+        request
+            .headers
+            .append("User-Agent", "Mozilla/4.08 [en] (Win98; I ;Nav)");
+        request
+    }
+
+    fn entry(request: &Request) -> Entry<'_> {
+        Entry {
+            peer: Some("127.0.0.1".parse().unwrap()),
+            time: UNIX_EPOCH + Duration::from_secs(971_186_136),
+            request,
+            status: 200,
+            bytes: 2326,
+            duration: Duration::from_micros(1500),
+        }
+    }
+
+    #[test]
+    fn common_and_combined() {
+        let request = request();
+        let entry = entry(&request);
+
+        assert_eq!(
+            Format::Common.format(&entry),
+            "127.0.0.1 - - [10/Oct/2000:13:55:36 +0000] \"GET /apache_pb.gif HTTP/1.0\" 200 2326 1500"
+        );
+        assert_eq!(
+            Format::Combined.format(&entry),
+            "127.0.0.1 - - [10/Oct/2000:13:55:36 +0000] \"GET /apache_pb.gif HTTP/1.0\" 200 2326 \
+             \"http://www.example.com/start.html\" \"Mozilla/4.08 [en] (Win98; I ;Nav)\" 1500"
+        );
+    }
+
+    #[test]
+    fn json_lines() {
+        let mut request = request();
+        request.target = "/\"quoted\"".to_string();
+        let entry = entry(&request);
+
+        assert_eq!(
+            Format::Json.format(&entry),
+            "{\"time\":\"2000-10-10T13:55:36.000Z\",\"remote_addr\":\"127.0.0.1\",\
+             \"method\":\"GET\",\"target\":\"/\\\"quoted\\\"\",\"version\":\"HTTP/1.0\",\
+             \"status\":200,\"bytes\":2326,\"duration_us\":1500,\
+             \"referer\":\"http://www.example.com/start.html\",\
+             \"user_agent\":\"Mozilla/4.08 [en] (Win98; I ;Nav)\"}"
+        );
+    }
+
+    #[test]
+    fn fields_are_escaped() {
+        let mut request = request();
+        request.target = "/\"\n".to_string();
+        let line = Format::Common.format(&entry(&request));
+
+        assert!(line.contains(r#""GET /\"\x0a HTTP/1.0""#), "{line}");
+    }
+
+    #[test]
+    fn full_queues_drop_lines() {
+        let (sender, receiver) = mpsc::sync_channel(2);
+        let queue = Queue {
+            sender,
+            dropped: Arc::new(AtomicU64::new(0)),
+        };
+        for n in 0..5 {
+            queue.line(n.to_string());
+        }
+        assert_eq!(queue.dropped.load(Ordering::Relaxed), 3);
+        let lines: Vec<_> = receiver
+            .try_iter()
+            .map(|message| match message {
+                Message::Line(line) => line,
+                Message::Flush(_) => unreachable!(),
+            })
+            .collect();
+        assert_eq!(lines, ["0", "1"]);
+    }
+
+    #[test]
+    fn rotation() {
+        let dir = TempDir::new("access-log");
+        let path = dir.join("access.log");
+
+        let request = request();
+        let log = AccessLog::builder()
+            .path(&path)
+            .max_size(200)
+            .max_files(2)
+            .build()
+            .unwrap();
+        for _ in 0..10 {
+            log.log(&entry(&request));
+        }
+        log.flush();
+
+        let line_len = Format::Common.format(&entry(&request)).len() + 1;
+        let per_file = 200 / line_len;
+        let read = |name: &str| fs::read_to_string(dir.join(name)).unwrap();
+        assert_eq!(read("access.log").lines().count(), (10 - 1) % per_file + 1);
+        assert_eq!(read("access.log.1").lines().count(), per_file);
+        assert_eq!(read("access.log.2").lines().count(), per_file);
+        assert!(!dir.join("access.log.3").exists());
+    }
+}
@@ -0,0 +1,104 @@
+//! Base64 with the standard alphabet and padding (RFC 4648 section 4), and
+//! its URL-safe variant (section 5).
+
+const ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+pub(crate) fn encode(data: &[u8]) -> String {
+    let mut out = String::with_capacity(data.len().div_ceil(3) * 4);
+
+    for chunk in data.chunks(3) {
+        let bytes = [
+            chunk[0],
+            *chunk.get(1).unwrap_or(&0),
+            *chunk.get(2).unwrap_or(&0),
+        ];
+        let n = u32::from_be_bytes([0, bytes[0], bytes[1], bytes[2]]);
+        for i in 0..4 {
+            if i <= chunk.len() {
+                out.push(ALPHABET[(n >> (18 - 6 * i) & 0x3f) as usize] as char);
+            } else {
+                out.push('=');
+            }
+        }
+    }
+
+    out
+}
+
+/// Decode padded base64. Returns `None` if `s` isn't valid base64.
+pub(crate) fn decode(s: &str) -> Option<Vec<u8>> {
+    let s = s.as_bytes();
+    if !s.len().is_multiple_of(4) {
+        return None;
+    }
+    let mut out = Vec::with_capacity(s.len() / 4 * 3);
+
+    for (i, chunk) in s.chunks(4).enumerate() {
+        let last = i + 1 == s.len() / 4;
+        let padding = chunk.iter().rev().take_while(|&&b| b == b'=').count();
+        if padding > 2 || (padding > 0 && !last) {
+            return None;
+        }
+
+        let mut n = 0u32;
+        for &b in &chunk[..4 - padding] {
+            let value = ALPHABET.iter().position(|&a| a == b)? as u32;
+            n = n << 6 | value;
+        }
+        n <<= 6 * padding;
+
+        let bytes = n.to_be_bytes();
+        out.extend_from_slice(&bytes[1..4 - padding]);
+    }
+
+    Some(out)
+}
+
+/// Decode base64 with the URL-safe alphabet, padded or not.
+pub(crate) fn decode_url(s: &str) -> Option<Vec<u8>> {
+    if s.contains(['+', '/']) {
+        return None;
+    }
+    let mut padded: String = s
+        .chars()
+        .map(|c| match c {
+            '-' => '+',
+            '_' => '/',
+            c => c,
+        })
+        .collect();
+    while !padded.len().is_multiple_of(4) {
+        padded.push('=');
+    }
+    decode(&padded)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trips() {
+        for (data, encoded) in [
+            (&b""[..], ""),
+            (b"f", "Zg=="),
+            (b"fo", "Zm8="),
+            (b"foo", "Zm9v"),
+            (b"foob", "Zm9vYg=="),
+            (b"foobar", "Zm9vYmFy"),
+            (&[0xfb, 0xff, 0xfe], "+//+"),
+        ] {
+            assert_eq!(encode(data), encoded);
+            assert_eq!(decode(encoded).as_deref(), Some(data));
+        }
+
+        assert_eq!(decode("Zg="), None);
+        assert_eq!(decode("Zg==Zg=="), None);
+        assert_eq!(decode("Z==="), None);
+        assert_eq!(decode("Zm9v!A=="), None);
+
+        assert_eq!(decode_url("-__-").as_deref(), Some(&[0xfb, 0xff, 0xfe][..]));
+        assert_eq!(decode_url("Zm9vYg").as_deref(), Some(&b"foob"[..]));
+        assert_eq!(decode_url("+//+"), None);
+    }
+}
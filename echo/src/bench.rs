@@ -0,0 +1,437 @@
+//! Load generation against a running server, for the `echo-bench` tool:
+//! keep-alive connections sending the same request over and over, and
+//! statistics about how fast the answers came.
+//!
+//! ```no_run
+//! use echo::bench::{Bench, Target};
+//! use std::time::Duration;
+//!
+//! let target = Target::parse("http://127.0.0.1:7878/").unwrap();
+//! let report = Bench::new(target)
+//!     .connections(16)
+//!     .duration(Duration::from_secs(5))
+//!     .run();
+//! println!("{report}");
+//! ```
+
+use std::{
+    collections::BTreeMap,
+    fmt,
+    net::{SocketAddr, ToSocketAddrs},
+    sync::atomic::{AtomicU64, Ordering},
+    thread,
+    time::{Duration, Instant},
+};
+
+use crate::{client::Client, http::Request, json};
+
+/// Where to send requests: an `http://` URL, resolved.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Target {
+    pub addr: SocketAddr,
+    /// The `Host` header, like `localhost:7878`.
+    pub host: String,
+    /// The request target, like `/search?q=rust`.
+    pub path: String,
+}
+
+impl Target {
+    /// Parse and resolve a URL like `http://localhost:7878/path`.
+    pub fn parse(url: &str) -> Result<Target, String> {
+        let rest = url
+            .strip_prefix("http://")
+            .ok_or_else(|| format!("`{url}` isn't an http:// URL"))?;
+        let (host, path) = match rest.find(['/', '?']) {
+            Some(i) if rest[i..].starts_with('/') => (&rest[..i], rest[i..].to_string()),
+            Some(i) => (&rest[..i], format!("/{}", &rest[i..])),
+            None => (rest, "/".to_string()),
+        };
+        if host.is_empty() {
+            return Err(format!("`{url}` has no host"));
+        }
+
+        // A port after the last colon, unless it's within an IPv6 address.
+        let with_port = match host.rfind(':') {
+            Some(colon) if !host[colon..].contains(']') => host.to_string(),
+            _ => format!("{host}:80"),
+        };
+        let addr = with_port
+            .to_socket_addrs()
+            .map_err(|e| format!("can't resolve `{host}`: {e}"))?
+            .next()
+            .ok_or_else(|| format!("`{host}` has no address"))?;
+        Ok(Target {
+            addr,
+            host: host.to_string(),
+            path,
+        })
+    }
+}
+
+/// When a run stops.
+#[derive(Debug, Clone, Copy)]
+enum Limit {
+    Duration(Duration),
+    Requests(u64),
+}
+
+/// A load test: `connections` clients sending requests one after the other
+/// as fast as they're answered, for a while or for a number of requests.
+#[derive(Debug, Clone)]
+pub struct Bench {
+    target: Target,
+    connections: usize,
+    limit: Limit,
+    request: Request,
+    timeout: Duration,
+}
+
+impl Bench {
+    /// Ten connections sending `GET` requests for ten seconds.
+    pub fn new(target: Target) -> Bench {
+        let mut request = Request::new("GET", &target.path);
+        request.headers.insert("Host", target.host.clone());
+        Bench {
+            target,
+            connections: 10,
+            limit: Limit::Duration(Duration::from_secs(10)),
+            request,
+            timeout: Duration::from_secs(10),
+        }
+    }
+
+    pub fn connections(mut self, connections: usize) -> Bench {
+        assert!(connections > 0, "a benchmark needs a connection");
+        self.connections = connections;
+        self
+    }
+
+    /// Send requests for `duration`.
+    pub fn duration(mut self, duration: Duration) -> Bench {
+        self.limit = Limit::Duration(duration);
+        self
+    }
+
+    /// Send `requests` requests in all, rather than for a while.
+    pub fn requests(mut self, requests: u64) -> Bench {
+        self.limit = Limit::Requests(requests);
+        self
+    }
+
+    pub fn method(mut self, method: &str) -> Bench {
+        self.request.method = method.to_string();
+        self
+    }
+
+    pub fn header(mut self, name: &str, value: &str) -> Bench {
+        self.request.headers.insert(name, value);
+        self
+    }
+
+    pub fn body(mut self, body: impl Into<Vec<u8>>) -> Bench {
+        let body: Vec<u8> = body.into();
+        self.request.body = body.into();
+        self
+    }
+
+    /// How long to wait for an answer before counting a request as failed.
+    /// Ten seconds by default.
+    pub fn timeout(mut self, timeout: Duration) -> Bench {
+        self.timeout = timeout;
+        self
+    }
+
+    /// Run the benchmark, blocking until it's over.
+    pub fn run(&self) -> Report {
+        let remaining = AtomicU64::new(match self.limit {
+            Limit::Requests(requests) => requests,
+            Limit::Duration(_) => u64::MAX,
+        });
+        let start = Instant::now();
+        let deadline = match self.limit {
+            Limit::Duration(duration) => Some(start + duration),
+            Limit::Requests(_) => None,
+        };
+        // Takes the next request of the budget, if there's one left.
+        let next = || {
+            deadline.is_none_or(|deadline| Instant::now() < deadline)
+                && remaining
+                    .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |n| n.checked_sub(1))
+                    .is_ok()
+        };
+
+        let samples: Vec<Samples> = thread::scope(|scope| {
+            let workers: Vec<_> = (0..self.connections)
+                .map(|_| scope.spawn(|| self.connection(&next)))
+                .collect();
+            workers
+                .into_iter()
+                .map(|worker| worker.join().unwrap())
+                .collect()
+        });
+        let elapsed = start.elapsed();
+
+        let mut report = Report {
+            connections: self.connections,
+            elapsed,
+            requests: 0,
+            bytes: 0,
+            latency: Latency::default(),
+            statuses: BTreeMap::new(),
+            errors: BTreeMap::new(),
+        };
+        let mut latencies = Vec::new();
+        for samples in samples {
+            latencies.extend(samples.latencies);
+            report.bytes += samples.bytes;
+            for (status, count) in samples.statuses {
+                *report.statuses.entry(status).or_default() += count;
+            }
+            for (error, count) in samples.errors {
+                *report.errors.entry(error).or_default() += count;
+            }
+        }
+        report.requests = latencies.len() as u64;
+        report.latency = Latency::of(&mut latencies);
+        report
+    }
+
+    /// Send requests on one connection while `next` allows.
+    fn connection(&self, next: &(impl Fn() -> bool + Sync)) -> Samples {
+        let mut client = Client::new(self.target.addr).timeout(Some(self.timeout));
+        let mut samples = Samples::default();
+        while next() {
+            let start = Instant::now();
+            match client.send(&self.request) {
+                Ok(response) => {
+                    samples.latencies.push(start.elapsed());
+                    samples.bytes += response.body.len() as u64;
+                    *samples.statuses.entry(response.status).or_default() += 1;
+                }
+                Err(e) => *samples.errors.entry(e.kind().to_string()).or_default() += 1,
+            }
+        }
+        samples
+    }
+}
+
+/// What one connection saw.
+#[derive(Default)]
+struct Samples {
+    latencies: Vec<Duration>,
+    bytes: u64,
+    statuses: BTreeMap<u16, u64>,
+    errors: BTreeMap<String, u64>,
+}
+
+/// The results of a run.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Report {
+    pub connections: usize,
+    pub elapsed: Duration,
+    /// The requests that were answered, whatever the status.
+    pub requests: u64,
+    /// The bytes of the response bodies.
+    pub bytes: u64,
+    pub latency: Latency,
+    /// How many responses had each status.
+    pub statuses: BTreeMap<u16, u64>,
+    /// How many requests failed, by the kind of error, like `timed out`.
+    pub errors: BTreeMap<String, u64>,
+}
+
+impl Report {
+    /// Answered requests a second.
+    pub fn throughput(&self) -> f64 {
+        self.requests as f64 / self.elapsed.as_secs_f64().max(f64::MIN_POSITIVE)
+    }
+
+    /// The responses with a status outside of 2xx.
+    pub fn non_success(&self) -> u64 {
+        self.statuses
+            .iter()
+            .filter(|(status, _)| !(200..300).contains(*status))
+            .map(|(_, count)| count)
+            .sum()
+    }
+
+    /// The report as JSON, with durations in seconds, to keep track of it
+    /// from one version to the next.
+    pub fn to_json(&self) -> json::Value {
+        let seconds = |duration: Duration| json::Value::from(duration.as_secs_f64());
+        let latency: json::Value = [
+            ("mean", seconds(self.latency.mean)),
+            ("p50", seconds(self.latency.p50)),
+            ("p90", seconds(self.latency.p90)),
+            ("p99", seconds(self.latency.p99)),
+            ("max", seconds(self.latency.max)),
+        ]
+        .into_iter()
+        .collect();
+        let statuses: json::Value = self
+            .statuses
+            .iter()
+            .map(|(status, count)| (status.to_string(), *count))
+            .collect();
+        let errors: json::Value = self
+            .errors
+            .iter()
+            .map(|(error, count)| (error.clone(), *count))
+            .collect();
+        [
+            ("connections", json::Value::from(self.connections)),
+            ("duration", seconds(self.elapsed)),
+            ("requests", self.requests.into()),
+            ("bytes", self.bytes.into()),
+            ("throughput", self.throughput().into()),
+            ("latency", latency),
+            ("statuses", statuses),
+            ("errors", errors),
+        ]
+        .into_iter()
+        .collect()
+    }
+}
+
+impl fmt::Display for Report {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "{} requests in {:.2}s on {} connections, {} bytes read",
+            self.requests,
+            self.elapsed.as_secs_f64(),
+            self.connections,
+            self.bytes
+        )?;
+        writeln!(f, "Throughput: {:.1} requests/s", self.throughput())?;
+        let latency = &self.latency;
+        writeln!(
+            f,
+            "Latency: mean {:?}, p50 {:?}, p90 {:?}, p99 {:?}, max {:?}",
+            latency.mean, latency.p50, latency.p90, latency.p99, latency.max
+        )?;
+        let statuses: Vec<_> = self
+            .statuses
+            .iter()
+            .map(|(status, count)| format!("{status}: {count}"))
+            .collect();
+        if !statuses.is_empty() {
+            writeln!(f, "Statuses: {}", statuses.join(", "))?;
+        }
+        if self.non_success() > 0 {
+            writeln!(f, "Non-2xx responses: {}", self.non_success())?;
+        }
+        let errors: u64 = self.errors.values().sum();
+        write!(f, "Errors: {errors}")?;
+        for (error, count) in &self.errors {
+            write!(f, "\n  {error}: {count}")?;
+        }
+        writeln!(f)
+    }
+}
+
+/// How long requests took to be answered.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Latency {
+    pub mean: Duration,
+    pub p50: Duration,
+    pub p90: Duration,
+    pub p99: Duration,
+    pub max: Duration,
+}
+
+impl Latency {
+    /// The statistics of `samples`, which get sorted. All zero if there are
+    /// none.
+    fn of(samples: &mut [Duration]) -> Latency {
+        if samples.is_empty() {
+            return Latency::default();
+        }
+        samples.sort_unstable();
+        let total: Duration = samples.iter().sum();
+        // The nearest-rank percentile: the smallest sample that at least
+        // `p` percent of the samples are no greater than.
+        let percentile = |p: usize| samples[(samples.len() * p).div_ceil(100).max(1) - 1];
+        Latency {
+            mean: total / samples.len() as u32,
+            p50: percentile(50),
+            p90: percentile(90),
+            p99: percentile(99),
+            max: samples[samples.len() - 1],
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{http::Response, testing::TestServer};
+
+    #[test]
+    fn targets() {
+        let target = Target::parse("http://127.0.0.1:8080/a?b=c").unwrap();
+        assert_eq!(target.addr, "127.0.0.1:8080".parse().unwrap());
+        assert_eq!(
+            (target.host.as_str(), target.path.as_str()),
+            ("127.0.0.1:8080", "/a?b=c")
+        );
+
+        let target = Target::parse("http://[::1]").unwrap();
+        assert_eq!(target.addr, "[::1]:80".parse().unwrap());
+        assert_eq!(target.path, "/");
+        assert_eq!(Target::parse("http://[::1]:9?x").unwrap().path, "/?x");
+
+        assert!(Target::parse("https://example.com/").is_err());
+        assert!(Target::parse("http:///").is_err());
+    }
+
+    #[test]
+    fn percentiles() {
+        let mut samples: Vec<_> = (1..=100).rev().map(Duration::from_millis).collect();
+        let latency = Latency::of(&mut samples);
+        assert_eq!(latency.p50, Duration::from_millis(50));
+        assert_eq!(latency.p90, Duration::from_millis(90));
+        assert_eq!(latency.p99, Duration::from_millis(99));
+        assert_eq!(latency.max, Duration::from_millis(100));
+        assert_eq!(latency.mean, Duration::from_micros(50_500));
+
+        let latency = Latency::of(&mut [Duration::from_millis(7)]);
+        assert_eq!(
+            (latency.p50, latency.p99),
+            (Duration::from_millis(7), Duration::from_millis(7))
+        );
+        assert_eq!(Latency::of(&mut []), Latency::default());
+    }
+
+    #[test]
+    fn runs() {
+        let server = TestServer::new(|request: &Request| match request.path() {
+            "/missing" => Response::text(404, "nope"),
+            _ => Response::text(200, "ok"),
+        });
+        let target = Target::parse(&server.url("/")).unwrap();
+
+        let report = Bench::new(target.clone()).connections(3).requests(20).run();
+        assert_eq!(report.requests, 20);
+        assert_eq!(report.bytes, 40);
+        assert_eq!(report.statuses, BTreeMap::from([(200, 20)]));
+        assert!(report.errors.is_empty());
+        assert!(report.latency.p50 <= report.latency.max);
+
+        let missing = Target {
+            path: "/missing".to_string(),
+            ..target
+        };
+        let report = Bench::new(missing)
+            .duration(Duration::from_millis(100))
+            .run();
+        assert!(report.requests > 0);
+        assert_eq!(report.non_success(), report.requests);
+        let json = report.to_json();
+        assert_eq!(
+            json.get("requests").and_then(json::Value::as_i64),
+            Some(report.requests as i64)
+        );
+        assert!(json.get("latency").and_then(|l| l.get("p99")).is_some());
+    }
+}
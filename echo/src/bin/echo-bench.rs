@@ -0,0 +1,123 @@
+//! Load a server with requests and report how fast it answers, e.g. to pick
+//! the number of workers:
+//!
+//! ```text
+//! echo-bench -c 64 -d 30s http://127.0.0.1:7878/
+//! ```
+
+use echo::bench::{Bench, Target};
+
+use std::{env, process, time::Duration};
+
+const USAGE: &str = "\
+Usage: echo-bench [OPTIONS] <URL>
+
+Sends requests to URL, like http://127.0.0.1:7878/, on keep-alive
+connections, each sending its next request once the last is answered.
+
+Options:
+  -c, --connections <N>       Keep N connections busy [default: 10]
+  -d, --duration <TIME>       Send requests for TIME, e.g. 30s or 500ms
+                              [default: 10s]
+  -n, --requests <N>          Send N requests in all, rather than for a while
+  -m, --method <METHOD>       Send METHOD requests [default: GET]
+  -H, --header <NAME: VALUE>  Add a header to the requests; repeatable
+  -b, --body <BODY>           Send BODY with the requests
+      --timeout <TIME>        Count requests unanswered after TIME as failed
+                              [default: 10s]
+      --json                  Print the report as JSON
+  -h, --help                  Print this help and exit
+";
+
+fn main() {
+    let (bench, json) = parse_args(env::args().skip(1)).unwrap_or_else(|e| fail(e));
+    let report = bench.run();
+    if json {
+        println!("{}", report.to_json().to_pretty_string());
+    } else {
+        print!("{report}");
+    }
+}
+
+/// The benchmark the arguments describe, and whether to print JSON.
+fn parse_args(mut args: impl Iterator<Item = String>) -> Result<(Bench, bool), String> {
+    let mut options = Vec::new();
+    let mut url = None;
+    let mut json = false;
+
+    while let Some(arg) = args.next() {
+        let (name, inline) = match arg.split_once('=') {
+            Some((name, value)) if name.starts_with("--") => (name.to_string(), Some(value)),
+            _ => (arg.clone(), None),
+        };
+        match name.as_str() {
+            "-h" | "--help" => {
+                print!("{USAGE}");
+                process::exit(0);
+            }
+            "--json" => json = true,
+            "-c" | "--connections" | "-d" | "--duration" | "-n" | "--requests" | "-m"
+            | "--method" | "-H" | "--header" | "-b" | "--body" | "--timeout" => {
+                let value = match inline {
+                    Some(value) => value.to_string(),
+                    None => args.next().ok_or(format!("`{name}` needs a value"))?,
+                };
+                options.push((name, value));
+            }
+            _ if name.starts_with('-') => return Err(format!("unknown option `{name}`")),
+            _ if url.is_none() => url = Some(arg),
+            _ => return Err(format!("unexpected argument `{arg}`")),
+        }
+    }
+
+    let url = url.ok_or("no URL to send requests to; see --help")?;
+    let mut bench = Bench::new(Target::parse(&url)?);
+    for (name, value) in options {
+        let invalid = |message: &str| format!("{name}: {message}, not `{value}`");
+        bench = match name.as_str() {
+            "-c" | "--connections" => match value.parse() {
+                Ok(n) if n > 0 => bench.connections(n),
+                _ => return Err(invalid("expected a positive number")),
+            },
+            "-d" | "--duration" => bench.duration(
+                parse_duration(&value)
+                    .ok_or_else(|| invalid("expected a duration like `30s` or `500ms`"))?,
+            ),
+            "-n" | "--requests" => {
+                bench.requests(value.parse().map_err(|_| invalid("expected a number"))?)
+            }
+            "-m" | "--method" => bench.method(&value),
+            "-H" | "--header" => {
+                let (header, header_value) = value
+                    .split_once(':')
+                    .ok_or_else(|| invalid("expected `Name: value`"))?;
+                bench.header(header.trim(), header_value.trim())
+            }
+            "-b" | "--body" => bench.body(value.as_bytes()),
+            "--timeout" => bench.timeout(
+                parse_duration(&value)
+                    .ok_or_else(|| invalid("expected a duration like `30s` or `500ms`"))?,
+            ),
+            _ => unreachable!("option `{name}` is checked above"),
+        };
+    }
+    Ok((bench, json))
+}
+
+/// A duration such as `500ms`, `30s` or `5m`.
+fn parse_duration(s: &str) -> Option<Duration> {
+    let split = s.find(|c: char| !c.is_ascii_digit())?;
+    let n: u64 = s[..split].parse().ok()?;
+    let duration = match &s[split..] {
+        "ms" => Duration::from_millis(n),
+        "s" => Duration::from_secs(n),
+        "m" => Duration::from_secs(n * 60),
+        _ => return None,
+    };
+    (!duration.is_zero()).then_some(duration)
+}
+
+fn fail(message: impl std::fmt::Display) -> ! {
+    eprintln!("echo-bench: {message}");
+    process::exit(1);
+}
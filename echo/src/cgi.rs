@@ -0,0 +1,452 @@
+//! Running programs to answer requests, through the Common Gateway Interface
+//! (RFC 3875).
+
+use std::{
+    env, fs,
+    io::{self, BufRead, BufReader, Read, Write},
+    path::PathBuf,
+    process::{Child, ChildStdout, Command, Stdio},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        mpsc::{self, RecvTimeoutError},
+        Arc, Mutex,
+    },
+    thread,
+    time::Duration,
+};
+
+use crate::{
+    form::percent_decode,
+    http::{Request, Response},
+    server::Handler,
+};
+
+/// The most a program may write before the blank line that ends its header
+/// fields.
+const MAX_HEADER_SIZE: usize = 64 * 1024;
+
+/// A handler running a program for every request, with the request in its
+/// environment and on its standard input.
+///
+/// The program's output starts with header fields, `Content-Type`, `Status`
+/// and `Location` being the CGI ones, and the rest is streamed to the client
+/// as the program writes it. Its standard error goes to the server's.
+#[derive(Debug, Clone)]
+pub struct Cgi {
+    program: PathBuf,
+    script_name: String,
+    env: Vec<(String, String)>,
+    timeout: Option<Duration>,
+}
+
+impl Cgi {
+    pub fn new(program: impl Into<PathBuf>) -> Cgi {
+        Cgi {
+            program: program.into(),
+            script_name: String::new(),
+            env: Vec::new(),
+            timeout: Some(Duration::from_secs(30)),
+        }
+    }
+
+    /// The path the program is mounted at, e.g. `/cgi-bin/app`. The rest of a
+    /// request's path is the program's `PATH_INFO`, and requests outside the
+    /// mount point get a 404. The default is the root.
+    pub fn script_name(mut self, prefix: impl Into<String>) -> Cgi {
+        self.script_name = prefix.into().trim_end_matches('/').to_string();
+        self
+    }
+
+    /// Set an environment variable for the program, besides those describing
+    /// the request.
+    pub fn env(mut self, name: impl Into<String>, value: impl Into<String>) -> Cgi {
+        self.env.push((name.into(), value.into()));
+        self
+    }
+
+    /// Kill the program if it's still running after `timeout`: 30 seconds by
+    /// default, `None` to let it run as long as it likes.
+    pub fn timeout(mut self, timeout: Option<Duration>) -> Cgi {
+        self.timeout = timeout;
+        self
+    }
+
+    /// Run the program for `request`. Answers 500 if it can't be started, 502
+    /// if its output is malformed and 504 if it times out before writing its
+    /// header fields.
+    pub fn serve(&self, request: &Request) -> Response {
+        let Some(path_info) = self.path_info(request.path()) else {
+            return Response::text(404, "Not Found\n");
+        };
+
+        let mut child = match self.command(request, &path_info).spawn() {
+            Ok(child) => child,
+            Err(e) => {
+                eprintln!("Failed to run {}: {e}", self.program.display());
+                return Response::text(500, "Internal Server Error\n");
+            }
+        };
+
+        // Write the body from another thread: a program may well write its
+        // output before it has read all of its input.
+        if let Some(mut stdin) = child.stdin.take() {
+            let body = request.body.clone();
+            thread::spawn(move || {
+                let _ = stdin.write_all(&body);
+            });
+        }
+
+        let stdout = child.stdout.take().expect("stdout is piped");
+        let mut output = Output::new(child, stdout, self.timeout);
+        match output.read_header_fields() {
+            Ok(response) => response.with_stream(output),
+            Err(e) if output.timed_out() => {
+                eprintln!("{} timed out: {e}", self.program.display());
+                Response::text(504, "Gateway Timeout\n")
+            }
+            Err(e) => {
+                eprintln!("Bad output from {}: {e}", self.program.display());
+                Response::text(502, "Bad Gateway\n")
+            }
+        }
+    }
+
+    /// What follows the script name in `path`, decoded, or `None` if `path`
+    /// isn't under it.
+    fn path_info(&self, path: &str) -> Option<String> {
+        let rest = path.strip_prefix(&self.script_name)?;
+        if !rest.is_empty() && !rest.starts_with('/') {
+            return None;
+        }
+        percent_decode(rest)
+    }
+
+    /// The command running the program with the meta-variables of RFC 3875
+    /// section 4.1. The environment is cleared first, so that the server's
+    /// own variables don't leak to the program.
+    fn command(&self, request: &Request, path_info: &str) -> Command {
+        // An absolute path, as a relative one would be looked up in `PATH`
+        // or resolved from the new working directory depending on the
+        // platform.
+        let program = fs::canonicalize(&self.program).unwrap_or_else(|_| self.program.clone());
+        let mut command = Command::new(&program);
+        command
+            .env_clear()
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::inherit());
+        if let Some(dir) = program.parent().filter(|dir| !dir.as_os_str().is_empty()) {
+            command.current_dir(dir);
+        }
+        // A group of its own, so that a timeout kills whatever it started.
+        #[cfg(unix)]
+        std::os::unix::process::CommandExt::process_group(&mut command, 0);
+
+        if let Some(path) = env::var_os("PATH") {
+            command.env("PATH", path);
+        }
+
+        let host = request.header("Host").unwrap_or("localhost");
+        let (server_name, server_port) = match host.rsplit_once(':') {
+            Some((name, port)) if !port.contains(']') => (name, port),
+            _ => (host, "80"),
+        };
+        command
+            .env("GATEWAY_INTERFACE", "CGI/1.1")
+            .env(
+                "SERVER_SOFTWARE",
+                concat!("echo/", env!("CARGO_PKG_VERSION")),
+            )
+            .env("SERVER_NAME", server_name)
+            .env("SERVER_PORT", server_port)
+            .env("SERVER_PROTOCOL", request.version.to_string())
+            .env("REQUEST_METHOD", &request.method)
+            .env("SCRIPT_NAME", &self.script_name)
+            .env("SCRIPT_FILENAME", &program)
+            .env("PATH_INFO", path_info)
+            .env("QUERY_STRING", request.query().unwrap_or(""));
+        if let Some(peer) = request.peer {
+            command
+                .env("REMOTE_ADDR", peer.to_string())
+                .env("REMOTE_HOST", peer.to_string());
+        }
+        if !request.body.is_empty() {
+            command.env("CONTENT_LENGTH", request.body.len().to_string());
+        }
+        if let Some(content_type) = request.header("Content-Type") {
+            command.env("CONTENT_TYPE", content_type);
+        }
+        // The scheme only: the credentials are the server's business.
+        if let Some(authorization) = request.header("Authorization") {
+            let scheme = authorization.split(' ').next().unwrap_or_default();
+            command.env("AUTH_TYPE", scheme);
+        }
+
+        for (name, _) in request.headers.iter() {
+            let Some(variable) = header_variable(name) else {
+                continue;
+            };
+            let values: Vec<_> = request.headers.get_all(name).collect();
+            command.env(variable, values.join(", "));
+        }
+
+        command.envs(self.env.iter().map(|(name, value)| (name, value)));
+        command
+    }
+}
+
+impl Handler for Cgi {
+    fn handle(&self, request: &Request) -> Response {
+        self.serve(request)
+    }
+}
+
+/// The `HTTP_*` variable for a request header, or `None` for the headers
+/// that have variables of their own or mustn't reach the program.
+fn header_variable(name: &str) -> Option<String> {
+    let skipped = [
+        "Content-Length",
+        "Content-Type",
+        "Authorization",
+        // `HTTP_PROXY` would be taken for the proxy to make requests through
+        // ("httpoxy").
+        "Proxy",
+    ];
+    if skipped.iter().any(|s| s.eq_ignore_ascii_case(name)) {
+        return None;
+    }
+    let name = name.to_ascii_uppercase().replace('-', "_");
+    Some(format!("HTTP_{name}"))
+}
+
+/// A running program's standard output. Dropping it kills the program if it
+/// hasn't exited.
+struct Output {
+    stdout: BufReader<ChildStdout>,
+    child: Arc<Mutex<Child>>,
+    timed_out: Arc<AtomicBool>,
+    /// Dropped to call off the watchdog.
+    _watchdog: Option<mpsc::Sender<()>>,
+}
+
+impl Output {
+    fn new(child: Child, stdout: ChildStdout, timeout: Option<Duration>) -> Output {
+        let child = Arc::new(Mutex::new(child));
+        let timed_out = Arc::new(AtomicBool::new(false));
+
+        let watchdog = timeout.map(|timeout| {
+            let (cancel, cancelled) = mpsc::channel::<()>();
+            let child = Arc::clone(&child);
+            let timed_out = Arc::clone(&timed_out);
+            thread::spawn(move || {
+                if let Err(RecvTimeoutError::Timeout) = cancelled.recv_timeout(timeout) {
+                    timed_out.store(true, Ordering::SeqCst);
+                    kill(&mut child.lock().unwrap());
+                }
+            });
+            cancel
+        });
+
+        Output {
+            stdout: BufReader::new(stdout),
+            child,
+            timed_out,
+            _watchdog: watchdog,
+        }
+    }
+
+    fn timed_out(&self) -> bool {
+        self.timed_out.load(Ordering::SeqCst)
+    }
+
+    /// Read the header fields up to the blank line, as a response without
+    /// a body (RFC 3875 section 6.3).
+    fn read_header_fields(&mut self) -> io::Result<Response> {
+        let invalid = |message: &str| io::Error::new(io::ErrorKind::InvalidData, message);
+
+        let mut response = Response::new(200);
+        let mut status = None;
+        let mut read = 0;
+        loop {
+            let mut line = Vec::new();
+            let n = (&mut self.stdout)
+                .take((MAX_HEADER_SIZE - read) as u64 + 1)
+                .read_until(b'\n', &mut line)?;
+            read += n;
+            if n == 0 {
+                return Err(invalid("the output ended before the header fields did"));
+            }
+            if read > MAX_HEADER_SIZE {
+                return Err(invalid("the header fields are too large"));
+            }
+
+            let line =
+                String::from_utf8(line).map_err(|_| invalid("a header field isn't UTF-8"))?;
+            let line = line.trim_end_matches(['\r', '\n']);
+            if line.is_empty() {
+                break;
+            }
+            let (name, value) = line
+                .split_once(':')
+                .ok_or_else(|| invalid("a header field has no colon"))?;
+            let (name, value) = (name.trim(), value.trim());
+
+            if name.eq_ignore_ascii_case("Status") {
+                let code = value.split(' ').next().unwrap_or_default();
+                status = match code.parse() {
+                    Ok(code @ 100..=999) => Some(code),
+                    _ => return Err(invalid("the status isn't a status code")),
+                };
+            } else {
+                response.headers.append(name, value);
+            }
+        }
+
+        let redirect = response.headers.contains("Location");
+        if status.is_none() && !redirect && !response.headers.contains("Content-Type") {
+            return Err(invalid("no Content-Type, Location or Status field"));
+        }
+        response.status = status.unwrap_or(if redirect { 302 } else { 200 });
+        Ok(response)
+    }
+}
+
+impl Read for Output {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.stdout.read(buf)
+    }
+}
+
+impl Drop for Output {
+    fn drop(&mut self) {
+        let mut child = self.child.lock().unwrap();
+        if let Ok(None) = child.try_wait() {
+            kill(&mut child);
+        }
+        let _ = child.wait();
+    }
+}
+
+/// Kill the program, along with the processes it started on Unix.
+fn kill(child: &mut Child) {
+    #[cfg(unix)]
+    {
+        extern "C" {
+            fn kill(pid: i32, signal: i32) -> i32;
+        }
+        const SIGKILL: i32 = 9;
+        if let Ok(pid) = i32::try_from(child.id()) {
+            // SAFETY: `kill` takes no pointers. The group is the child's own,
+            // and the child isn't reaped yet, so its id can't have been
+            // reused.
+            unsafe { kill(-pid, SIGKILL) };
+        }
+    }
+    let _ = child.kill();
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+    use crate::testing::TempDir;
+    use std::{fs, os::unix::fs::PermissionsExt, path::Path};
+
+    struct Script {
+        _dir: TempDir,
+        path: PathBuf,
+    }
+
+    impl Script {
+        fn new(name: &str, source: &str) -> Script {
+            let dir = TempDir::new(&format!("cgi-{name}"));
+            let path = dir.join("script.sh");
+            fs::write(&path, format!("#!/bin/sh\n{source}")).unwrap();
+            fs::set_permissions(&path, fs::Permissions::from_mode(0o755)).unwrap();
+            Script { _dir: dir, path }
+        }
+
+        fn path(&self) -> &Path {
+            &self.path
+        }
+    }
+
+    fn body(response: Response) -> String {
+        let mut body = String::new();
+        if let Some(mut stream) = response.stream.and_then(|stream| stream.take()) {
+            stream.read_to_string(&mut body).unwrap();
+        }
+        body
+    }
+
+    #[test]
+    fn environment_and_body() {
+        let script = Script::new(
+            "env",
+            "printf 'Content-Type: text/plain\\r\\nX-Script: yes\\r\\n\\r\\n'\n\
+             echo \"$REQUEST_METHOD $SCRIPT_NAME $PATH_INFO $QUERY_STRING\"\n\
+             echo \"$SERVER_NAME $SERVER_PORT $REMOTE_ADDR $HTTP_X_THING\"\n\
+             echo \"$CONTENT_LENGTH $AUTH_TYPE [$HTTP_AUTHORIZATION] [$HTTP_PROXY] $EXTRA\"\n\
+             cat\n",
+        );
+        let cgi = Cgi::new(script.path())
+            .script_name("/app/")
+            .env("EXTRA", "extra");
+
+        let mut request = Request::new("POST", "/app/a%20b?x=1");
+        request.headers.insert("Host", "example.com:8080");
+        request.headers.insert("X-Thing", "thing");
+        request.headers.insert("Authorization", "Basic c2VjcmV0");
+        request.headers.insert("Proxy", "http://evil");
+        request.peer = Some([10, 0, 0, 1].into());
+        request.body = b"input".to_vec().into();
+
+        let response = cgi.serve(&request);
+        assert_eq!(response.status, 200);
+        assert_eq!(response.headers.get("Content-Type"), Some("text/plain"));
+        assert_eq!(response.headers.get("X-Script"), Some("yes"));
+        assert_eq!(
+            body(response),
+            "POST /app /a b x=1\n\
+             example.com 8080 10.0.0.1 thing\n\
+             5 Basic [] [] extra\n\
+             input"
+        );
+
+        assert_eq!(cgi.serve(&Request::new("GET", "/other")).status, 404);
+        assert_eq!(cgi.serve(&Request::new("GET", "/application")).status, 404);
+    }
+
+    #[test]
+    fn status_and_redirects() {
+        let script = Script::new(
+            "status",
+            "case \"$PATH_INFO\" in\n\
+             /missing) printf 'Status: 404 Not Found\\nContent-Type: text/plain\\n\\ngone' ;;\n\
+             /moved) printf 'Location: /elsewhere\\n\\n' ;;\n\
+             *) echo 'not a header' ;;\n\
+             esac\n",
+        );
+        let cgi = Cgi::new(script.path());
+
+        let response = cgi.serve(&Request::new("GET", "/missing"));
+        assert_eq!(response.status, 404);
+        assert!(!response.headers.contains("Status"));
+        assert_eq!(body(response), "gone");
+
+        let response = cgi.serve(&Request::new("GET", "/moved"));
+        assert_eq!(response.status, 302);
+        assert_eq!(response.headers.get("Location"), Some("/elsewhere"));
+
+        assert_eq!(cgi.serve(&Request::new("GET", "/")).status, 502);
+    }
+
+    #[test]
+    fn failures() {
+        let cgi = Cgi::new("/nonexistent/program");
+        assert_eq!(cgi.serve(&Request::new("GET", "/")).status, 500);
+
+        let script = Script::new("timeout", "sleep 10\n");
+        let cgi = Cgi::new(script.path()).timeout(Some(Duration::from_millis(200)));
+        assert_eq!(cgi.serve(&Request::new("GET", "/")).status, 504);
+    }
+}
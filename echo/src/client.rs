@@ -0,0 +1,176 @@
+//! A minimal blocking HTTP/1.1 client, enough to talk to the server in tests
+//! and tools.
+//!
+//! ```no_run
+//! use echo::client::Client;
+//!
+//! let mut client = Client::new("127.0.0.1:7878".parse().unwrap());
+//! let response = client.get("/")?;
+//! assert_eq!(response.status, 200);
+//! # Ok::<(), std::io::Error>(())
+//! ```
+
+use std::{
+    io::{self, Read, Write},
+    net::{SocketAddr, TcpStream},
+    time::Duration,
+};
+
+use crate::http::{parse_partial_response, parse_response, Request, Response};
+
+/// A client of one server, keeping its connection alive between requests
+/// when the server does.
+#[derive(Debug)]
+pub struct Client {
+    addr: SocketAddr,
+    timeout: Option<Duration>,
+    /// `None` before the first request, and once the server closes the
+    /// connection.
+    stream: Option<TcpStream>,
+    buf: Vec<u8>,
+}
+
+impl Client {
+    /// A client of the server at `addr`. It connects on the first request.
+    pub fn new(addr: SocketAddr) -> Client {
+        Client {
+            addr,
+            timeout: Some(Duration::from_secs(30)),
+            stream: None,
+            buf: Vec::new(),
+        }
+    }
+
+    /// How long to wait for the server to accept or answer a request before
+    /// failing with a timeout. 30 seconds by default.
+    pub fn timeout(mut self, timeout: Option<Duration>) -> Client {
+        self.timeout = timeout;
+        self
+    }
+
+    /// Send a `GET` request for `target`, like `/search?q=rust`.
+    pub fn get(&mut self, target: &str) -> io::Result<Response> {
+        self.send(&Request::new("GET", target))
+    }
+
+    /// Send `request` and wait for the response. `Host` and `Content-Length`
+    /// are filled in if they're missing.
+    ///
+    /// # Errors
+    ///
+    /// If the server can't be reached, or doesn't answer with a valid
+    /// response in time.
+    pub fn send(&mut self, request: &Request) -> io::Result<Response> {
+        let mut request = request.clone();
+        if !request.headers.contains("Host") {
+            request.headers.insert("Host", self.addr.to_string());
+        }
+        if !request.body.is_empty()
+            && !request.headers.contains("Content-Length")
+            && !request.headers.contains("Transfer-Encoding")
+        {
+            let length = request.body.len();
+            request.headers.insert("Content-Length", length.to_string());
+        }
+        let bytes = request.to_bytes();
+
+        // The server may have closed a kept-alive connection in the meantime,
+        // in which case the request goes again on a new one.
+        let reused = self.stream.is_some();
+        let mut result = self.exchange(&bytes, &request.method);
+        if reused && result.as_ref().is_err_and(is_closed) {
+            self.disconnect();
+            result = self.exchange(&bytes, &request.method);
+        }
+
+        match &result {
+            Ok((response, at_eof)) if !at_eof && keeps_alive(&request, response) => {}
+            _ => self.disconnect(),
+        }
+        result.map(|(response, _)| response)
+    }
+
+    /// Whether the client has a connection open, which it has after a
+    /// response if the server keeps the connection alive.
+    pub fn is_connected(&self) -> bool {
+        self.stream.is_some()
+    }
+
+    /// Write a request and read its response, connecting first if needed.
+    /// Also returns whether the response ended with the connection. The
+    /// caller disconnects on errors.
+    fn exchange(&mut self, bytes: &[u8], method: &str) -> io::Result<(Response, bool)> {
+        let stream = match &mut self.stream {
+            Some(stream) => stream,
+            None => {
+                let stream = match self.timeout {
+                    Some(timeout) => TcpStream::connect_timeout(&self.addr, timeout)?,
+                    None => TcpStream::connect(self.addr)?,
+                };
+                stream.set_read_timeout(self.timeout)?;
+                stream.set_write_timeout(self.timeout)?;
+                self.buf.clear();
+                self.stream.insert(stream)
+            }
+        };
+        stream.write_all(bytes)?;
+
+        let mut chunk = [0; 4096];
+        loop {
+            let parsed = parse_partial_response(&self.buf, method, false).map_err(invalid_data)?;
+            if let Some((response, len)) = parsed {
+                self.buf.drain(..len);
+                return Ok((response, false));
+            }
+
+            let n = match stream.read(&mut chunk) {
+                Ok(n) => n,
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                Err(e) => return Err(e),
+            };
+            if n == 0 {
+                if self.buf.is_empty() {
+                    return Err(io::Error::new(
+                        io::ErrorKind::UnexpectedEof,
+                        "the server closed the connection without a response",
+                    ));
+                }
+                let response = parse_response(&self.buf, method).map_err(invalid_data)?;
+                return Ok((response, true));
+            }
+            self.buf.extend_from_slice(&chunk[..n]);
+        }
+    }
+
+    fn disconnect(&mut self) {
+        self.stream = None;
+        self.buf.clear();
+    }
+}
+
+/// Send a `GET` request for `target` to the server at `addr`.
+pub fn get(addr: SocketAddr, target: &str) -> io::Result<Response> {
+    Client::new(addr).get(target)
+}
+
+/// Whether the connection stays open after `response`.
+fn keeps_alive(request: &Request, response: &Response) -> bool {
+    request.keep_alive()
+        && response.status != 101
+        && !response.headers.has_token("Connection", "close")
+}
+
+/// Whether the error is that of a connection the server had closed.
+fn is_closed(e: &io::Error) -> bool {
+    matches!(
+        e.kind(),
+        io::ErrorKind::UnexpectedEof
+            | io::ErrorKind::ConnectionReset
+            | io::ErrorKind::ConnectionAborted
+            | io::ErrorKind::BrokenPipe
+    )
+}
+
+fn invalid_data(e: crate::http::Error) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, e.to_string())
+}
@@ -0,0 +1,715 @@
+//! A DEFLATE encoder (RFC 1951): LZ77 with hash chains and lazy matching,
+//! then each block is written with whichever of the stored, fixed Huffman
+//! and dynamic Huffman encodings comes out shortest.
+
+use std::{cmp::Reverse, collections::BinaryHeap};
+
+const WINDOW_SIZE: usize = 1 << 15;
+const MIN_MATCH: usize = 3;
+const MAX_MATCH: usize = 258;
+const HASH_BITS: u32 = 15;
+/// How many earlier positions to try before settling for the best match so
+/// far, and the match length that's good enough to stop looking.
+const MAX_CHAIN: usize = 128;
+const NICE_MATCH: usize = 128;
+/// Tokens per block, so that each block gets codes fitted to its data.
+const BLOCK_TOKENS: usize = 1 << 14;
+
+const END_OF_BLOCK: usize = 256;
+
+const LENGTH_BASE: [u16; 29] = [
+    3, 4, 5, 6, 7, 8, 9, 10, 11, 13, 15, 17, 19, 23, 27, 31, 35, 43, 51, 59, 67, 83, 99, 115, 131,
+    163, 195, 227, 258,
+];
+const LENGTH_EXTRA: [u8; 29] = [
+    0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 2, 2, 2, 2, 3, 3, 3, 3, 4, 4, 4, 4, 5, 5, 5, 5, 0,
+];
+const DISTANCE_BASE: [u16; 30] = [
+    1, 2, 3, 4, 5, 7, 9, 13, 17, 25, 33, 49, 65, 97, 129, 193, 257, 385, 513, 769, 1025, 1537,
+    2049, 3073, 4097, 6145, 8193, 12289, 16385, 24577,
+];
+const DISTANCE_EXTRA: [u8; 30] = [
+    0, 0, 0, 0, 1, 1, 2, 2, 3, 3, 4, 4, 5, 5, 6, 6, 7, 7, 8, 8, 9, 9, 10, 10, 11, 11, 12, 12, 13,
+    13,
+];
+/// The order code length code lengths are written in.
+const CODE_LENGTH_ORDER: [usize; 19] = [
+    16, 17, 18, 0, 8, 7, 9, 6, 10, 5, 11, 4, 12, 3, 13, 2, 14, 1, 15,
+];
+
+#[derive(Debug, Clone, Copy)]
+enum Token {
+    Literal(u8),
+    Match { length: u16, distance: u16 },
+}
+
+/// Compress `data` into a raw DEFLATE stream.
+pub fn deflate(data: &[u8]) -> Vec<u8> {
+    let tokens = Matcher::new(data).tokens();
+    let mut writer = BitWriter::new();
+
+    let mut start = 0;
+    let mut chunks = tokens.chunks(BLOCK_TOKENS).peekable();
+    if chunks.peek().is_none() {
+        write_block(&mut writer, &[], &[], true);
+    }
+    while let Some(chunk) = chunks.next() {
+        let len: usize = chunk.iter().map(Token::len).sum();
+        let last = chunks.peek().is_none();
+        write_block(&mut writer, chunk, &data[start..start + len], last);
+        start += len;
+    }
+
+    writer.finish()
+}
+
+impl Token {
+    /// The number of input bytes the token stands for.
+    fn len(&self) -> usize {
+        match self {
+            Token::Literal(_) => 1,
+            Token::Match { length, .. } => usize::from(*length),
+        }
+    }
+}
+
+/// Finds repeated strings with chains of earlier positions that start with
+/// the same three bytes.
+struct Matcher<'a> {
+    data: &'a [u8],
+    /// The latest position for each hash.
+    head: Vec<Option<usize>>,
+    /// The previous position with the same hash, for each position in the
+    /// window.
+    prev: Vec<Option<usize>>,
+}
+
+impl<'a> Matcher<'a> {
+    fn new(data: &'a [u8]) -> Matcher<'a> {
+        Matcher {
+            data,
+            head: vec![None; 1 << HASH_BITS],
+            prev: vec![None; WINDOW_SIZE],
+        }
+    }
+
+    fn hash(&self, pos: usize) -> usize {
+        let bytes = [self.data[pos], self.data[pos + 1], self.data[pos + 2], 0];
+        (u32::from_le_bytes(bytes).wrapping_mul(0x9E37_79B1) >> (32 - HASH_BITS)) as usize
+    }
+
+    fn insert(&mut self, pos: usize) {
+        if pos + MIN_MATCH <= self.data.len() {
+            let hash = self.hash(pos);
+            self.prev[pos % WINDOW_SIZE] = self.head[hash];
+            self.head[hash] = Some(pos);
+        }
+    }
+
+    /// The longest earlier match for the bytes at `pos`, as a length and a
+    /// distance, if there's one of at least `MIN_MATCH` bytes.
+    fn longest_match(&self, pos: usize) -> Option<(usize, usize)> {
+        if pos + MIN_MATCH > self.data.len() {
+            return None;
+        }
+        let max_len = MAX_MATCH.min(self.data.len() - pos);
+        let mut best: Option<(usize, usize)> = None;
+        let mut candidate = self.head[self.hash(pos)];
+
+        for _ in 0..MAX_CHAIN {
+            let Some(earlier) = candidate else { break };
+            let distance = pos - earlier;
+            if distance > WINDOW_SIZE - 1 {
+                break;
+            }
+
+            let len = self.data[earlier..earlier + max_len]
+                .iter()
+                .zip(&self.data[pos..pos + max_len])
+                .take_while(|(a, b)| a == b)
+                .count();
+            if len >= MIN_MATCH && best.is_none_or(|(best_len, _)| len > best_len) {
+                best = Some((len, distance));
+                if len >= NICE_MATCH.min(max_len) {
+                    break;
+                }
+            }
+
+            // Positions only ever point further back; anything else is a
+            // slot that was reused for a newer position.
+            candidate = self.prev[earlier % WINDOW_SIZE].filter(|&next| next < earlier);
+        }
+
+        best
+    }
+
+    fn tokens(mut self) -> Vec<Token> {
+        let mut tokens = Vec::new();
+        let mut pos = 0;
+
+        while pos < self.data.len() {
+            let found = self.longest_match(pos);
+            self.insert(pos);
+
+            let Some((len, distance)) = found else {
+                tokens.push(Token::Literal(self.data[pos]));
+                pos += 1;
+                continue;
+            };
+
+            // Lazy matching: a longer match starting at the next byte is
+            // worth a literal.
+            if len < NICE_MATCH {
+                if let Some((next_len, _)) = self.longest_match(pos + 1) {
+                    if next_len > len {
+                        tokens.push(Token::Literal(self.data[pos]));
+                        pos += 1;
+                        continue;
+                    }
+                }
+            }
+
+            tokens.push(Token::Match {
+                length: len as u16,
+                distance: distance as u16,
+            });
+            for skipped in pos + 1..pos + len {
+                self.insert(skipped);
+            }
+            pos += len;
+        }
+
+        tokens
+    }
+}
+
+fn length_symbol(length: u16) -> usize {
+    LENGTH_BASE.partition_point(|&base| base <= length) - 1
+}
+
+fn distance_symbol(distance: u16) -> usize {
+    DISTANCE_BASE.partition_point(|&base| base <= distance) - 1
+}
+
+/// Writes bits starting from the least significant bit of each byte.
+struct BitWriter {
+    bytes: Vec<u8>,
+    buffer: u64,
+    bits: u32,
+}
+
+impl BitWriter {
+    fn new() -> BitWriter {
+        BitWriter {
+            bytes: Vec::new(),
+            buffer: 0,
+            bits: 0,
+        }
+    }
+
+    fn write(&mut self, value: u32, count: u8) {
+        self.buffer |= u64::from(value) << self.bits;
+        self.bits += u32::from(count);
+        while self.bits >= 8 {
+            self.bytes.push(self.buffer as u8);
+            self.buffer >>= 8;
+            self.bits -= 8;
+        }
+    }
+
+    /// Write a Huffman code, which goes most significant bit first.
+    fn write_code(&mut self, code: u16, len: u8) {
+        self.write(u32::from(code.reverse_bits() >> (16 - len)), len);
+    }
+
+    fn align(&mut self) {
+        if self.bits > 0 {
+            self.write(0, (8 - self.bits) as u8);
+        }
+    }
+
+    fn finish(mut self) -> Vec<u8> {
+        self.align();
+        self.bytes
+    }
+}
+
+/// A Huffman code: the length and the code of each symbol.
+struct Code {
+    lengths: Vec<u8>,
+    codes: Vec<u16>,
+}
+
+impl Code {
+    /// The canonical code for the given lengths (RFC 1951 section 3.2.2).
+    fn from_lengths(lengths: Vec<u8>) -> Code {
+        let mut count = [0u16; 16];
+        for &len in &lengths {
+            count[usize::from(len)] += 1;
+        }
+        count[0] = 0;
+
+        let mut next = [0u16; 16];
+        let mut code = 0;
+        for bits in 1..16 {
+            code = (code + count[bits - 1]) << 1;
+            next[bits] = code;
+        }
+
+        let codes = lengths
+            .iter()
+            .map(|&len| {
+                let code = next[usize::from(len)];
+                next[usize::from(len)] += 1;
+                code
+            })
+            .collect();
+
+        Code { lengths, codes }
+    }
+
+    /// A code fitted to the symbol frequencies, with no code longer than
+    /// `limit` bits.
+    fn fitted(freqs: &[u32], limit: u8) -> Code {
+        let mut freqs = freqs.to_vec();
+        loop {
+            let lengths = huffman_lengths(&freqs);
+            if lengths.iter().all(|&len| len <= limit) {
+                return Code::from_lengths(lengths);
+            }
+            // Flatten the distribution until the tree is shallow enough.
+            for freq in freqs.iter_mut().filter(|freq| **freq > 0) {
+                *freq = (*freq >> 1) | 1;
+            }
+        }
+    }
+
+    fn fixed_literals() -> Code {
+        let mut lengths = vec![8; 288];
+        lengths[144..256].fill(9);
+        lengths[256..280].fill(7);
+        Code::from_lengths(lengths)
+    }
+
+    fn fixed_distances() -> Code {
+        Code::from_lengths(vec![5; 30])
+    }
+
+    fn write(&self, writer: &mut BitWriter, symbol: usize) {
+        writer.write_code(self.codes[symbol], self.lengths[symbol]);
+    }
+
+    /// The number of bits the symbols with these frequencies take.
+    fn cost(&self, freqs: &[u32]) -> u64 {
+        freqs
+            .iter()
+            .zip(&self.lengths)
+            .map(|(&freq, &len)| u64::from(freq) * u64::from(len))
+            .sum()
+    }
+}
+
+/// The lengths of an optimal (unlimited) Huffman code.
+fn huffman_lengths(freqs: &[u32]) -> Vec<u8> {
+    let mut lengths = vec![0; freqs.len()];
+    let used: Vec<usize> = (0..freqs.len()).filter(|&i| freqs[i] > 0).collect();
+
+    match used[..] {
+        [] => return lengths,
+        // A single symbol still needs a one bit code.
+        [only] => {
+            lengths[only] = 1;
+            return lengths;
+        }
+        _ => {}
+    }
+
+    // Leaves are the symbols, and every merge adds an inner node.
+    let mut parent = vec![usize::MAX; freqs.len() + used.len()];
+    let mut heap: BinaryHeap<Reverse<(u64, usize)>> = used
+        .iter()
+        .map(|&symbol| Reverse((u64::from(freqs[symbol]), symbol)))
+        .collect();
+    let mut next = freqs.len();
+
+    while heap.len() > 1 {
+        let Reverse((a_weight, a)) = heap.pop().unwrap();
+        let Reverse((b_weight, b)) = heap.pop().unwrap();
+        parent[a] = next;
+        parent[b] = next;
+        heap.push(Reverse((a_weight + b_weight, next)));
+        next += 1;
+    }
+
+    for &symbol in &used {
+        let mut depth = 0;
+        let mut node = symbol;
+        while parent[node] != usize::MAX {
+            node = parent[node];
+            depth += 1;
+        }
+        lengths[symbol] = depth.min(u8::MAX as usize) as u8;
+    }
+    lengths
+}
+
+/// The code lengths of the literal/length and distance codes, run-length
+/// encoded with the code length alphabet: `(symbol, extra bits, count)`.
+fn run_length_encode(lengths: &[u8]) -> Vec<(usize, u32, u8)> {
+    let mut encoded = Vec::new();
+    let mut i = 0;
+
+    while i < lengths.len() {
+        let len = lengths[i];
+        let run = lengths[i..].iter().take_while(|&&l| l == len).count();
+
+        if len == 0 && run >= 11 {
+            let n = run.min(138);
+            encoded.push((18, (n - 11) as u32, 7));
+            i += n;
+        } else if len == 0 && run >= 3 {
+            encoded.push((17, (run - 3) as u32, 3));
+            i += run;
+        } else if len != 0 && run >= 4 {
+            // The length once, then repeats of it.
+            encoded.push((usize::from(len), 0, 0));
+            let n = (run - 1).min(6);
+            encoded.push((16, (n - 3) as u32, 2));
+            i += 1 + n;
+        } else {
+            encoded.push((usize::from(len), 0, 0));
+            i += 1;
+        }
+    }
+
+    encoded
+}
+
+fn write_block(writer: &mut BitWriter, tokens: &[Token], raw: &[u8], last: bool) {
+    let mut literal_freqs = [0u32; 286];
+    let mut distance_freqs = [0u32; 30];
+    let mut extra_bits = 0u64;
+    for token in tokens {
+        match *token {
+            Token::Literal(byte) => literal_freqs[usize::from(byte)] += 1,
+            Token::Match { length, distance } => {
+                let length = length_symbol(length);
+                let distance = distance_symbol(distance);
+                literal_freqs[257 + length] += 1;
+                distance_freqs[distance] += 1;
+                extra_bits += u64::from(LENGTH_EXTRA[length] + DISTANCE_EXTRA[distance]);
+            }
+        }
+    }
+    literal_freqs[END_OF_BLOCK] = 1;
+
+    let fixed_literals = Code::fixed_literals();
+    let fixed_distances = Code::fixed_distances();
+    let fixed_cost = 3
+        + fixed_literals.cost(&literal_freqs)
+        + fixed_distances.cost(&distance_freqs)
+        + extra_bits;
+
+    let literals = Code::fitted(&literal_freqs, 15);
+    let mut distances = Code::fitted(&distance_freqs, 15);
+    if distances.lengths.iter().all(|&len| len == 0) {
+        // Decoders want at least one distance code, even if it's unused.
+        let mut lengths = distances.lengths;
+        lengths[0] = 1;
+        distances = Code::from_lengths(lengths);
+    }
+
+    let literal_count = 257.max(trimmed_len(&literals.lengths));
+    let distance_count = 1.max(trimmed_len(&distances.lengths));
+    let mut all_lengths = literals.lengths[..literal_count].to_vec();
+    all_lengths.extend_from_slice(&distances.lengths[..distance_count]);
+    let encoded_lengths = run_length_encode(&all_lengths);
+
+    let mut length_freqs = [0u32; 19];
+    for &(symbol, _, _) in &encoded_lengths {
+        length_freqs[symbol] += 1;
+    }
+    let length_code = Code::fitted(&length_freqs, 7);
+    let length_code_count = 4.max(
+        CODE_LENGTH_ORDER
+            .iter()
+            .rposition(|&symbol| length_code.lengths[symbol] != 0)
+            .map_or(0, |i| i + 1),
+    );
+
+    let dynamic_cost = 3
+        + 5
+        + 5
+        + 4
+        + 3 * length_code_count as u64
+        + encoded_lengths
+            .iter()
+            .map(|&(symbol, _, extra)| u64::from(length_code.lengths[symbol] + extra))
+            .sum::<u64>()
+        + literals.cost(&literal_freqs)
+        + distances.cost(&distance_freqs)
+        + extra_bits;
+
+    // Stored blocks start on a byte boundary and hold up to 65535 bytes
+    // each, behind a 4 byte header.
+    let stored_cost =
+        (raw.len().div_ceil(0xffff).max(1) as u64) * (3 + 7 + 32) + 8 * raw.len() as u64;
+
+    if stored_cost <= fixed_cost.min(dynamic_cost) {
+        write_stored(writer, raw, last);
+        return;
+    }
+
+    writer.write(u32::from(last), 1);
+    if fixed_cost <= dynamic_cost {
+        writer.write(0b01, 2);
+        write_tokens(writer, tokens, &fixed_literals, &fixed_distances);
+    } else {
+        writer.write(0b10, 2);
+        writer.write((literal_count - 257) as u32, 5);
+        writer.write((distance_count - 1) as u32, 5);
+        writer.write((length_code_count - 4) as u32, 4);
+        for &symbol in &CODE_LENGTH_ORDER[..length_code_count] {
+            writer.write(u32::from(length_code.lengths[symbol]), 3);
+        }
+        for &(symbol, extra, bits) in &encoded_lengths {
+            length_code.write(writer, symbol);
+            writer.write(extra, bits);
+        }
+        write_tokens(writer, tokens, &literals, &distances);
+    }
+}
+
+/// The number of symbols up to the last one that has a code.
+fn trimmed_len(lengths: &[u8]) -> usize {
+    lengths
+        .iter()
+        .rposition(|&len| len != 0)
+        .map_or(0, |i| i + 1)
+}
+
+fn write_tokens(writer: &mut BitWriter, tokens: &[Token], literals: &Code, distances: &Code) {
+    for token in tokens {
+        match *token {
+            Token::Literal(byte) => literals.write(writer, usize::from(byte)),
+            Token::Match { length, distance } => {
+                let symbol = length_symbol(length);
+                literals.write(writer, 257 + symbol);
+                writer.write(
+                    u32::from(length - LENGTH_BASE[symbol]),
+                    LENGTH_EXTRA[symbol],
+                );
+
+                let symbol = distance_symbol(distance);
+                distances.write(writer, symbol);
+                writer.write(
+                    u32::from(distance - DISTANCE_BASE[symbol]),
+                    DISTANCE_EXTRA[symbol],
+                );
+            }
+        }
+    }
+    literals.write(writer, END_OF_BLOCK);
+}
+
+fn write_stored(writer: &mut BitWriter, raw: &[u8], last: bool) {
+    // An empty block still needs writing, to carry the last block flag.
+    let chunks: Vec<&[u8]> = if raw.is_empty() {
+        vec![raw]
+    } else {
+        raw.chunks(0xffff).collect()
+    };
+
+    let count = chunks.len();
+    for (i, chunk) in chunks.into_iter().enumerate() {
+        let len = chunk.len() as u16;
+        writer.write(u32::from(last && i + 1 == count), 1);
+        writer.write(0b00, 2);
+        writer.align();
+        writer.write(u32::from(len), 16);
+        writer.write(u32::from(!len), 16);
+        for &byte in chunk {
+            writer.write(u32::from(byte), 8);
+        }
+    }
+}
+
+#[cfg(test)]
+pub(super) mod tests {
+    use super::*;
+
+    /// A straightforward decoder (RFC 1951 section 3.2.5), to check the
+    /// encoder's output.
+    pub fn inflate(data: &[u8]) -> Vec<u8> {
+        let mut reader = BitReader { data, pos: 0 };
+        let mut out = Vec::new();
+
+        loop {
+            let last = reader.bits(1) == 1;
+            match reader.bits(2) {
+                0b00 => {
+                    reader.pos = reader.pos.div_ceil(8) * 8;
+                    let len = reader.bits(16) as usize;
+                    assert_eq!(reader.bits(16) as usize, !len & 0xffff);
+                    for _ in 0..len {
+                        out.push(reader.bits(8) as u8);
+                    }
+                }
+                0b01 => {
+                    let literals = Decoder::new(&Code::fixed_literals().lengths);
+                    let distances = Decoder::new(&Code::fixed_distances().lengths);
+                    inflate_block(&mut reader, &mut out, &literals, &distances);
+                }
+                0b10 => {
+                    let literal_count = reader.bits(5) as usize + 257;
+                    let distance_count = reader.bits(5) as usize + 1;
+                    let length_code_count = reader.bits(4) as usize + 4;
+                    let mut length_lengths = [0u8; 19];
+                    for &symbol in &CODE_LENGTH_ORDER[..length_code_count] {
+                        length_lengths[symbol] = reader.bits(3) as u8;
+                    }
+                    let length_decoder = Decoder::new(&length_lengths);
+
+                    let mut lengths = Vec::new();
+                    while lengths.len() < literal_count + distance_count {
+                        match length_decoder.decode(&mut reader) {
+                            16 => {
+                                let previous = *lengths.last().unwrap();
+                                let n = 3 + reader.bits(2);
+                                lengths.extend((0..n).map(|_| previous));
+                            }
+                            17 => lengths.extend((0..3 + reader.bits(3)).map(|_| 0)),
+                            18 => lengths.extend((0..11 + reader.bits(7)).map(|_| 0)),
+                            len => lengths.push(len as u8),
+                        }
+                    }
+                    let literals = Decoder::new(&lengths[..literal_count]);
+                    let distances = Decoder::new(&lengths[literal_count..]);
+                    inflate_block(&mut reader, &mut out, &literals, &distances);
+                }
+                _ => panic!("invalid block type"),
+            }
+            if last {
+                return out;
+            }
+        }
+    }
+
+    struct BitReader<'a> {
+        data: &'a [u8],
+        pos: usize,
+    }
+
+    impl BitReader<'_> {
+        fn bits(&mut self, count: u32) -> u32 {
+            let mut value = 0;
+            for i in 0..count {
+                let bit = (self.data[self.pos / 8] >> (self.pos % 8)) & 1;
+                value |= u32::from(bit) << i;
+                self.pos += 1;
+            }
+            value
+        }
+    }
+
+    struct Decoder {
+        /// `(length, code) -> symbol`.
+        symbols: std::collections::HashMap<(u8, u16), usize>,
+    }
+
+    impl Decoder {
+        fn new(lengths: &[u8]) -> Decoder {
+            let code = Code::from_lengths(lengths.to_vec());
+            let symbols = (0..lengths.len())
+                .filter(|&symbol| lengths[symbol] > 0)
+                .map(|symbol| ((lengths[symbol], code.codes[symbol]), symbol))
+                .collect();
+            Decoder { symbols }
+        }
+
+        fn decode(&self, reader: &mut BitReader<'_>) -> usize {
+            let mut code = 0;
+            for len in 1..=15 {
+                code = (code << 1) | reader.bits(1) as u16;
+                if let Some(&symbol) = self.symbols.get(&(len, code)) {
+                    return symbol;
+                }
+            }
+            panic!("invalid code");
+        }
+    }
+
+    fn inflate_block(
+        reader: &mut BitReader<'_>,
+        out: &mut Vec<u8>,
+        literals: &Decoder,
+        distances: &Decoder,
+    ) {
+        loop {
+            let symbol = literals.decode(reader);
+            match symbol {
+                0..=255 => out.push(symbol as u8),
+                END_OF_BLOCK => return,
+                _ => {
+                    let i = symbol - 257;
+                    let length =
+                        LENGTH_BASE[i] as usize + reader.bits(LENGTH_EXTRA[i].into()) as usize;
+                    let i = distances.decode(reader);
+                    let distance =
+                        DISTANCE_BASE[i] as usize + reader.bits(DISTANCE_EXTRA[i].into()) as usize;
+                    let start = out.len() - distance;
+                    for k in 0..length {
+                        out.push(out[start + k]);
+                    }
+                }
+            }
+        }
+    }
+
+    fn round_trip(data: &[u8]) -> Vec<u8> {
+        let compressed = deflate(data);
+        assert_eq!(inflate(&compressed), data);
+        compressed
+    }
+
+    #[test]
+    fn empty_and_tiny_inputs() {
+        round_trip(b"");
+        round_trip(b"a");
+        round_trip(b"ab");
+        round_trip(b"aaaaaaaaaa");
+    }
+
+    #[test]
+    fn repetitive_text_shrinks() {
+        let text = "<li>The quick brown fox jumps over the lazy dog.</li>\n".repeat(500);
+        let compressed = round_trip(text.as_bytes());
+        assert!(compressed.len() < text.len() / 20, "{}", compressed.len());
+    }
+
+    #[test]
+    fn incompressible_data_is_stored() {
+        // A simple xorshift generator, for bytes without repetitions.
+        let mut state = 0x2545_f491_4f6c_dd1du64;
+        let data: Vec<u8> = (0..200_000)
+            .map(|_| {
+                state ^= state << 13;
+                state ^= state >> 7;
+                state ^= state << 17;
+                state as u8
+            })
+            .collect();
+
+        let compressed = round_trip(&data);
+        assert!(compressed.len() < data.len() + data.len() / 100);
+    }
+
+    #[test]
+    fn long_inputs_with_far_matches() {
+        let mut data = Vec::new();
+        for i in 0..100_000u32 {
+            data.extend_from_slice(format!("{} ", i % 7919).as_bytes());
+        }
+        round_trip(&data);
+    }
+}
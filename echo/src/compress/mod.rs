@@ -0,0 +1,393 @@
+//! Response compression: a DEFLATE encoder, the gzip and zlib formats around
+//! it, and negotiating them with `Accept-Encoding`.
+
+mod deflate;
+
+pub use deflate::deflate;
+
+use crate::{
+    http::{Request, Response},
+    server::Handler,
+};
+
+/// Responses smaller than this aren't worth compressing by default: the
+/// saving is a few bytes at best, and small bodies can even grow.
+pub const MIN_SIZE: usize = 1024;
+
+/// A content coding the server can apply to response bodies.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Encoding {
+    /// The gzip format (RFC 1952).
+    Gzip,
+    /// The zlib format (RFC 1950), which is what HTTP calls `deflate`.
+    Deflate,
+}
+
+impl Encoding {
+    /// The coding's name in `Accept-Encoding` and `Content-Encoding`.
+    pub fn name(self) -> &'static str {
+        match self {
+            Encoding::Gzip => "gzip",
+            Encoding::Deflate => "deflate",
+        }
+    }
+
+    pub fn encode(self, data: &[u8]) -> Vec<u8> {
+        match self {
+            Encoding::Gzip => gzip(data),
+            Encoding::Deflate => zlib(data),
+        }
+    }
+}
+
+/// Compress `data` into a gzip member with no file name or time stamp.
+pub fn gzip(data: &[u8]) -> Vec<u8> {
+    // Magic, DEFLATE, no flags, no time, no extra flags, unknown OS.
+    let mut out = vec![0x1f, 0x8b, 8, 0, 0, 0, 0, 0, 0, 255];
+    out.extend_from_slice(&deflate(data));
+    out.extend_from_slice(&crc32(data).to_le_bytes());
+    // The length modulo 2^32.
+    out.extend_from_slice(&(data.len() as u32).to_le_bytes());
+    out
+}
+
+/// Compress `data` into a zlib stream.
+pub fn zlib(data: &[u8]) -> Vec<u8> {
+    // DEFLATE with a 32K window, and a check value making the header a
+    // multiple of 31.
+    let mut out = vec![0x78, 0x9c];
+    out.extend_from_slice(&deflate(data));
+    out.extend_from_slice(&adler32(data).to_be_bytes());
+    out
+}
+
+/// The CRC-32 of ISO 3309, which gzip uses.
+fn crc32(data: &[u8]) -> u32 {
+    const TABLE: [u32; 256] = {
+        let mut table = [0; 256];
+        let mut i = 0;
+        while i < 256 {
+            let mut crc = i as u32;
+            let mut bit = 0;
+            while bit < 8 {
+                crc = if crc & 1 == 1 {
+                    (crc >> 1) ^ 0xedb8_8320
+                } else {
+                    crc >> 1
+                };
+                bit += 1;
+            }
+            table[i] = crc;
+            i += 1;
+        }
+        table
+    };
+
+    !data.iter().fold(!0, |crc, &byte| {
+        TABLE[((crc ^ u32::from(byte)) & 0xff) as usize] ^ (crc >> 8)
+    })
+}
+
+fn adler32(data: &[u8]) -> u32 {
+    const MOD: u32 = 65_521;
+    // The largest number of bytes that can be summed before `b` could
+    // overflow.
+    const CHUNK: usize = 5552;
+
+    let (mut a, mut b) = (1u32, 0u32);
+    for chunk in data.chunks(CHUNK) {
+        for &byte in chunk {
+            a += u32::from(byte);
+            b += a;
+        }
+        a %= MOD;
+        b %= MOD;
+    }
+    (b << 16) | a
+}
+
+/// The coding out of `offered` that the client prefers according to its
+/// `Accept-Encoding` q-values, or `None` if it accepts none of them.
+/// Earlier codings in `offered` win ties.
+///
+/// A request without `Accept-Encoding` gets no coding: clients that can
+/// decompress say so.
+pub fn negotiate(request: &Request, offered: &[Encoding]) -> Option<Encoding> {
+    let mut qualities: Vec<(String, f32)> = Vec::new();
+    for element in request
+        .headers
+        .get_all("Accept-Encoding")
+        .flat_map(|value| value.split(','))
+    {
+        let mut params = element.split(';');
+        let coding = params.next().unwrap_or("").trim().to_ascii_lowercase();
+        if coding.is_empty() {
+            continue;
+        }
+        let quality = params
+            .filter_map(|param| param.split_once('='))
+            .find(|(name, _)| name.trim().eq_ignore_ascii_case("q"))
+            // A malformed q-value is read as "not acceptable".
+            .map_or(1.0, |(_, q)| q.trim().parse::<f32>().unwrap_or(0.0));
+        qualities.push((coding, quality));
+    }
+
+    let quality = |encoding: Encoding| {
+        let named = |coding: &str| {
+            coding == encoding.name() || (encoding == Encoding::Gzip && coding == "x-gzip")
+        };
+        qualities
+            .iter()
+            .find(|(coding, _)| named(coding))
+            .or_else(|| qualities.iter().find(|(coding, _)| coding == "*"))
+            .map_or(0.0, |&(_, q)| q)
+    };
+
+    offered
+        .iter()
+        .map(|&encoding| (encoding, quality(encoding)))
+        .filter(|&(_, q)| q > 0.0)
+        .fold(
+            None,
+            |best: Option<(Encoding, f32)>, (encoding, q)| match best {
+                Some((_, best_q)) if best_q >= q => best,
+                _ => Some((encoding, q)),
+            },
+        )
+        .map(|(encoding, _)| encoding)
+}
+
+/// Whether bodies of the given `Content-Type` shrink when compressed: text,
+/// and the structured formats that are text in disguise. Images, audio,
+/// video and archives are compressed already.
+pub fn is_compressible(content_type: &str) -> bool {
+    let essence = content_type
+        .split(';')
+        .next()
+        .unwrap_or("")
+        .trim()
+        .to_ascii_lowercase();
+
+    essence.starts_with("text/")
+        || essence.ends_with("+json")
+        || essence.ends_with("+xml")
+        || matches!(
+            essence.as_str(),
+            "application/json"
+                | "application/javascript"
+                | "application/xml"
+                | "application/wasm"
+                | "image/svg+xml"
+                | "image/x-icon"
+        )
+}
+
+/// Mark `response` as carrying a body in `encoding`: set `Content-Encoding`
+/// and send the body chunked.
+pub(crate) fn mark_encoded(response: &mut Response, encoding: Encoding) {
+    response.headers.insert("Content-Encoding", encoding.name());
+    response.headers.remove("Content-Length");
+    response.headers.insert("Transfer-Encoding", "chunked");
+}
+
+/// The entity tag of the encoded representation of a body, which has to
+/// differ from that of the original: `"abc"` becomes `"abc-gzip"`, and
+/// `W/"abc"` becomes `W/"abc-gzip"`.
+pub(crate) fn encoded_etag(etag: &str, encoding: Encoding) -> String {
+    match etag.strip_suffix('"') {
+        Some(opaque) => format!("{opaque}-{}\"", encoding.name()),
+        None => etag.to_string(),
+    }
+}
+
+/// A handler that compresses the responses of another handler, for the
+/// clients that accept it.
+///
+/// Only 200 responses with a compressible `Content-Type` and a body of at
+/// least `min_size` bytes are compressed, and never those that already have
+/// a `Content-Encoding` or say `Cache-Control: no-transform`. Streamed
+/// bodies are left as they are; files get `StaticFiles::precompress`
+/// instead. Compressed responses are sent with chunked transfer encoding.
+///
+/// ```no_run
+/// use echo::{compress::Compress, http::{Request, Response}, Server};
+/// use std::net::TcpListener;
+///
+/// let page = "<p>Hello, world!</p>\n".repeat(100);
+/// let handler = move |_: &Request| Response::html(200, page.clone());
+/// let server = Server::new(Compress::new(handler));
+/// server.serve(TcpListener::bind("127.0.0.1:7878").unwrap()).unwrap();
+/// ```
+#[derive(Debug, Clone)]
+pub struct Compress<H> {
+    inner: H,
+    min_size: usize,
+}
+
+impl<H: Handler> Compress<H> {
+    pub fn new(inner: H) -> Compress<H> {
+        Compress {
+            inner,
+            min_size: MIN_SIZE,
+        }
+    }
+
+    /// The smallest body worth compressing. Defaults to `MIN_SIZE`.
+    pub fn min_size(mut self, bytes: usize) -> Compress<H> {
+        self.min_size = bytes;
+        self
+    }
+}
+
+impl<H: Handler> Handler for Compress<H> {
+    fn handle(&self, request: &Request) -> Response {
+        let mut response = self.inner.handle(request);
+
+        let compressible = response.status == 200
+            && response.stream.is_none()
+            && !response.headers.contains("Content-Encoding")
+            && !response.headers.has_token("Cache-Control", "no-transform")
+            && response
+                .headers
+                .get("Content-Type")
+                .is_some_and(is_compressible);
+        if !compressible {
+            return response;
+        }
+        // Caches have to know that the body depends on `Accept-Encoding`,
+        // even when this client got it uncompressed.
+        if !response.headers.has_token("Vary", "Accept-Encoding") {
+            response.headers.append("Vary", "Accept-Encoding");
+        }
+
+        if response.body.len() >= self.min_size {
+            if let Some(encoding) = negotiate(request, &[Encoding::Gzip, Encoding::Deflate]) {
+                response.body = encoding.encode(&response.body);
+                mark_encoded(&mut response, encoding);
+                if let Some(etag) = response.headers.get("ETag") {
+                    let etag = encoded_etag(etag, encoding);
+                    response.headers.insert("ETag", etag);
+                }
+            }
+        }
+        response
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn request(accept_encoding: &[&str]) -> Request {
+        let mut request = Request::new("GET", "/");
+        for value in accept_encoding {
+            request.headers.append("Accept-Encoding", *value);
+        }
+        request
+    }
+
+    #[test]
+    fn checksums() {
+        assert_eq!(crc32(b""), 0);
+        assert_eq!(crc32(b"123456789"), 0xcbf4_3926);
+        assert_eq!(adler32(b"Wikipedia"), 0x11e6_0398);
+        // Long enough for the sums to need reducing along the way.
+        assert_eq!(adler32(&[0xff; 100_000]), 0x149a_302c);
+    }
+
+    #[test]
+    fn gzip_and_zlib_framing() {
+        let data = b"hello hello hello hello".repeat(10);
+
+        let gzipped = gzip(&data);
+        assert_eq!(gzipped[..3], [0x1f, 0x8b, 8]);
+        let trailer = &gzipped[gzipped.len() - 8..];
+        assert_eq!(trailer[..4], crc32(&data).to_le_bytes());
+        assert_eq!(trailer[4..], (data.len() as u32).to_le_bytes());
+        assert_eq!(deflate::tests::inflate(&gzipped[10..]), data);
+
+        let zlibbed = zlib(&data);
+        assert_eq!(u16::from_be_bytes([zlibbed[0], zlibbed[1]]) % 31, 0);
+        assert_eq!(deflate::tests::inflate(&zlibbed[2..]), data);
+    }
+
+    #[test]
+    fn negotiation() {
+        let both = [Encoding::Gzip, Encoding::Deflate];
+        let negotiate = |values: &[&str]| negotiate(&request(values), &both);
+
+        assert_eq!(negotiate(&[]), None);
+        assert_eq!(negotiate(&["gzip, deflate, br"]), Some(Encoding::Gzip));
+        assert_eq!(negotiate(&["deflate"]), Some(Encoding::Deflate));
+        assert_eq!(
+            negotiate(&["gzip;q=0.5", "deflate;q=0.8"]),
+            Some(Encoding::Deflate)
+        );
+        assert_eq!(negotiate(&["GZIP; Q=1.0"]), Some(Encoding::Gzip));
+        assert_eq!(negotiate(&["x-gzip"]), Some(Encoding::Gzip));
+        assert_eq!(negotiate(&["*"]), Some(Encoding::Gzip));
+        assert_eq!(negotiate(&["gzip;q=0, *;q=0.1"]), Some(Encoding::Deflate));
+        assert_eq!(negotiate(&["identity, br"]), None);
+        assert_eq!(negotiate(&["gzip;q=0, deflate;q=bogus"]), None);
+    }
+
+    #[test]
+    fn compressible_types() {
+        assert!(is_compressible("text/html; charset=utf-8"));
+        assert!(is_compressible("application/json"));
+        assert!(is_compressible("application/ld+json"));
+        assert!(is_compressible("image/svg+xml"));
+        assert!(!is_compressible("image/png"));
+        assert!(!is_compressible("application/gzip"));
+    }
+
+    #[test]
+    fn compress_handler() {
+        let page = "<p>Hello, world!</p>\n".repeat(100);
+        let handler = Compress::new(move |request: &Request| match request.path() {
+            "/small" => Response::html(200, "<p>Hi</p>"),
+            "/png" => Response::new(200)
+                .with_header("Content-Type", "image/png")
+                .with_body(vec![0; 4096]),
+            "/stream" => Response::html(200, "").with_stream(&[b'x'; 4096][..]),
+            _ => Response::html(200, page.clone()).with_header("ETag", "\"v1\""),
+        });
+
+        let mut gzip_request = request(&["gzip"]);
+        let response = handler.handle(&gzip_request);
+        assert_eq!(response.headers.get("Content-Encoding"), Some("gzip"));
+        assert_eq!(response.headers.get("Transfer-Encoding"), Some("chunked"));
+        assert_eq!(response.headers.get("Vary"), Some("Accept-Encoding"));
+        assert_eq!(response.headers.get("ETag"), Some("\"v1-gzip\""));
+        assert_eq!(
+            deflate::tests::inflate(&response.body[10..response.body.len() - 8]),
+            "<p>Hello, world!</p>\n".repeat(100).as_bytes()
+        );
+
+        let response = handler.handle(&request(&[]));
+        assert_eq!(response.headers.get("Content-Encoding"), None);
+        assert_eq!(response.headers.get("Vary"), Some("Accept-Encoding"));
+        assert_eq!(response.headers.get("ETag"), Some("\"v1\""));
+
+        gzip_request.target = "/small".to_string();
+        assert_eq!(
+            handler
+                .handle(&gzip_request)
+                .headers
+                .get("Content-Encoding"),
+            None
+        );
+        gzip_request.target = "/stream".to_string();
+        let response = handler.handle(&gzip_request);
+        assert_eq!(response.headers.get("Content-Encoding"), None);
+        assert!(response.stream.is_some());
+        gzip_request.target = "/png".to_string();
+        assert_eq!(
+            handler
+                .handle(&gzip_request)
+                .headers
+                .get("Content-Encoding"),
+            None
+        );
+    }
+}
@@ -0,0 +1,283 @@
+use std::path::PathBuf;
+
+use super::{
+    parse_backend, parse_format, parse_listen, parse_log_path, parse_mode, parse_size,
+    parse_timeout, parse_workers, Config, Error,
+};
+
+pub const USAGE: &str = "\
+Usage: echo [OPTIONS]
+
+Options:
+  -c, --config <FILE>         Read settings from FILE; options given on the
+                              command line take precedence
+  -l, --listen <ADDR>         Listen on ADDR, e.g. 127.0.0.1:7878, [::1]:7878 or
+                              unix:/run/echo.sock; repeat to listen on several
+                              addresses
+      --socket-mode <MODE>    Make Unix sockets accessible as MODE, e.g. 660
+  -w, --workers <N>           Run handlers on N threads
+      --queue-capacity <N>    Answer 503 once N requests are waiting (0: no limit)
+      --backend <NAME>        `threaded` or `event-loop`
+      --threaded              Same as --backend threaded
+      --event-loop            Same as --backend event-loop
+  -r, --root <DIR>            Serve pages from DIR
+      --cache-control <VALUE> Send `Cache-Control: VALUE` with the files
+      --templates <DIR>       Render error pages from the templates in DIR
+      --no-compression        Don't gzip responses
+      --no-metrics            Don't expose metrics at /metrics
+      --read-timeout <TIME>   e.g. 30s, 500ms or off
+      --write-timeout <TIME>  e.g. 30s, 500ms or off
+      --header-timeout <TIME> Answer 408 to clients slower to send a request
+                              head, e.g. 10s or off
+      --max-body-size <SIZE>  Answer 413 to larger request bodies, e.g. 1MB
+      --max-connections-per-ip <N>
+                              Answer 429 to clients with N connections open
+                              already (0: no limit)
+      --access-log <FILE>     Write the access log to FILE, `-` for standard
+                              output or `off`
+      --log-format <FORMAT>   `common`, `combined` or `json`
+      --log-max-size <SIZE>   Rotate the access log at SIZE, e.g. 10MB
+      --check-config          Validate the settings and exit
+  -h, --help                  Print this help and exit
+";
+
+/// What the command line asks for.
+#[derive(Debug)]
+pub enum Command {
+    /// Run the server.
+    Run(Config),
+    /// Validate the configuration and exit.
+    CheckConfig(Config),
+    Help,
+}
+
+/// Parse the command line arguments, without the program name, loading the
+/// configuration file if one is given.
+pub fn parse_args<I>(args: I) -> Result<Command, Error>
+where
+    I: IntoIterator<Item = String>,
+{
+    let mut options = Vec::new();
+    let mut args = args.into_iter();
+
+    while let Some(arg) = args.next() {
+        let (name, inline) = match arg.split_once('=') {
+            Some((name, value)) if name.starts_with("--") => (name.to_string(), Some(value)),
+            _ => (arg.clone(), None),
+        };
+
+        let takes_value = match name.as_str() {
+            "--threaded" | "--event-loop" | "--no-compression" | "--no-metrics"
+            | "--check-config" | "-h" | "--help" => false,
+            "-c"
+            | "--config"
+            | "-l"
+            | "--listen"
+            | "--socket-mode"
+            | "-w"
+            | "--workers"
+            | "--queue-capacity"
+            | "--backend"
+            | "-r"
+            | "--root"
+            | "--cache-control"
+            | "--templates"
+            | "--read-timeout"
+            | "--write-timeout"
+            | "--header-timeout"
+            | "--max-body-size"
+            | "--max-connections-per-ip"
+            | "--access-log"
+            | "--log-format"
+            | "--log-max-size" => true,
+            _ => return Err(Error::Usage(format!("unknown option `{name}`"))),
+        };
+        let value = match (takes_value, inline) {
+            (true, Some(value)) => Some(value.to_string()),
+            (true, None) => Some(
+                args.next()
+                    .ok_or_else(|| Error::Usage(format!("`{name}` needs a value")))?,
+            ),
+            (false, Some(_)) => return Err(Error::Usage(format!("`{name}` takes no value"))),
+            (false, None) => None,
+        };
+        options.push((name, value));
+    }
+
+    // The file comes first, whatever the order on the command line, so that
+    // the other options override it.
+    let config_path = options
+        .iter()
+        .rev()
+        .find(|(name, _)| name == "-c" || name == "--config")
+        .and_then(|(_, value)| value.clone());
+    let mut config = match config_path {
+        Some(path) => Config::load(path)?,
+        None => Config::default(),
+    };
+
+    let mut check = false;
+    let mut listen_given = false;
+    for (name, value) in options {
+        let value = value.unwrap_or_default();
+        let invalid = |message: String| Error::Usage(format!("{name}: {message}"));
+
+        match name.as_str() {
+            "-h" | "--help" => return Ok(Command::Help),
+            "-c" | "--config" => {}
+            "-l" | "--listen" => {
+                if !listen_given {
+                    config.listen.clear();
+                    listen_given = true;
+                }
+                config.listen.push(parse_listen(&value).map_err(invalid)?);
+            }
+            "--socket-mode" => config.socket_mode = Some(parse_mode(&value).map_err(invalid)?),
+            "-w" | "--workers" => {
+                let n = value
+                    .parse()
+                    .map_err(|_| invalid(format!("invalid number `{value}`")))?;
+                config.workers = parse_workers(n).map_err(invalid)?;
+            }
+            "--queue-capacity" => {
+                let n: usize = value
+                    .parse()
+                    .map_err(|_| invalid(format!("invalid number `{value}`")))?;
+                config.queue_capacity = Some(n).filter(|&n| n > 0);
+            }
+            "--backend" => config.backend = parse_backend(&value).map_err(invalid)?,
+            "--threaded" => config.backend = parse_backend("threaded").map_err(invalid)?,
+            "--event-loop" => config.backend = parse_backend("event-loop").map_err(invalid)?,
+            "-r" | "--root" => config.document_root = PathBuf::from(value),
+            "--cache-control" => config.cache_control = Some(value),
+            "--templates" => config.templates = PathBuf::from(value),
+            "--no-compression" => config.compression = false,
+            "--no-metrics" => config.metrics = false,
+            "--read-timeout" => config.read_timeout = parse_timeout(&value).map_err(invalid)?,
+            "--write-timeout" => config.write_timeout = parse_timeout(&value).map_err(invalid)?,
+            "--header-timeout" => config.header_timeout = parse_timeout(&value).map_err(invalid)?,
+            "--max-body-size" => {
+                config.limits.body = parse_size(&value)
+                    .map_err(invalid)?
+                    .and_then(|size| usize::try_from(size).ok())
+                    .ok_or_else(|| invalid("the size must be positive".to_string()))?;
+            }
+            "--max-connections-per-ip" => {
+                let n: usize = value
+                    .parse()
+                    .map_err(|_| invalid(format!("invalid number `{value}`")))?;
+                config.max_connections_per_ip = Some(n).filter(|&n| n > 0);
+            }
+            "--access-log" => match parse_log_path(&value) {
+                Some(path) => {
+                    let defaults = Config::default().access_log.unwrap();
+                    config.access_log.get_or_insert(defaults).path = path;
+                }
+                None => config.access_log = None,
+            },
+            "--log-format" => {
+                let format = parse_format(&value).map_err(invalid)?;
+                if let Some(log) = &mut config.access_log {
+                    log.format = format;
+                }
+            }
+            "--log-max-size" => {
+                let max_size = parse_size(&value).map_err(invalid)?;
+                if let Some(log) = &mut config.access_log {
+                    log.max_size = max_size;
+                }
+            }
+            "--check-config" => check = true,
+            _ => unreachable!("unknown options are rejected above"),
+        }
+    }
+
+    if check {
+        Ok(Command::CheckConfig(config))
+    } else {
+        Ok(Command::Run(config))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::ListenAddr;
+    use std::time::Duration;
+
+    fn parse(args: &[&str]) -> Result<Command, Error> {
+        parse_args(args.iter().map(|arg| arg.to_string()))
+    }
+
+    #[test]
+    fn options_override_defaults() {
+        let Ok(Command::Run(config)) = parse(&[
+            "--listen",
+            "[::1]:8080",
+            "--listen=0.0.0.0:8080",
+            "--listen=unix:echo.sock",
+            "--socket-mode=600",
+            "--workers=2",
+            "--read-timeout",
+            "off",
+            "--write-timeout=2s",
+            "--max-body-size=1KB",
+            "--max-connections-per-ip",
+            "8",
+            "--access-log",
+            "off",
+            "--no-metrics",
+        ]) else {
+            panic!("expected a run command");
+        };
+
+        assert_eq!(
+            config.listen,
+            [
+                ListenAddr::Tcp("[::1]:8080".parse().unwrap()),
+                ListenAddr::Tcp("0.0.0.0:8080".parse().unwrap()),
+                ListenAddr::Unix(PathBuf::from("echo.sock")),
+            ]
+        );
+        assert_eq!(config.socket_mode, Some(0o600));
+        assert_eq!(config.workers, 2);
+        assert_eq!(config.read_timeout, None);
+        assert_eq!(config.write_timeout, Some(Duration::from_secs(2)));
+        assert_eq!(config.limits.body, 1024);
+        assert_eq!(config.max_connections_per_ip, Some(8));
+        assert_eq!(config.access_log, None);
+        assert!(!config.metrics);
+    }
+
+    #[test]
+    fn commands() {
+        assert!(matches!(parse(&[]), Ok(Command::Run(_))));
+        assert!(matches!(
+            parse(&["--check-config"]),
+            Ok(Command::CheckConfig(_))
+        ));
+        assert!(matches!(parse(&["-w", "2", "--help"]), Ok(Command::Help)));
+    }
+
+    #[test]
+    fn usage_errors() {
+        let error = |args: &[&str]| parse(args).unwrap_err().to_string();
+
+        assert_eq!(error(&["--port", "80"]), "unknown option `--port`");
+        assert_eq!(error(&["--workers"]), "`--workers` needs a value");
+        assert_eq!(
+            error(&["--workers", "zero"]),
+            "--workers: invalid number `zero`"
+        );
+        assert_eq!(
+            error(&["--workers", "0"]),
+            "--workers: `workers` must be at least 1, not 0"
+        );
+        assert_eq!(
+            error(&["--check-config=yes"]),
+            "`--check-config` takes no value"
+        );
+        assert!(error(&["--config", "/nonexistent/echo.toml"])
+            .starts_with("can't read /nonexistent/echo.toml: "));
+    }
+}
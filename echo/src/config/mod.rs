@@ -0,0 +1,1486 @@
+//! Server configuration, read from a TOML-like file and the command line.
+//!
+//! ```toml
+//! listen = ["127.0.0.1:7878", "[::1]:7878", "unix:/run/echo/echo.sock"]
+//! socket_mode = "660"
+//! backend = "event-loop"
+//! workers = 8
+//! queue_capacity = 64
+//! document_root = "public"
+//! cache_control = "public, max-age=3600"
+//! templates = "templates"
+//! compression = true
+//! metrics = true
+//!
+//! [timeouts]
+//! read = "30s"
+//! write = "1m"
+//! header = "10s"
+//!
+//! [limits]
+//! request_line = "8KB"
+//! header_size = "64KB"
+//! body_size = "10MB"
+//! connections_per_ip = 64
+//! long_lived_streams = 1024
+//!
+//! [access_log]
+//! path = "logs/access.log"
+//! format = "combined"
+//! max_size = "10MB"
+//! max_files = 5
+//!
+//! [cgi]
+//! prefix = "/cgi-bin/app"
+//! program = "cgi-bin/app.py"
+//! timeout = "30s"
+//!
+//! [proxy]
+//! prefix = "/api"
+//! upstream = "127.0.0.1:3000"
+//! timeout = "30s"
+//!
+//! [rate_limit]
+//! requests = 600
+//! per = "1m"
+//! burst = 50
+//! header = "X-Api-Key"
+//!
+//! [site.docs]
+//! hosts = ["docs.example.com", "*.docs.example.com"]
+//! document_root = "sites/docs"
+//! templates = "sites/docs/templates"
+//!
+//! [site.docs.proxy]
+//! prefix = "/search"
+//! upstream = "127.0.0.1:3001"
+//!
+//! [basic_auth]
+//! prefix = "/admin"
+//! users = "htpasswd"
+//! realm = "Admin"
+//!
+//! [cors]
+//! allow_origins = ["https://app.example.com"]
+//! allow_methods = ["PUT", "DELETE"]
+//! allow_headers = ["Authorization"]
+//! expose_headers = ["X-Request-Id"]
+//! allow_credentials = true
+//! max_age = "10m"
+//! ```
+//!
+//! When started by systemd through socket activation, the server listens on
+//! the sockets it's given instead of the `listen` addresses.
+//!
+//! The top-level `document_root`, `cache_control`, `templates`, `[cgi]` and
+//! `[proxy]` make the default site, for requests whose host has no `[site.*]`
+//! of its own. Relative paths in a file are relative to the directory of the
+//! file.
+
+mod cli;
+pub mod toml;
+
+use std::{
+    collections::BTreeMap,
+    error, fmt, fs, io,
+    net::SocketAddr,
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+pub use cli::{parse_args, Command, USAGE};
+
+use crate::{
+    access_log::Format,
+    http::Limits,
+    server::{Backend, DEFAULT_MAX_LONG_LIVED_STREAMS},
+};
+use toml::{Item, Value};
+
+/// Why a configuration couldn't be loaded.
+#[derive(Debug)]
+pub enum Error {
+    /// The file couldn't be read.
+    Io { path: PathBuf, error: io::Error },
+    /// The file isn't well-formed.
+    Syntax { line: usize, message: String },
+    /// A setting has a wrong value, on the given line of the file if it came
+    /// from a file.
+    Invalid {
+        line: Option<usize>,
+        message: String,
+    },
+    /// Something is wrong in the file at `path`.
+    InFile { path: PathBuf, error: Box<Error> },
+    /// The command line is malformed.
+    Usage(String),
+}
+
+impl Error {
+    fn invalid(line: impl Into<Option<usize>>, message: impl Into<String>) -> Error {
+        Error::Invalid {
+            line: line.into(),
+            message: message.into(),
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Io { path, error } => write!(f, "can't read {}: {error}", path.display()),
+            Error::Syntax { line, message } => write!(f, "line {line}: {message}"),
+            Error::Invalid {
+                line: Some(line),
+                message,
+            } => write!(f, "line {line}: {message}"),
+            Error::Invalid {
+                line: None,
+                message,
+            } => write!(f, "{message}"),
+            Error::InFile { path, error } => write!(f, "{}: {error}", path.display()),
+            Error::Usage(message) => write!(f, "{message}"),
+        }
+    }
+}
+
+impl error::Error for Error {}
+
+/// Where and how to write the access log.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AccessLogConfig {
+    /// The log file, or `None` for standard output.
+    pub path: Option<PathBuf>,
+    pub format: Format,
+    pub max_size: Option<u64>,
+    pub max_files: usize,
+}
+
+/// A program answering the requests under a path, through CGI.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CgiConfig {
+    /// The path the program is mounted at.
+    pub prefix: String,
+    pub program: PathBuf,
+    /// How long the program may run.
+    pub timeout: Option<Duration>,
+}
+
+/// A server the requests under a path are forwarded to, without the path.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ProxyConfig {
+    pub prefix: String,
+    pub upstream: SocketAddr,
+    /// How long the upstream may go quiet.
+    pub timeout: Option<Duration>,
+}
+
+/// A site of its own for some host names, from a `[site.NAME]` table.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SiteConfig {
+    pub name: String,
+    /// Host names, and wildcards like `*.example.com`.
+    pub hosts: Vec<String>,
+    pub document_root: PathBuf,
+    pub cache_control: Option<String>,
+    /// The templates of the error pages, or `None` for the default site's.
+    pub templates: Option<PathBuf>,
+    pub cgi: Option<CgiConfig>,
+    pub proxy: Option<ProxyConfig>,
+}
+
+/// How often each client may make requests.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RateLimitConfig {
+    pub requests: u32,
+    pub per: Duration,
+    /// How many requests a client may make at once; `requests` if `None`.
+    pub burst: Option<u32>,
+    /// The header telling clients apart, if not their address.
+    pub header: Option<String>,
+}
+
+/// The users allowed below a path, who must log in with HTTP Basic
+/// authentication.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AuthConfig {
+    /// The path to protect, or `""` for everything.
+    pub prefix: String,
+    /// The htpasswd file of the users.
+    pub users: PathBuf,
+    pub realm: String,
+}
+
+/// The origins whose scripts may read responses.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct CorsConfig {
+    /// The allowed origins, or `*` for any.
+    pub allow_origins: Vec<String>,
+    /// Methods besides `GET`, `HEAD` and `POST`.
+    pub allow_methods: Vec<String>,
+    pub allow_headers: Vec<String>,
+    pub expose_headers: Vec<String>,
+    pub allow_credentials: bool,
+    /// How long browsers may cache preflight results.
+    pub max_age: Option<Duration>,
+}
+
+/// An address to accept connections on.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ListenAddr {
+    Tcp(SocketAddr),
+    /// A Unix domain socket, written `unix:/run/echo.sock`.
+    Unix(PathBuf),
+}
+
+impl From<SocketAddr> for ListenAddr {
+    fn from(addr: SocketAddr) -> ListenAddr {
+        ListenAddr::Tcp(addr)
+    }
+}
+
+impl fmt::Display for ListenAddr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ListenAddr::Tcp(addr) => write!(f, "{addr}"),
+            ListenAddr::Unix(path) => write!(f, "unix:{}", path.display()),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Config {
+    /// The addresses to accept connections on.
+    pub listen: Vec<ListenAddr>,
+    /// The permissions of Unix sockets, like `0o660`, or `None` to leave
+    /// them to the umask.
+    pub socket_mode: Option<u32>,
+    pub backend: Backend,
+    /// The number of pool threads running handlers.
+    pub workers: usize,
+    /// The maximum number of requests waiting for a worker, beyond which the
+    /// server answers 503.
+    pub queue_capacity: Option<usize>,
+    /// The directory pages are served from.
+    pub document_root: PathBuf,
+    /// The `Cache-Control` header of the files, if any.
+    pub cache_control: Option<String>,
+    /// The directory of the templates error pages are rendered from.
+    pub templates: PathBuf,
+    /// Whether to gzip compressible responses for the clients that accept
+    /// it.
+    pub compression: bool,
+    /// Whether to expose metrics in the Prometheus format at `/metrics`.
+    pub metrics: bool,
+    /// How long a client may take to send something before the connection is
+    /// closed.
+    pub read_timeout: Option<Duration>,
+    /// How long a client may take to accept a response.
+    pub write_timeout: Option<Duration>,
+    /// How long a client may take to send the head of a request, however
+    /// steadily it sends it.
+    pub header_timeout: Option<Duration>,
+    /// The largest requests to accept.
+    pub limits: Limits,
+    /// The most connections a client may have open at once.
+    pub max_connections_per_ip: Option<usize>,
+    /// The most long-lived responses, like event streams, to send at once.
+    pub max_long_lived_streams: usize,
+    /// `None` if access logging is off.
+    pub access_log: Option<AccessLogConfig>,
+    pub cgi: Option<CgiConfig>,
+    pub proxy: Option<ProxyConfig>,
+    /// The sites for particular hosts, besides the default one.
+    pub sites: Vec<SiteConfig>,
+    pub rate_limit: Option<RateLimitConfig>,
+    pub basic_auth: Option<AuthConfig>,
+    pub cors: Option<CorsConfig>,
+}
+
+impl Default for Config {
+    fn default() -> Config {
+        Config {
+            listen: vec![SocketAddr::from(([127, 0, 0, 1], 7878)).into()],
+            socket_mode: None,
+            backend: Backend::default(),
+            workers: 4,
+            queue_capacity: Some(16),
+            // A directory of its own, so that the server's own files, like
+            // its log, are never served.
+            document_root: PathBuf::from("public"),
+            cache_control: None,
+            templates: PathBuf::from("templates"),
+            compression: true,
+            metrics: true,
+            read_timeout: Some(Duration::from_secs(30)),
+            write_timeout: Some(Duration::from_secs(30)),
+            header_timeout: Some(Duration::from_secs(10)),
+            limits: Limits::default(),
+            max_connections_per_ip: Some(64),
+            max_long_lived_streams: DEFAULT_MAX_LONG_LIVED_STREAMS,
+            access_log: Some(AccessLogConfig {
+                path: Some(PathBuf::from("logs/access.log")),
+                format: Format::Combined,
+                max_size: Some(10 * 1024 * 1024),
+                max_files: 5,
+            }),
+            cgi: None,
+            proxy: None,
+            sites: Vec::new(),
+            rate_limit: None,
+            basic_auth: None,
+            cors: None,
+        }
+    }
+}
+
+impl Config {
+    /// Read the configuration file at `path`. Settings it leaves out keep
+    /// their default values.
+    pub fn load(path: impl AsRef<Path>) -> Result<Config, Error> {
+        let path = path.as_ref();
+        let source = fs::read_to_string(path).map_err(|error| Error::Io {
+            path: path.to_path_buf(),
+            error,
+        })?;
+
+        let mut config = Config::parse(&source).map_err(|error| Error::InFile {
+            path: path.to_path_buf(),
+            error: Box::new(error),
+        })?;
+
+        // Make paths relative to the file rather than to wherever the server
+        // happens to be started from.
+        let base = path.parent().unwrap_or(Path::new(""));
+        for addr in &mut config.listen {
+            if let ListenAddr::Unix(path) = addr {
+                *path = base.join(&*path);
+            }
+        }
+        config.document_root = base.join(&config.document_root);
+        config.templates = base.join(&config.templates);
+        if let Some(log) = &mut config.access_log {
+            if let Some(log_path) = &mut log.path {
+                *log_path = base.join(&*log_path);
+            }
+        }
+        if let Some(cgi) = &mut config.cgi {
+            cgi.program = base.join(&cgi.program);
+        }
+        for site in &mut config.sites {
+            site.document_root = base.join(&site.document_root);
+            if let Some(templates) = &mut site.templates {
+                *templates = base.join(&*templates);
+            }
+            if let Some(cgi) = &mut site.cgi {
+                cgi.program = base.join(&cgi.program);
+            }
+        }
+        if let Some(auth) = &mut config.basic_auth {
+            auth.users = base.join(&auth.users);
+        }
+
+        Ok(config)
+    }
+
+    /// Parse the contents of a configuration file.
+    pub fn parse(source: &str) -> Result<Config, Error> {
+        let mut document = toml::parse(source)?;
+        let mut config = Config::default();
+
+        let mut root = document.remove("").unwrap_or_default();
+        if let Some(item) = root.remove("listen") {
+            let line = item.line;
+            let addresses = match item.value {
+                Value::Array(values) => values,
+                value => vec![value],
+            };
+            config.listen = addresses
+                .into_iter()
+                .map(|value| match value {
+                    Value::String(s) => parse_listen(&s).map_err(|e| Error::invalid(line, e)),
+                    value => Err(wrong_type(line, "listen", "a string", &value)),
+                })
+                .collect::<Result<_, _>>()?;
+        }
+        if let Some(item) = root.remove("socket_mode") {
+            let (line, mode) = string(item, "socket_mode")?;
+            config.socket_mode = Some(parse_mode(&mode).map_err(|e| Error::invalid(line, e))?);
+        }
+        if let Some(item) = root.remove("backend") {
+            let (line, name) = string(item, "backend")?;
+            config.backend = parse_backend(&name).map_err(|e| Error::invalid(line, e))?;
+        }
+        if let Some(item) = root.remove("workers") {
+            let (line, workers) = integer(item, "workers")?;
+            config.workers = parse_workers(workers).map_err(|e| Error::invalid(line, e))?;
+        }
+        if let Some(item) = root.remove("queue_capacity") {
+            let (line, capacity) = integer(item, "queue_capacity")?;
+            config.queue_capacity = match capacity {
+                0 => None,
+                n => Some(
+                    usize::try_from(n)
+                        .map_err(|_| Error::invalid(line, "`queue_capacity` can't be negative"))?,
+                ),
+            };
+        }
+        if let Some(item) = root.remove("document_root") {
+            config.document_root = PathBuf::from(string(item, "document_root")?.1);
+        }
+        if let Some(item) = root.remove("cache_control") {
+            config.cache_control = Some(string(item, "cache_control")?.1);
+        }
+        if let Some(item) = root.remove("templates") {
+            config.templates = PathBuf::from(string(item, "templates")?.1);
+        }
+        if let Some(item) = root.remove("compression") {
+            config.compression = boolean(item, "compression")?.1;
+        }
+        if let Some(item) = root.remove("metrics") {
+            config.metrics = boolean(item, "metrics")?.1;
+        }
+        unknown_keys("", &root)?;
+
+        let mut timeouts = document.remove("timeouts").unwrap_or_default();
+        for (key, setting) in [
+            ("read", &mut config.read_timeout),
+            ("write", &mut config.write_timeout),
+            ("header", &mut config.header_timeout),
+        ] {
+            if let Some(item) = timeouts.remove(key) {
+                let (line, value) = string(item, &format!("timeouts.{key}"))?;
+                *setting = parse_timeout(&value).map_err(|e| Error::invalid(line, e))?;
+            }
+        }
+        unknown_keys("timeouts", &timeouts)?;
+
+        if let Some(mut table) = document.remove("limits") {
+            parse_limits(&mut table, &mut config)?;
+            unknown_keys("limits", &table)?;
+        }
+        if let Some(mut table) = document.remove("access_log") {
+            config.access_log = parse_access_log(&mut table)?;
+            unknown_keys("access_log", &table)?;
+        }
+        if let Some(mut table) = document.remove("cgi") {
+            config.cgi = Some(parse_cgi(&mut table, "cgi")?);
+            unknown_keys("cgi", &table)?;
+        }
+        if let Some(mut table) = document.remove("proxy") {
+            config.proxy = Some(parse_proxy(&mut table, "proxy")?);
+            unknown_keys("proxy", &table)?;
+        }
+        let names: Vec<String> = document
+            .keys()
+            .filter_map(|table| table.strip_prefix("site."))
+            .filter(|name| !name.contains('.'))
+            .map(str::to_string)
+            .collect();
+        for name in names {
+            let site = parse_site(&mut document, name)?;
+            for host in &site.hosts {
+                if let Some(other) = config.sites.iter().find(|s| s.hosts.contains(host)) {
+                    return Err(Error::invalid(
+                        None,
+                        format!(
+                            "host `{host}` is in both `[site.{}]` and `[site.{}]`",
+                            other.name, site.name
+                        ),
+                    ));
+                }
+            }
+            config.sites.push(site);
+        }
+        if let Some(mut table) = document.remove("rate_limit") {
+            config.rate_limit = Some(parse_rate_limit(&mut table)?);
+            unknown_keys("rate_limit", &table)?;
+        }
+        if let Some(mut table) = document.remove("basic_auth") {
+            config.basic_auth = Some(parse_basic_auth(&mut table)?);
+            unknown_keys("basic_auth", &table)?;
+        }
+        if let Some(mut table) = document.remove("cors") {
+            config.cors = Some(parse_cors(&mut table)?);
+            unknown_keys("cors", &table)?;
+        }
+
+        if let Some(name) = document.keys().next() {
+            return Err(Error::invalid(None, format!("unknown table `[{name}]`")));
+        }
+
+        Ok(config)
+    }
+
+    /// Check the settings that depend on the file system.
+    pub fn validate(&self) -> Result<(), Error> {
+        if self.listen.is_empty() {
+            return Err(Error::invalid(None, "no address to listen on"));
+        }
+
+        for addr in &self.listen {
+            if let ListenAddr::Unix(path) = addr {
+                if !parent_dir(path).is_dir() {
+                    return Err(Error::invalid(
+                        None,
+                        format!(
+                            "the directory of the socket {} doesn't exist",
+                            path.display()
+                        ),
+                    ));
+                }
+            }
+        }
+
+        validate_site(&self.default_site())?;
+        for site in &self.sites {
+            validate_site(site)
+                .map_err(|e| Error::invalid(None, format!("in `[site.{}]`: {e}", site.name)))?;
+        }
+
+        if let Some(auth) = &self.basic_auth {
+            if !auth.users.is_file() {
+                return Err(Error::invalid(
+                    None,
+                    format!("users file {} is not a file", auth.users.display()),
+                ));
+            }
+        }
+
+        Ok(())
+    }
+
+    /// The site for hosts without one of their own, from the top-level
+    /// settings.
+    pub fn default_site(&self) -> SiteConfig {
+        SiteConfig {
+            name: "default".to_string(),
+            hosts: Vec::new(),
+            document_root: self.document_root.clone(),
+            cache_control: self.cache_control.clone(),
+            templates: Some(self.templates.clone()),
+            cgi: self.cgi.clone(),
+            proxy: self.proxy.clone(),
+        }
+    }
+}
+
+/// The directory a file goes in.
+fn parent_dir(path: &Path) -> &Path {
+    match path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir,
+        _ => Path::new("."),
+    }
+}
+
+fn validate_site(site: &SiteConfig) -> Result<(), Error> {
+    if !site.document_root.is_dir() {
+        return Err(Error::invalid(
+            None,
+            format!(
+                "document root {} is not a directory",
+                site.document_root.display()
+            ),
+        ));
+    }
+    if let Some(cgi) = &site.cgi {
+        if !cgi.program.is_file() {
+            return Err(Error::invalid(
+                None,
+                format!("CGI program {} is not a file", cgi.program.display()),
+            ));
+        }
+    }
+    Ok(())
+}
+
+impl fmt::Display for Config {
+    /// The settings in the configuration file syntax.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let listen: Vec<_> = self.listen.iter().map(|a| format!("\"{a}\"")).collect();
+        writeln!(f, "listen = [{}]", listen.join(", "))?;
+        if let Some(mode) = self.socket_mode {
+            writeln!(f, "socket_mode = \"{mode:o}\"")?;
+        }
+        writeln!(f, "backend = \"{}\"", backend_name(self.backend))?;
+        writeln!(f, "workers = {}", self.workers)?;
+        writeln!(f, "queue_capacity = {}", self.queue_capacity.unwrap_or(0))?;
+        writeln!(f, "document_root = {:?}", self.document_root)?;
+        if let Some(cache_control) = &self.cache_control {
+            writeln!(f, "cache_control = {cache_control:?}")?;
+        }
+        writeln!(f, "templates = {:?}", self.templates)?;
+        writeln!(f, "compression = {}", self.compression)?;
+        writeln!(f, "metrics = {}", self.metrics)?;
+
+        writeln!(f, "\n[timeouts]")?;
+        writeln!(f, "read = {}", timeout(self.read_timeout))?;
+        writeln!(f, "write = {}", timeout(self.write_timeout))?;
+        writeln!(f, "header = {}", timeout(self.header_timeout))?;
+
+        writeln!(f, "\n[limits]")?;
+        writeln!(f, "request_line = {}", self.limits.request_line)?;
+        writeln!(f, "header_size = {}", self.limits.head)?;
+        writeln!(f, "body_size = {}", self.limits.body)?;
+        writeln!(
+            f,
+            "connections_per_ip = {}",
+            self.max_connections_per_ip.unwrap_or(0)
+        )?;
+        writeln!(f, "long_lived_streams = {}", self.max_long_lived_streams)?;
+
+        writeln!(f, "\n[access_log]")?;
+        match &self.access_log {
+            None => writeln!(f, "path = \"off\"")?,
+            Some(log) => {
+                match &log.path {
+                    Some(path) => writeln!(f, "path = {path:?}")?,
+                    None => writeln!(f, "path = \"-\"")?,
+                }
+                writeln!(f, "format = \"{}\"", format_name(log.format))?;
+                if let Some(max_size) = log.max_size {
+                    writeln!(f, "max_size = {max_size}")?;
+                }
+                writeln!(f, "max_files = {}", log.max_files)?;
+            }
+        }
+
+        write_handlers(f, "", self.cgi.as_ref(), self.proxy.as_ref())?;
+        for site in &self.sites {
+            writeln!(f, "\n[site.{}]", site.name)?;
+            writeln!(f, "hosts = {:?}", site.hosts)?;
+            writeln!(f, "document_root = {:?}", site.document_root)?;
+            if let Some(cache_control) = &site.cache_control {
+                writeln!(f, "cache_control = {cache_control:?}")?;
+            }
+            if let Some(templates) = &site.templates {
+                writeln!(f, "templates = {templates:?}")?;
+            }
+            let table = format!("site.{}.", site.name);
+            write_handlers(f, &table, site.cgi.as_ref(), site.proxy.as_ref())?;
+        }
+        if let Some(limit) = &self.rate_limit {
+            writeln!(f, "\n[rate_limit]")?;
+            writeln!(f, "requests = {}", limit.requests)?;
+            writeln!(f, "per = {}", timeout(Some(limit.per)))?;
+            if let Some(burst) = limit.burst {
+                writeln!(f, "burst = {burst}")?;
+            }
+            if let Some(header) = &limit.header {
+                writeln!(f, "header = {header:?}")?;
+            }
+        }
+        if let Some(auth) = &self.basic_auth {
+            writeln!(f, "\n[basic_auth]")?;
+            if !auth.prefix.is_empty() {
+                writeln!(f, "prefix = {:?}", auth.prefix)?;
+            }
+            writeln!(f, "users = {:?}", auth.users)?;
+            writeln!(f, "realm = {:?}", auth.realm)?;
+        }
+        if let Some(cors) = &self.cors {
+            writeln!(f, "\n[cors]")?;
+            writeln!(f, "allow_origins = {:?}", cors.allow_origins)?;
+            writeln!(f, "allow_methods = {:?}", cors.allow_methods)?;
+            writeln!(f, "allow_headers = {:?}", cors.allow_headers)?;
+            writeln!(f, "expose_headers = {:?}", cors.expose_headers)?;
+            writeln!(f, "allow_credentials = {}", cors.allow_credentials)?;
+            writeln!(f, "max_age = {}", timeout(cors.max_age))?;
+        }
+        Ok(())
+    }
+}
+
+/// The `[cgi]` and `[proxy]` tables, with their names after `prefix`.
+fn write_handlers(
+    f: &mut fmt::Formatter<'_>,
+    prefix: &str,
+    cgi: Option<&CgiConfig>,
+    proxy: Option<&ProxyConfig>,
+) -> fmt::Result {
+    if let Some(cgi) = cgi {
+        writeln!(f, "\n[{prefix}cgi]")?;
+        writeln!(f, "prefix = {:?}", cgi.prefix)?;
+        writeln!(f, "program = {:?}", cgi.program)?;
+        writeln!(f, "timeout = {}", timeout(cgi.timeout))?;
+    }
+    if let Some(proxy) = proxy {
+        writeln!(f, "\n[{prefix}proxy]")?;
+        writeln!(f, "prefix = {:?}", proxy.prefix)?;
+        writeln!(f, "upstream = \"{}\"", proxy.upstream)?;
+        writeln!(f, "timeout = {}", timeout(proxy.timeout))?;
+    }
+    Ok(())
+}
+
+/// A timeout in the configuration file syntax.
+fn timeout(timeout: Option<Duration>) -> String {
+    match timeout {
+        Some(timeout) => format!("\"{}ms\"", timeout.as_millis()),
+        None => "\"off\"".to_string(),
+    }
+}
+
+fn parse_access_log(table: &mut BTreeMap<String, Item>) -> Result<Option<AccessLogConfig>, Error> {
+    let mut log = Config::default().access_log.unwrap();
+
+    if let Some(item) = table.remove("path") {
+        match parse_log_path(&string(item, "access_log.path")?.1) {
+            Some(path) => log.path = path,
+            None => return Ok(None),
+        }
+    }
+    if let Some(item) = table.remove("format") {
+        let (line, format) = string(item, "access_log.format")?;
+        log.format = parse_format(&format).map_err(|e| Error::invalid(line, e))?;
+    }
+    if let Some(item) = table.remove("max_size") {
+        let line = item.line;
+        log.max_size = match item.value {
+            Value::Integer(0) => None,
+            Value::Integer(n) if n > 0 => Some(n as u64),
+            Value::String(s) => parse_size(&s).map_err(|e| Error::invalid(line, e))?,
+            value => {
+                return Err(wrong_type(line, "access_log.max_size", "a size", &value));
+            }
+        };
+    }
+    if let Some(item) = table.remove("max_files") {
+        let (line, n) = integer(item, "access_log.max_files")?;
+        log.max_files = usize::try_from(n)
+            .map_err(|_| Error::invalid(line, "`access_log.max_files` can't be negative"))?;
+    }
+
+    Ok(Some(log))
+}
+
+fn parse_limits(table: &mut BTreeMap<String, Item>, config: &mut Config) -> Result<(), Error> {
+    for (key, limit) in [
+        ("request_line", &mut config.limits.request_line),
+        ("header_size", &mut config.limits.head),
+        ("body_size", &mut config.limits.body),
+    ] {
+        if let Some(item) = table.remove(key) {
+            let line = item.line;
+            let size = match item.value {
+                Value::Integer(n) => u64::try_from(n).ok().filter(|&n| n > 0),
+                Value::String(s) => parse_size(&s).map_err(|e| Error::invalid(line, e))?,
+                value => {
+                    return Err(wrong_type(line, &format!("limits.{key}"), "a size", &value));
+                }
+            };
+            *limit = size
+                .and_then(|size| usize::try_from(size).ok())
+                .ok_or_else(|| Error::invalid(line, format!("`limits.{key}` must be positive")))?;
+        }
+    }
+    if let Some(item) = table.remove("connections_per_ip") {
+        let (line, n) = integer(item, "limits.connections_per_ip")?;
+        config.max_connections_per_ip = match n {
+            0 => None,
+            n => Some(usize::try_from(n).map_err(|_| {
+                Error::invalid(line, "`limits.connections_per_ip` can't be negative")
+            })?),
+        };
+    }
+    if let Some(item) = table.remove("long_lived_streams") {
+        let (line, n) = integer(item, "limits.long_lived_streams")?;
+        config.max_long_lived_streams = usize::try_from(n)
+            .ok()
+            .filter(|&n| n > 0)
+            .ok_or_else(|| Error::invalid(line, "`limits.long_lived_streams` must be positive"))?;
+    }
+    Ok(())
+}
+
+/// The `[cgi]` table, or another named `name` like `[site.docs.cgi]`.
+fn parse_cgi(table: &mut BTreeMap<String, Item>, name: &str) -> Result<CgiConfig, Error> {
+    let prefix = parse_prefix(table, name)?;
+    let program = table
+        .remove("program")
+        .ok_or_else(|| Error::invalid(None, format!("`[{name}]` needs a `program`")))?;
+    let mut cgi = CgiConfig {
+        prefix,
+        program: PathBuf::from(string(program, &format!("{name}.program"))?.1),
+        timeout: Some(Duration::from_secs(30)),
+    };
+    if let Some(item) = table.remove("timeout") {
+        let (line, value) = string(item, &format!("{name}.timeout"))?;
+        cgi.timeout = parse_timeout(&value).map_err(|e| Error::invalid(line, e))?;
+    }
+    Ok(cgi)
+}
+
+/// The `[proxy]` table, or another named `name` like `[site.docs.proxy]`.
+fn parse_proxy(table: &mut BTreeMap<String, Item>, name: &str) -> Result<ProxyConfig, Error> {
+    let prefix = parse_prefix(table, name)?;
+    let upstream = table
+        .remove("upstream")
+        .ok_or_else(|| Error::invalid(None, format!("`[{name}]` needs an `upstream`")))?;
+    let (line, upstream) = string(upstream, &format!("{name}.upstream"))?;
+    let mut proxy = ProxyConfig {
+        prefix,
+        upstream: parse_address(&upstream).map_err(|e| Error::invalid(line, e))?,
+        timeout: Some(Duration::from_secs(30)),
+    };
+    if let Some(item) = table.remove("timeout") {
+        let (line, value) = string(item, &format!("{name}.timeout"))?;
+        proxy.timeout = parse_timeout(&value).map_err(|e| Error::invalid(line, e))?;
+    }
+    Ok(proxy)
+}
+
+/// The `[site.NAME]` table, and the `[site.NAME.cgi]` and
+/// `[site.NAME.proxy]` tables with it.
+fn parse_site(
+    document: &mut BTreeMap<String, BTreeMap<String, Item>>,
+    name: String,
+) -> Result<SiteConfig, Error> {
+    let table_name = format!("site.{name}");
+    let mut table = document.remove(&table_name).unwrap_or_default();
+    let required = |table: &mut BTreeMap<String, Item>, key: &str| {
+        table
+            .remove(key)
+            .ok_or_else(|| Error::invalid(None, format!("`[{table_name}]` needs `{key}`")))
+    };
+
+    let hosts = strings(
+        required(&mut table, "hosts")?,
+        &format!("{table_name}.hosts"),
+    )?;
+    if hosts.is_empty() {
+        return Err(Error::invalid(
+            None,
+            format!("`{table_name}.hosts` can't be empty"),
+        ));
+    }
+    let mut normalized: Vec<String> = Vec::new();
+    for host in hosts {
+        let host = host.trim_end_matches('.').to_ascii_lowercase();
+        if normalized.contains(&host) {
+            return Err(Error::invalid(
+                None,
+                format!("host `{host}` is in `{table_name}.hosts` twice"),
+            ));
+        }
+        normalized.push(host);
+    }
+    let document_root = required(&mut table, "document_root")?;
+    let mut site = SiteConfig {
+        name,
+        hosts: normalized,
+        document_root: PathBuf::from(
+            string(document_root, &format!("{table_name}.document_root"))?.1,
+        ),
+        cache_control: None,
+        templates: None,
+        cgi: None,
+        proxy: None,
+    };
+    if let Some(item) = table.remove("cache_control") {
+        site.cache_control = Some(string(item, &format!("{table_name}.cache_control"))?.1);
+    }
+    if let Some(item) = table.remove("templates") {
+        site.templates = Some(PathBuf::from(
+            string(item, &format!("{table_name}.templates"))?.1,
+        ));
+    }
+    unknown_keys(&table_name, &table)?;
+
+    let cgi = format!("{table_name}.cgi");
+    if let Some(mut table) = document.remove(&cgi) {
+        site.cgi = Some(parse_cgi(&mut table, &cgi)?);
+        unknown_keys(&cgi, &table)?;
+    }
+    let proxy = format!("{table_name}.proxy");
+    if let Some(mut table) = document.remove(&proxy) {
+        site.proxy = Some(parse_proxy(&mut table, &proxy)?);
+        unknown_keys(&proxy, &table)?;
+    }
+    Ok(site)
+}
+
+fn parse_rate_limit(table: &mut BTreeMap<String, Item>) -> Result<RateLimitConfig, Error> {
+    let positive = |item: Item, key: &str| {
+        let (line, n) = integer(item, &format!("rate_limit.{key}"))?;
+        u32::try_from(n)
+            .ok()
+            .filter(|&n| n > 0)
+            .ok_or_else(|| Error::invalid(line, format!("`rate_limit.{key}` must be positive")))
+    };
+    let requests = table
+        .remove("requests")
+        .ok_or_else(|| Error::invalid(None, "`[rate_limit]` needs a number of `requests`"))?;
+    let mut limit = RateLimitConfig {
+        requests: positive(requests, "requests")?,
+        per: Duration::from_secs(1),
+        burst: None,
+        header: None,
+    };
+    if let Some(item) = table.remove("per") {
+        let (line, value) = string(item, "rate_limit.per")?;
+        limit.per = parse_timeout(&value)
+            .map_err(|e| Error::invalid(line, e))?
+            .ok_or_else(|| Error::invalid(line, "`rate_limit.per` can't be `off`"))?;
+    }
+    if let Some(item) = table.remove("burst") {
+        limit.burst = Some(positive(item, "burst")?);
+    }
+    if let Some(item) = table.remove("header") {
+        limit.header = Some(string(item, "rate_limit.header")?.1);
+    }
+    Ok(limit)
+}
+
+fn parse_basic_auth(table: &mut BTreeMap<String, Item>) -> Result<AuthConfig, Error> {
+    let prefix = if table.contains_key("prefix") {
+        parse_prefix(table, "basic_auth")?
+    } else {
+        String::new()
+    };
+    let users = table
+        .remove("users")
+        .ok_or_else(|| Error::invalid(None, "`[basic_auth]` needs a `users` file"))?;
+    let mut auth = AuthConfig {
+        prefix,
+        users: PathBuf::from(string(users, "basic_auth.users")?.1),
+        realm: "echo".to_string(),
+    };
+    if let Some(item) = table.remove("realm") {
+        auth.realm = string(item, "basic_auth.realm")?.1;
+    }
+    Ok(auth)
+}
+
+fn parse_cors(table: &mut BTreeMap<String, Item>) -> Result<CorsConfig, Error> {
+    let mut cors = CorsConfig::default();
+    for (key, list) in [
+        ("allow_origins", &mut cors.allow_origins),
+        ("allow_methods", &mut cors.allow_methods),
+        ("allow_headers", &mut cors.allow_headers),
+        ("expose_headers", &mut cors.expose_headers),
+    ] {
+        if let Some(item) = table.remove(key) {
+            *list = strings(item, &format!("cors.{key}"))?;
+        }
+    }
+    if cors.allow_origins.is_empty() {
+        return Err(Error::invalid(None, "`[cors]` needs `allow_origins`"));
+    }
+    if let Some(item) = table.remove("allow_credentials") {
+        cors.allow_credentials = boolean(item, "cors.allow_credentials")?.1;
+    }
+    if let Some(item) = table.remove("max_age") {
+        let (line, value) = string(item, "cors.max_age")?;
+        cors.max_age = parse_timeout(&value).map_err(|e| Error::invalid(line, e))?;
+    }
+    Ok(cors)
+}
+
+/// The path a handler is mounted at, without a trailing slash.
+fn parse_prefix(table: &mut BTreeMap<String, Item>, name: &str) -> Result<String, Error> {
+    let item = table
+        .remove("prefix")
+        .ok_or_else(|| Error::invalid(None, format!("`[{name}]` needs a `prefix`")))?;
+    let (line, prefix) = string(item, &format!("{name}.prefix"))?;
+    if !prefix.starts_with('/') {
+        return Err(Error::invalid(
+            line,
+            format!("`{name}.prefix` must start with `/`, not `{prefix}`"),
+        ));
+    }
+    Ok(prefix.trim_end_matches('/').to_string())
+}
+
+fn string(item: Item, key: &str) -> Result<(usize, String), Error> {
+    match item.value {
+        Value::String(s) => Ok((item.line, s)),
+        value => Err(wrong_type(item.line, key, "a string", &value)),
+    }
+}
+
+/// An array of strings, or a single string.
+fn strings(item: Item, key: &str) -> Result<Vec<String>, Error> {
+    let line = item.line;
+    let values = match item.value {
+        Value::Array(values) => values,
+        value => vec![value],
+    };
+    values
+        .into_iter()
+        .map(|value| match value {
+            Value::String(s) => Ok(s),
+            value => Err(wrong_type(line, key, "a string", &value)),
+        })
+        .collect()
+}
+
+fn integer(item: Item, key: &str) -> Result<(usize, i64), Error> {
+    match item.value {
+        Value::Integer(n) => Ok((item.line, n)),
+        value => Err(wrong_type(item.line, key, "an integer", &value)),
+    }
+}
+
+fn boolean(item: Item, key: &str) -> Result<(usize, bool), Error> {
+    match item.value {
+        Value::Bool(b) => Ok((item.line, b)),
+        value => Err(wrong_type(item.line, key, "a boolean", &value)),
+    }
+}
+
+fn wrong_type(line: usize, key: &str, expected: &str, value: &Value) -> Error {
+    Error::invalid(
+        line,
+        format!("`{key}` must be {expected}, not {}", value.type_name()),
+    )
+}
+
+fn unknown_keys(table: &str, keys: &BTreeMap<String, Item>) -> Result<(), Error> {
+    match keys.iter().next() {
+        Some((key, item)) if table.is_empty() => Err(Error::invalid(
+            item.line,
+            format!("unknown setting `{key}`"),
+        )),
+        Some((key, item)) => Err(Error::invalid(
+            item.line,
+            format!("unknown setting `{key}` in `[{table}]`"),
+        )),
+        None => Ok(()),
+    }
+}
+
+// The value parsers below are shared with the command line, so they report
+// errors without a line number.
+
+fn parse_address(s: &str) -> Result<SocketAddr, String> {
+    s.parse().map_err(|_| {
+        format!("invalid address `{s}`, expected e.g. `127.0.0.1:7878` or `[::1]:7878`")
+    })
+}
+
+/// A TCP address, or `unix:` and the path of a Unix socket.
+fn parse_listen(s: &str) -> Result<ListenAddr, String> {
+    match s.strip_prefix("unix:") {
+        Some("") => Err("`unix:` needs the path of the socket".to_string()),
+        Some(path) => Ok(ListenAddr::Unix(PathBuf::from(path))),
+        None => parse_address(s).map(ListenAddr::Tcp),
+    }
+}
+
+/// Permissions in octal, like `660` or `0o660`.
+fn parse_mode(s: &str) -> Result<u32, String> {
+    let digits = s.strip_prefix("0o").unwrap_or(s);
+    match u32::from_str_radix(digits, 8) {
+        Ok(mode) if mode <= 0o777 => Ok(mode),
+        _ => Err(format!("invalid mode `{s}`, expected octal like `660`")),
+    }
+}
+
+fn parse_backend(s: &str) -> Result<Backend, String> {
+    match s {
+        "threaded" => Ok(Backend::Threaded),
+        #[cfg(target_os = "linux")]
+        "event-loop" => Ok(Backend::EventLoop),
+        #[cfg(not(target_os = "linux"))]
+        "event-loop" => Err("the `event-loop` backend is only available on Linux".to_string()),
+        _ => Err(format!(
+            "unknown backend `{s}`, expected `threaded` or `event-loop`"
+        )),
+    }
+}
+
+fn backend_name(backend: Backend) -> &'static str {
+    match backend {
+        Backend::Threaded => "threaded",
+        #[cfg(target_os = "linux")]
+        Backend::EventLoop => "event-loop",
+    }
+}
+
+fn parse_workers(n: i64) -> Result<usize, String> {
+    match usize::try_from(n) {
+        Ok(n) if n > 0 => Ok(n),
+        _ => Err(format!("`workers` must be at least 1, not {n}")),
+    }
+}
+
+fn parse_format(s: &str) -> Result<Format, String> {
+    match s {
+        "common" => Ok(Format::Common),
+        "combined" => Ok(Format::Combined),
+        "json" => Ok(Format::Json),
+        _ => Err(format!(
+            "unknown log format `{s}`, expected `common`, `combined` or `json`"
+        )),
+    }
+}
+
+fn format_name(format: Format) -> &'static str {
+    match format {
+        Format::Common => "common",
+        Format::Combined => "combined",
+        Format::Json => "json",
+    }
+}
+
+/// `None` if logging is off, `Some(None)` for standard output.
+fn parse_log_path(s: &str) -> Option<Option<PathBuf>> {
+    match s {
+        "off" => None,
+        "-" => Some(None),
+        path => Some(Some(PathBuf::from(path))),
+    }
+}
+
+/// A duration such as `500ms`, `30s`, `5m` or `1h`, or `off`.
+fn parse_timeout(s: &str) -> Result<Option<Duration>, String> {
+    if s == "off" {
+        return Ok(None);
+    }
+
+    let error = || format!("invalid duration `{s}`, expected e.g. `500ms`, `30s` or `off`");
+    let split = s.find(|c: char| !c.is_ascii_digit()).ok_or_else(error)?;
+    let (n, unit) = s.split_at(split);
+    let n: u64 = n.parse().map_err(|_| error())?;
+    let duration = match unit {
+        "ms" => Duration::from_millis(n),
+        "s" => Duration::from_secs(n),
+        "m" => Duration::from_secs(n * 60),
+        "h" => Duration::from_secs(n * 3600),
+        _ => return Err(error()),
+    };
+
+    if duration.is_zero() {
+        return Err(format!("timeout `{s}` must be longer than zero, or `off`"));
+    }
+    Ok(Some(duration))
+}
+
+/// A size such as `512KB`, `10MB` or `1GB` (in powers of 1024), or a number
+/// of bytes. `None` if it's zero.
+fn parse_size(s: &str) -> Result<Option<u64>, String> {
+    let error = || format!("invalid size `{s}`, expected e.g. `512KB` or `10MB`");
+    let split = s.find(|c: char| !c.is_ascii_digit()).unwrap_or(s.len());
+    let (n, unit) = s.split_at(split);
+    let n: u64 = n.parse().map_err(|_| error())?;
+    let multiplier: u64 = match unit.trim_start() {
+        "" | "B" => 1,
+        "KB" => 1 << 10,
+        "MB" => 1 << 20,
+        "GB" => 1 << 30,
+        _ => return Err(error()),
+    };
+
+    match n.checked_mul(multiplier) {
+        Some(0) => Ok(None),
+        Some(size) => Ok(Some(size)),
+        None => Err(error()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn full_config() {
+        let config = Config::parse(
+            r#"
+            listen = ["0.0.0.0:80", "[::]:80", "unix:/run/echo.sock"]
+            socket_mode = "0o660"
+            backend = "threaded"
+            workers = 8
+            queue_capacity = 0
+            document_root = "/srv/www"
+            cache_control = "no-cache"
+            templates = "/srv/templates"
+            compression = false
+            metrics = false
+
+            [timeouts]
+            read = "500ms"
+            write = "off"
+            header = "5s"
+
+            [limits]
+            request_line = "4KB"
+            body_size = 1000
+            connections_per_ip = 0
+            long_lived_streams = 10
+
+            [access_log]
+            path = "-"
+            format = "json"
+            max_size = "1MB"
+
+            [cgi]
+            prefix = "/cgi-bin/app/"
+            program = "/srv/app.cgi"
+            timeout = "off"
+
+            [proxy]
+            prefix = "/api"
+            upstream = "127.0.0.1:3000"
+
+            [site.docs]
+            hosts = ["Docs.example.com", "*.docs.example.com"]
+            document_root = "/srv/docs"
+
+            [site.docs.cgi]
+            prefix = "/search"
+            program = "/srv/search.cgi"
+
+            [site.blog]
+            hosts = "blog.example.com"
+            document_root = "/srv/blog"
+            templates = "/srv/blog/templates"
+
+            [rate_limit]
+            requests = 100
+            per = "1m"
+
+            [basic_auth]
+            users = "/srv/htpasswd"
+
+            [cors]
+            allow_origins = "*"
+            allow_headers = ["X-Token", "Authorization"]
+            max_age = "10m"
+            "#,
+        )
+        .unwrap();
+
+        assert_eq!(
+            config.listen,
+            [
+                ListenAddr::Tcp("0.0.0.0:80".parse().unwrap()),
+                ListenAddr::Tcp("[::]:80".parse().unwrap()),
+                ListenAddr::Unix(PathBuf::from("/run/echo.sock")),
+            ]
+        );
+        assert_eq!(config.socket_mode, Some(0o660));
+        assert_eq!(config.workers, 8);
+        assert_eq!(config.queue_capacity, None);
+        assert_eq!(config.document_root, Path::new("/srv/www"));
+        assert_eq!(config.cache_control.as_deref(), Some("no-cache"));
+        assert_eq!(config.templates, Path::new("/srv/templates"));
+        assert!(!config.compression);
+        assert!(!config.metrics);
+        assert_eq!(config.read_timeout, Some(Duration::from_millis(500)));
+        assert_eq!(config.write_timeout, None);
+        assert_eq!(config.header_timeout, Some(Duration::from_secs(5)));
+        assert_eq!(
+            config.limits,
+            Limits {
+                request_line: 4096,
+                body: 1000,
+                ..Limits::default()
+            }
+        );
+        assert_eq!(config.max_connections_per_ip, None);
+        assert_eq!(config.max_long_lived_streams, 10);
+
+        let log = config.access_log.unwrap();
+        assert_eq!(log.path, None);
+        assert_eq!(log.format, Format::Json);
+        assert_eq!(log.max_size, Some(1024 * 1024));
+        assert_eq!(log.max_files, 5);
+
+        let cgi = config.cgi.unwrap();
+        assert_eq!(cgi.prefix, "/cgi-bin/app");
+        assert_eq!(cgi.program, Path::new("/srv/app.cgi"));
+        assert_eq!(cgi.timeout, None);
+        let proxy = config.proxy.unwrap();
+        assert_eq!(proxy.prefix, "/api");
+        assert_eq!(proxy.upstream, "127.0.0.1:3000".parse().unwrap());
+        assert_eq!(proxy.timeout, Some(Duration::from_secs(30)));
+
+        let [blog, docs] = &config.sites[..] else {
+            panic!("expected two sites, not {:?}", config.sites);
+        };
+        assert_eq!(docs.name, "docs");
+        assert_eq!(docs.hosts, ["docs.example.com", "*.docs.example.com"]);
+        assert_eq!(docs.document_root, Path::new("/srv/docs"));
+        assert_eq!(docs.templates, None);
+        assert_eq!(docs.cgi.as_ref().unwrap().prefix, "/search");
+        assert_eq!(docs.proxy, None);
+        assert_eq!(blog.hosts, ["blog.example.com"]);
+        assert_eq!(
+            blog.templates.as_deref(),
+            Some(Path::new("/srv/blog/templates"))
+        );
+
+        let limit = config.rate_limit.unwrap();
+        assert_eq!(limit.requests, 100);
+        assert_eq!(limit.per, Duration::from_secs(60));
+        assert_eq!((limit.burst, limit.header), (None, None));
+
+        let auth = config.basic_auth.unwrap();
+        assert_eq!(auth.prefix, "");
+        assert_eq!(auth.users, Path::new("/srv/htpasswd"));
+        assert_eq!(auth.realm, "echo");
+        let cors = config.cors.unwrap();
+        assert_eq!(cors.allow_origins, ["*"]);
+        assert_eq!(cors.allow_headers, ["X-Token", "Authorization"]);
+        assert!(cors.allow_methods.is_empty());
+        assert!(!cors.allow_credentials);
+        assert_eq!(cors.max_age, Some(Duration::from_secs(600)));
+    }
+
+    #[test]
+    fn defaults_and_round_trip() {
+        assert_eq!(Config::parse("").unwrap(), Config::default());
+        // Nothing of the server's own is served by default.
+        let defaults = Config::default();
+        let log = defaults.access_log.unwrap().path.unwrap();
+        assert_ne!(defaults.document_root, Path::new("."));
+        assert!(!log.starts_with(&defaults.document_root));
+
+        let config = Config {
+            listen: vec![
+                ListenAddr::Unix(PathBuf::from("echo.sock")),
+                ListenAddr::Tcp("[::1]:80".parse().unwrap()),
+            ],
+            socket_mode: Some(0o600),
+            read_timeout: None,
+            access_log: None,
+            cgi: Some(CgiConfig {
+                prefix: "/cgi".to_string(),
+                program: PathBuf::from("app.cgi"),
+                timeout: None,
+            }),
+            proxy: Some(ProxyConfig {
+                prefix: "/api".to_string(),
+                upstream: "[::1]:3000".parse().unwrap(),
+                timeout: Some(Duration::from_secs(5)),
+            }),
+            sites: vec![SiteConfig {
+                name: "api-docs".to_string(),
+                hosts: vec!["docs.example.com".to_string(), "*.example.org".to_string()],
+                document_root: PathBuf::from("docs"),
+                cache_control: Some("max-age=60".to_string()),
+                templates: None,
+                cgi: None,
+                proxy: Some(ProxyConfig {
+                    prefix: "/api".to_string(),
+                    upstream: "127.0.0.1:3000".parse().unwrap(),
+                    timeout: None,
+                }),
+            }],
+            rate_limit: Some(RateLimitConfig {
+                requests: 5,
+                per: Duration::from_millis(1500),
+                burst: Some(10),
+                header: Some("X-Api-Key".to_string()),
+            }),
+            basic_auth: Some(AuthConfig {
+                prefix: "/admin".to_string(),
+                users: PathBuf::from("htpasswd"),
+                realm: "Admin \"area\"".to_string(),
+            }),
+            cors: Some(CorsConfig {
+                allow_origins: vec!["https://a.example".into(), "https://b.example".into()],
+                allow_methods: vec!["PUT".into()],
+                allow_credentials: true,
+                ..CorsConfig::default()
+            }),
+            ..Config::default()
+        };
+        assert_eq!(Config::parse(&config.to_string()).unwrap(), config);
+    }
+
+    #[test]
+    fn invalid_settings() {
+        let error = |source| Config::parse(source).unwrap_err().to_string();
+
+        assert_eq!(
+            error("workers = 0"),
+            "line 1: `workers` must be at least 1, not 0"
+        );
+        assert_eq!(
+            error("workers = \"4\""),
+            "line 1: `workers` must be an integer, not a string"
+        );
+        assert_eq!(
+            error("\nlisten = \"localhost\""),
+            "line 2: invalid address `localhost`, expected e.g. `127.0.0.1:7878` or `[::1]:7878`"
+        );
+        assert_eq!(
+            error("[timeouts]\nread = \"5 minutes\""),
+            "line 2: invalid duration `5 minutes`, expected e.g. `500ms`, `30s` or `off`"
+        );
+        assert_eq!(
+            error("[access_log]\nformat = \"xml\""),
+            "line 2: unknown log format `xml`, expected `common`, `combined` or `json`"
+        );
+        assert_eq!(
+            error("[limits]\nbody_size = 0"),
+            "line 2: `limits.body_size` must be positive"
+        );
+        assert_eq!(
+            error("[limits]\nlong_lived_streams = 0"),
+            "line 2: `limits.long_lived_streams` must be positive"
+        );
+        assert_eq!(
+            error("listen = \"unix:\""),
+            "line 1: `unix:` needs the path of the socket"
+        );
+        assert_eq!(
+            error("socket_mode = \"rw\""),
+            "line 1: invalid mode `rw`, expected octal like `660`"
+        );
+        assert_eq!(error("port = 80"), "line 1: unknown setting `port`");
+        assert_eq!(
+            error("[timeouts]\nidle = \"1s\""),
+            "line 2: unknown setting `idle` in `[timeouts]`"
+        );
+        assert_eq!(error("[tls]"), "unknown table `[tls]`");
+        assert_eq!(
+            error("[cgi]\nprefix = \"/cgi\""),
+            "`[cgi]` needs a `program`"
+        );
+        assert_eq!(
+            error("[proxy]\nprefix = \"api\"\nupstream = \"127.0.0.1:3000\""),
+            "line 2: `proxy.prefix` must start with `/`, not `api`"
+        );
+        assert_eq!(
+            error("[site.docs]\ndocument_root = \"docs\""),
+            "`[site.docs]` needs `hosts`"
+        );
+        assert_eq!(
+            error("[site.a]\nhosts = \"a\"\ndocument_root = \"a\"\n[site.a.cgi]\nprefix = \"/\""),
+            "`[site.a.cgi]` needs a `program`"
+        );
+        assert_eq!(
+            error("[site.a.proxy]\nprefix = \"/\"\nupstream = \"127.0.0.1:1\""),
+            "unknown table `[site.a.proxy]`"
+        );
+        assert_eq!(
+            error("[site.a]\nhosts = \"x\"\ndocument_root = \"a\"\n[site.b]\nhosts = \"X\"\ndocument_root = \"b\""),
+            "host `x` is in both `[site.a]` and `[site.b]`"
+        );
+        assert_eq!(
+            error("[rate_limit]\nrequests = 0"),
+            "line 2: `rate_limit.requests` must be positive"
+        );
+        assert_eq!(
+            error("[basic_auth]\nrealm = \"x\""),
+            "`[basic_auth]` needs a `users` file"
+        );
+        assert_eq!(
+            error("[cors]\nallow_origins = [\"a\", 1]"),
+            "line 2: `cors.allow_origins` must be a string, not an integer"
+        );
+    }
+
+    #[test]
+    fn sizes() {
+        assert_eq!(parse_size("512"), Ok(Some(512)));
+        assert_eq!(parse_size("512KB"), Ok(Some(512 * 1024)));
+        assert_eq!(parse_size("10 MB"), Ok(Some(10 << 20)));
+        assert_eq!(parse_size("0MB"), Ok(None));
+        assert!(parse_size("10TB").is_err());
+        assert!(parse_size("MB").is_err());
+    }
+}
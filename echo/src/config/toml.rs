@@ -0,0 +1,295 @@
+//! The subset of TOML the configuration file uses: `[table]` headers,
+//! `key = value` pairs and `#` comments, where a value is a string, an
+//! integer, a boolean or a single-line array of those.
+
+use std::collections::BTreeMap;
+
+use super::Error;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Value {
+    String(String),
+    Integer(i64),
+    Bool(bool),
+    Array(Vec<Value>),
+}
+
+impl Value {
+    /// The name of the value's type, for error messages.
+    pub fn type_name(&self) -> &'static str {
+        match self {
+            Value::String(_) => "a string",
+            Value::Integer(_) => "an integer",
+            Value::Bool(_) => "a boolean",
+            Value::Array(_) => "an array",
+        }
+    }
+}
+
+/// A value along with the line it was defined on.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Item {
+    pub value: Value,
+    pub line: usize,
+}
+
+/// The keys of each table. Keys before the first header are in the table
+/// named `""`.
+pub type Document = BTreeMap<String, BTreeMap<String, Item>>;
+
+pub fn parse(source: &str) -> Result<Document, Error> {
+    let mut document = Document::new();
+    let mut table = String::new();
+    document.insert(table.clone(), BTreeMap::new());
+
+    for (i, line) in source.lines().enumerate() {
+        let line_number = i + 1;
+        let error = |message: String| Error::Syntax {
+            line: line_number,
+            message,
+        };
+
+        let mut cursor = Cursor::new(line);
+        cursor.skip_whitespace();
+        if cursor.at_end_of_line() {
+            continue;
+        }
+
+        if cursor.eat('[') {
+            cursor.skip_whitespace();
+            let name = cursor.key().map_err(error)?;
+            cursor.skip_whitespace();
+            if !cursor.eat(']') {
+                return Err(error("expected `]` after the table name".to_string()));
+            }
+            cursor.expect_end().map_err(error)?;
+
+            if document.contains_key(&name) {
+                return Err(error(format!("table `[{name}]` is defined twice")));
+            }
+            document.insert(name.clone(), BTreeMap::new());
+            table = name;
+            continue;
+        }
+
+        let key = cursor.key().map_err(error)?;
+        cursor.skip_whitespace();
+        if !cursor.eat('=') {
+            return Err(error(format!("expected `=` after `{key}`")));
+        }
+        cursor.skip_whitespace();
+        let value = cursor.value().map_err(error)?;
+        cursor.expect_end().map_err(error)?;
+
+        let keys = document.get_mut(&table).unwrap();
+        if keys.contains_key(&key) {
+            return Err(error(format!("`{key}` is defined twice")));
+        }
+        keys.insert(
+            key,
+            Item {
+                value,
+                line: line_number,
+            },
+        );
+    }
+
+    Ok(document)
+}
+
+struct Cursor<'a> {
+    rest: &'a str,
+}
+
+impl<'a> Cursor<'a> {
+    fn new(line: &'a str) -> Cursor<'a> {
+        Cursor { rest: line }
+    }
+
+    fn peek(&self) -> Option<char> {
+        self.rest.chars().next()
+    }
+
+    fn eat(&mut self, c: char) -> bool {
+        match self.rest.strip_prefix(c) {
+            Some(rest) => {
+                self.rest = rest;
+                true
+            }
+            None => false,
+        }
+    }
+
+    fn skip_whitespace(&mut self) {
+        self.rest = self.rest.trim_start_matches([' ', '\t']);
+    }
+
+    fn at_end_of_line(&self) -> bool {
+        self.rest.is_empty() || self.rest.starts_with('#')
+    }
+
+    fn expect_end(&mut self) -> Result<(), String> {
+        self.skip_whitespace();
+        if self.at_end_of_line() {
+            Ok(())
+        } else {
+            Err(format!("unexpected `{}`", self.rest.trim_end()))
+        }
+    }
+
+    /// A bare key: letters, digits, `_` and `-`, with `.` between parts.
+    fn key(&mut self) -> Result<String, String> {
+        let len = self
+            .rest
+            .find(|c: char| !(c.is_ascii_alphanumeric() || "_-.".contains(c)))
+            .unwrap_or(self.rest.len());
+        let (key, rest) = self.rest.split_at(len);
+        if key.is_empty() || key.split('.').any(str::is_empty) {
+            return Err("expected a key".to_string());
+        }
+        self.rest = rest;
+        Ok(key.to_string())
+    }
+
+    fn value(&mut self) -> Result<Value, String> {
+        match self.peek() {
+            Some('"') => self.string().map(Value::String),
+            Some('\'') => self.literal_string().map(Value::String),
+            Some('[') => self.array(),
+            Some(c) if c == '-' || c == '+' || c.is_ascii_digit() => self.integer(),
+            Some(_) if self.rest.starts_with("true") => {
+                self.rest = &self.rest[4..];
+                Ok(Value::Bool(true))
+            }
+            Some(_) if self.rest.starts_with("false") => {
+                self.rest = &self.rest[5..];
+                Ok(Value::Bool(false))
+            }
+            _ => Err("expected a value".to_string()),
+        }
+    }
+
+    fn string(&mut self) -> Result<String, String> {
+        self.eat('"');
+        let mut s = String::new();
+        let mut chars = self.rest.char_indices();
+
+        while let Some((i, c)) = chars.next() {
+            match c {
+                '"' => {
+                    self.rest = &self.rest[i + 1..];
+                    return Ok(s);
+                }
+                '\\' => match chars.next().map(|(_, c)| c) {
+                    Some('"') => s.push('"'),
+                    Some('\\') => s.push('\\'),
+                    Some('n') => s.push('\n'),
+                    Some('t') => s.push('\t'),
+                    Some('r') => s.push('\r'),
+                    Some(c) => return Err(format!("unknown escape `\\{c}`")),
+                    None => break,
+                },
+                c => s.push(c),
+            }
+        }
+
+        Err("unterminated string".to_string())
+    }
+
+    fn literal_string(&mut self) -> Result<String, String> {
+        self.eat('\'');
+        let Some(end) = self.rest.find('\'') else {
+            return Err("unterminated string".to_string());
+        };
+        let s = self.rest[..end].to_string();
+        self.rest = &self.rest[end + 1..];
+        Ok(s)
+    }
+
+    fn integer(&mut self) -> Result<Value, String> {
+        let len = self
+            .rest
+            .char_indices()
+            .find(|&(i, c)| !(c.is_ascii_digit() || c == '_' || (i == 0 && "+-".contains(c))))
+            .map_or(self.rest.len(), |(i, _)| i);
+        let (digits, rest) = self.rest.split_at(len);
+        let n = digits
+            .replace('_', "")
+            .parse()
+            .map_err(|_| format!("invalid integer `{digits}`"))?;
+        self.rest = rest;
+        Ok(Value::Integer(n))
+    }
+
+    fn array(&mut self) -> Result<Value, String> {
+        self.eat('[');
+        let mut values = Vec::new();
+
+        loop {
+            self.skip_whitespace();
+            if self.eat(']') {
+                return Ok(Value::Array(values));
+            }
+            if self.at_end_of_line() {
+                return Err("unterminated array".to_string());
+            }
+            values.push(self.value()?);
+            self.skip_whitespace();
+            if self.at_end_of_line() {
+                return Err("unterminated array".to_string());
+            }
+            if !self.eat(',') && self.peek() != Some(']') {
+                return Err("expected `,` or `]` in array".to_string());
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn tables_and_values() {
+        let document = parse(
+            r#"
+            # A comment.
+            workers = 4
+            listen = ["127.0.0.1:7878", '[::1]:7878'] # Another comment.
+
+            [log]
+            access = "logs/access \"main\".log"
+            enabled = true
+            "#,
+        )
+        .unwrap();
+
+        let root = &document[""];
+        assert_eq!(root["workers"].value, Value::Integer(4));
+        assert_eq!(root["workers"].line, 3);
+        assert_eq!(
+            root["listen"].value,
+            Value::Array(vec![
+                Value::String("127.0.0.1:7878".to_string()),
+                Value::String("[::1]:7878".to_string()),
+            ])
+        );
+        assert_eq!(
+            document["log"]["access"].value,
+            Value::String("logs/access \"main\".log".to_string())
+        );
+        assert_eq!(document["log"]["enabled"].value, Value::Bool(true));
+    }
+
+    #[test]
+    fn syntax_errors_have_line_numbers() {
+        let error = |source| parse(source).unwrap_err().to_string();
+
+        assert_eq!(error("a = 1\nb = \"x"), "line 2: unterminated string");
+        assert_eq!(error("[log\n"), "line 1: expected `]` after the table name");
+        assert_eq!(error("a = 1\na = 2"), "line 2: `a` is defined twice");
+        assert_eq!(error("a = [1, 2"), "line 1: unterminated array");
+        assert_eq!(error("a 1"), "line 1: expected `=` after `a`");
+        assert_eq!(error("a = 1 2"), "line 1: unexpected `2`");
+    }
+}
@@ -0,0 +1,685 @@
+//! Serving files from a directory, with the validators and byte ranges that
+//! browser caches and media players rely on.
+
+mod mime;
+mod range;
+
+use std::{
+    fs::{self, File, Metadata},
+    io::{self, Cursor, Read, Seek, SeekFrom},
+    ops::RangeInclusive,
+    path::{Path, PathBuf},
+    sync::atomic::{AtomicU64, Ordering},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+pub use mime::content_type;
+
+use crate::{
+    compress::{self, Encoding},
+    date::{http_date, parse_http_date},
+    http::{normalize_path, Request, Response},
+    server::Handler,
+};
+use range::{parse_range, Ranges};
+
+/// The largest files compressed on the fly by default, see
+/// `StaticFiles::precompress_limit`.
+pub const DEFAULT_PRECOMPRESS_LIMIT: u64 = 4 * 1024 * 1024;
+
+/// A handler serving the files under a directory.
+///
+/// Responses carry an `ETag` and a `Last-Modified` date, so that clients
+/// can revalidate them with `If-None-Match` or `If-Modified-Since` and get an
+/// empty 304 response if the file didn't change. `Range` requests get the
+/// requested bytes in a 206 response, or a `multipart/byteranges` body for
+/// several ranges. Optionally, compressible files are sent gzipped to the
+/// clients that accept it.
+///
+/// ```no_run
+/// use echo::{files::StaticFiles, Server};
+/// use std::net::TcpListener;
+///
+/// let files = StaticFiles::new("public").cache_control("public, max-age=3600");
+/// let server = Server::new(files);
+/// server.serve(TcpListener::bind("127.0.0.1:7878").unwrap()).unwrap();
+/// ```
+#[derive(Debug, Clone)]
+pub struct StaticFiles {
+    root: PathBuf,
+    index: String,
+    cache_control: Option<String>,
+    precompress: bool,
+    precompress_limit: u64,
+}
+
+impl StaticFiles {
+    /// Serve the files under `root`, with `index.html` as the index file of
+    /// directories.
+    pub fn new(root: impl Into<PathBuf>) -> StaticFiles {
+        StaticFiles {
+            root: root.into(),
+            index: "index.html".to_string(),
+            cache_control: None,
+            precompress: false,
+            precompress_limit: DEFAULT_PRECOMPRESS_LIMIT,
+        }
+    }
+
+    /// The file served for a request for a directory.
+    pub fn index(mut self, name: impl Into<String>) -> StaticFiles {
+        self.index = name.into();
+        self
+    }
+
+    /// The `Cache-Control` header of every response, e.g. `no-cache` or
+    /// `public, max-age=3600`. There's none by default.
+    pub fn cache_control(mut self, value: impl Into<String>) -> StaticFiles {
+        self.cache_control = Some(value.into());
+        self
+    }
+
+    /// Send compressible files of at least `compress::MIN_SIZE` bytes
+    /// gzipped to the clients that accept it. The compressed copy is kept in
+    /// a `.gz` file next to the original, which is created on the first
+    /// request and recreated once the original changes; a `.gz` file made
+    /// ahead of time is used as is. Off by default.
+    ///
+    /// Range requests are always answered from the original file.
+    pub fn precompress(mut self, enabled: bool) -> StaticFiles {
+        self.precompress = enabled;
+        self
+    }
+
+    /// The largest files to compress on the first request. Compressing
+    /// holds the whole file and its compressed copy in memory, so larger
+    /// ones are sent as they are, unless they have a `.gz` file made ahead
+    /// of time. Defaults to `DEFAULT_PRECOMPRESS_LIMIT`.
+    pub fn precompress_limit(mut self, bytes: u64) -> StaticFiles {
+        self.precompress_limit = bytes;
+        self
+    }
+
+    /// Answer a request for the file at the request's path.
+    pub fn serve(&self, request: &Request) -> Response {
+        if request.method != "GET" && request.method != "HEAD" {
+            return Response::text(405, "Method Not Allowed\n").with_header("Allow", "GET, HEAD");
+        }
+        let Some(mut path) = self.resolve(request.path()) else {
+            return Response::text(404, "Not Found\n");
+        };
+
+        let result = (|| {
+            let mut file = File::open(&path)?;
+            let mut metadata = file.metadata()?;
+
+            if metadata.is_dir() {
+                if !request.path().ends_with('/') {
+                    return Ok(redirect_to_directory(request));
+                }
+                path.push(&self.index);
+                file = File::open(&path)?;
+                metadata = file.metadata()?;
+            }
+            if !metadata.is_file() {
+                return Err(io::ErrorKind::NotFound.into());
+            }
+
+            self.file_response(request, &path, file, &metadata)
+        })();
+
+        result.unwrap_or_else(|e| match e.kind() {
+            io::ErrorKind::NotFound | io::ErrorKind::NotADirectory => {
+                Response::text(404, "Not Found\n")
+            }
+            io::ErrorKind::PermissionDenied => Response::text(403, "Forbidden\n"),
+            _ => Response::text(500, "Internal Server Error\n"),
+        })
+    }
+
+    /// Map a request path to a file under the root, or `None` if it's
+    /// malformed or tries to get out of the root.
+    fn resolve(&self, request_path: &str) -> Option<PathBuf> {
+        let mut path = self.root.clone();
+        for segment in normalize_path(request_path)?.split('/') {
+            if !segment.is_empty() {
+                path.push(segment);
+            }
+        }
+        Some(path)
+    }
+
+    fn file_response(
+        &self,
+        request: &Request,
+        path: &Path,
+        file: File,
+        metadata: &Metadata,
+    ) -> io::Result<Response> {
+        let len = metadata.len();
+        let modified = metadata.modified().ok();
+        let content_type = content_type(path);
+
+        let compressible =
+            compress::is_compressible(content_type) && len >= compress::MIN_SIZE as u64;
+        let wants_gzip = self.precompress
+            && compressible
+            && !request.headers.contains("Range")
+            && compress::negotiate(request, &[Encoding::Gzip]).is_some();
+        let sibling = wants_gzip.then(|| gzip_sibling(path, modified)).flatten();
+        let gzip = wants_gzip && (sibling.is_some() || len <= self.precompress_limit);
+        let etag = match etag(len, modified) {
+            etag if gzip => compress::encoded_etag(&etag, Encoding::Gzip),
+            etag => etag,
+        };
+
+        let mut response = Response::new(200).with_header("ETag", etag.clone());
+        if self.precompress && compressible {
+            response.headers.insert("Vary", "Accept-Encoding");
+        }
+        if let Some(modified) = modified {
+            response
+                .headers
+                .insert("Last-Modified", http_date(modified));
+        }
+        if let Some(cache_control) = &self.cache_control {
+            response
+                .headers
+                .insert("Cache-Control", cache_control.clone());
+        }
+
+        if is_not_modified(request, &etag, modified) {
+            response.status = 304;
+            return Ok(response);
+        }
+
+        response.headers.insert("Accept-Ranges", "bytes");
+
+        if gzip {
+            response.headers.insert("Content-Type", content_type);
+            compress::mark_encoded(&mut response, Encoding::Gzip);
+            // The length is known, but for a HEAD request before the file
+            // was ever compressed.
+            match sibling {
+                Some((sibling, sibling_len)) => {
+                    response.headers.remove("Transfer-Encoding");
+                    response
+                        .headers
+                        .insert("Content-Length", sibling_len.to_string());
+                    if request.method == "GET" {
+                        response = response.with_stream(Section::new(sibling, 0, sibling_len));
+                    }
+                }
+                None if request.method == "GET" => {
+                    response.headers.remove("Transfer-Encoding");
+                    response.body = gzip_now(path, file, len)?;
+                }
+                None => {}
+            }
+            return Ok(response);
+        }
+
+        // Ranges only make sense for GET, a HEAD request gets the headers of
+        // the full response.
+        let ranges = match request.header("Range") {
+            Some(range) if request.method == "GET" && if_range_holds(request, &etag, modified) => {
+                parse_range(range, len)
+            }
+            _ => None,
+        };
+
+        // The body is read from the file as it's sent, so that a large file
+        // (or many ranges of one) doesn't have to fit in memory.
+        match ranges {
+            None => {
+                response.headers.insert("Content-Type", content_type);
+                response.headers.insert("Content-Length", len.to_string());
+                if request.method == "GET" {
+                    response = response.with_stream(Section::new(file, 0, len));
+                }
+            }
+            Some(Ranges::Unsatisfiable) => {
+                response.status = 416;
+                response
+                    .headers
+                    .insert("Content-Range", format!("bytes */{len}"));
+                response
+                    .headers
+                    .insert("Content-Type", "text/plain; charset=utf-8");
+                response.body = b"Range Not Satisfiable\n".to_vec();
+            }
+            Some(Ranges::Satisfiable(ranges)) if ranges.len() == 1 => {
+                let range = &ranges[0];
+                response.status = 206;
+                response.headers.insert("Content-Type", content_type);
+                response
+                    .headers
+                    .insert("Content-Range", content_range(range, len));
+                response
+                    .headers
+                    .insert("Content-Length", range_len(range).to_string());
+                response =
+                    response.with_stream(Section::new(file, *range.start(), range_len(range)));
+            }
+            Some(Ranges::Satisfiable(ranges)) => {
+                let boundary = boundary();
+                let mut body: Box<dyn Read + Send> = Box::new(io::empty());
+                let mut body_len = 0;
+                for range in &ranges {
+                    let part_head = format!(
+                        "--{boundary}\r\nContent-Type: {content_type}\r\n\
+                         Content-Range: {}\r\n\r\n",
+                        content_range(range, len)
+                    );
+                    body_len += part_head.len() as u64 + range_len(range) + 2;
+                    let section = Section::new(file.try_clone()?, *range.start(), range_len(range));
+                    body = Box::new(
+                        body.chain(Cursor::new(part_head))
+                            .chain(section)
+                            .chain(&b"\r\n"[..]),
+                    );
+                }
+                let closing = format!("--{boundary}--\r\n");
+                body_len += closing.len() as u64;
+                body = Box::new(body.chain(Cursor::new(closing)));
+
+                response.status = 206;
+                response.headers.insert(
+                    "Content-Type",
+                    format!("multipart/byteranges; boundary={boundary}"),
+                );
+                response
+                    .headers
+                    .insert("Content-Length", body_len.to_string());
+                response = response.with_stream(body);
+            }
+        }
+
+        Ok(response)
+    }
+}
+
+impl Handler for StaticFiles {
+    fn handle(&self, request: &Request) -> Response {
+        self.serve(request)
+    }
+}
+
+/// The path of the gzipped copy of the file at `path`.
+fn sibling_path(path: &Path) -> PathBuf {
+    let mut sibling = path.as_os_str().to_owned();
+    sibling.push(".gz");
+    PathBuf::from(sibling)
+}
+
+/// The `.gz` sibling of the file at `path` and its length, if it's at least
+/// as recent as the file.
+fn gzip_sibling(path: &Path, modified: Option<SystemTime>) -> Option<(File, u64)> {
+    let sibling = File::open(sibling_path(path)).ok()?;
+    let metadata = sibling.metadata().ok()?;
+    let fresh = metadata.is_file() && metadata.modified().ok()? >= modified?;
+    fresh.then_some((sibling, metadata.len()))
+}
+
+/// The gzipped contents of `file`, of `len` bytes at `path`, compressed now
+/// and written to its sibling for next time.
+fn gzip_now(path: &Path, file: File, len: u64) -> io::Result<Vec<u8>> {
+    let sibling = sibling_path(path);
+    let mut contents = Vec::new();
+    file.take(len).read_to_end(&mut contents)?;
+    let body = compress::gzip(&contents);
+
+    // Write under a temporary name and rename, so that concurrent requests
+    // never read a half written file. The cache is only an optimization:
+    // a read-only directory just means compressing every time.
+    let mut temporary = sibling.as_os_str().to_owned();
+    temporary.push(format!(".{}.tmp", boundary()));
+    let temporary = PathBuf::from(temporary);
+    if fs::write(&temporary, &body)
+        .and_then(|()| fs::rename(&temporary, &sibling))
+        .is_err()
+    {
+        let _ = fs::remove_file(&temporary);
+    }
+
+    Ok(body)
+}
+
+fn redirect_to_directory(request: &Request) -> Response {
+    let mut location = format!("{}/", request.path());
+    if let Some(query) = request.query() {
+        location.push('?');
+        location.push_str(query);
+    }
+    Response::new(301).with_header("Location", location)
+}
+
+/// A validator that changes whenever the file is written to.
+fn etag(len: u64, modified: Option<SystemTime>) -> String {
+    let modified = modified
+        .and_then(|modified| modified.duration_since(UNIX_EPOCH).ok())
+        .unwrap_or_default();
+    format!("\"{len:x}-{:x}\"", modified.as_nanos())
+}
+
+/// Whether the client's cached copy is still fresh, from `If-None-Match`,
+/// or `If-Modified-Since` if there's no `If-None-Match` (RFC 9110 section
+/// 13.2.2).
+fn is_not_modified(request: &Request, etag: &str, modified: Option<SystemTime>) -> bool {
+    if request.headers.contains("If-None-Match") {
+        // The weak comparison: `W/"x"` matches `"x"`.
+        let opaque = |tag: &str| tag.trim().trim_start_matches("W/").to_string();
+        return request
+            .headers
+            .get_all("If-None-Match")
+            .flat_map(|value| value.split(','))
+            .any(|tag| tag.trim() == "*" || opaque(tag) == opaque(etag));
+    }
+
+    match (request.header("If-Modified-Since"), modified) {
+        (Some(since), Some(modified)) => match parse_http_date(since) {
+            // HTTP dates have a resolution of a second.
+            Some(since) => truncate_to_secs(modified) <= since,
+            None => false,
+        },
+        _ => false,
+    }
+}
+
+/// Whether to honor the `Range` header according to `If-Range`: only if the
+/// client's partial copy is of the current version of the file.
+fn if_range_holds(request: &Request, etag: &str, modified: Option<SystemTime>) -> bool {
+    match request.header("If-Range") {
+        None => true,
+        // Weak entity tags never match here.
+        Some(tag) if tag.starts_with('"') || tag.starts_with("W/") => tag == etag,
+        Some(date) => match (parse_http_date(date), modified) {
+            (Some(date), Some(modified)) => truncate_to_secs(modified) == date,
+            _ => false,
+        },
+    }
+}
+
+fn truncate_to_secs(time: SystemTime) -> SystemTime {
+    let secs = time
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs();
+    UNIX_EPOCH + std::time::Duration::from_secs(secs)
+}
+
+fn content_range(range: &RangeInclusive<u64>, len: u64) -> String {
+    format!("bytes {}-{}/{len}", range.start(), range.end())
+}
+
+fn range_len(range: &RangeInclusive<u64>) -> u64 {
+    range.end() - range.start() + 1
+}
+
+/// `len` bytes of a file from `start` on, read as the body is sent.
+///
+/// It seeks when it's first read rather than up front: the sections of a
+/// multipart body share the file's position, and are read one after the
+/// other.
+struct Section {
+    file: File,
+    start: u64,
+    remaining: u64,
+    positioned: bool,
+}
+
+impl Section {
+    fn new(file: File, start: u64, len: u64) -> Section {
+        Section {
+            file,
+            start,
+            remaining: len,
+            positioned: false,
+        }
+    }
+}
+
+impl Read for Section {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if !self.positioned {
+            self.file.seek(SeekFrom::Start(self.start))?;
+            self.positioned = true;
+        }
+        let max = buf
+            .len()
+            .min(usize::try_from(self.remaining).unwrap_or(usize::MAX));
+        let n = self.file.read(&mut buf[..max])?;
+        self.remaining -= n as u64;
+        Ok(n)
+    }
+}
+
+/// A separator for the parts of a multipart body that's vanishingly unlikely
+/// to show up in the file.
+fn boundary() -> String {
+    static COUNTER: AtomicU64 = AtomicU64::new(0);
+    let nanos = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .subsec_nanos();
+    let count = COUNTER.fetch_add(1, Ordering::Relaxed);
+    format!("echo-{nanos:08x}{count:016x}")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::TempDir;
+    use std::fs;
+
+    struct Fixture {
+        root: TempDir,
+        files: StaticFiles,
+    }
+
+    impl Fixture {
+        fn new(name: &str) -> Fixture {
+            let root = TempDir::new(&format!("files-{name}"));
+            fs::create_dir_all(root.join("docs")).unwrap();
+            fs::write(root.join("index.html"), "<h1>Home</h1>").unwrap();
+            fs::write(root.join("docs/index.html"), "<h1>Docs</h1>").unwrap();
+            fs::write(root.join("digits.txt"), "0123456789").unwrap();
+
+            let files = StaticFiles::new(root.path()).cache_control("max-age=60");
+            Fixture { root, files }
+        }
+
+        fn get(&self, target: &str, headers: &[(&str, &str)]) -> Response {
+            let mut request = Request::new("GET", target);
+            for (name, value) in headers {
+                request.headers.append(*name, *value);
+            }
+            self.files.serve(&request)
+        }
+    }
+
+    /// The whole body of a response, streamed or not.
+    fn body(response: &Response) -> Vec<u8> {
+        let mut body = response.body.clone();
+        if let Some(stream) = &response.stream {
+            stream.take().unwrap().read_to_end(&mut body).unwrap();
+        }
+        body
+    }
+
+    #[test]
+    fn files_and_directories() {
+        let fixture = Fixture::new("paths");
+
+        let response = fixture.get("/", &[]);
+        assert_eq!(response.status, 200);
+        assert_eq!(body(&response), b"<h1>Home</h1>");
+        assert_eq!(
+            response.headers.get("Content-Type"),
+            Some("text/html; charset=utf-8")
+        );
+        assert_eq!(response.headers.get("Cache-Control"), Some("max-age=60"));
+
+        let response = fixture.get("/docs?page=2", &[]);
+        assert_eq!(response.status, 301);
+        assert_eq!(response.headers.get("Location"), Some("/docs/?page=2"));
+        assert_eq!(body(&fixture.get("/docs/", &[])), b"<h1>Docs</h1>");
+        assert_eq!(body(&fixture.get("/%64igits.txt", &[])), b"0123456789");
+
+        assert_eq!(fixture.get("/missing.txt", &[]).status, 404);
+        assert_eq!(fixture.get("/digits.txt/x", &[]).status, 404);
+        assert_eq!(fixture.get("/../etc/passwd", &[]).status, 404);
+        assert_eq!(
+            fixture.get("/docs/%2e%2e/%2e%2e/etc/passwd", &[]).status,
+            404
+        );
+
+        let request = Request::new("POST", "/");
+        assert_eq!(fixture.files.serve(&request).status, 405);
+    }
+
+    #[test]
+    fn conditional_requests() {
+        let fixture = Fixture::new("conditional");
+        let response = fixture.get("/digits.txt", &[]);
+        let etag = response.headers.get("ETag").unwrap();
+        let modified = response.headers.get("Last-Modified").unwrap();
+
+        let response = fixture.get("/digits.txt", &[("If-None-Match", etag)]);
+        assert_eq!(response.status, 304);
+        assert!(response.body.is_empty());
+        assert_eq!(response.headers.get("ETag"), Some(etag));
+
+        let weak = format!("\"other\", W/{etag}");
+        assert_eq!(
+            fixture
+                .get("/digits.txt", &[("If-None-Match", &weak)])
+                .status,
+            304
+        );
+        assert_eq!(
+            fixture
+                .get("/digits.txt", &[("If-None-Match", "\"other\"")])
+                .status,
+            200
+        );
+        assert_eq!(
+            fixture
+                .get("/digits.txt", &[("If-Modified-Since", modified)])
+                .status,
+            304
+        );
+        assert_eq!(
+            fixture
+                .get(
+                    "/digits.txt",
+                    &[("If-Modified-Since", "Thu, 01 Jan 1970 00:00:00 GMT")]
+                )
+                .status,
+            200
+        );
+        // If-None-Match takes precedence.
+        let both = [
+            ("If-None-Match", "\"other\""),
+            ("If-Modified-Since", modified),
+        ];
+        assert_eq!(fixture.get("/digits.txt", &both).status, 200);
+    }
+
+    #[test]
+    fn byte_ranges() {
+        let fixture = Fixture::new("ranges");
+
+        let response = fixture.get("/digits.txt", &[("Range", "bytes=2-4")]);
+        assert_eq!(response.status, 206);
+        assert_eq!(body(&response), b"234");
+        assert_eq!(response.headers.get("Content-Length"), Some("3"));
+        assert_eq!(response.headers.get("Content-Range"), Some("bytes 2-4/10"));
+
+        let response = fixture.get("/digits.txt", &[("Range", "bytes=20-")]);
+        assert_eq!(response.status, 416);
+        assert_eq!(response.headers.get("Content-Range"), Some("bytes */10"));
+
+        let response = fixture.get("/digits.txt", &[("Range", "bytes=0-1,-2")]);
+        assert_eq!(response.status, 206);
+        let content_type = response.headers.get("Content-Type").unwrap();
+        let boundary = content_type
+            .strip_prefix("multipart/byteranges; boundary=")
+            .unwrap();
+        let multipart = body(&response);
+        assert_eq!(
+            response.headers.get("Content-Length"),
+            Some(multipart.len().to_string().as_str())
+        );
+        assert_eq!(
+            String::from_utf8(multipart).unwrap(),
+            format!(
+                "--{boundary}\r\nContent-Type: text/plain; charset=utf-8\r\n\
+                 Content-Range: bytes 0-1/10\r\n\r\n01\r\n\
+                 --{boundary}\r\nContent-Type: text/plain; charset=utf-8\r\n\
+                 Content-Range: bytes 8-9/10\r\n\r\n89\r\n--{boundary}--\r\n"
+            )
+        );
+
+        // A stale If-Range gets the whole file.
+        let stale = [("Range", "bytes=2-4"), ("If-Range", "\"stale\"")];
+        let response = fixture.get("/digits.txt", &stale);
+        assert_eq!(response.status, 200);
+        assert_eq!(body(&response), b"0123456789");
+    }
+
+    #[test]
+    fn precompressed_siblings() {
+        let mut fixture = Fixture::new("gzip");
+        fixture.files = fixture.files.clone().precompress(true);
+        let page = "<p>Lorem ipsum dolor sit amet.</p>\n".repeat(100);
+        fs::write(fixture.root.join("page.html"), &page).unwrap();
+        let gzip = [("Accept-Encoding", "gzip, deflate")];
+
+        let response = fixture.get("/page.html", &gzip);
+        assert_eq!(response.status, 200);
+        assert_eq!(response.headers.get("Content-Encoding"), Some("gzip"));
+        assert_eq!(response.headers.get("Transfer-Encoding"), None);
+        assert_eq!(response.headers.get("Vary"), Some("Accept-Encoding"));
+        assert_eq!(response.body, compress::gzip(page.as_bytes()));
+        let sibling = fixture.root.join("page.html.gz");
+        assert_eq!(fs::read(&sibling).unwrap(), response.body);
+
+        // The sibling is what gets served from now on, read as it's sent.
+        fs::write(&sibling, b"cached").unwrap();
+        let response = fixture.get("/page.html", &gzip);
+        assert_eq!(response.headers.get("Content-Length"), Some("6"));
+        assert!(response.body.is_empty());
+        assert_eq!(body(&response), b"cached");
+
+        let etag = response.headers.get("ETag").unwrap();
+        assert!(etag.ends_with("-gzip\""), "{etag}");
+        let revalidation = [("Accept-Encoding", "gzip"), ("If-None-Match", etag)];
+        assert_eq!(fixture.get("/page.html", &revalidation).status, 304);
+
+        // No gzip for clients that don't want it, for ranges and for files
+        // too small to bother.
+        let response = fixture.get("/page.html", &[]);
+        assert_eq!(response.headers.get("Content-Encoding"), None);
+        assert_eq!(response.headers.get("Vary"), Some("Accept-Encoding"));
+        assert_eq!(body(&response), page.as_bytes());
+        let response = fixture.get("/page.html", &[gzip[0], ("Range", "bytes=0-2")]);
+        assert_eq!(response.status, 206);
+        assert_eq!(body(&response), b"<p>");
+        let response = fixture.get("/index.html", &gzip);
+        assert_eq!(response.headers.get("Content-Encoding"), None);
+
+        // Files past the limit are only sent gzipped from a sibling made
+        // ahead of time.
+        fixture.files = fixture.files.clone().precompress_limit(1000);
+        fs::write(fixture.root.join("big.html"), &page).unwrap();
+        let response = fixture.get("/big.html", &gzip);
+        assert_eq!(response.headers.get("Content-Encoding"), None);
+        assert_eq!(body(&response), page.as_bytes());
+        assert!(!fixture.root.join("big.html.gz").exists());
+        fs::write(fixture.root.join("big.html.gz"), b"made ahead").unwrap();
+        let response = fixture.get("/big.html", &gzip);
+        assert_eq!(response.headers.get("Content-Encoding"), Some("gzip"));
+        assert_eq!(body(&response), b"made ahead");
+    }
+}
@@ -0,0 +1,114 @@
+use std::ops::RangeInclusive;
+
+/// More ranges than this and the `Range` header is ignored: nobody needs
+/// them, and each one costs a part header and a seek.
+const MAX_RANGES: usize = 16;
+
+/// The outcome of a `Range` header that could be parsed.
+#[derive(Debug, PartialEq, Eq)]
+pub(super) enum Ranges {
+    /// The byte ranges to send, in the order they were asked for.
+    Satisfiable(Vec<RangeInclusive<u64>>),
+    /// None of the ranges overlaps the file.
+    Unsatisfiable,
+}
+
+/// Parse a `Range` header for a file of `len` bytes (RFC 9110 section
+/// 14.2). Returns `None` if the header should be ignored, because it's
+/// malformed, uses another unit or asks for too many ranges.
+pub(super) fn parse_range(header: &str, len: u64) -> Option<Ranges> {
+    let (unit, specs) = header.split_once('=')?;
+    if !unit.trim().eq_ignore_ascii_case("bytes") {
+        return None;
+    }
+
+    let mut ranges = Vec::new();
+    let mut count = 0;
+
+    for spec in specs
+        .split(',')
+        .map(str::trim)
+        .filter(|spec| !spec.is_empty())
+    {
+        count += 1;
+        if count > MAX_RANGES {
+            return None;
+        }
+
+        let (first, last) = spec.split_once('-')?;
+        let number = |s: &str| -> Option<u64> {
+            if s.is_empty() || !s.bytes().all(|b| b.is_ascii_digit()) {
+                return None;
+            }
+            // Positions past the end of any file are as good as the end.
+            Some(s.parse().unwrap_or(u64::MAX))
+        };
+
+        let range = if first.is_empty() {
+            // A suffix: the last `n` bytes.
+            let n = number(last)?;
+            (n > 0 && len > 0).then(|| len.saturating_sub(n)..=len - 1)
+        } else {
+            let first = number(first)?;
+            let last = match last {
+                "" => u64::MAX,
+                last => number(last)?,
+            };
+            if last < first {
+                return None;
+            }
+            (first < len).then(|| first..=last.min(len - 1))
+        };
+        ranges.extend(range);
+    }
+
+    if count == 0 {
+        None
+    } else if ranges.is_empty() {
+        Some(Ranges::Unsatisfiable)
+    } else {
+        Some(Ranges::Satisfiable(ranges))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn byte_ranges() {
+        let ranges = |header| match parse_range(header, 10_000) {
+            Some(Ranges::Satisfiable(ranges)) => ranges,
+            other => panic!("{header}: {other:?}"),
+        };
+
+        assert_eq!(ranges("bytes=0-499"), [0..=499]);
+        assert_eq!(ranges("bytes=9500-"), [9500..=9999]);
+        assert_eq!(ranges("bytes=-500"), [9500..=9999]);
+        assert_eq!(ranges("bytes=-20000"), [0..=9999]);
+        assert_eq!(ranges("bytes=9000-20000"), [9000..=9999]);
+        assert_eq!(
+            ranges("bytes=0-0, 5000-5001 ,20000-,-1"),
+            [0..=0, 5000..=5001, 9999..=9999]
+        );
+    }
+
+    #[test]
+    fn unsatisfiable_and_ignored() {
+        assert_eq!(
+            parse_range("bytes=10000-", 10_000),
+            Some(Ranges::Unsatisfiable)
+        );
+        assert_eq!(parse_range("bytes=-0", 10_000), Some(Ranges::Unsatisfiable));
+        assert_eq!(parse_range("bytes=0-", 0), Some(Ranges::Unsatisfiable));
+
+        assert_eq!(parse_range("items=0-1", 10_000), None);
+        assert_eq!(parse_range("bytes=5-1", 10_000), None);
+        assert_eq!(parse_range("bytes=a-b", 10_000), None);
+        assert_eq!(parse_range("bytes=", 10_000), None);
+        assert_eq!(
+            parse_range(&format!("bytes={}", "0-0,".repeat(17)), 10_000),
+            None
+        );
+    }
+}
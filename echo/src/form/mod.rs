@@ -0,0 +1,261 @@
+//! Query strings, form posts and file uploads.
+//!
+//! ```
+//! use echo::Request;
+//!
+//! let mut request = Request::new("POST", "/search?q=rust+http&page=2");
+//! request.headers.insert("Content-Type", "application/x-www-form-urlencoded");
+//! request.body = b"tag=a&tag=b%26c".to_vec().into();
+//!
+//! let query = request.query_params();
+//! assert_eq!(query.get("q"), Some("rust http"));
+//! let form = request.form().unwrap();
+//! assert_eq!(form.get_all("tag").collect::<Vec<_>>(), ["a", "b&c"]);
+//! ```
+
+mod multipart;
+
+use std::{error, fmt, io};
+
+pub use multipart::{FilePart, FormData, Multipart};
+
+use crate::http::Response;
+
+/// Name-value pairs, in the order they were sent. A name may come more than
+/// once, as with checkboxes or `<select multiple>`.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Params {
+    pairs: Vec<(String, String)>,
+}
+
+impl Params {
+    pub fn new() -> Params {
+        Params::default()
+    }
+
+    /// The first value of `name`.
+    pub fn get(&self, name: &str) -> Option<&str> {
+        self.pairs
+            .iter()
+            .find(|(n, _)| n == name)
+            .map(|(_, v)| v.as_str())
+    }
+
+    /// Every value of `name`.
+    pub fn get_all<'a>(&'a self, name: &'a str) -> impl Iterator<Item = &'a str> + 'a {
+        self.pairs
+            .iter()
+            .filter(move |(n, _)| n == name)
+            .map(|(_, v)| v.as_str())
+    }
+
+    pub fn contains(&self, name: &str) -> bool {
+        self.get(name).is_some()
+    }
+
+    pub fn append(&mut self, name: impl Into<String>, value: impl Into<String>) {
+        self.pairs.push((name.into(), value.into()));
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.pairs.iter().map(|(n, v)| (n.as_str(), v.as_str()))
+    }
+
+    pub fn len(&self) -> usize {
+        self.pairs.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.pairs.is_empty()
+    }
+}
+
+impl<N: Into<String>, V: Into<String>> FromIterator<(N, V)> for Params {
+    fn from_iter<I: IntoIterator<Item = (N, V)>>(pairs: I) -> Params {
+        Params {
+            pairs: pairs
+                .into_iter()
+                .map(|(n, v)| (n.into(), v.into()))
+                .collect(),
+        }
+    }
+}
+
+/// Parse a query string or an `application/x-www-form-urlencoded` body,
+/// like `a=1&b=x+y`. As browsers do, `+` is a space, malformed escapes are
+/// kept as they are and invalid UTF-8 is replaced.
+pub fn parse_urlencoded(s: &str) -> Params {
+    s.split('&')
+        .filter(|pair| !pair.is_empty())
+        .map(|pair| {
+            let (name, value) = pair.split_once('=').unwrap_or((pair, ""));
+            (decode_component(name), decode_component(value))
+        })
+        .collect()
+}
+
+/// Decode a name or value of a query string or form.
+fn decode_component(s: &str) -> String {
+    let bytes = decode_bytes(s.as_bytes(), true);
+    String::from_utf8_lossy(&bytes).into_owned()
+}
+
+/// Decode `%XX` escapes, strictly, as in paths. Returns `None` for malformed
+/// escapes or if the result isn't UTF-8.
+pub fn percent_decode(s: &str) -> Option<String> {
+    let mut bytes = Vec::with_capacity(s.len());
+    let mut rest = s.as_bytes();
+
+    while let Some((&b, tail)) = rest.split_first() {
+        if b == b'%' {
+            bytes.push(hex_byte(tail)?);
+            rest = &tail[2..];
+        } else {
+            bytes.push(b);
+            rest = tail;
+        }
+    }
+
+    String::from_utf8(bytes).ok()
+}
+
+/// Decode `%XX` escapes, keeping malformed ones, and `+` as a space if
+/// `plus_is_space`.
+fn decode_bytes(s: &[u8], plus_is_space: bool) -> Vec<u8> {
+    let mut bytes = Vec::with_capacity(s.len());
+    let mut rest = s;
+
+    while let Some((&b, tail)) = rest.split_first() {
+        match (b, hex_byte(tail)) {
+            (b'%', Some(decoded)) => {
+                bytes.push(decoded);
+                rest = &tail[2..];
+                continue;
+            }
+            (b'+', _) if plus_is_space => bytes.push(b' '),
+            (b, _) => bytes.push(b),
+        }
+        rest = tail;
+    }
+    bytes
+}
+
+/// The byte of the two hex digits `s` starts with.
+fn hex_byte(s: &[u8]) -> Option<u8> {
+    let hex = s.get(..2)?;
+    let digit = |b: u8| (b as char).to_digit(16);
+    Some((digit(hex[0])? * 16 + digit(hex[1])?) as u8)
+}
+
+/// Why a form couldn't be read.
+#[derive(Debug)]
+pub enum Error {
+    /// The body isn't a form of the kind asked for.
+    UnsupportedMediaType,
+    /// The body is malformed.
+    Malformed(&'static str),
+    /// The form has more parts, or larger fields, than allowed.
+    TooLarge(&'static str),
+    /// Spilling a file to disk failed.
+    Io(io::Error),
+}
+
+impl Error {
+    /// The status code of the response to a request with this error.
+    pub fn status(&self) -> u16 {
+        match self {
+            Error::UnsupportedMediaType => 415,
+            Error::Malformed(_) => 400,
+            Error::TooLarge(_) => 413,
+            Error::Io(_) => 500,
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::UnsupportedMediaType => write!(f, "the body is not a form of the expected type"),
+            Error::Malformed(reason) => write!(f, "malformed form: {reason}"),
+            Error::TooLarge(reason) => write!(f, "form too large: {reason}"),
+            Error::Io(e) => write!(f, "failed to store an upload: {e}"),
+        }
+    }
+}
+
+impl error::Error for Error {}
+
+impl From<io::Error> for Error {
+    fn from(e: io::Error) -> Error {
+        Error::Io(e)
+    }
+}
+
+/// The response to a request whose form couldn't be read, for handlers to
+/// return with `.into()`. I/O errors are the server's, so their details stay
+/// out of it.
+impl From<Error> for Response {
+    fn from(e: Error) -> Response {
+        let status = e.status();
+        match e {
+            Error::Io(e) => {
+                eprintln!("Failed to read a form: {e}");
+                Response::text(status, "Internal Server Error\n")
+            }
+            e => Response::text(status, format!("{e}\n")),
+        }
+    }
+}
+
+/// Whether the `Content-Type` value is of `media_type`, whatever its
+/// parameters.
+pub(crate) fn is_media_type(content_type: &str, media_type: &str) -> bool {
+    let essence = content_type.split(';').next().unwrap_or_default();
+    essence.trim().eq_ignore_ascii_case(media_type)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::http::Request;
+
+    #[test]
+    fn urlencoded() {
+        let params = parse_urlencoded("a=1&b=x+y%21&a=2&&flag&e=%zz%4&u=%C3%A9%FF");
+        assert_eq!(params.get("a"), Some("1"));
+        assert_eq!(params.get_all("a").collect::<Vec<_>>(), ["1", "2"]);
+        assert_eq!(params.get("b"), Some("x y!"));
+        assert_eq!(params.get("flag"), Some(""));
+        assert_eq!(params.get("e"), Some("%zz%4"));
+        assert_eq!(params.get("u"), Some("é\u{fffd}"));
+        assert_eq!(params.len(), 6);
+        assert!(parse_urlencoded("").is_empty());
+    }
+
+    #[test]
+    fn strict_decoding() {
+        assert_eq!(percent_decode("a%20b+c").as_deref(), Some("a b+c"));
+        assert_eq!(percent_decode("%e2%82%ac"), Some("€".to_string()));
+        assert_eq!(percent_decode("%2"), None);
+        assert_eq!(percent_decode("%zz"), None);
+        assert_eq!(percent_decode("%ff"), None);
+    }
+
+    #[test]
+    fn request_forms() {
+        let mut request = Request::new("POST", "/?x=1");
+        request.body = b"name=a+b".to_vec().into();
+        assert!(matches!(request.form(), Err(Error::UnsupportedMediaType)));
+
+        request.headers.insert(
+            "Content-Type",
+            "Application/X-WWW-Form-Urlencoded; charset=UTF-8",
+        );
+        assert_eq!(request.form().unwrap().get("name"), Some("a b"));
+        assert_eq!(request.query_params().get("x"), Some("1"));
+        assert!(Request::new("GET", "/").query_params().is_empty());
+
+        let response = Response::from(Request::new("POST", "/").form().unwrap_err());
+        assert_eq!(response.status, 415);
+    }
+}
@@ -0,0 +1,595 @@
+//! `multipart/form-data` bodies (RFC 7578), as browsers send file uploads.
+
+use std::{
+    env,
+    fs::{self, File},
+    io::{self, Cursor, Read, Write},
+    path::{Path, PathBuf},
+    process,
+    sync::atomic::{AtomicU64, Ordering},
+};
+
+use super::{is_media_type, Error, Params};
+
+/// The most the header fields of a part may take.
+const MAX_HEAD: usize = 8 * 1024;
+
+/// How much to read from the body at a time.
+const READ_SIZE: usize = 8 * 1024;
+
+/// A parser for `multipart/form-data` bodies, read from any reader a piece
+/// at a time. Files are kept in memory while they're small, and spilled to
+/// temporary files once they grow larger than `spill_above` bytes.
+///
+/// The server reads a request's whole body before its handler runs, within
+/// `Limits::body`, so for uploads this only keeps the parsed files out of
+/// memory, not the body they came in; larger uploads are refused with 413
+/// before they're parsed.
+#[derive(Debug, Clone)]
+pub struct Multipart {
+    boundary: String,
+    spill_above: usize,
+    max_field_size: usize,
+    max_parts: usize,
+    temp_dir: PathBuf,
+}
+
+impl Multipart {
+    /// A parser for parts delimited by `boundary`.
+    pub fn new(boundary: impl Into<String>) -> Multipart {
+        Multipart {
+            boundary: boundary.into(),
+            spill_above: 64 * 1024,
+            max_field_size: 1024 * 1024,
+            max_parts: 100,
+            temp_dir: env::temp_dir(),
+        }
+    }
+
+    /// A parser for a body with the given `Content-Type`, or `None` if it
+    /// isn't `multipart/form-data` with a boundary.
+    pub fn from_content_type(content_type: &str) -> Option<Multipart> {
+        if !is_media_type(content_type, "multipart/form-data") {
+            return None;
+        }
+        let (_, params) = content_type.split_once(';')?;
+        let boundary = parse_params(params)
+            .into_iter()
+            .find(|(name, _)| name.eq_ignore_ascii_case("boundary"))
+            .map(|(_, value)| value)?;
+        // RFC 2046 section 5.1.1.
+        if boundary.is_empty() || boundary.len() > 70 {
+            return None;
+        }
+        Some(Multipart::new(boundary))
+    }
+
+    /// Keep files of up to `bytes` in memory, and spill larger ones to
+    /// temporary files. 64 KiB by default.
+    pub fn spill_above(mut self, bytes: usize) -> Multipart {
+        self.spill_above = bytes;
+        self
+    }
+
+    /// The largest value a field that isn't a file may have. 1 MiB by
+    /// default.
+    pub fn max_field_size(mut self, bytes: usize) -> Multipart {
+        self.max_field_size = bytes;
+        self
+    }
+
+    /// The most parts a body may have. 100 by default.
+    pub fn max_parts(mut self, parts: usize) -> Multipart {
+        self.max_parts = parts;
+        self
+    }
+
+    /// Where to spill files to. The system's temporary directory by default.
+    pub fn temp_dir(mut self, dir: impl Into<PathBuf>) -> Multipart {
+        self.temp_dir = dir.into();
+        self
+    }
+
+    /// Read the fields and files of a body.
+    ///
+    /// # Errors
+    ///
+    /// If the body is malformed or too large, or a file can't be spilled.
+    pub fn parse<R: Read>(&self, reader: R) -> Result<FormData, Error> {
+        let delimiter = format!("\r\n--{}", self.boundary).into_bytes();
+        let mut body = Body {
+            reader,
+            // As if the body started with a line break, so that the first
+            // boundary is a delimiter like the others.
+            buf: b"\r\n".to_vec(),
+        };
+
+        // Skip the preamble.
+        loop {
+            if let Some(i) = find(&body.buf, &delimiter) {
+                body.buf.drain(..i + delimiter.len());
+                break;
+            }
+            let keep = body.buf.len().saturating_sub(delimiter.len() - 1);
+            body.buf.drain(..keep);
+            if !body.fill()? {
+                return Err(Error::Malformed("no boundary in the body"));
+            }
+        }
+
+        let mut form = FormData::default();
+        let mut parts = 0;
+        loop {
+            // After a delimiter comes `--` if it was the last, or the end of
+            // the line, maybe after some white space.
+            while body.buf.len() < 2 {
+                if !body.fill()? {
+                    return Err(Error::Malformed("unexpected end of the body"));
+                }
+            }
+            if body.buf.starts_with(b"--") {
+                return Ok(form);
+            }
+            let line_end = loop {
+                if let Some(i) = find(&body.buf, b"\r\n") {
+                    break i;
+                }
+                if body.buf.len() > MAX_HEAD || !body.fill()? {
+                    return Err(Error::Malformed("malformed boundary"));
+                }
+            };
+            if !body.buf[..line_end]
+                .iter()
+                .all(|&b| b == b' ' || b == b'\t')
+            {
+                return Err(Error::Malformed("malformed boundary"));
+            }
+            body.buf.drain(..line_end + 2);
+
+            parts += 1;
+            if parts > self.max_parts {
+                return Err(Error::TooLarge("too many parts"));
+            }
+            let head = body.head()?;
+            let mut sink = match head.filename {
+                Some(_) => Sink::File {
+                    contents: Contents::Memory(Vec::new()),
+                    size: 0,
+                },
+                None => Sink::Field(Vec::new()),
+            };
+
+            // Everything up to the next delimiter is content. Whatever might
+            // be the start of one waits for more of the body.
+            loop {
+                if let Some(i) = find(&body.buf, &delimiter) {
+                    self.write(&mut sink, &body.buf[..i])?;
+                    body.buf.drain(..i + delimiter.len());
+                    break;
+                }
+                let ready = body.buf.len().saturating_sub(delimiter.len() - 1);
+                self.write(&mut sink, &body.buf[..ready])?;
+                body.buf.drain(..ready);
+                if !body.fill()? {
+                    return Err(Error::Malformed("unexpected end of the body"));
+                }
+            }
+
+            match (sink, head.filename) {
+                (Sink::Field(value), _) => form
+                    .fields
+                    .append(head.name, String::from_utf8_lossy(&value)),
+                (Sink::File { contents, size }, filename) => form.files.push(FilePart {
+                    name: head.name,
+                    filename: filename.unwrap_or_default(),
+                    content_type: head
+                        .content_type
+                        .unwrap_or_else(|| "application/octet-stream".to_string()),
+                    size,
+                    contents,
+                }),
+            }
+        }
+    }
+
+    fn write(&self, sink: &mut Sink, bytes: &[u8]) -> Result<(), Error> {
+        match sink {
+            Sink::Field(value) => {
+                if value.len() + bytes.len() > self.max_field_size {
+                    return Err(Error::TooLarge("a field is too large"));
+                }
+                value.extend_from_slice(bytes);
+            }
+            Sink::File { contents, size } => {
+                if let Contents::Memory(memory) = contents {
+                    if memory.len() + bytes.len() > self.spill_above {
+                        let mut temp = TempFile::create(&self.temp_dir)?;
+                        temp.file.write_all(memory)?;
+                        *contents = Contents::Spilled(temp);
+                    }
+                }
+                match contents {
+                    Contents::Memory(memory) => memory.extend_from_slice(bytes),
+                    Contents::Spilled(temp) => temp.file.write_all(bytes)?,
+                }
+                *size += bytes.len() as u64;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// The fields and files of a form.
+#[derive(Debug, Default)]
+pub struct FormData {
+    pub fields: Params,
+    pub files: Vec<FilePart>,
+}
+
+impl FormData {
+    /// The first file sent as the field called `name`.
+    pub fn file(&self, name: &str) -> Option<&FilePart> {
+        self.files.iter().find(|file| file.name == name)
+    }
+}
+
+/// A file sent with a form. If it was spilled to a temporary file, that's
+/// deleted along with the part, unless it's saved.
+#[derive(Debug)]
+pub struct FilePart {
+    /// The name of the form field.
+    pub name: String,
+    /// The name of the file on the client, which is up to the client: it
+    /// may be empty, or hold `..` and slashes.
+    pub filename: String,
+    /// `application/octet-stream` unless the client said otherwise.
+    pub content_type: String,
+    pub size: u64,
+    contents: Contents,
+}
+
+impl FilePart {
+    /// The temporary file the contents were spilled to, if they were too
+    /// large to keep in memory.
+    pub fn path(&self) -> Option<&Path> {
+        match &self.contents {
+            Contents::Memory(_) => None,
+            Contents::Spilled(temp) => temp.path.as_deref(),
+        }
+    }
+
+    /// Read the contents.
+    pub fn reader(&self) -> io::Result<Box<dyn Read + '_>> {
+        match &self.contents {
+            Contents::Memory(memory) => Ok(Box::new(Cursor::new(memory))),
+            Contents::Spilled(temp) => Ok(Box::new(File::open(temp.path())?)),
+        }
+    }
+
+    pub fn bytes(&self) -> io::Result<Vec<u8>> {
+        let mut bytes = Vec::new();
+        self.reader()?.read_to_end(&mut bytes)?;
+        Ok(bytes)
+    }
+
+    /// Store the contents at `path`, moving the temporary file there if
+    /// there's one.
+    pub fn save(self, path: impl AsRef<Path>) -> io::Result<()> {
+        let path = path.as_ref();
+        match self.contents {
+            Contents::Memory(memory) => fs::write(path, memory),
+            Contents::Spilled(mut temp) => {
+                temp.file.flush()?;
+                // Renaming fails across file systems, where copying is
+                // the only way.
+                if fs::rename(temp.path(), path).is_ok() {
+                    temp.path = None;
+                    return Ok(());
+                }
+                fs::copy(temp.path(), path).map(drop)
+            }
+        }
+    }
+}
+
+#[derive(Debug)]
+enum Contents {
+    Memory(Vec<u8>),
+    Spilled(TempFile),
+}
+
+/// Where the content of the part being read goes.
+enum Sink {
+    Field(Vec<u8>),
+    File { contents: Contents, size: u64 },
+}
+
+/// A file in the temporary directory, deleted when dropped.
+#[derive(Debug)]
+struct TempFile {
+    /// `None` once the file is moved elsewhere.
+    path: Option<PathBuf>,
+    file: File,
+}
+
+impl TempFile {
+    fn create(dir: &Path) -> io::Result<TempFile> {
+        static COUNT: AtomicU64 = AtomicU64::new(0);
+        loop {
+            let n = COUNT.fetch_add(1, Ordering::Relaxed);
+            let path = dir.join(format!("echo-upload-{}-{n}", process::id()));
+            match File::options().write(true).create_new(true).open(&path) {
+                Ok(file) => {
+                    return Ok(TempFile {
+                        path: Some(path),
+                        file,
+                    })
+                }
+                // Left over by an earlier process with the same id.
+                Err(e) if e.kind() == io::ErrorKind::AlreadyExists => {}
+                Err(e) => return Err(e),
+            }
+        }
+    }
+
+    fn path(&self) -> &Path {
+        self.path.as_deref().unwrap()
+    }
+}
+
+impl Drop for TempFile {
+    fn drop(&mut self) {
+        if let Some(path) = &self.path {
+            let _ = fs::remove_file(path);
+        }
+    }
+}
+
+/// The body being parsed, read as it's needed.
+struct Body<R> {
+    reader: R,
+    buf: Vec<u8>,
+}
+
+impl<R: Read> Body<R> {
+    /// Read more of the body. Returns `false` at its end.
+    fn fill(&mut self) -> Result<bool, Error> {
+        let mut chunk = [0; READ_SIZE];
+        loop {
+            match self.reader.read(&mut chunk) {
+                Ok(0) => return Ok(false),
+                Ok(n) => {
+                    self.buf.extend_from_slice(&chunk[..n]);
+                    return Ok(true);
+                }
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+                Err(e) => return Err(e.into()),
+            }
+        }
+    }
+
+    /// Read the header fields of a part.
+    fn head(&mut self) -> Result<PartHead, Error> {
+        let head_len = loop {
+            // A part may have no header fields at all.
+            if self.buf.starts_with(b"\r\n") {
+                break 0;
+            }
+            if let Some(i) = find(&self.buf, b"\r\n\r\n") {
+                break i + 2;
+            }
+            if self.buf.len() > MAX_HEAD {
+                return Err(Error::TooLarge("part headers are too large"));
+            }
+            if !self.fill()? {
+                return Err(Error::Malformed("unexpected end of the body"));
+            }
+        };
+        let head: Vec<u8> = self.buf.drain(..head_len + 2).collect();
+        let head = String::from_utf8_lossy(&head[..head_len]);
+
+        let mut disposition = None;
+        let mut content_type = None;
+        for line in head.split("\r\n").filter(|line| !line.is_empty()) {
+            let (name, value) = line
+                .split_once(':')
+                .ok_or(Error::Malformed("malformed part header"))?;
+            if name.trim().eq_ignore_ascii_case("Content-Disposition") {
+                disposition = Some(value.trim().to_string());
+            } else if name.trim().eq_ignore_ascii_case("Content-Type") {
+                content_type = Some(value.trim().to_string());
+            }
+        }
+
+        let disposition =
+            disposition.ok_or(Error::Malformed("a part has no Content-Disposition"))?;
+        let (kind, params) = disposition.split_once(';').unwrap_or((&disposition, ""));
+        if !kind.trim().eq_ignore_ascii_case("form-data") {
+            return Err(Error::Malformed("a part is not form-data"));
+        }
+        let params = parse_params(params);
+        let param = |wanted: &str| {
+            params
+                .iter()
+                .find(|(name, _)| name.eq_ignore_ascii_case(wanted))
+                .map(|(_, value)| value.clone())
+        };
+        Ok(PartHead {
+            name: param("name").ok_or(Error::Malformed("a part has no name"))?,
+            filename: param("filename"),
+            content_type,
+        })
+    }
+}
+
+struct PartHead {
+    name: String,
+    filename: Option<String>,
+    content_type: Option<String>,
+}
+
+/// Parse `; name=value; name="quoted \" value"` parameters.
+fn parse_params(s: &str) -> Vec<(String, String)> {
+    let mut params = Vec::new();
+    let mut chars = s.chars().peekable();
+    loop {
+        while chars
+            .next_if(|&c| c == ';' || c == ' ' || c == '\t')
+            .is_some()
+        {}
+        let name: String = chars.by_ref().take_while(|&c| c != '=').collect();
+        if name.is_empty() {
+            return params;
+        }
+
+        let mut value = String::new();
+        if chars.next_if_eq(&'"').is_some() {
+            while let Some(c) = chars.next() {
+                match c {
+                    '"' => break,
+                    '\\' => value.extend(chars.next()),
+                    c => value.push(c),
+                }
+            }
+            while chars.next_if(|&c| c != ';').is_some() {}
+        } else {
+            value = chars.by_ref().take_while(|&c| c != ';').collect();
+        }
+        params.push((name.trim().to_string(), value.trim_end().to_string()));
+    }
+}
+
+fn find(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    haystack
+        .windows(needle.len())
+        .position(|window| window == needle)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::TempDir;
+
+    const BODY: &[u8] = b"preamble\r\n\
+        --XyZ\r\n\
+        Content-Disposition: form-data; name=\"title\"\r\n\
+        \r\n\
+        Hello, world\r\n\
+        --XyZ\r\n\
+        Content-Disposition: form-data; name=\"title\"\r\n\
+        \r\n\
+        not --XyZ the end\r\n\
+        --XyZ  \r\n\
+        Content-Disposition: form-data; name=\"upload\"; filename=\"a \\\"b\\\"; c.txt\"\r\n\
+        Content-Type: text/plain\r\n\
+        \r\n\
+        0123456789abcdefghij\r\n\
+        --XyZ\r\n\
+        Content-Disposition: form-data; name=\"empty\"; filename=\"\"\r\n\
+        \r\n\
+        \r\n\
+        --XyZ--\r\n\
+        epilogue";
+
+    /// Hands out a few bytes at a time, to split boundaries across reads.
+    struct Trickle<'a>(&'a [u8]);
+
+    impl Read for Trickle<'_> {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            let n = buf.len().min(3).min(self.0.len());
+            buf[..n].copy_from_slice(&self.0[..n]);
+            self.0 = &self.0[n..];
+            Ok(n)
+        }
+    }
+
+    #[test]
+    fn fields_and_files() {
+        let multipart =
+            Multipart::from_content_type("multipart/form-data; boundary=\"XyZ\"").unwrap();
+        for form in [
+            multipart.parse(BODY).unwrap(),
+            multipart.parse(Trickle(BODY)).unwrap(),
+        ] {
+            let titles: Vec<_> = form.fields.get_all("title").collect();
+            assert_eq!(titles, ["Hello, world", "not --XyZ the end"]);
+
+            let upload = form.file("upload").unwrap();
+            assert_eq!(upload.filename, "a \"b\"; c.txt");
+            assert_eq!(upload.content_type, "text/plain");
+            assert_eq!(upload.size, 20);
+            assert_eq!(upload.path(), None);
+            assert_eq!(upload.bytes().unwrap(), b"0123456789abcdefghij");
+
+            let empty = form.file("empty").unwrap();
+            assert_eq!((empty.filename.as_str(), empty.size), ("", 0));
+            assert_eq!(empty.content_type, "application/octet-stream");
+        }
+    }
+
+    #[test]
+    fn spills_large_files() {
+        let dir = TempDir::new("multipart");
+        let multipart = Multipart::new("XyZ").spill_above(8).temp_dir(dir.path());
+
+        let form = multipart.parse(Trickle(BODY)).unwrap();
+        let FormData { mut files, .. } = form;
+        let upload = files.remove(0);
+        let spilled = upload.path().unwrap().to_path_buf();
+        assert!(spilled.starts_with(dir.path()));
+        assert_eq!(upload.bytes().unwrap(), b"0123456789abcdefghij");
+
+        // Saving moves the file, dropping the part deletes it.
+        let saved = dir.join("saved.txt");
+        upload.save(&saved).unwrap();
+        assert_eq!(fs::read(&saved).unwrap(), b"0123456789abcdefghij");
+        assert!(!spilled.exists());
+
+        let form = multipart.parse(BODY).unwrap();
+        let spilled = form.files[0].path().unwrap().to_path_buf();
+        assert!(spilled.exists());
+        drop(form);
+        assert!(!spilled.exists());
+    }
+
+    #[test]
+    fn rejects_bad_bodies() {
+        let error =
+            |multipart: Multipart, body: &[u8]| multipart.parse(body).unwrap_err().to_string();
+        let part = "--b\r\nContent-Disposition: form-data; name=\"a\"\r\n\r\n";
+
+        assert_eq!(
+            error(Multipart::new("b"), b"nothing"),
+            "malformed form: no boundary in the body"
+        );
+        assert_eq!(
+            error(Multipart::new("b"), part.as_bytes()),
+            "malformed form: unexpected end of the body"
+        );
+        assert_eq!(
+            error(
+                Multipart::new("b"),
+                b"--b\r\nContent-Type: text/plain\r\n\r\nx\r\n--b--"
+            ),
+            "malformed form: a part has no Content-Disposition"
+        );
+        assert_eq!(
+            error(
+                Multipart::new("b").max_field_size(3),
+                format!("{part}abcd\r\n--b--").as_bytes()
+            ),
+            "form too large: a field is too large"
+        );
+        assert_eq!(
+            error(
+                Multipart::new("b").max_parts(1),
+                format!("{part}\r\n{part}\r\n--b--").as_bytes()
+            ),
+            "form too large: too many parts"
+        );
+
+        assert!(Multipart::from_content_type("multipart/form-data").is_none());
+        assert!(Multipart::from_content_type("multipart/mixed; boundary=b").is_none());
+        assert!(Multipart::from_content_type("text/plain; boundary=b").is_none());
+    }
+}
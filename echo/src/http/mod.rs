@@ -0,0 +1,407 @@
+mod body;
+mod parse;
+mod stream;
+mod upgrade;
+
+use std::{
+    fmt,
+    io::{self, Read, Write},
+    net::IpAddr,
+};
+
+pub use body::Body;
+pub(crate) use parse::{
+    has_body, is_interim, parse_partial_response, parse_response_head, response_framing, Framing,
+    RequestParser,
+};
+pub use parse::{parse_request, parse_request_with_limits, parse_response, Error, Limits};
+pub use stream::BodyStream;
+pub(crate) use stream::Exact;
+pub use upgrade::{Upgrade, Upgraded};
+
+use crate::{
+    form::{self, is_media_type, parse_urlencoded, FormData, Multipart, Params},
+    json,
+};
+
+/// Decode a request path and resolve it the way a file system would: `.`
+/// and empty segments are dropped, so `//a/./b` is `/a/b`. Returns `None`
+/// for malformed escapes, for `..` segments, and for segments holding NUL or
+/// a backslash.
+///
+/// Anything deciding by path, like which prefix a request falls under,
+/// should look at this rather than the raw path, which spells the same
+/// resource in many ways.
+pub fn normalize_path(path: &str) -> Option<String> {
+    let decoded = form::percent_decode(path.strip_prefix('/')?)?;
+    let mut normalized = String::with_capacity(decoded.len() + 1);
+    for segment in decoded.split('/') {
+        match segment {
+            "" | "." => {}
+            ".." => return None,
+            s if s.contains(['\0', '\\']) => return None,
+            s => {
+                normalized.push('/');
+                normalized.push_str(s);
+            }
+        }
+    }
+    if normalized.is_empty() || decoded.ends_with('/') {
+        normalized.push('/');
+    }
+    Some(normalized)
+}
+
+/// The HTTP versions the server speaks.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Version {
+    Http10,
+    Http11,
+    Http2,
+}
+
+impl fmt::Display for Version {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Version::Http10 => write!(f, "HTTP/1.0"),
+            Version::Http11 => write!(f, "HTTP/1.1"),
+            Version::Http2 => write!(f, "HTTP/2.0"),
+        }
+    }
+}
+
+/// A list of header fields. Names are matched case-insensitively and keep
+/// the order they were added in.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Headers {
+    entries: Vec<(String, String)>,
+}
+
+impl Headers {
+    pub fn new() -> Headers {
+        Headers::default()
+    }
+
+    /// The value of the first field called `name`.
+    pub fn get(&self, name: &str) -> Option<&str> {
+        self.entries
+            .iter()
+            .find(|(n, _)| n.eq_ignore_ascii_case(name))
+            .map(|(_, v)| v.as_str())
+    }
+
+    /// The values of every field called `name`.
+    pub fn get_all<'a>(&'a self, name: &'a str) -> impl Iterator<Item = &'a str> + 'a {
+        self.entries
+            .iter()
+            .filter(move |(n, _)| n.eq_ignore_ascii_case(name))
+            .map(|(_, v)| v.as_str())
+    }
+
+    pub fn contains(&self, name: &str) -> bool {
+        self.get(name).is_some()
+    }
+
+    /// Whether one of the comma-separated values of `name` is `token`, as in
+    /// `Connection: keep-alive, Upgrade`.
+    pub fn has_token(&self, name: &str, token: &str) -> bool {
+        self.get_all(name)
+            .flat_map(|value| value.split(','))
+            .any(|t| t.trim().eq_ignore_ascii_case(token))
+    }
+
+    /// Set `name` to `value`, replacing any previous fields with that name.
+    pub fn insert(&mut self, name: impl Into<String>, value: impl Into<String>) {
+        let name = name.into();
+        self.remove(&name);
+        self.entries.push((name, value.into()));
+    }
+
+    /// Add a field, keeping any previous fields with the same name.
+    pub fn append(&mut self, name: impl Into<String>, value: impl Into<String>) {
+        self.entries.push((name.into(), value.into()));
+    }
+
+    pub fn remove(&mut self, name: &str) {
+        self.entries.retain(|(n, _)| !n.eq_ignore_ascii_case(name));
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.entries.iter().map(|(n, v)| (n.as_str(), v.as_str()))
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Request {
+    pub method: String,
+    /// The request target as sent by the client, e.g. `/search?q=rust`.
+    pub target: String,
+    pub version: Version,
+    pub headers: Headers,
+    pub body: Body,
+    /// The address of the client, filled in by the server.
+    pub peer: Option<IpAddr>,
+}
+
+impl Request {
+    pub fn new(method: impl Into<String>, target: impl Into<String>) -> Request {
+        Request {
+            method: method.into(),
+            target: target.into(),
+            version: Version::Http11,
+            headers: Headers::new(),
+            body: Body::default(),
+            peer: None,
+        }
+    }
+
+    /// The path part of the target, without the query string.
+    pub fn path(&self) -> &str {
+        match self.target.split_once('?') {
+            Some((path, _)) => path,
+            None => &self.target,
+        }
+    }
+
+    /// The path decoded and normalized, as `normalize_path` does.
+    pub fn normalized_path(&self) -> Option<String> {
+        normalize_path(self.path())
+    }
+
+    /// The query string, without the leading `?`.
+    pub fn query(&self) -> Option<&str> {
+        self.target.split_once('?').map(|(_, query)| query)
+    }
+
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers.get(name)
+    }
+
+    /// Whether the client wants to keep the connection open after the
+    /// response: the default in HTTP/1.1, opt-in in HTTP/1.0. HTTP/2
+    /// connections stay open for more streams.
+    pub fn keep_alive(&self) -> bool {
+        match self.version {
+            Version::Http2 => true,
+            Version::Http11 => !self.headers.has_token("Connection", "close"),
+            Version::Http10 => self.headers.has_token("Connection", "keep-alive"),
+        }
+    }
+
+    /// Parse the body as JSON.
+    ///
+    /// ```
+    /// use echo::{json::Value, Request, Response};
+    ///
+    /// fn handle(request: &Request) -> Response {
+    ///     let value = match request.json() {
+    ///         Ok(value) => value,
+    ///         // A 400 response saying where the JSON went wrong.
+    ///         Err(e) => return e.into(),
+    ///     };
+    ///     let name = value.get("name").and_then(Value::as_str).unwrap_or("you");
+    ///     let greeting: Value = [("greeting", format!("Hello, {name}!"))].into_iter().collect();
+    ///     Response::json(200, &greeting)
+    /// }
+    ///
+    /// let mut request = Request::new("POST", "/");
+    /// request.body = b"{\"name\": 1".to_vec().into();
+    /// assert_eq!(handle(&request).status, 400);
+    /// ```
+    pub fn json(&self) -> Result<json::Value, json::Error> {
+        json::from_slice(&self.body)
+    }
+
+    /// The parameters of the query string.
+    pub fn query_params(&self) -> Params {
+        self.query().map(parse_urlencoded).unwrap_or_default()
+    }
+
+    /// The fields of an `application/x-www-form-urlencoded` body.
+    pub fn form(&self) -> Result<Params, form::Error> {
+        match self.header("Content-Type") {
+            Some(content_type)
+                if is_media_type(content_type, "application/x-www-form-urlencoded") =>
+            {
+                Ok(parse_urlencoded(&String::from_utf8_lossy(&self.body)))
+            }
+            _ => Err(form::Error::UnsupportedMediaType),
+        }
+    }
+
+    /// The fields and files of a `multipart/form-data` body, with the
+    /// default limits of `Multipart`. The body is already in memory, so only
+    /// the copies of the files are spilled to disk.
+    pub fn multipart(&self) -> Result<FormData, form::Error> {
+        self.header("Content-Type")
+            .and_then(Multipart::from_content_type)
+            .ok_or(form::Error::UnsupportedMediaType)?
+            .parse(&self.body[..])
+    }
+
+    /// Write the request line, headers and body as they go on the wire.
+    pub fn write_to<W: Write>(&self, w: &mut W) -> io::Result<()> {
+        let mut head = format!("{} {} {}\r\n", self.method, self.target, self.version);
+        for (name, value) in self.headers.iter() {
+            head.push_str(&format!("{name}: {value}\r\n"));
+        }
+        head.push_str("\r\n");
+
+        w.write_all(head.as_bytes())?;
+        w.write_all(&self.body)
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(128 + self.body.len());
+        self.write_to(&mut bytes).unwrap();
+        bytes
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Response {
+    pub status: u16,
+    pub headers: Headers,
+    pub body: Vec<u8>,
+    /// More of the body, read while the response is sent.
+    pub stream: Option<BodyStream>,
+    /// What takes over the connection after a 101 response.
+    pub upgrade: Option<Upgrade>,
+}
+
+impl Response {
+    /// An empty response with the given status code.
+    pub fn new(status: u16) -> Response {
+        Response {
+            status,
+            headers: Headers::new(),
+            body: Vec::new(),
+            stream: None,
+            upgrade: None,
+        }
+    }
+
+    /// A response with a `text/html` body.
+    pub fn html(status: u16, body: impl Into<Vec<u8>>) -> Response {
+        Response::new(status)
+            .with_header("Content-Type", "text/html; charset=utf-8")
+            .with_body(body)
+    }
+
+    /// A response with a `text/plain` body.
+    pub fn text(status: u16, body: impl Into<Vec<u8>>) -> Response {
+        Response::new(status)
+            .with_header("Content-Type", "text/plain; charset=utf-8")
+            .with_body(body)
+    }
+
+    /// A response with an `application/json` body.
+    pub fn json(status: u16, value: &json::Value) -> Response {
+        Response::new(status)
+            .with_header("Content-Type", "application/json")
+            .with_body(value.to_string())
+    }
+
+    pub fn with_header(mut self, name: impl Into<String>, value: impl Into<String>) -> Response {
+        self.headers.insert(name, value);
+        self
+    }
+
+    pub fn with_body(mut self, body: impl Into<Vec<u8>>) -> Response {
+        self.body = body.into();
+        self
+    }
+
+    /// Send `reader`'s contents after the body, as they're read, in chunked
+    /// transfer coding (or up to the end of the connection for HTTP/1.0
+    /// clients). For bodies whose length isn't known up front, like the
+    /// output of a process; if it is, give it in `Content-Length`, which
+    /// then frames the body instead, and the reader must fill it.
+    pub fn with_stream(mut self, reader: impl Read + Send + 'static) -> Response {
+        self.stream = Some(BodyStream::new(reader));
+        self
+    }
+
+    /// Hand the connection to `on_upgrade` once the response is sent. Only
+    /// meaningful for a 101 response.
+    pub fn with_upgrade<F>(mut self, on_upgrade: F) -> Response
+    where
+        F: FnOnce(Upgraded) + Send + 'static,
+    {
+        self.upgrade = Some(Upgrade::new(on_upgrade));
+        self
+    }
+
+    /// The status line, e.g. `HTTP/1.1 404 Not Found`.
+    pub fn status_line(&self) -> String {
+        format!("HTTP/1.1 {} {}", self.status, reason(self.status))
+    }
+
+    /// Write the status line, headers and body as they go on the wire.
+    pub fn write_to<W: Write>(&self, w: &mut W) -> io::Result<()> {
+        let mut head = self.status_line();
+        head.push_str("\r\n");
+        for (name, value) in self.headers.iter() {
+            head.push_str(&format!("{name}: {value}\r\n"));
+        }
+        head.push_str("\r\n");
+
+        w.write_all(head.as_bytes())?;
+        w.write_all(&self.body)
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(128 + self.body.len());
+        self.write_to(&mut bytes).unwrap();
+        bytes
+    }
+}
+
+/// The reason phrase for a status code.
+pub fn reason(status: u16) -> &'static str {
+    match status {
+        100 => "Continue",
+        101 => "Switching Protocols",
+        200 => "OK",
+        201 => "Created",
+        202 => "Accepted",
+        204 => "No Content",
+        206 => "Partial Content",
+        301 => "Moved Permanently",
+        302 => "Found",
+        303 => "See Other",
+        304 => "Not Modified",
+        307 => "Temporary Redirect",
+        308 => "Permanent Redirect",
+        400 => "Bad Request",
+        401 => "Unauthorized",
+        403 => "Forbidden",
+        404 => "Not Found",
+        405 => "Method Not Allowed",
+        408 => "Request Timeout",
+        411 => "Length Required",
+        413 => "Content Too Large",
+        414 => "URI Too Long",
+        415 => "Unsupported Media Type",
+        416 => "Range Not Satisfiable",
+        421 => "Misdirected Request",
+        426 => "Upgrade Required",
+        429 => "Too Many Requests",
+        431 => "Request Header Fields Too Large",
+        500 => "Internal Server Error",
+        501 => "Not Implemented",
+        502 => "Bad Gateway",
+        503 => "Service Unavailable",
+        504 => "Gateway Timeout",
+        505 => "HTTP Version Not Supported",
+        _ => "Unknown",
+    }
+}
@@ -0,0 +1,697 @@
+use std::{error, fmt, str};
+
+use super::{Headers, Request, Response, Version};
+
+/// Why a request or a response couldn't be parsed.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Error {
+    /// The request is malformed.
+    BadRequest(&'static str),
+    /// The request is neither HTTP/1.0 nor HTTP/1.1.
+    VersionNotSupported,
+    /// A response from another server is malformed.
+    BadResponse(&'static str),
+    /// The request line is longer than the limit.
+    UriTooLong,
+    /// The request head is larger than the limit.
+    HeadersTooLarge,
+    /// The request body is larger than the limit.
+    PayloadTooLarge,
+}
+
+impl Error {
+    /// The status code to answer with.
+    pub fn status(&self) -> u16 {
+        match self {
+            Error::BadRequest(_) => 400,
+            Error::VersionNotSupported => 505,
+            Error::BadResponse(_) => 502,
+            Error::UriTooLong => 414,
+            Error::HeadersTooLarge => 431,
+            Error::PayloadTooLarge => 413,
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::BadRequest(reason) => write!(f, "bad request: {reason}"),
+            Error::VersionNotSupported => write!(f, "HTTP version not supported"),
+            Error::BadResponse(reason) => write!(f, "bad response: {reason}"),
+            Error::UriTooLong => write!(f, "request line too long"),
+            Error::HeadersTooLarge => write!(f, "request header fields too large"),
+            Error::PayloadTooLarge => write!(f, "request body too large"),
+        }
+    }
+}
+
+impl error::Error for Error {}
+
+/// The largest requests the parser accepts, so that a client can't make the
+/// server buffer as much as it likes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Limits {
+    /// The request line, e.g. `GET /index.html HTTP/1.1`.
+    pub request_line: usize,
+    /// The request line and the header fields together.
+    pub head: usize,
+    /// The body, once decoded.
+    pub body: usize,
+}
+
+impl Default for Limits {
+    fn default() -> Limits {
+        Limits {
+            request_line: 8 * 1024,
+            head: 64 * 1024,
+            body: 10 * 1024 * 1024,
+        }
+    }
+}
+
+/// Parse a request from the start of `buf`, within the default limits.
+///
+/// Returns `Ok(None)` if `buf` doesn't hold a complete request yet, or the
+/// request along with the number of bytes it took up. Whatever follows is
+/// the start of the next, pipelined, request.
+pub fn parse_request(buf: &[u8]) -> Result<Option<(Request, usize)>, Error> {
+    parse_request_with_limits(buf, &Limits::default())
+}
+
+/// Parse a request like `parse_request`, failing as soon as it's bound to
+/// exceed `limits`, before the rest of it arrives.
+pub fn parse_request_with_limits(
+    buf: &[u8],
+    limits: &Limits,
+) -> Result<Option<(Request, usize)>, Error> {
+    RequestParser::default().parse(buf, limits)
+}
+
+/// A request being parsed as it comes in. Each call picks up where the last
+/// one left off, rather than going over the head, or the chunks decoded so
+/// far, again.
+#[derive(Debug, Default)]
+pub(crate) struct RequestParser {
+    /// How far the search for the end of the head got.
+    scanned: usize,
+    /// The parsed head, once it's complete, and where the body starts.
+    head: Option<(Request, usize, Framing)>,
+    chunks: ChunkDecoder,
+}
+
+impl RequestParser {
+    /// Parse the request at the start of `buf` like
+    /// `parse_request_with_limits`. `buf` holds what the last call got, and
+    /// what came in since. Once it returns a request, or an error, the
+    /// parser starts over with the next one.
+    pub(crate) fn parse(
+        &mut self,
+        buf: &[u8],
+        limits: &Limits,
+    ) -> Result<Option<(Request, usize)>, Error> {
+        let result = self.resume(buf, limits);
+        if !matches!(result, Ok(None)) {
+            *self = RequestParser::default();
+        }
+        result
+    }
+
+    /// Whether the head of the request is complete, if not its body.
+    pub(crate) fn has_head(&self) -> bool {
+        self.head.is_some()
+    }
+
+    fn resume(&mut self, buf: &[u8], limits: &Limits) -> Result<Option<(Request, usize)>, Error> {
+        if self.head.is_none() {
+            let Some(head_end) = self.find_head(buf, limits)? else {
+                return Ok(None);
+            };
+            // Empty lines before the request line are ignored (RFC 9112
+            // section 2.2).
+            let start = skip_empty_lines(buf);
+            let head = str::from_utf8(&buf[start..head_end])
+                .map_err(|_| Error::BadRequest("request head is not valid UTF-8"))?;
+            let mut lines = head.lines();
+
+            let request_line = lines.next().unwrap_or_default();
+            let mut request = parse_request_line(request_line)?;
+            request.headers = parse_headers(lines)?;
+            let framing = body_framing(&request.headers)?;
+            if let Framing::Length(length) = framing {
+                if length > limits.body {
+                    return Err(Error::PayloadTooLarge);
+                }
+            }
+            self.head = Some((request, head_end, framing));
+        }
+        let (_, head_end, framing) = self.head.as_ref().expect("the head is parsed");
+        let head_end = *head_end;
+
+        let rest = &buf[head_end..];
+        let (body, body_len) = match framing {
+            Framing::Chunked => match self.chunks.decode(rest, limits.body)? {
+                Some(decoded) => decoded,
+                // The chunk framing may take up as much as a head on top of
+                // the body, but no more.
+                None if rest.len() > limits.body.saturating_add(limits.head) => {
+                    return Err(Error::PayloadTooLarge)
+                }
+                None => return Ok(None),
+            },
+            &Framing::Length(length) => match rest.get(..length) {
+                Some(body) => (body.to_vec(), length),
+                None => return Ok(None),
+            },
+        };
+        let (mut request, _, _) = self.head.take().expect("the head is parsed");
+        request.body = body.into();
+
+        Ok(Some((request, head_end + body_len)))
+    }
+
+    /// Where the head ends, once it's complete, checking it against the
+    /// limits until then.
+    fn find_head(&mut self, buf: &[u8], limits: &Limits) -> Result<Option<usize>, Error> {
+        let start = skip_empty_lines(buf);
+        // The end of the head is up to three bytes long, so the search
+        // resumes a little before where it stopped.
+        let from = self.scanned.saturating_sub(2).max(start);
+        let head_end = find_head_end(&buf[from..]).map(|len| from + len);
+        self.scanned = buf.len();
+
+        let head = &buf[..head_end.unwrap_or(buf.len())];
+        let line = &head[start..];
+        let line_len = line
+            .iter()
+            .take(limits.request_line.saturating_add(1))
+            .position(|&b| b == b'\n')
+            .unwrap_or(line.len());
+        if line_len > limits.request_line {
+            return Err(Error::UriTooLong);
+        }
+        if head.len() > limits.head {
+            return Err(Error::HeadersTooLarge);
+        }
+        Ok(head_end)
+    }
+}
+
+/// Where the request starts, after any empty lines.
+fn skip_empty_lines(buf: &[u8]) -> usize {
+    buf.iter()
+        .position(|&b| b != b'\r' && b != b'\n')
+        .unwrap_or(buf.len())
+}
+
+/// Parse a complete response from another server, read up to the end of the
+/// connection. `method` is that of the request it answers, as responses to
+/// `HEAD` have no body. Interim 1xx responses are skipped.
+pub fn parse_response(buf: &[u8], method: &str) -> Result<Response, Error> {
+    match parse_partial_response(buf, method, true)? {
+        Some((response, _)) => Ok(response),
+        None => Err(Error::BadResponse("truncated body")),
+    }
+}
+
+/// Parse the response at the start of `buf`, returning it and its length,
+/// or `None` if more of it is still to come. A response without a length
+/// ends with the connection, so it's only complete once `at_eof`.
+pub(crate) fn parse_partial_response(
+    buf: &[u8],
+    method: &str,
+    at_eof: bool,
+) -> Result<Option<(Response, usize)>, Error> {
+    let Some(head_len) = find_head_end(buf) else {
+        if at_eof {
+            return Err(Error::BadResponse("incomplete head"));
+        }
+        return Ok(None);
+    };
+    let mut response = parse_response_head(&buf[..head_len])?;
+    if is_interim(response.status) {
+        let rest = parse_partial_response(&buf[head_len..], method, at_eof)?;
+        return Ok(rest.map(|(response, len)| (response, head_len + len)));
+    }
+    if !has_body(&response, method) {
+        return Ok(Some((response, head_len)));
+    }
+
+    let rest = &buf[head_len..];
+    let (body, body_len) = match response_framing(&response.headers)? {
+        Some(framing) => {
+            let body = match framing {
+                Framing::Chunked => decode_chunked(rest, usize::MAX).map_err(as_response)?,
+                Framing::Length(length) => rest.get(..length).map(|body| (body.to_vec(), length)),
+            };
+            match (body, at_eof) {
+                (Some(body), _) => body,
+                (None, true) => return Err(Error::BadResponse("truncated body")),
+                (None, false) => return Ok(None),
+            }
+        }
+        // Delimited by the end of the connection.
+        None if at_eof => (rest.to_vec(), rest.len()),
+        None => return Ok(None),
+    };
+    response.body = body;
+
+    Ok(Some((response, head_len + body_len)))
+}
+
+/// Parse a response's head, from the status line up to the empty line
+/// that ends it, into a response without a body.
+pub(crate) fn parse_response_head(head: &[u8]) -> Result<Response, Error> {
+    let head =
+        str::from_utf8(head).map_err(|_| Error::BadResponse("response head is not valid UTF-8"))?;
+    let mut lines = head.lines();
+
+    let status_line = lines.next().unwrap_or_default();
+    let mut parts = status_line.splitn(3, ' ');
+    let status = match (parts.next(), parts.next()) {
+        (Some(version), Some(status))
+            if version.starts_with("HTTP/1.")
+                && status.len() == 3
+                && status.bytes().all(|b| b.is_ascii_digit()) =>
+        {
+            status.parse::<u16>().unwrap()
+        }
+        _ => return Err(Error::BadResponse("malformed status line")),
+    };
+
+    let mut response = Response::new(status);
+    response.headers = parse_headers(lines).map_err(as_response)?;
+    Ok(response)
+}
+
+/// Whether a response is an interim one, which the final response follows.
+pub(crate) fn is_interim(status: u16) -> bool {
+    (100..200).contains(&status) && status != 101
+}
+
+/// Whether a response to a `method` request has a body.
+pub(crate) fn has_body(response: &Response, method: &str) -> bool {
+    method != "HEAD" && response.status >= 200 && response.status != 204 && response.status != 304
+}
+
+/// How a response's body is delimited, or `None` if it ends with the
+/// connection.
+pub(crate) fn response_framing(headers: &Headers) -> Result<Option<Framing>, Error> {
+    if !headers.contains("Transfer-Encoding") && !headers.contains("Content-Length") {
+        return Ok(None);
+    }
+    body_framing(headers).map(Some).map_err(as_response)
+}
+
+fn as_response(e: Error) -> Error {
+    match e {
+        Error::BadRequest(reason) => Error::BadResponse(reason),
+        e => e,
+    }
+}
+
+/// The length of the head, including the empty line that ends it.
+fn find_head_end(buf: &[u8]) -> Option<usize> {
+    buf.iter().enumerate().find_map(|(i, &b)| {
+        if b != b'\n' {
+            return None;
+        }
+        let rest = &buf[i + 1..];
+        if rest.starts_with(b"\r\n") {
+            Some(i + 3)
+        } else if rest.starts_with(b"\n") {
+            Some(i + 2)
+        } else {
+            None
+        }
+    })
+}
+
+fn parse_request_line(line: &str) -> Result<Request, Error> {
+    let mut parts = line.split(' ');
+    let (Some(method), Some(target), Some(version), None) =
+        (parts.next(), parts.next(), parts.next(), parts.next())
+    else {
+        return Err(Error::BadRequest("malformed request line"));
+    };
+
+    if !is_token(method) {
+        return Err(Error::BadRequest("invalid method"));
+    }
+    if target.is_empty() || target.bytes().any(|b| b.is_ascii_control()) {
+        return Err(Error::BadRequest("invalid request target"));
+    }
+
+    let version = match version {
+        "HTTP/1.1" => Version::Http11,
+        "HTTP/1.0" => Version::Http10,
+        v if v.starts_with("HTTP/") => return Err(Error::VersionNotSupported),
+        _ => return Err(Error::BadRequest("malformed request line")),
+    };
+
+    let mut request = Request::new(method, target);
+    request.version = version;
+    Ok(request)
+}
+
+fn parse_headers<'a>(lines: impl Iterator<Item = &'a str>) -> Result<Headers, Error> {
+    let mut headers = Headers::new();
+
+    for line in lines.take_while(|line| !line.is_empty()) {
+        if line.starts_with([' ', '\t']) {
+            return Err(Error::BadRequest("obsolete line folding"));
+        }
+        let Some((name, value)) = line.split_once(':') else {
+            return Err(Error::BadRequest("malformed header field"));
+        };
+        if !is_token(name) {
+            return Err(Error::BadRequest("invalid header name"));
+        }
+        headers.append(name, value.trim_matches([' ', '\t']));
+    }
+
+    Ok(headers)
+}
+
+#[derive(Debug)]
+pub(crate) enum Framing {
+    Chunked,
+    Length(usize),
+}
+
+/// How the body is delimited, from `Transfer-Encoding` and `Content-Length`.
+fn body_framing(headers: &Headers) -> Result<Framing, Error> {
+    if headers.contains("Transfer-Encoding") {
+        if headers.contains("Content-Length") {
+            return Err(Error::BadRequest(
+                "both Transfer-Encoding and Content-Length",
+            ));
+        }
+        let last = headers
+            .get_all("Transfer-Encoding")
+            .flat_map(|value| value.split(','))
+            .last()
+            .unwrap_or_default();
+        if !last.trim().eq_ignore_ascii_case("chunked") {
+            return Err(Error::BadRequest("unsupported transfer coding"));
+        }
+        return Ok(Framing::Chunked);
+    }
+
+    let mut length = None;
+    for value in headers.get_all("Content-Length") {
+        let n = parse_content_length(value)?;
+        if length.is_some_and(|l| l != n) {
+            return Err(Error::BadRequest("conflicting Content-Length"));
+        }
+        length = Some(n);
+    }
+    Ok(Framing::Length(length.unwrap_or(0)))
+}
+
+fn parse_content_length(value: &str) -> Result<usize, Error> {
+    if value.is_empty() || !value.bytes().all(|b| b.is_ascii_digit()) {
+        return Err(Error::BadRequest("invalid Content-Length"));
+    }
+    value
+        .parse()
+        .map_err(|_| Error::BadRequest("invalid Content-Length"))
+}
+
+/// Decode a chunked body of at most `max_len` bytes from the start of `buf`,
+/// returning the decoded body and the number of bytes it took up, or `None`
+/// if it isn't complete yet. Trailer fields are skipped.
+fn decode_chunked(buf: &[u8], max_len: usize) -> Result<Option<(Vec<u8>, usize)>, Error> {
+    ChunkDecoder::default().decode(buf, max_len)
+}
+
+/// A chunked body being decoded as it comes in, which keeps its place
+/// between calls.
+#[derive(Debug, Default)]
+struct ChunkDecoder {
+    body: Vec<u8>,
+    /// How much of the input is decoded.
+    pos: usize,
+    /// How far the search for the end of the current line got.
+    scanned: usize,
+    /// The size of the chunk whose data comes next.
+    chunk: Option<usize>,
+    /// The last chunk is in, the trailer fields are being skipped.
+    trailer: bool,
+}
+
+impl ChunkDecoder {
+    /// Decode what more of the body `buf` holds; `buf` starts where the body
+    /// does, and holds what the last call got and more. Returns the body and
+    /// the number of bytes it took up once it's complete.
+    fn decode(&mut self, buf: &[u8], max_len: usize) -> Result<Option<(Vec<u8>, usize)>, Error> {
+        loop {
+            if let Some(size) = self.chunk {
+                let Some(chunk) = buf.get(self.pos..self.pos.saturating_add(size)) else {
+                    return Ok(None);
+                };
+                let end = self.pos + size;
+                let line_break = match &buf[end..] {
+                    [b'\r', b'\n', ..] => 2,
+                    [b'\n', ..] => 1,
+                    [] | [b'\r'] => return Ok(None),
+                    _ => return Err(Error::BadRequest("missing CRLF after chunk")),
+                };
+                self.body.extend_from_slice(chunk);
+                self.pos = end + line_break;
+                self.chunk = None;
+            }
+
+            let Some(line) = self.next_line(buf) else {
+                return Ok(None);
+            };
+            if self.trailer {
+                if line.is_empty() || line == b"\r" {
+                    return Ok(Some((std::mem::take(&mut self.body), self.pos)));
+                }
+                continue;
+            }
+
+            let line = str::from_utf8(line).map_err(|_| Error::BadRequest("invalid chunk size"))?;
+            let size = line
+                .trim_end_matches('\r')
+                .split(';')
+                .next()
+                .unwrap_or_default();
+            let size = usize::from_str_radix(size.trim(), 16)
+                .map_err(|_| Error::BadRequest("invalid chunk size"))?;
+            if size > max_len - self.body.len() {
+                return Err(Error::PayloadTooLarge);
+            }
+            match size {
+                0 => self.trailer = true,
+                size => self.chunk = Some(size),
+            }
+        }
+    }
+
+    /// The next line, without its `\n`, once it's complete.
+    fn next_line<'a>(&mut self, buf: &'a [u8]) -> Option<&'a [u8]> {
+        let from = self.scanned.max(self.pos);
+        let Some(len) = buf[from..].iter().position(|&b| b == b'\n') else {
+            self.scanned = buf.len();
+            return None;
+        };
+        let line = &buf[self.pos..from + len];
+        self.pos = from + len + 1;
+        Some(line)
+    }
+}
+
+/// Whether `s` is a non-empty token (RFC 9110 section 5.6.2).
+fn is_token(s: &str) -> bool {
+    !s.is_empty()
+        && s.bytes()
+            .all(|b| b.is_ascii_alphanumeric() || b"!#$%&'*+-.^_`|~".contains(&b))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn simple_get() {
+        let buf = b"GET /sleep?for=5 HTTP/1.1\r\nHost: localhost\r\nAccept: */*\r\n\r\n";
+        let (request, len) = parse_request(buf).unwrap().unwrap();
+
+        assert_eq!(len, buf.len());
+        assert_eq!(request.method, "GET");
+        assert_eq!(request.path(), "/sleep");
+        assert_eq!(request.query(), Some("for=5"));
+        assert_eq!(request.version, Version::Http11);
+        assert_eq!(request.header("host"), Some("localhost"));
+        assert!(request.keep_alive());
+    }
+
+    #[test]
+    fn incomplete_requests() {
+        assert_eq!(parse_request(b"GET / HTTP/1.1\r\nHost: x\r\n"), Ok(None));
+
+        let post = b"POST / HTTP/1.1\r\nContent-Length: 10\r\n\r\nhello";
+        assert_eq!(parse_request(post), Ok(None));
+    }
+
+    #[test]
+    fn pipelined_requests() {
+        let buf = b"POST /a HTTP/1.1\r\nContent-Length: 5\r\n\r\nhelloGET /b HTTP/1.0\n\n";
+
+        let (first, len) = parse_request(buf).unwrap().unwrap();
+        assert_eq!(first.body, b"hello");
+
+        let (second, rest) = parse_request(&buf[len..]).unwrap().unwrap();
+        assert_eq!(second.target, "/b");
+        assert_eq!(second.version, Version::Http10);
+        assert!(!second.keep_alive());
+        assert_eq!(len + rest, buf.len());
+    }
+
+    #[test]
+    fn chunked_body() {
+        let buf = b"POST / HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\n\
+                    5\r\nhello\r\n7;ext=1\r\n, world\r\n0\r\nTrailer: x\r\n\r\n";
+        let (request, len) = parse_request(buf).unwrap().unwrap();
+
+        assert_eq!(request.body, b"hello, world");
+        assert_eq!(len, buf.len());
+        assert_eq!(parse_request(&buf[..buf.len() - 2]), Ok(None));
+    }
+
+    #[test]
+    fn responses() {
+        let response = parse_response(
+            b"HTTP/1.1 100 Continue\r\n\r\n\
+              HTTP/1.1 404 Not Found\r\nContent-Length: 5\r\n\r\nhello, extra",
+            "GET",
+        )
+        .unwrap();
+        assert_eq!(response.status, 404);
+        assert_eq!(response.body, b"hello");
+
+        let chunked =
+            b"HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\r\n5\r\nhello\r\n0\r\n\r\n";
+        assert_eq!(parse_response(chunked, "GET").unwrap().body, b"hello");
+        assert_eq!(parse_response(chunked, "HEAD").unwrap().body, b"");
+
+        let until_close = b"HTTP/1.0 200 OK\r\n\r\nall of it";
+        assert_eq!(
+            parse_response(until_close, "GET").unwrap().body,
+            b"all of it"
+        );
+
+        let bad = |buf: &[u8]| parse_response(buf, "GET").unwrap_err().status();
+        assert_eq!(bad(b"HTTP/1.1 200 OK\r\nContent-Length: 5\r\n\r\nhi"), 502);
+        assert_eq!(bad(b"HTTP/1.1 200 OK\r\n"), 502);
+        assert_eq!(bad(b"SMTP 200 OK\r\n\r\n"), 502);
+    }
+
+    #[test]
+    fn partial_responses() {
+        let buf = b"HTTP/1.1 200 OK\r\nContent-Length: 2\r\n\r\nhiHTTP/1.1 204 No Content\r\n\r\n";
+        let (first, len) = parse_partial_response(buf, "GET", false).unwrap().unwrap();
+        assert_eq!(first.body, b"hi");
+        let (second, rest) = parse_partial_response(&buf[len..], "GET", false)
+            .unwrap()
+            .unwrap();
+        assert_eq!(second.status, 204);
+        assert_eq!(len + rest, buf.len());
+
+        for end in [10, len - 1] {
+            assert!(parse_partial_response(&buf[..end], "GET", false)
+                .unwrap()
+                .is_none());
+        }
+        let until_close = b"HTTP/1.0 200 OK\r\n\r\nsome";
+        assert!(parse_partial_response(until_close, "GET", false)
+            .unwrap()
+            .is_none());
+        let (response, _) = parse_partial_response(until_close, "GET", true)
+            .unwrap()
+            .unwrap();
+        assert_eq!(response.body, b"some");
+    }
+
+    #[test]
+    fn malformed_requests() {
+        let bad = |buf: &[u8]| parse_request(buf).unwrap_err().status();
+
+        assert_eq!(bad(b"GET /\r\n\r\n"), 400);
+        assert_eq!(bad(b"GET / HTTP/2.0\r\n\r\n"), 505);
+        assert_eq!(bad(b"GET / HTTP/1.1\r\nNo colon\r\n\r\n"), 400);
+        assert_eq!(bad(b"GET / HTTP/1.1\r\nA: b\r\n folded\r\n\r\n"), 400);
+        assert_eq!(bad(b"POST / HTTP/1.1\r\nContent-Length: -1\r\n\r\n"), 400);
+    }
+
+    #[test]
+    fn limits() {
+        let limits = Limits {
+            request_line: 32,
+            head: 64,
+            body: 8,
+        };
+        let parse = |buf: &[u8]| parse_request_with_limits(buf, &limits);
+
+        assert!(parse(b"GET /short HTTP/1.1\r\nHost: a\r\n\r\n").is_ok());
+        // Limits apply before the head is complete.
+        assert_eq!(parse(&[b'a'; 33]), Err(Error::UriTooLong));
+        assert_eq!(
+            parse(b"GET /a-rather-long-path-to-somewhere HTTP/1.1\r\n\r\n"),
+            Err(Error::UriTooLong)
+        );
+        let mut big_head = b"GET / HTTP/1.1\r\n".to_vec();
+        big_head.extend_from_slice(&[&b"X-Header: 0123456789\r\n"[..]; 3].concat());
+        assert_eq!(parse(&big_head), Err(Error::HeadersTooLarge));
+
+        // A body announced too large is refused before it's sent.
+        assert_eq!(
+            parse(b"POST / HTTP/1.1\r\nContent-Length: 9\r\n\r\n"),
+            Err(Error::PayloadTooLarge)
+        );
+        assert!(
+            parse(b"POST / HTTP/1.1\r\nContent-Length: 8\r\n\r\n12345678")
+                .unwrap()
+                .is_some()
+        );
+        let chunked = b"POST / HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\n";
+        assert_eq!(
+            parse(&[&chunked[..], b"5\r\n12345\r\n4\r\n"].concat()),
+            Err(Error::PayloadTooLarge)
+        );
+        assert_eq!(
+            parse(&[&chunked[..], &[b'0'; 100]].concat()),
+            Err(Error::PayloadTooLarge)
+        );
+        assert_eq!(parse(&[&chunked[..], b"5\r\n12345\r\n"].concat()), Ok(None));
+    }
+
+    #[test]
+    fn resumes_parsing() {
+        let buf = b"\r\nPOST / HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\n\
+                    5\r\nhello\r\n7;ext=1\r\n, world\r\n0\r\nTrailer: x\r\n\r\nGET /next";
+        let limits = Limits::default();
+
+        // Fed a byte at a time, it gets the same request as all at once.
+        let mut parser = RequestParser::default();
+        let mut parsed = None;
+        for end in 1..=buf.len() {
+            if let Some(request) = parser.parse(&buf[..end], &limits).unwrap() {
+                parsed = Some((request, end));
+                break;
+            }
+            let head_end = b"\r\nPOST / HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\n".len();
+            assert_eq!(parser.has_head(), end >= head_end, "{end}");
+        }
+        let ((request, len), end) = parsed.unwrap();
+        assert_eq!(request.body, b"hello, world");
+        assert_eq!(len, end);
+        assert_eq!(&buf[len..], b"GET /next");
+
+        // It starts over after a request.
+        assert!(!parser.has_head());
+        assert_eq!(parser.parse(&buf[len..], &limits), Ok(None));
+    }
+}
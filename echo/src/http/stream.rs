@@ -0,0 +1,98 @@
+//! Response bodies produced while they're sent, rather than up front.
+
+use std::{
+    fmt,
+    io::{self, Read},
+    sync::{Arc, Mutex},
+};
+
+type Reader = Box<dyn Read + Send>;
+
+/// A response body that's read while it's being sent.
+#[derive(Clone)]
+pub struct BodyStream {
+    // Shared so that responses stay cloneable; whichever clone the server
+    // sends gets read.
+    reader: Arc<Mutex<Option<Reader>>>,
+    long_lived: bool,
+}
+
+impl BodyStream {
+    pub fn new(reader: impl Read + Send + 'static) -> BodyStream {
+        BodyStream {
+            reader: Arc::new(Mutex::new(Some(Box::new(reader)))),
+            long_lived: false,
+        }
+    }
+
+    /// A body that spends most of its time waiting, like a stream of
+    /// events. It's sent from a thread of its own rather than holding up a
+    /// worker of the pool, and the connection closes after it. The server
+    /// caps how many it sends at once, see `Builder::max_long_lived_streams`.
+    pub fn long_lived(reader: impl Read + Send + 'static) -> BodyStream {
+        BodyStream {
+            long_lived: true,
+            ..BodyStream::new(reader)
+        }
+    }
+
+    pub fn is_long_lived(&self) -> bool {
+        self.long_lived
+    }
+
+    /// The reader, unless a clone took it already.
+    pub fn take(&self) -> Option<Box<dyn Read + Send>> {
+        self.reader.lock().unwrap().take()
+    }
+}
+
+impl fmt::Debug for BodyStream {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("BodyStream").finish_non_exhaustive()
+    }
+}
+
+impl PartialEq for BodyStream {
+    fn eq(&self, other: &BodyStream) -> bool {
+        Arc::ptr_eq(&self.reader, &other.reader)
+    }
+}
+
+impl Eq for BodyStream {}
+
+/// A streamed body whose length was given up front, in `Content-Length`.
+/// It's cut off there, and ending sooner is an error: the response would be
+/// misframed, so it has to be cut short instead.
+pub(crate) struct Exact {
+    reader: Reader,
+    remaining: u64,
+}
+
+impl Exact {
+    pub(crate) fn new(reader: Reader, len: u64) -> Exact {
+        Exact {
+            reader,
+            remaining: len,
+        }
+    }
+}
+
+impl Read for Exact {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.remaining == 0 {
+            return Ok(0);
+        }
+        let max = buf
+            .len()
+            .min(usize::try_from(self.remaining).unwrap_or(usize::MAX));
+        let n = self.reader.read(&mut buf[..max])?;
+        if n == 0 && max > 0 {
+            return Err(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                "the body ended before its Content-Length",
+            ));
+        }
+        self.remaining -= n as u64;
+        Ok(n)
+    }
+}
@@ -0,0 +1,102 @@
+//! Switching a connection to another protocol after a `101 Switching
+//! Protocols` response (RFC 9110 section 7.8).
+
+use std::{
+    fmt,
+    io::{self, Read, Write},
+    sync::{Arc, Mutex},
+};
+
+use crate::server::Stream;
+
+type OnUpgrade = Box<dyn FnOnce(Upgraded) + Send>;
+
+/// What takes over a connection once the server has sent the `101`
+/// response it's attached to. It runs on a thread of its own, as upgraded
+/// connections usually last as long as the client likes.
+#[derive(Clone)]
+pub struct Upgrade {
+    // Shared so that responses stay cloneable; whichever clone the server
+    // sends runs it.
+    on_upgrade: Arc<Mutex<Option<OnUpgrade>>>,
+}
+
+impl Upgrade {
+    pub fn new<F>(on_upgrade: F) -> Upgrade
+    where
+        F: FnOnce(Upgraded) + Send + 'static,
+    {
+        Upgrade {
+            on_upgrade: Arc::new(Mutex::new(Some(Box::new(on_upgrade)))),
+        }
+    }
+
+    /// Run the callback with the connection, unless a clone already did.
+    pub fn run(self, connection: Upgraded) {
+        let on_upgrade = self.on_upgrade.lock().unwrap().take();
+        if let Some(on_upgrade) = on_upgrade {
+            on_upgrade(connection);
+        }
+    }
+}
+
+impl fmt::Debug for Upgrade {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Upgrade").finish_non_exhaustive()
+    }
+}
+
+impl PartialEq for Upgrade {
+    fn eq(&self, other: &Upgrade) -> bool {
+        Arc::ptr_eq(&self.on_upgrade, &other.on_upgrade)
+    }
+}
+
+impl Eq for Upgrade {}
+
+/// A connection handed over by the server. Reading starts with whatever the
+/// client sent right after its request, which the server may have read
+/// already.
+#[derive(Debug)]
+pub struct Upgraded {
+    stream: Stream,
+    buffered: Vec<u8>,
+    pos: usize,
+}
+
+impl Upgraded {
+    pub(crate) fn new(stream: Stream, buffered: Vec<u8>) -> Upgraded {
+        Upgraded {
+            stream,
+            buffered,
+            pos: 0,
+        }
+    }
+
+    /// The underlying socket, e.g. to clone it for writing from another
+    /// thread. Reading from it directly skips the buffered bytes.
+    pub fn stream(&self) -> &Stream {
+        &self.stream
+    }
+}
+
+impl Read for Upgraded {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.pos < self.buffered.len() {
+            let n = (&self.buffered[self.pos..]).read(buf)?;
+            self.pos += n;
+            return Ok(n);
+        }
+        self.stream.read(buf)
+    }
+}
+
+impl Write for Upgraded {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.stream.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.stream.flush()
+    }
+}
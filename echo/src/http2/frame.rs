@@ -0,0 +1,694 @@
+//! The frames of HTTP/2 (RFC 9113 sections 4 and 6).
+
+use std::{error::Error as StdError, fmt};
+
+/// The length of the header every frame starts with.
+pub const HEADER_LEN: usize = 9;
+
+/// The largest frame payload either end accepts until told otherwise, and
+/// the smallest limit it may set.
+pub const DEFAULT_MAX_FRAME_SIZE: u32 = 16_384;
+
+const DATA: u8 = 0x0;
+const HEADERS: u8 = 0x1;
+const PRIORITY: u8 = 0x2;
+const RST_STREAM: u8 = 0x3;
+const SETTINGS: u8 = 0x4;
+const PUSH_PROMISE: u8 = 0x5;
+const PING: u8 = 0x6;
+const GOAWAY: u8 = 0x7;
+const WINDOW_UPDATE: u8 = 0x8;
+const CONTINUATION: u8 = 0x9;
+
+const END_STREAM: u8 = 0x1;
+const ACK: u8 = 0x1;
+const END_HEADERS: u8 = 0x4;
+const PADDED: u8 = 0x8;
+const PRIORITY_FLAG: u8 = 0x20;
+
+/// Stream identifiers are 31 bits; the top bit is reserved.
+const STREAM_MASK: u32 = 0x7fff_ffff;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Frame {
+    Data {
+        stream: u32,
+        data: Vec<u8>,
+        end_stream: bool,
+        /// The length of the padding that followed the data, if the frame
+        /// was padded. It counts towards flow control.
+        padding: Option<u8>,
+    },
+    Headers {
+        stream: u32,
+        /// A header block fragment, the rest of which comes in
+        /// `Continuation` frames unless `end_headers` is set.
+        block: Vec<u8>,
+        end_stream: bool,
+        end_headers: bool,
+        priority: Option<Priority>,
+    },
+    Priority {
+        stream: u32,
+        priority: Priority,
+    },
+    RstStream {
+        stream: u32,
+        error: ErrorCode,
+    },
+    Settings {
+        ack: bool,
+        /// Identifiers and values, in the order sent.
+        settings: Vec<(u16, u32)>,
+    },
+    PushPromise {
+        stream: u32,
+        promised: u32,
+        block: Vec<u8>,
+        end_headers: bool,
+    },
+    Ping {
+        ack: bool,
+        data: [u8; 8],
+    },
+    GoAway {
+        last_stream: u32,
+        error: ErrorCode,
+        debug: Vec<u8>,
+    },
+    WindowUpdate {
+        /// 0 for the connection as a whole.
+        stream: u32,
+        increment: u32,
+    },
+    Continuation {
+        stream: u32,
+        block: Vec<u8>,
+        end_headers: bool,
+    },
+    /// A frame of a type this end doesn't know, to be ignored.
+    Unknown {
+        kind: u8,
+        flags: u8,
+        stream: u32,
+        payload: Vec<u8>,
+    },
+}
+
+/// The deprecated prioritization scheme of RFC 7540, which is parsed but
+/// otherwise ignored.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Priority {
+    pub dependency: u32,
+    pub exclusive: bool,
+    pub weight: u8,
+}
+
+/// Why a stream or the connection is closed (section 7).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ErrorCode {
+    NoError,
+    ProtocolError,
+    InternalError,
+    FlowControlError,
+    SettingsTimeout,
+    StreamClosed,
+    FrameSizeError,
+    RefusedStream,
+    Cancel,
+    CompressionError,
+    ConnectError,
+    EnhanceYourCalm,
+    InadequateSecurity,
+    Http11Required,
+    /// A code this end doesn't know, which means `InternalError`.
+    Other(u32),
+}
+
+impl ErrorCode {
+    pub fn from_u32(n: u32) -> ErrorCode {
+        match n {
+            0x0 => ErrorCode::NoError,
+            0x1 => ErrorCode::ProtocolError,
+            0x2 => ErrorCode::InternalError,
+            0x3 => ErrorCode::FlowControlError,
+            0x4 => ErrorCode::SettingsTimeout,
+            0x5 => ErrorCode::StreamClosed,
+            0x6 => ErrorCode::FrameSizeError,
+            0x7 => ErrorCode::RefusedStream,
+            0x8 => ErrorCode::Cancel,
+            0x9 => ErrorCode::CompressionError,
+            0xa => ErrorCode::ConnectError,
+            0xb => ErrorCode::EnhanceYourCalm,
+            0xc => ErrorCode::InadequateSecurity,
+            0xd => ErrorCode::Http11Required,
+            n => ErrorCode::Other(n),
+        }
+    }
+
+    pub fn to_u32(self) -> u32 {
+        match self {
+            ErrorCode::NoError => 0x0,
+            ErrorCode::ProtocolError => 0x1,
+            ErrorCode::InternalError => 0x2,
+            ErrorCode::FlowControlError => 0x3,
+            ErrorCode::SettingsTimeout => 0x4,
+            ErrorCode::StreamClosed => 0x5,
+            ErrorCode::FrameSizeError => 0x6,
+            ErrorCode::RefusedStream => 0x7,
+            ErrorCode::Cancel => 0x8,
+            ErrorCode::CompressionError => 0x9,
+            ErrorCode::ConnectError => 0xa,
+            ErrorCode::EnhanceYourCalm => 0xb,
+            ErrorCode::InadequateSecurity => 0xc,
+            ErrorCode::Http11Required => 0xd,
+            ErrorCode::Other(n) => n,
+        }
+    }
+}
+
+/// A violation of the protocol, and how much of the connection it takes
+/// down (section 5.4).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Error {
+    /// The connection is closed with a `GOAWAY` frame.
+    Connection(ErrorCode, &'static str),
+    /// Only the stream is, with a `RST_STREAM` frame.
+    Stream(u32, ErrorCode, &'static str),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Connection(code, reason) => write!(f, "{reason} ({code:?})"),
+            Error::Stream(stream, code, reason) => {
+                write!(f, "stream {stream}: {reason} ({code:?})")
+            }
+        }
+    }
+}
+
+impl StdError for Error {}
+
+/// Parse a frame from the start of `buf`, refusing payloads larger than
+/// `max_size`.
+///
+/// Returns `Ok(None)` if `buf` doesn't hold a complete frame yet, or the
+/// frame along with the number of bytes it took up. Stream errors are only
+/// returned for complete frames, which [`frame_len`] tells how to skip.
+pub fn parse_frame(buf: &[u8], max_size: u32) -> Result<Option<(Frame, usize)>, Error> {
+    if buf.len() < HEADER_LEN {
+        return Ok(None);
+    }
+    let len = u32::from_be_bytes([0, buf[0], buf[1], buf[2]]);
+    let kind = buf[3];
+    let flags = buf[4];
+    let stream = read_u32(&buf[5..]) & STREAM_MASK;
+    if len > max_size {
+        return Err(Error::Connection(
+            ErrorCode::FrameSizeError,
+            "frame is larger than the maximum frame size",
+        ));
+    }
+    let Some(end) = frame_len(buf) else {
+        return Ok(None);
+    };
+
+    let frame = decode(kind, flags, stream, &buf[HEADER_LEN..end])?;
+    Ok(Some((frame, end)))
+}
+
+/// How many bytes the frame at the start of `buf` takes up, if it's all
+/// there.
+pub fn frame_len(buf: &[u8]) -> Option<usize> {
+    let header = buf.get(..HEADER_LEN)?;
+    let end = HEADER_LEN + u32::from_be_bytes([0, header[0], header[1], header[2]]) as usize;
+    (buf.len() >= end).then_some(end)
+}
+
+fn decode(kind: u8, flags: u8, stream: u32, payload: &[u8]) -> Result<Frame, Error> {
+    use Error::Connection;
+    use ErrorCode::{FrameSizeError, ProtocolError};
+
+    let on_stream = |name| match stream {
+        0 => Err(Connection(ProtocolError, name)),
+        _ => Ok(()),
+    };
+    let on_connection = |name| match stream {
+        0 => Ok(()),
+        _ => Err(Connection(ProtocolError, name)),
+    };
+
+    let frame = match kind {
+        DATA => {
+            on_stream("DATA frame on stream 0")?;
+            let (data, padding) = unpad(flags, payload)?;
+            Frame::Data {
+                stream,
+                data: data.to_vec(),
+                end_stream: flags & END_STREAM != 0,
+                padding,
+            }
+        }
+        HEADERS => {
+            on_stream("HEADERS frame on stream 0")?;
+            let (mut block, _) = unpad(flags, payload)?;
+            let priority = if flags & PRIORITY_FLAG != 0 {
+                if block.len() < 5 {
+                    return Err(Connection(FrameSizeError, "HEADERS frame is too short"));
+                }
+                let priority = parse_priority(block);
+                block = &block[5..];
+                Some(priority)
+            } else {
+                None
+            };
+            Frame::Headers {
+                stream,
+                block: block.to_vec(),
+                end_stream: flags & END_STREAM != 0,
+                end_headers: flags & END_HEADERS != 0,
+                priority,
+            }
+        }
+        PRIORITY => {
+            on_stream("PRIORITY frame on stream 0")?;
+            if payload.len() != 5 {
+                return Err(Error::Stream(
+                    stream,
+                    FrameSizeError,
+                    "PRIORITY frame isn't 5 bytes",
+                ));
+            }
+            Frame::Priority {
+                stream,
+                priority: parse_priority(payload),
+            }
+        }
+        RST_STREAM => {
+            on_stream("RST_STREAM frame on stream 0")?;
+            if payload.len() != 4 {
+                return Err(Connection(FrameSizeError, "RST_STREAM frame isn't 4 bytes"));
+            }
+            Frame::RstStream {
+                stream,
+                error: ErrorCode::from_u32(read_u32(payload)),
+            }
+        }
+        SETTINGS => {
+            on_connection("SETTINGS frame on a stream")?;
+            let ack = flags & ACK != 0;
+            if (ack && !payload.is_empty()) || !payload.len().is_multiple_of(6) {
+                return Err(Connection(
+                    FrameSizeError,
+                    "SETTINGS frame has a bad length",
+                ));
+            }
+            let settings = payload
+                .chunks(6)
+                .map(|setting| {
+                    let id = u16::from_be_bytes([setting[0], setting[1]]);
+                    (id, read_u32(&setting[2..]))
+                })
+                .collect();
+            Frame::Settings { ack, settings }
+        }
+        PUSH_PROMISE => {
+            on_stream("PUSH_PROMISE frame on stream 0")?;
+            let (block, _) = unpad(flags, payload)?;
+            if block.len() < 4 {
+                return Err(Connection(
+                    FrameSizeError,
+                    "PUSH_PROMISE frame is too short",
+                ));
+            }
+            Frame::PushPromise {
+                stream,
+                promised: read_u32(block) & STREAM_MASK,
+                block: block[4..].to_vec(),
+                end_headers: flags & END_HEADERS != 0,
+            }
+        }
+        PING => {
+            on_connection("PING frame on a stream")?;
+            let data = payload
+                .try_into()
+                .map_err(|_| Connection(FrameSizeError, "PING frame isn't 8 bytes"))?;
+            Frame::Ping {
+                ack: flags & ACK != 0,
+                data,
+            }
+        }
+        GOAWAY => {
+            on_connection("GOAWAY frame on a stream")?;
+            if payload.len() < 8 {
+                return Err(Connection(FrameSizeError, "GOAWAY frame is too short"));
+            }
+            Frame::GoAway {
+                last_stream: read_u32(payload) & STREAM_MASK,
+                error: ErrorCode::from_u32(read_u32(&payload[4..])),
+                debug: payload[8..].to_vec(),
+            }
+        }
+        WINDOW_UPDATE => {
+            if payload.len() != 4 {
+                return Err(Connection(
+                    FrameSizeError,
+                    "WINDOW_UPDATE frame isn't 4 bytes",
+                ));
+            }
+            let increment = read_u32(payload) & STREAM_MASK;
+            if increment == 0 {
+                let reason = "WINDOW_UPDATE frame with no increment";
+                return Err(match stream {
+                    0 => Connection(ProtocolError, reason),
+                    stream => Error::Stream(stream, ProtocolError, reason),
+                });
+            }
+            Frame::WindowUpdate { stream, increment }
+        }
+        CONTINUATION => {
+            on_stream("CONTINUATION frame on stream 0")?;
+            Frame::Continuation {
+                stream,
+                block: payload.to_vec(),
+                end_headers: flags & END_HEADERS != 0,
+            }
+        }
+        kind => Frame::Unknown {
+            kind,
+            flags,
+            stream,
+            payload: payload.to_vec(),
+        },
+    };
+    Ok(frame)
+}
+
+/// Split the padding off the payload of a frame that may be padded.
+fn unpad(flags: u8, payload: &[u8]) -> Result<(&[u8], Option<u8>), Error> {
+    if flags & PADDED == 0 {
+        return Ok((payload, None));
+    }
+    let Some((&padding, rest)) = payload.split_first() else {
+        return Err(Error::Connection(
+            ErrorCode::FrameSizeError,
+            "padded frame is empty",
+        ));
+    };
+    match rest.len().checked_sub(usize::from(padding)) {
+        Some(len) => Ok((&rest[..len], Some(padding))),
+        None => Err(Error::Connection(
+            ErrorCode::ProtocolError,
+            "padding is longer than the payload",
+        )),
+    }
+}
+
+fn parse_priority(payload: &[u8]) -> Priority {
+    let dependency = read_u32(payload);
+    Priority {
+        dependency: dependency & STREAM_MASK,
+        exclusive: dependency & !STREAM_MASK != 0,
+        weight: payload[4],
+    }
+}
+
+fn read_u32(bytes: &[u8]) -> u32 {
+    u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]])
+}
+
+impl Frame {
+    /// The part of the frame that counts towards flow control: the payload
+    /// of a `Data` frame, padding and all.
+    pub fn flow_controlled_len(&self) -> usize {
+        match self {
+            Frame::Data { data, padding, .. } => {
+                data.len() + padding.map_or(0, |padding| 1 + usize::from(padding))
+            }
+            _ => 0,
+        }
+    }
+
+    /// Serialize the frame. Payloads have to be within the peer's maximum
+    /// frame size.
+    pub fn encode(&self) -> Vec<u8> {
+        let mut payload = Vec::new();
+        let (kind, flags, stream) = match self {
+            Frame::Data {
+                stream,
+                data,
+                end_stream,
+                padding,
+            } => {
+                let flags = flag(*end_stream, END_STREAM) | pad(&mut payload, *padding);
+                payload.extend_from_slice(data);
+                payload.resize(payload.len() + padding.map_or(0, usize::from), 0);
+                (DATA, flags, *stream)
+            }
+            Frame::Headers {
+                stream,
+                block,
+                end_stream,
+                end_headers,
+                priority,
+            } => {
+                if let Some(priority) = priority {
+                    encode_priority(&mut payload, priority);
+                }
+                payload.extend_from_slice(block);
+                let flags = flag(*end_stream, END_STREAM)
+                    | flag(*end_headers, END_HEADERS)
+                    | flag(priority.is_some(), PRIORITY_FLAG);
+                (HEADERS, flags, *stream)
+            }
+            Frame::Priority { stream, priority } => {
+                encode_priority(&mut payload, priority);
+                (PRIORITY, 0, *stream)
+            }
+            Frame::RstStream { stream, error } => {
+                payload.extend_from_slice(&error.to_u32().to_be_bytes());
+                (RST_STREAM, 0, *stream)
+            }
+            Frame::Settings { ack, settings } => {
+                for (id, value) in settings {
+                    payload.extend_from_slice(&id.to_be_bytes());
+                    payload.extend_from_slice(&value.to_be_bytes());
+                }
+                (SETTINGS, flag(*ack, ACK), 0)
+            }
+            Frame::PushPromise {
+                stream,
+                promised,
+                block,
+                end_headers,
+            } => {
+                payload.extend_from_slice(&promised.to_be_bytes());
+                payload.extend_from_slice(block);
+                (PUSH_PROMISE, flag(*end_headers, END_HEADERS), *stream)
+            }
+            Frame::Ping { ack, data } => {
+                payload.extend_from_slice(data);
+                (PING, flag(*ack, ACK), 0)
+            }
+            Frame::GoAway {
+                last_stream,
+                error,
+                debug,
+            } => {
+                payload.extend_from_slice(&last_stream.to_be_bytes());
+                payload.extend_from_slice(&error.to_u32().to_be_bytes());
+                payload.extend_from_slice(debug);
+                (GOAWAY, 0, 0)
+            }
+            Frame::WindowUpdate { stream, increment } => {
+                payload.extend_from_slice(&increment.to_be_bytes());
+                (WINDOW_UPDATE, 0, *stream)
+            }
+            Frame::Continuation {
+                stream,
+                block,
+                end_headers,
+            } => {
+                payload.extend_from_slice(block);
+                (CONTINUATION, flag(*end_headers, END_HEADERS), *stream)
+            }
+            Frame::Unknown {
+                kind,
+                flags,
+                stream,
+                payload: unknown,
+            } => {
+                payload.extend_from_slice(unknown);
+                (*kind, *flags, *stream)
+            }
+        };
+
+        let mut out = Vec::with_capacity(HEADER_LEN + payload.len());
+        out.extend_from_slice(&(payload.len() as u32).to_be_bytes()[1..]);
+        out.push(kind);
+        out.push(flags);
+        out.extend_from_slice(&stream.to_be_bytes());
+        out.extend_from_slice(&payload);
+        out
+    }
+}
+
+fn flag(set: bool, flag: u8) -> u8 {
+    if set {
+        flag
+    } else {
+        0
+    }
+}
+
+/// Start a padded payload with the padding's length.
+fn pad(payload: &mut Vec<u8>, padding: Option<u8>) -> u8 {
+    match padding {
+        Some(padding) => {
+            payload.push(padding);
+            PADDED
+        }
+        None => 0,
+    }
+}
+
+fn encode_priority(payload: &mut Vec<u8>, priority: &Priority) {
+    let exclusive = if priority.exclusive { !STREAM_MASK } else { 0 };
+    payload.extend_from_slice(&(priority.dependency | exclusive).to_be_bytes());
+    payload.push(priority.weight);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trips() {
+        let frames = [
+            Frame::Data {
+                stream: 1,
+                data: b"hello".to_vec(),
+                end_stream: true,
+                padding: Some(3),
+            },
+            Frame::Headers {
+                stream: 3,
+                block: vec![0x82, 0x86],
+                end_stream: false,
+                end_headers: true,
+                priority: Some(Priority {
+                    dependency: 1,
+                    exclusive: true,
+                    weight: 15,
+                }),
+            },
+            Frame::RstStream {
+                stream: 3,
+                error: ErrorCode::Cancel,
+            },
+            Frame::Settings {
+                ack: false,
+                settings: vec![(0x3, 100), (0x4, 1 << 20)],
+            },
+            Frame::Ping {
+                ack: true,
+                data: *b"12345678",
+            },
+            Frame::GoAway {
+                last_stream: 5,
+                error: ErrorCode::Other(0x42),
+                debug: b"bye".to_vec(),
+            },
+            Frame::WindowUpdate {
+                stream: 0,
+                increment: 1000,
+            },
+            Frame::Continuation {
+                stream: 3,
+                block: vec![0x84],
+                end_headers: true,
+            },
+            Frame::Unknown {
+                kind: 0xfa,
+                flags: 0x1,
+                stream: 7,
+                payload: b"?".to_vec(),
+            },
+        ];
+
+        let mut buf: Vec<u8> = frames.iter().flat_map(Frame::encode).collect();
+        for frame in &frames {
+            let (parsed, len) = parse_frame(&buf, DEFAULT_MAX_FRAME_SIZE).unwrap().unwrap();
+            assert_eq!(&parsed, frame);
+            buf.drain(..len);
+        }
+        assert!(buf.is_empty());
+    }
+
+    #[test]
+    fn waits_for_whole_frames() {
+        let ping = Frame::Ping {
+            ack: false,
+            data: [0; 8],
+        }
+        .encode();
+        for len in 0..ping.len() {
+            assert_eq!(parse_frame(&ping[..len], DEFAULT_MAX_FRAME_SIZE), Ok(None));
+        }
+        let data = Frame::Data {
+            stream: 1,
+            data: b"abc".to_vec(),
+            end_stream: false,
+            padding: Some(2),
+        };
+        assert_eq!(data.flow_controlled_len(), 6);
+    }
+
+    #[test]
+    fn rejects_malformed_frames() {
+        let reject = |bytes: &[u8]| match parse_frame(bytes, DEFAULT_MAX_FRAME_SIZE) {
+            Err(Error::Connection(code, _)) => code,
+            other => panic!("expected a connection error, got {other:?}"),
+        };
+
+        // Too large.
+        assert_eq!(
+            reject(&[0x00, 0x40, 0x01, DATA, 0, 0, 0, 0, 1]),
+            ErrorCode::FrameSizeError
+        );
+        // DATA on stream 0.
+        assert_eq!(
+            reject(&[0, 0, 1, DATA, 0, 0, 0, 0, 0, b'x']),
+            ErrorCode::ProtocolError
+        );
+        // More padding than payload.
+        assert_eq!(
+            reject(&[0, 0, 2, DATA, PADDED, 0, 0, 0, 1, 5, b'x']),
+            ErrorCode::ProtocolError
+        );
+        // A SETTINGS acknowledgement with settings.
+        assert_eq!(
+            reject(&[0, 0, 6, SETTINGS, ACK, 0, 0, 0, 0, 0, 3, 0, 0, 0, 1]),
+            ErrorCode::FrameSizeError
+        );
+        // A short PING.
+        assert_eq!(
+            reject(&[0, 0, 1, PING, 0, 0, 0, 0, 0, 0]),
+            ErrorCode::FrameSizeError
+        );
+
+        // A zero increment only resets the stream it's for.
+        assert_eq!(
+            parse_frame(
+                &[0, 0, 4, WINDOW_UPDATE, 0, 0, 0, 0, 3, 0, 0, 0, 0],
+                DEFAULT_MAX_FRAME_SIZE
+            ),
+            Err(Error::Stream(
+                3,
+                ErrorCode::ProtocolError,
+                "WINDOW_UPDATE frame with no increment"
+            ))
+        );
+    }
+}
@@ -0,0 +1,480 @@
+//! HPACK, the header compression of HTTP/2 (RFC 7541).
+//!
+//! A header block is a list of fields, each sent as the index of a field in
+//! a table, or as a literal whose strings may be Huffman coded. Literals can
+//! be added to a dynamic table that both ends keep in step, so an `Encoder`
+//! and the `Decoder` on the other end last as long as the connection.
+
+mod huffman;
+mod table;
+
+use std::{error::Error, fmt};
+
+use table::{entry_size, Table};
+
+/// A header field as it's sent: a name and a value, both raw bytes.
+pub type Field = (Vec<u8>, Vec<u8>);
+
+/// The size of the dynamic table until the decoder says otherwise.
+pub const DEFAULT_TABLE_SIZE: usize = 4096;
+
+/// Fields whose values are secrets, or easily guessed once in a table that
+/// other requests' fields share (section 7.1.3). They're never indexed.
+const SENSITIVE: &[&[u8]] = &[
+    b"authorization",
+    b"cookie",
+    b"proxy-authorization",
+    b"set-cookie",
+];
+
+/// Fields whose values change from one response to the next. Indexing them
+/// would only evict fields that repeat.
+const VOLATILE: &[&[u8]] = &[
+    b"age",
+    b"content-length",
+    b"content-range",
+    b"date",
+    b"etag",
+    b"last-modified",
+    b"x-request-id",
+];
+
+/// Why a header block couldn't be decoded.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DecodeError {
+    /// The block ends in the middle of a field.
+    Truncated,
+    /// An integer too large to be meant.
+    IntegerOverflow,
+    /// An index that's in neither table.
+    InvalidIndex(usize),
+    /// A Huffman-coded string with bad padding, or the end-of-string symbol.
+    InvalidHuffman,
+    /// A dynamic table size update after a field, or above the limit.
+    InvalidSizeUpdate,
+    /// The fields add up to more than the decoder's limit. The block was
+    /// decoded all the same, so the dynamic table is still in step.
+    ListTooLarge,
+}
+
+impl fmt::Display for DecodeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DecodeError::Truncated => write!(f, "header block is truncated"),
+            DecodeError::IntegerOverflow => write!(f, "integer is too large"),
+            DecodeError::InvalidIndex(index) => write!(f, "no header field at index {index}"),
+            DecodeError::InvalidHuffman => write!(f, "invalid Huffman-coded string"),
+            DecodeError::InvalidSizeUpdate => write!(f, "invalid dynamic table size update"),
+            DecodeError::ListTooLarge => write!(f, "header list is too large"),
+        }
+    }
+}
+
+impl Error for DecodeError {}
+
+/// Decodes the header blocks received on a connection.
+#[derive(Debug)]
+pub struct Decoder {
+    table: Table,
+    /// The largest dynamic table the encoder may ask for: the
+    /// `SETTINGS_HEADER_TABLE_SIZE` sent to it.
+    max_table_size: usize,
+    max_list_size: usize,
+}
+
+impl Decoder {
+    pub fn new() -> Decoder {
+        Decoder {
+            table: Table::new(DEFAULT_TABLE_SIZE),
+            max_table_size: DEFAULT_TABLE_SIZE,
+            max_list_size: usize::MAX,
+        }
+    }
+
+    /// Let the encoder's dynamic table grow to `size`, as announced in the
+    /// settings. Defaults to `DEFAULT_TABLE_SIZE`.
+    pub fn max_table_size(mut self, size: usize) -> Decoder {
+        self.max_table_size = size;
+        self
+    }
+
+    /// Refuse header lists larger than `size`, counted like
+    /// `SETTINGS_MAX_HEADER_LIST_SIZE`. There's no limit by default.
+    pub fn max_list_size(mut self, size: usize) -> Decoder {
+        self.max_list_size = size;
+        self
+    }
+
+    /// The fields of a complete header block, as names and values.
+    pub fn decode(&mut self, block: &[u8]) -> Result<Vec<Field>, DecodeError> {
+        let mut fields = Vec::new();
+        let mut list_size = 0;
+        let mut pos = 0;
+
+        while pos < block.len() {
+            let first = block[pos];
+            let field = if first & 0x80 != 0 {
+                // Indexed field (section 6.1).
+                let index = decode_int(block, &mut pos, 7)?;
+                let (name, value) = self
+                    .table
+                    .get(index)
+                    .ok_or(DecodeError::InvalidIndex(index))?;
+                (name.to_vec(), value.to_vec())
+            } else if first & 0x40 != 0 {
+                // Literal with incremental indexing (section 6.2.1).
+                let (name, value) = self.literal(block, &mut pos, 6)?;
+                self.table.insert(name.clone(), value.clone());
+                (name, value)
+            } else if first & 0x20 != 0 {
+                // Dynamic table size update (section 6.3), which only comes
+                // first in a block.
+                let size = decode_int(block, &mut pos, 5)?;
+                if size > self.max_table_size || list_size > 0 {
+                    return Err(DecodeError::InvalidSizeUpdate);
+                }
+                self.table.set_max_size(size);
+                continue;
+            } else {
+                // Literal without indexing, or never indexed (sections 6.2.2
+                // and 6.2.3).
+                self.literal(block, &mut pos, 4)?
+            };
+
+            list_size += entry_size(&field.0, &field.1);
+            if list_size <= self.max_list_size {
+                fields.push(field);
+            }
+        }
+
+        if list_size > self.max_list_size {
+            return Err(DecodeError::ListTooLarge);
+        }
+        Ok(fields)
+    }
+
+    /// A literal field whose name is indexed in the first byte's low
+    /// `prefix` bits, or follows if the index is 0.
+    fn literal(&self, block: &[u8], pos: &mut usize, prefix: u8) -> Result<Field, DecodeError> {
+        let index = decode_int(block, pos, prefix)?;
+        let name = match index {
+            0 => decode_string(block, pos)?,
+            index => match self.table.get(index) {
+                Some((name, _)) => name.to_vec(),
+                None => return Err(DecodeError::InvalidIndex(index)),
+            },
+        };
+        let value = decode_string(block, pos)?;
+        Ok((name, value))
+    }
+}
+
+impl Default for Decoder {
+    fn default() -> Decoder {
+        Decoder::new()
+    }
+}
+
+/// Encodes the header blocks sent on a connection.
+///
+/// Fields are indexed once sent, except for secrets like `authorization`
+/// and values that rarely repeat like `date`. Strings are Huffman coded
+/// when that makes them shorter.
+#[derive(Debug)]
+pub struct Encoder {
+    table: Table,
+    /// The smallest the table was resized to since the last block, which
+    /// the decoder learns of at the start of the next one.
+    resized: Option<usize>,
+}
+
+impl Encoder {
+    pub fn new() -> Encoder {
+        Encoder {
+            table: Table::new(DEFAULT_TABLE_SIZE),
+            resized: None,
+        }
+    }
+
+    /// Follow the decoder's `SETTINGS_HEADER_TABLE_SIZE`. The table never
+    /// grows beyond `DEFAULT_TABLE_SIZE` though.
+    pub fn set_max_table_size(&mut self, size: usize) {
+        let size = size.min(DEFAULT_TABLE_SIZE);
+        if size != self.table.max_size() {
+            self.table.set_max_size(size);
+            self.resized = Some(self.resized.map_or(size, |smallest| smallest.min(size)));
+        }
+    }
+
+    pub fn encode<N, V>(&mut self, fields: impl IntoIterator<Item = (N, V)>) -> Vec<u8>
+    where
+        N: AsRef<[u8]>,
+        V: AsRef<[u8]>,
+    {
+        let mut block = Vec::new();
+        if let Some(smallest) = self.resized.take() {
+            encode_int(&mut block, smallest, 5, 0x20);
+            if smallest != self.table.max_size() {
+                encode_int(&mut block, self.table.max_size(), 5, 0x20);
+            }
+        }
+        for (name, value) in fields {
+            self.encode_field(&mut block, name.as_ref(), value.as_ref());
+        }
+        block
+    }
+
+    fn encode_field(&mut self, block: &mut Vec<u8>, name: &[u8], value: &[u8]) {
+        let found = self.table.find(name, value);
+        if let Some((index, true)) = found {
+            encode_int(block, index, 7, 0x80);
+            return;
+        }
+
+        let sensitive = SENSITIVE.contains(&name);
+        let indexed = !sensitive
+            && !VOLATILE.contains(&name)
+            && entry_size(name, value) <= self.table.max_size() / 2;
+        let name_index = found.map_or(0, |(index, _)| index);
+        match (indexed, sensitive) {
+            (true, _) => encode_int(block, name_index, 6, 0x40),
+            (false, true) => encode_int(block, name_index, 4, 0x10),
+            (false, false) => encode_int(block, name_index, 4, 0x00),
+        }
+        if name_index == 0 {
+            encode_string(block, name);
+        }
+        encode_string(block, value);
+
+        if indexed {
+            self.table.insert(name.to_vec(), value.to_vec());
+        }
+    }
+}
+
+impl Default for Encoder {
+    fn default() -> Encoder {
+        Encoder::new()
+    }
+}
+
+/// Decode an integer that starts in the low `prefix` bits of the byte at
+/// `pos` (section 5.1).
+fn decode_int(block: &[u8], pos: &mut usize, prefix: u8) -> Result<usize, DecodeError> {
+    let max = ((1u16 << prefix) - 1) as u8;
+    let first = *block.get(*pos).ok_or(DecodeError::Truncated)?;
+    *pos += 1;
+    let mut value = usize::from(first & max);
+    if value < usize::from(max) {
+        return Ok(value);
+    }
+
+    let mut shift = 0;
+    loop {
+        let byte = *block.get(*pos).ok_or(DecodeError::Truncated)?;
+        *pos += 1;
+        // Nothing in a header block comes near 2^28.
+        if shift > 21 {
+            return Err(DecodeError::IntegerOverflow);
+        }
+        value += usize::from(byte & 0x7f) << shift;
+        shift += 7;
+        if byte & 0x80 == 0 {
+            return Ok(value);
+        }
+    }
+}
+
+/// Encode `value` in the low `prefix` bits of a byte whose high bits are
+/// `flags`, continued in the bytes after if it doesn't fit.
+fn encode_int(block: &mut Vec<u8>, value: usize, prefix: u8, flags: u8) {
+    let max = (1usize << prefix) - 1;
+    if value < max {
+        block.push(flags | value as u8);
+        return;
+    }
+    block.push(flags | max as u8);
+    let mut value = value - max;
+    while value >= 0x80 {
+        block.push(value as u8 | 0x80);
+        value >>= 7;
+    }
+    block.push(value as u8);
+}
+
+/// A string literal, Huffman coded or not (section 5.2).
+fn decode_string(block: &[u8], pos: &mut usize) -> Result<Vec<u8>, DecodeError> {
+    let huffman = block.get(*pos).ok_or(DecodeError::Truncated)? & 0x80 != 0;
+    let len = decode_int(block, pos, 7)?;
+    let bytes = block.get(*pos..*pos + len).ok_or(DecodeError::Truncated)?;
+    *pos += len;
+    if huffman {
+        huffman::decode(bytes)
+    } else {
+        Ok(bytes.to_vec())
+    }
+}
+
+fn encode_string(block: &mut Vec<u8>, s: &[u8]) {
+    let len = huffman::encoded_len(s);
+    if len < s.len() {
+        encode_int(block, len, 7, 0x80);
+        huffman::encode(s, block);
+    } else {
+        encode_int(block, s.len(), 7, 0x00);
+        block.extend_from_slice(s);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn unhex(s: &str) -> Vec<u8> {
+        let s: String = s.split_whitespace().collect();
+        (0..s.len())
+            .step_by(2)
+            .map(|i| u8::from_str_radix(&s[i..i + 2], 16).unwrap())
+            .collect()
+    }
+
+    fn fields(fields: &[(&str, &str)]) -> Vec<Field> {
+        fields
+            .iter()
+            .map(|(name, value)| (name.as_bytes().to_vec(), value.as_bytes().to_vec()))
+            .collect()
+    }
+
+    #[test]
+    fn integers() {
+        // RFC 7541 appendix C.1.
+        let mut block = Vec::new();
+        encode_int(&mut block, 10, 5, 0);
+        encode_int(&mut block, 1337, 5, 0);
+        encode_int(&mut block, 42, 8, 0);
+        assert_eq!(block, [0x0a, 0x1f, 0x9a, 0x0a, 0x2a]);
+
+        let mut pos = 0;
+        assert_eq!(decode_int(&block, &mut pos, 5), Ok(10));
+        assert_eq!(decode_int(&block, &mut pos, 5), Ok(1337));
+        assert_eq!(decode_int(&block, &mut pos, 8), Ok(42));
+        assert_eq!(pos, block.len());
+
+        let mut pos = 0;
+        assert_eq!(
+            decode_int(&[0x1f, 0x9a], &mut pos, 5),
+            Err(DecodeError::Truncated)
+        );
+        let mut pos = 0;
+        assert_eq!(
+            decode_int(&[0x1f, 0xff, 0xff, 0xff, 0xff, 0x0f], &mut pos, 5),
+            Err(DecodeError::IntegerOverflow)
+        );
+    }
+
+    #[test]
+    fn decodes_the_rfc_requests() {
+        // RFC 7541 appendix C.3 and C.4: the same requests without and with
+        // Huffman coding, sharing a dynamic table.
+        for blocks in [
+            [
+                "8286 8441 0f77 7777 2e65 7861 6d70 6c65 2e63 6f6d",
+                "8286 84be 5808 6e6f 2d63 6163 6865",
+                "8287 85bf 400a 6375 7374 6f6d 2d6b 6579 0c63 7573 746f 6d2d 7661 6c75 65",
+            ],
+            [
+                "8286 8441 8cf1 e3c2 e5f2 3a6b a0ab 90f4 ff",
+                "8286 84be 5886 a8eb 1064 9cbf",
+                "8287 85bf 4088 25a8 49e9 5ba9 7d7f 8925 a849 e95b b8e8 b4bf",
+            ],
+        ] {
+            let mut decoder = Decoder::new();
+            let first = decoder.decode(&unhex(blocks[0])).unwrap();
+            assert_eq!(
+                first,
+                fields(&[
+                    (":method", "GET"),
+                    (":scheme", "http"),
+                    (":path", "/"),
+                    (":authority", "www.example.com"),
+                ])
+            );
+            let second = decoder.decode(&unhex(blocks[1])).unwrap();
+            assert_eq!(second[4], fields(&[("cache-control", "no-cache")])[0]);
+            let third = decoder.decode(&unhex(blocks[2])).unwrap();
+            assert_eq!(
+                third,
+                fields(&[
+                    (":method", "GET"),
+                    (":scheme", "https"),
+                    (":path", "/index.html"),
+                    (":authority", "www.example.com"),
+                    ("custom-key", "custom-value"),
+                ])
+            );
+        }
+    }
+
+    #[test]
+    fn round_trips_responses() {
+        let responses = [
+            fields(&[
+                (":status", "200"),
+                ("content-type", "text/html"),
+                ("content-length", "1234"),
+                ("set-cookie", "session=secret"),
+                ("x-custom", "one"),
+            ]),
+            fields(&[
+                (":status", "404"),
+                ("content-type", "text/html"),
+                ("content-length", "99"),
+                ("x-custom", "one"),
+            ]),
+        ];
+        let mut encoder = Encoder::new();
+        let mut decoder = Decoder::new();
+        let first = encoder.encode(responses[0].clone());
+        let second = encoder.encode(responses[1].clone());
+        assert_eq!(decoder.decode(&first).unwrap(), responses[0]);
+        assert_eq!(decoder.decode(&second).unwrap(), responses[1]);
+        // Everything but the length was indexed by then.
+        assert_eq!(second.len(), 1 + 1 + 5 + 1);
+
+        // Shrinking the table is announced in the next block.
+        encoder.set_max_table_size(0);
+        encoder.set_max_table_size(256);
+        let third = encoder.encode(responses[1].clone());
+        assert_eq!(&third[..3], [0x20, 0x3f, 0xe1]);
+        assert_eq!(decoder.decode(&third).unwrap(), responses[1]);
+    }
+
+    #[test]
+    fn rejects_malformed_blocks() {
+        let mut decoder = Decoder::new();
+        assert_eq!(decoder.decode(&[0xbe]), Err(DecodeError::InvalidIndex(62)));
+        assert_eq!(decoder.decode(&[0x80]), Err(DecodeError::InvalidIndex(0)));
+        assert_eq!(
+            decoder.decode(&[0x41, 0x05, b'a']),
+            Err(DecodeError::Truncated)
+        );
+        // A size update after a field, or above the limit.
+        assert_eq!(
+            decoder.decode(&[0x82, 0x20]),
+            Err(DecodeError::InvalidSizeUpdate)
+        );
+        assert_eq!(
+            decoder.decode(&[0x3f, 0xe2, 0x1f]),
+            Err(DecodeError::InvalidSizeUpdate)
+        );
+
+        // Too large a list is decoded all the same, into the table too.
+        let mut decoder = Decoder::new().max_list_size(64);
+        let block = unhex("4088 25a8 49e9 5ba9 7d7f 8925 a849 e95b b8e8 b4bf 82");
+        assert_eq!(decoder.decode(&block), Err(DecodeError::ListTooLarge));
+        assert_eq!(
+            decoder.decode(&[0xbe]).unwrap(),
+            fields(&[("custom-key", "custom-value")])
+        );
+    }
+}
@@ -0,0 +1,195 @@
+//! The tables header fields are indexed in (RFC 7541 section 2.3): a static
+//! one of common fields, followed by a dynamic one of fields seen on the
+//! connection, newest first.
+
+use std::collections::VecDeque;
+
+use super::Field;
+
+/// Appendix A.
+const STATIC: [(&str, &str); 61] = [
+    (":authority", ""),
+    (":method", "GET"),
+    (":method", "POST"),
+    (":path", "/"),
+    (":path", "/index.html"),
+    (":scheme", "http"),
+    (":scheme", "https"),
+    (":status", "200"),
+    (":status", "204"),
+    (":status", "206"),
+    (":status", "304"),
+    (":status", "400"),
+    (":status", "404"),
+    (":status", "500"),
+    ("accept-charset", ""),
+    ("accept-encoding", "gzip, deflate"),
+    ("accept-language", ""),
+    ("accept-ranges", ""),
+    ("accept", ""),
+    ("access-control-allow-origin", ""),
+    ("age", ""),
+    ("allow", ""),
+    ("authorization", ""),
+    ("cache-control", ""),
+    ("content-disposition", ""),
+    ("content-encoding", ""),
+    ("content-language", ""),
+    ("content-length", ""),
+    ("content-location", ""),
+    ("content-range", ""),
+    ("content-type", ""),
+    ("cookie", ""),
+    ("date", ""),
+    ("etag", ""),
+    ("expect", ""),
+    ("expires", ""),
+    ("from", ""),
+    ("host", ""),
+    ("if-match", ""),
+    ("if-modified-since", ""),
+    ("if-none-match", ""),
+    ("if-range", ""),
+    ("if-unmodified-since", ""),
+    ("last-modified", ""),
+    ("link", ""),
+    ("location", ""),
+    ("max-forwards", ""),
+    ("proxy-authenticate", ""),
+    ("proxy-authorization", ""),
+    ("range", ""),
+    ("referer", ""),
+    ("refresh", ""),
+    ("retry-after", ""),
+    ("server", ""),
+    ("set-cookie", ""),
+    ("strict-transport-security", ""),
+    ("transfer-encoding", ""),
+    ("user-agent", ""),
+    ("vary", ""),
+    ("via", ""),
+    ("www-authenticate", ""),
+];
+
+/// What an entry takes up in the dynamic table: its name and value, plus 32
+/// bytes of overhead (section 4.1).
+pub(super) fn entry_size(name: &[u8], value: &[u8]) -> usize {
+    name.len() + value.len() + 32
+}
+
+/// The static table and a dynamic table of at most `max_size`, indexed
+/// together from 1.
+#[derive(Debug)]
+pub(super) struct Table {
+    dynamic: VecDeque<Field>,
+    size: usize,
+    max_size: usize,
+}
+
+impl Table {
+    pub(super) fn new(max_size: usize) -> Table {
+        Table {
+            dynamic: VecDeque::new(),
+            size: 0,
+            max_size,
+        }
+    }
+
+    pub(super) fn max_size(&self) -> usize {
+        self.max_size
+    }
+
+    /// The field at `index`, or `None` if neither table has it.
+    pub(super) fn get(&self, index: usize) -> Option<(&[u8], &[u8])> {
+        match index {
+            0 => None,
+            1..=61 => {
+                let (name, value) = STATIC[index - 1];
+                Some((name.as_bytes(), value.as_bytes()))
+            }
+            _ => self
+                .dynamic
+                .get(index - 62)
+                .map(|(name, value)| (name.as_slice(), value.as_slice())),
+        }
+    }
+
+    /// The index of a field with this name and value, and `true`; or else
+    /// the index of one with this name, and `false`.
+    pub(super) fn find(&self, name: &[u8], value: &[u8]) -> Option<(usize, bool)> {
+        let fields = STATIC
+            .iter()
+            .map(|(n, v)| (n.as_bytes(), v.as_bytes()))
+            .chain(
+                self.dynamic
+                    .iter()
+                    .map(|(n, v)| (n.as_slice(), v.as_slice())),
+            );
+
+        let mut same_name = None;
+        for (i, (n, v)) in fields.enumerate() {
+            if n == name {
+                if v == value {
+                    return Some((i + 1, true));
+                }
+                same_name.get_or_insert(i + 1);
+            }
+        }
+        same_name.map(|index| (index, false))
+    }
+
+    /// Add a field, evicting the oldest ones to make room. A field larger
+    /// than the whole table empties it and isn't added (section 4.4).
+    pub(super) fn insert(&mut self, name: Vec<u8>, value: Vec<u8>) {
+        let size = entry_size(&name, &value);
+        self.evict(self.max_size.saturating_sub(size));
+        if size <= self.max_size {
+            self.size += size;
+            self.dynamic.push_front((name, value));
+        }
+    }
+
+    pub(super) fn set_max_size(&mut self, max_size: usize) {
+        self.max_size = max_size;
+        self.evict(max_size);
+    }
+
+    /// Evict the oldest fields until the table takes up `size` at most.
+    fn evict(&mut self, size: usize) {
+        while self.size > size {
+            let Some((name, value)) = self.dynamic.pop_back() else {
+                break;
+            };
+            self.size -= entry_size(&name, &value);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn evicts_the_oldest_fields() {
+        let mut table = Table::new(100);
+        table.insert(b"a".to_vec(), b"1".to_vec());
+        table.insert(b"b".to_vec(), b"2".to_vec());
+        assert_eq!(table.get(62), Some((&b"b"[..], &b"2"[..])));
+        assert_eq!(table.get(63), Some((&b"a"[..], &b"1"[..])));
+        assert_eq!(table.find(b"a", b"1"), Some((63, true)));
+        assert_eq!(table.find(b":status", b"204"), Some((9, true)));
+        assert_eq!(table.find(b":status", b"302"), Some((8, false)));
+
+        // 34 bytes each, so a third one pushes out the first.
+        table.insert(b"c".to_vec(), b"3".to_vec());
+        assert_eq!(table.find(b"a", b"1"), None);
+        assert_eq!(table.get(64), None);
+
+        table.set_max_size(40);
+        assert_eq!(table.get(62), Some((&b"c"[..], &b"3"[..])));
+        assert_eq!(table.get(63), None);
+
+        table.insert(vec![b'x'; 10], Vec::new());
+        assert_eq!(table.get(62), None);
+    }
+}
@@ -0,0 +1,401 @@
+//! HTTP/2 over cleartext connections, "h2c" (RFC 9113): the frames and the
+//! HPACK header compression, and how header blocks map to `Request`s.
+//!
+//! The server speaks it to clients that open with the connection preface,
+//! knowing in advance that it does, and to those that ask to switch from
+//! HTTP/1.1 with `Upgrade: h2c`. Handlers get the same `Request`s either
+//! way, with `Version::Http2`.
+
+pub mod frame;
+pub mod hpack;
+
+use crate::{
+    base64,
+    http::{Headers, Request, Version},
+};
+use frame::{Error, ErrorCode};
+use hpack::Field;
+
+/// What a client sends first on an HTTP/2 connection (section 3.4).
+pub const PREFACE: &[u8] = b"PRI * HTTP/2.0\r\n\r\nSM\r\n\r\n";
+
+/// The largest flow control window (section 6.9.1).
+pub const MAX_WINDOW_SIZE: u32 = (1 << 31) - 1;
+
+/// The window of each stream, and of the connection as a whole, until the
+/// peer says otherwise.
+pub const DEFAULT_WINDOW_SIZE: u32 = 65_535;
+
+/// The parameters of a `SETTINGS` frame (section 6.5.2).
+pub const SETTINGS_HEADER_TABLE_SIZE: u16 = 0x1;
+pub const SETTINGS_ENABLE_PUSH: u16 = 0x2;
+pub const SETTINGS_MAX_CONCURRENT_STREAMS: u16 = 0x3;
+pub const SETTINGS_INITIAL_WINDOW_SIZE: u16 = 0x4;
+pub const SETTINGS_MAX_FRAME_SIZE: u16 = 0x5;
+pub const SETTINGS_MAX_HEADER_LIST_SIZE: u16 = 0x6;
+
+/// Header fields that only concern one HTTP/1.1 connection, which HTTP/2
+/// messages don't have (section 8.2.2).
+pub(crate) const CONNECTION_SPECIFIC: &[&str] = &[
+    "connection",
+    "keep-alive",
+    "proxy-connection",
+    "transfer-encoding",
+    "upgrade",
+];
+
+/// How far the bytes a client sent are into the preface.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Preface {
+    /// All of it is in.
+    Complete,
+    /// What's in matches it so far.
+    Partial,
+    /// It's something else, like an HTTP/1 request.
+    Absent,
+}
+
+pub(crate) fn preface(buf: &[u8]) -> Preface {
+    if buf.starts_with(PREFACE) {
+        Preface::Complete
+    } else if !buf.is_empty() && PREFACE.starts_with(buf) {
+        Preface::Partial
+    } else {
+        Preface::Absent
+    }
+}
+
+/// What one end told the other about itself.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Settings {
+    pub header_table_size: u32,
+    pub enable_push: bool,
+    /// `None` if there's no limit.
+    pub max_concurrent_streams: Option<u32>,
+    pub initial_window_size: u32,
+    pub max_frame_size: u32,
+    /// `None` if there's no limit.
+    pub max_header_list_size: Option<u32>,
+}
+
+impl Default for Settings {
+    fn default() -> Settings {
+        Settings {
+            header_table_size: hpack::DEFAULT_TABLE_SIZE as u32,
+            enable_push: true,
+            max_concurrent_streams: None,
+            initial_window_size: DEFAULT_WINDOW_SIZE,
+            max_frame_size: frame::DEFAULT_MAX_FRAME_SIZE,
+            max_header_list_size: None,
+        }
+    }
+}
+
+impl Settings {
+    /// Take in the parameters of a `SETTINGS` frame, ignoring unknown ones.
+    pub fn apply(&mut self, settings: &[(u16, u32)]) -> Result<(), Error> {
+        for &(id, value) in settings {
+            match id {
+                SETTINGS_HEADER_TABLE_SIZE => self.header_table_size = value,
+                SETTINGS_ENABLE_PUSH => {
+                    self.enable_push = match value {
+                        0 => false,
+                        1 => true,
+                        _ => {
+                            return Err(Error::Connection(
+                                ErrorCode::ProtocolError,
+                                "SETTINGS_ENABLE_PUSH isn't 0 or 1",
+                            ))
+                        }
+                    }
+                }
+                SETTINGS_MAX_CONCURRENT_STREAMS => self.max_concurrent_streams = Some(value),
+                SETTINGS_INITIAL_WINDOW_SIZE => {
+                    if value > MAX_WINDOW_SIZE {
+                        return Err(Error::Connection(
+                            ErrorCode::FlowControlError,
+                            "SETTINGS_INITIAL_WINDOW_SIZE is too large",
+                        ));
+                    }
+                    self.initial_window_size = value;
+                }
+                SETTINGS_MAX_FRAME_SIZE => {
+                    if !(frame::DEFAULT_MAX_FRAME_SIZE..1 << 24).contains(&value) {
+                        return Err(Error::Connection(
+                            ErrorCode::ProtocolError,
+                            "SETTINGS_MAX_FRAME_SIZE is out of range",
+                        ));
+                    }
+                    self.max_frame_size = value;
+                }
+                SETTINGS_MAX_HEADER_LIST_SIZE => self.max_header_list_size = Some(value),
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+
+    /// The parameters that differ from the defaults, to send.
+    pub fn to_params(&self) -> Vec<(u16, u32)> {
+        let defaults = Settings::default();
+        let mut params = Vec::new();
+        if self.header_table_size != defaults.header_table_size {
+            params.push((SETTINGS_HEADER_TABLE_SIZE, self.header_table_size));
+        }
+        if self.enable_push != defaults.enable_push {
+            params.push((SETTINGS_ENABLE_PUSH, u32::from(self.enable_push)));
+        }
+        if let Some(max) = self.max_concurrent_streams {
+            params.push((SETTINGS_MAX_CONCURRENT_STREAMS, max));
+        }
+        if self.initial_window_size != defaults.initial_window_size {
+            params.push((SETTINGS_INITIAL_WINDOW_SIZE, self.initial_window_size));
+        }
+        if self.max_frame_size != defaults.max_frame_size {
+            params.push((SETTINGS_MAX_FRAME_SIZE, self.max_frame_size));
+        }
+        if let Some(max) = self.max_header_list_size {
+            params.push((SETTINGS_MAX_HEADER_LIST_SIZE, max));
+        }
+        params
+    }
+}
+
+/// The settings sent along with a request to switch to HTTP/2 with
+/// `Upgrade: h2c` (RFC 7540 section 3.2), or `None` if it isn't one, or the
+/// settings are malformed.
+pub fn upgrade_settings(request: &Request) -> Option<Vec<(u16, u32)>> {
+    let headers = &request.headers;
+    if request.version != Version::Http11
+        || !headers.has_token("Upgrade", "h2c")
+        || !headers.has_token("Connection", "Upgrade")
+        || !headers.has_token("Connection", "HTTP2-Settings")
+    {
+        return None;
+    }
+    let mut values = headers.get_all("HTTP2-Settings");
+    let (Some(value), None) = (values.next(), values.next()) else {
+        return None;
+    };
+
+    let payload = base64::decode_url(value.trim())?;
+    if payload.len() % 6 != 0 {
+        return None;
+    }
+    let settings = payload
+        .chunks(6)
+        .map(|setting| {
+            let id = u16::from_be_bytes([setting[0], setting[1]]);
+            let value = u32::from_be_bytes([setting[2], setting[3], setting[4], setting[5]]);
+            (id, value)
+        })
+        .collect();
+    Some(settings)
+}
+
+/// The request a decoded header block makes, or why it's malformed (section
+/// 8.3), which resets its stream.
+///
+/// `:authority` becomes the `Host` header unless there's one, and `cookie`
+/// fields, which HTTP/2 clients may split, are joined again.
+pub fn request_from_fields(fields: Vec<Field>) -> Result<Request, &'static str> {
+    let mut method = None;
+    let mut scheme = None;
+    let mut path = None;
+    let mut authority = None;
+    let mut headers = Headers::new();
+    let mut cookies = Vec::new();
+
+    for (name, value) in fields {
+        let name = String::from_utf8(name).map_err(|_| "header field name isn't UTF-8")?;
+        let value = String::from_utf8(value).map_err(|_| "header field value isn't UTF-8")?;
+        if value.contains(['\0', '\r', '\n']) || value.trim_matches([' ', '\t']) != value {
+            return Err("invalid header field value");
+        }
+
+        if let Some(pseudo) = name.strip_prefix(':') {
+            if !headers.is_empty() || !cookies.is_empty() {
+                return Err("pseudo-header field after a regular one");
+            }
+            let slot = match pseudo {
+                "method" => &mut method,
+                "scheme" => &mut scheme,
+                "path" => &mut path,
+                "authority" => &mut authority,
+                _ => return Err("unknown pseudo-header field"),
+            };
+            if slot.replace(value).is_some() {
+                return Err("repeated pseudo-header field");
+            }
+            continue;
+        }
+
+        if !is_field_name(&name) {
+            return Err("invalid header field name");
+        }
+        if CONNECTION_SPECIFIC.contains(&name.as_str()) || (name == "te" && value != "trailers") {
+            return Err("connection-specific header field");
+        }
+        if name == "cookie" {
+            cookies.push(value);
+        } else {
+            headers.append(name, value);
+        }
+    }
+
+    let method = method.ok_or("no :method pseudo-header field")?;
+    let target = if method == "CONNECT" {
+        if scheme.is_some() || path.is_some() {
+            return Err(":scheme or :path in a CONNECT request");
+        }
+        authority
+            .clone()
+            .ok_or("no :authority pseudo-header field")?
+    } else {
+        scheme.ok_or("no :scheme pseudo-header field")?;
+        path.filter(|path| !path.is_empty())
+            .ok_or("no :path pseudo-header field")?
+    };
+
+    if !cookies.is_empty() {
+        headers.append("cookie", cookies.join("; "));
+    }
+    if let Some(authority) = authority.filter(|_| !headers.contains("host")) {
+        headers.append("host", authority);
+    }
+
+    let mut request = Request::new(method, target);
+    request.version = Version::Http2;
+    request.headers = headers;
+    Ok(request)
+}
+
+/// A lowercase token (RFC 9110 section 5.1).
+fn is_field_name(name: &str) -> bool {
+    !name.is_empty()
+        && name.bytes().all(|b| {
+            b.is_ascii_lowercase() || b.is_ascii_digit() || b"!#$%&'*+-.^_`|~".contains(&b)
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn fields(fields: &[(&str, &str)]) -> Vec<Field> {
+        fields
+            .iter()
+            .map(|(name, value)| (name.as_bytes().to_vec(), value.as_bytes().to_vec()))
+            .collect()
+    }
+
+    #[test]
+    fn recognizes_the_preface() {
+        assert_eq!(preface(b""), Preface::Absent);
+        assert_eq!(preface(b"PRI * HTTP/2.0\r\n\r\n"), Preface::Partial);
+        assert_eq!(preface(b"POST / HTTP/1.1\r\n"), Preface::Absent);
+        let mut buf = PREFACE.to_vec();
+        buf.extend_from_slice(&[0, 0, 0, 4]);
+        assert_eq!(preface(&buf), Preface::Complete);
+    }
+
+    #[test]
+    fn applies_settings() {
+        let mut settings = Settings::default();
+        settings
+            .apply(&[
+                (SETTINGS_INITIAL_WINDOW_SIZE, 1 << 20),
+                (SETTINGS_MAX_CONCURRENT_STREAMS, 100),
+                (0xff, 1),
+            ])
+            .unwrap();
+        assert_eq!(settings.initial_window_size, 1 << 20);
+        assert_eq!(
+            settings.to_params(),
+            [
+                (SETTINGS_MAX_CONCURRENT_STREAMS, 100),
+                (SETTINGS_INITIAL_WINDOW_SIZE, 1 << 20)
+            ]
+        );
+
+        for bad in [
+            (SETTINGS_ENABLE_PUSH, 2),
+            (SETTINGS_INITIAL_WINDOW_SIZE, 1 << 31),
+            (SETTINGS_MAX_FRAME_SIZE, 100),
+        ] {
+            assert!(settings.apply(&[bad]).is_err());
+        }
+    }
+
+    #[test]
+    fn reads_upgrade_settings() {
+        let mut request = Request::new("GET", "/");
+        request
+            .headers
+            .append("Connection", "Upgrade, HTTP2-Settings");
+        request.headers.append("Upgrade", "h2c");
+        request
+            .headers
+            .append("HTTP2-Settings", "AAMAAABkAAQAoAAAAAIAAAAA");
+        assert_eq!(
+            upgrade_settings(&request),
+            Some(vec![
+                (SETTINGS_MAX_CONCURRENT_STREAMS, 100),
+                (SETTINGS_INITIAL_WINDOW_SIZE, 0xa0_0000),
+                (SETTINGS_ENABLE_PUSH, 0),
+            ])
+        );
+
+        request.headers.insert("HTTP2-Settings", "AAMAAABk!");
+        assert_eq!(upgrade_settings(&request), None);
+        request.headers.remove("HTTP2-Settings");
+        assert_eq!(upgrade_settings(&request), None);
+    }
+
+    #[test]
+    fn builds_requests() {
+        let request = request_from_fields(fields(&[
+            (":method", "GET"),
+            (":scheme", "http"),
+            (":authority", "example.com"),
+            (":path", "/search?q=h2"),
+            ("cookie", "a=1"),
+            ("accept", "*/*"),
+            ("cookie", "b=2"),
+        ]))
+        .unwrap();
+        assert_eq!(request.method, "GET");
+        assert_eq!(request.target, "/search?q=h2");
+        assert_eq!(request.version, Version::Http2);
+        assert_eq!(request.header("Host"), Some("example.com"));
+        assert_eq!(request.header("Cookie"), Some("a=1; b=2"));
+        assert_eq!(request.header("Accept"), Some("*/*"));
+    }
+
+    #[test]
+    fn rejects_malformed_requests() {
+        let base = [(":method", "GET"), (":scheme", "http"), (":path", "/")];
+        for extra in [
+            ("Accept", "*/*"),
+            ("connection", "close"),
+            ("te", "gzip"),
+            (":status", "200"),
+            (":path", "/again"),
+            ("x-bad", "a\r\nb"),
+            ("x-bad", " padded"),
+        ] {
+            let mut fields = fields(&base);
+            fields.push((extra.0.as_bytes().to_vec(), extra.1.as_bytes().to_vec()));
+            assert!(request_from_fields(fields).is_err(), "{extra:?}");
+        }
+
+        let late_pseudo = fields(&[
+            (":method", "GET"),
+            ("accept", "*/*"),
+            (":scheme", "http"),
+            (":path", "/"),
+        ]);
+        assert!(request_from_fields(late_pseudo).is_err());
+        assert!(request_from_fields(fields(&base[..2])).is_err());
+    }
+}
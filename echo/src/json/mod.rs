@@ -0,0 +1,448 @@
+//! JSON values, parsed from and serialized to text (RFC 8259).
+//!
+//! ```
+//! use echo::json::{self, Value};
+//!
+//! let value = json::from_str(r#"{"name": "echo", "tags": ["http", 1.1]}"#).unwrap();
+//! assert_eq!(value.get("name").and_then(Value::as_str), Some("echo"));
+//! assert_eq!(value.to_string(), r#"{"name":"echo","tags":["http",1.1]}"#);
+//! ```
+//!
+//! Numbers are `f64`s, so integers are exact up to 2<sup>53</sup>. Object
+//! keys are kept sorted.
+
+mod parse;
+
+use std::{
+    collections::BTreeMap,
+    error, fmt,
+    io::{self, Read},
+};
+
+use crate::http::Response;
+use parse::Parser;
+
+#[derive(Debug, Clone, PartialEq, Default)]
+pub enum Value {
+    #[default]
+    Null,
+    Bool(bool),
+    Number(f64),
+    String(String),
+    Array(Vec<Value>),
+    Object(BTreeMap<String, Value>),
+}
+
+impl Value {
+    /// The field called `key`, if this is an object that has one.
+    pub fn get(&self, key: &str) -> Option<&Value> {
+        match self {
+            Value::Object(fields) => fields.get(key),
+            _ => None,
+        }
+    }
+
+    pub fn is_null(&self) -> bool {
+        *self == Value::Null
+    }
+
+    pub fn as_bool(&self) -> Option<bool> {
+        match self {
+            Value::Bool(b) => Some(*b),
+            _ => None,
+        }
+    }
+
+    pub fn as_f64(&self) -> Option<f64> {
+        match self {
+            Value::Number(n) => Some(*n),
+            _ => None,
+        }
+    }
+
+    /// The number, if it's a whole one that fits.
+    pub fn as_i64(&self) -> Option<i64> {
+        match self {
+            Value::Number(n) if n.fract() == 0.0 && n.abs() < 2f64.powi(63) => Some(*n as i64),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(&self) -> Option<&str> {
+        match self {
+            Value::String(s) => Some(s),
+            _ => None,
+        }
+    }
+
+    pub fn as_array(&self) -> Option<&[Value]> {
+        match self {
+            Value::Array(items) => Some(items),
+            _ => None,
+        }
+    }
+
+    pub fn as_object(&self) -> Option<&BTreeMap<String, Value>> {
+        match self {
+            Value::Object(fields) => Some(fields),
+            _ => None,
+        }
+    }
+
+    /// The value as indented JSON, two spaces a level. Same as `{:#}`.
+    pub fn to_pretty_string(&self) -> String {
+        format!("{self:#}")
+    }
+
+    fn write(&self, f: &mut fmt::Formatter<'_>, indent: usize) -> fmt::Result {
+        let pretty = f.alternate();
+        let newline = |f: &mut fmt::Formatter<'_>, indent: usize| {
+            if pretty {
+                write!(f, "\n{:1$}", "", indent * 2)?;
+            }
+            Ok(())
+        };
+
+        match self {
+            Value::Null => f.write_str("null"),
+            Value::Bool(b) => write!(f, "{b}"),
+            Value::Number(n) if !n.is_finite() => f.write_str("null"),
+            // Whole numbers without the `.0` Rust would give them.
+            Value::Number(n) if n.fract() == 0.0 && n.abs() < 1e15 => write!(f, "{}", *n as i64),
+            Value::Number(n) => write!(f, "{n}"),
+            Value::String(s) => write_string(f, s),
+            Value::Array(items) if items.is_empty() => f.write_str("[]"),
+            Value::Array(items) => {
+                f.write_str("[")?;
+                for (i, item) in items.iter().enumerate() {
+                    if i > 0 {
+                        f.write_str(",")?;
+                    }
+                    newline(f, indent + 1)?;
+                    item.write(f, indent + 1)?;
+                }
+                newline(f, indent)?;
+                f.write_str("]")
+            }
+            Value::Object(fields) if fields.is_empty() => f.write_str("{}"),
+            Value::Object(fields) => {
+                f.write_str("{")?;
+                for (i, (key, value)) in fields.iter().enumerate() {
+                    if i > 0 {
+                        f.write_str(",")?;
+                    }
+                    newline(f, indent + 1)?;
+                    write_string(f, key)?;
+                    f.write_str(if pretty { ": " } else { ":" })?;
+                    value.write(f, indent + 1)?;
+                }
+                newline(f, indent)?;
+                f.write_str("}")
+            }
+        }
+    }
+}
+
+/// Compact JSON, or indented JSON with `{:#}`.
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.write(f, 0)
+    }
+}
+
+fn write_string(f: &mut fmt::Formatter<'_>, s: &str) -> fmt::Result {
+    f.write_str("\"")?;
+    for c in s.chars() {
+        match c {
+            '"' => f.write_str("\\\"")?,
+            '\\' => f.write_str("\\\\")?,
+            '\n' => f.write_str("\\n")?,
+            '\r' => f.write_str("\\r")?,
+            '\t' => f.write_str("\\t")?,
+            c if c < ' ' => write!(f, "\\u{:04x}", c as u32)?,
+            c => write!(f, "{c}")?,
+        }
+    }
+    f.write_str("\"")
+}
+
+impl From<bool> for Value {
+    fn from(b: bool) -> Value {
+        Value::Bool(b)
+    }
+}
+
+impl From<f64> for Value {
+    fn from(n: f64) -> Value {
+        Value::Number(n)
+    }
+}
+
+impl From<i64> for Value {
+    fn from(n: i64) -> Value {
+        Value::Number(n as f64)
+    }
+}
+
+impl From<i32> for Value {
+    fn from(n: i32) -> Value {
+        Value::Number(n.into())
+    }
+}
+
+impl From<u64> for Value {
+    fn from(n: u64) -> Value {
+        Value::Number(n as f64)
+    }
+}
+
+impl From<usize> for Value {
+    fn from(n: usize) -> Value {
+        Value::Number(n as f64)
+    }
+}
+
+impl From<&str> for Value {
+    fn from(s: &str) -> Value {
+        Value::String(s.to_string())
+    }
+}
+
+impl From<String> for Value {
+    fn from(s: String) -> Value {
+        Value::String(s)
+    }
+}
+
+impl<T: Into<Value>> From<Option<T>> for Value {
+    fn from(value: Option<T>) -> Value {
+        value.map_or(Value::Null, Into::into)
+    }
+}
+
+impl<T: Into<Value>> From<Vec<T>> for Value {
+    fn from(values: Vec<T>) -> Value {
+        Value::Array(values.into_iter().map(Into::into).collect())
+    }
+}
+
+/// An object from `(key, value)` pairs.
+impl<K: Into<String>, V: Into<Value>> FromIterator<(K, V)> for Value {
+    fn from_iter<I: IntoIterator<Item = (K, V)>>(pairs: I) -> Value {
+        Value::Object(
+            pairs
+                .into_iter()
+                .map(|(key, value)| (key.into(), value.into()))
+                .collect(),
+        )
+    }
+}
+
+/// Why JSON couldn't be parsed.
+#[derive(Debug)]
+pub enum Error {
+    /// The input isn't valid JSON. Lines and columns count from 1, columns
+    /// in characters.
+    Syntax {
+        line: usize,
+        column: usize,
+        message: String,
+    },
+    /// Reading the input failed.
+    Io(io::Error),
+}
+
+impl Error {
+    fn syntax(line: usize, column: usize, message: impl Into<String>) -> Error {
+        Error::Syntax {
+            line,
+            column,
+            message: message.into(),
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Syntax {
+                line,
+                column,
+                message,
+            } => write!(f, "line {line}, column {column}: {message}"),
+            Error::Io(e) => write!(f, "failed to read JSON: {e}"),
+        }
+    }
+}
+
+impl error::Error for Error {}
+
+/// A `400 Bad Request` response saying what's wrong with the JSON, for
+/// handlers to return with `?` or `.into()`.
+impl From<Error> for Response {
+    fn from(e: Error) -> Response {
+        let body: Value = [("error", format!("invalid JSON: {e}"))]
+            .into_iter()
+            .collect();
+        Response::json(400, &body)
+    }
+}
+
+/// Parse a JSON document.
+///
+/// # Errors
+///
+/// If the text isn't a single valid JSON value.
+pub fn from_str(s: &str) -> Result<Value, Error> {
+    from_reader(s.as_bytes())
+}
+
+pub fn from_slice(bytes: &[u8]) -> Result<Value, Error> {
+    from_reader(bytes)
+}
+
+/// Parse a JSON document as it's read, up to the end of `reader`.
+pub fn from_reader<R: Read>(reader: R) -> Result<Value, Error> {
+    Parser::new(reader).parse_document()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::http::Request;
+
+    fn syntax_error(source: &str) -> String {
+        from_str(source).unwrap_err().to_string()
+    }
+
+    #[test]
+    fn parses_values() {
+        let value = from_str(
+            " {\"a\": [1, -2.5, 3e2, 0, true, false, null],\n \"b\": {\"c\": \"\\\"\\u00e9\\ud83d\\ude00\\n\"}, \"a\": []} ",
+        )
+        .unwrap();
+        assert_eq!(value.get("a"), Some(&Value::Array(Vec::new())));
+        assert_eq!(
+            value
+                .get("b")
+                .and_then(|b| b.get("c"))
+                .and_then(Value::as_str),
+            Some("\"é😀\n")
+        );
+
+        let numbers = from_str("[1, -2.5, 3e2, 0, 1E-2]").unwrap();
+        let numbers: Vec<_> = numbers
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|n| n.as_f64().unwrap())
+            .collect();
+        assert_eq!(numbers, [1.0, -2.5, 300.0, 0.0, 0.01]);
+        assert_eq!(from_str("\"ünï\"").unwrap().as_str(), Some("ünï"));
+        assert_eq!(from_str("42").unwrap().as_i64(), Some(42));
+    }
+
+    #[test]
+    fn reports_where_errors_are() {
+        assert_eq!(
+            syntax_error(""),
+            "line 1, column 1: expected a value, found the end of the input"
+        );
+        assert_eq!(
+            syntax_error("{\n  \"a\": 1,\n  \"b\" 2\n}"),
+            "line 3, column 7: expected `:`, found `2`"
+        );
+        assert_eq!(
+            syntax_error("[1, 2"),
+            "line 1, column 6: the array is never closed"
+        );
+        assert_eq!(
+            syntax_error("[1,]"),
+            "line 1, column 4: expected a value, found `]`"
+        );
+        assert_eq!(
+            syntax_error("\"é\" x"),
+            "line 1, column 5: expected the end of the input, found `x`"
+        );
+        assert_eq!(
+            syntax_error("01"),
+            "line 1, column 2: expected the end of the input, found `1`"
+        );
+        assert_eq!(
+            syntax_error("1."),
+            "line 1, column 3: expected a digit after the decimal point"
+        );
+        assert_eq!(syntax_error("tru"), "line 1, column 4: expected `true`");
+        assert_eq!(
+            syntax_error("\"a\tb\""),
+            "line 1, column 3: control characters in strings must be escaped"
+        );
+        assert_eq!(
+            syntax_error("\"\\ud800\""),
+            "line 1, column 8: unpaired surrogate in a `\\u` escape"
+        );
+        assert_eq!(
+            syntax_error("1e999"),
+            "line 1, column 1: the number is out of range"
+        );
+        assert!(syntax_error(&"[".repeat(1000)).contains("nest deeper than 128"));
+    }
+
+    #[test]
+    fn serializes() {
+        let value: Value = [
+            ("name", Value::from("a \"b\"\n\u{1}")),
+            (
+                "list",
+                vec![
+                    Value::from(1),
+                    2.5.into(),
+                    Value::Null,
+                    Vec::<Value>::new().into(),
+                ]
+                .into(),
+            ),
+            ("empty", Value::Object(BTreeMap::new())),
+            ("nan", f64::NAN.into()),
+        ]
+        .into_iter()
+        .collect();
+
+        let compact = value.to_string();
+        assert_eq!(
+            compact,
+            r#"{"empty":{},"list":[1,2.5,null,[]],"name":"a \"b\"\n\u0001","nan":null}"#
+        );
+        assert_eq!(
+            value.to_pretty_string(),
+            "{\n  \"empty\": {},\n  \"list\": [\n    1,\n    2.5,\n    null,\n    []\n  ],\n  \
+             \"name\": \"a \\\"b\\\"\\n\\u0001\",\n  \"nan\": null\n}"
+        );
+
+        let round_trip = from_str(&compact).unwrap();
+        assert_eq!(round_trip.get("list"), value.get("list"));
+        assert_eq!(
+            from_str(&value.to_pretty_string()).unwrap().to_string(),
+            compact
+        );
+    }
+
+    #[test]
+    fn requests_and_responses() {
+        let mut request = Request::new("POST", "/");
+        request.body = b"{\"ok\": true}".to_vec().into();
+        assert_eq!(request.json().unwrap().get("ok"), Some(&Value::Bool(true)));
+
+        request.body = b"{\"ok\": tru}".to_vec().into();
+        let response = Response::from(request.json().unwrap_err());
+        assert_eq!(response.status, 400);
+        assert_eq!(
+            response.headers.get("Content-Type"),
+            Some("application/json")
+        );
+        assert_eq!(
+            response.body,
+            br#"{"error":"invalid JSON: line 1, column 11: expected `true`"}"#
+        );
+    }
+}
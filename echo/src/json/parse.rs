@@ -0,0 +1,318 @@
+//! Reading JSON (RFC 8259) a byte at a time, so that a value can be parsed
+//! straight from a stream.
+
+use std::{
+    collections::BTreeMap,
+    io::{self, BufReader, Bytes, Read},
+    iter::Peekable,
+};
+
+use super::{Error, Value};
+
+/// How deep arrays and objects may nest, which keeps hostile input from
+/// overflowing the stack.
+const MAX_DEPTH: usize = 128;
+
+pub(super) struct Parser<R: Read> {
+    bytes: Peekable<Bytes<BufReader<R>>>,
+    /// Where the next byte is, counting from 1.
+    line: usize,
+    column: usize,
+    depth: usize,
+}
+
+impl<R: Read> Parser<R> {
+    pub(super) fn new(reader: R) -> Parser<R> {
+        Parser {
+            bytes: BufReader::new(reader).bytes().peekable(),
+            line: 1,
+            column: 1,
+            depth: 0,
+        }
+    }
+
+    /// Parse the one value the input holds, with nothing but whitespace
+    /// around it.
+    pub(super) fn parse_document(&mut self) -> Result<Value, Error> {
+        let value = self.parse_value()?;
+        self.skip_whitespace()?;
+        match self.peek()? {
+            None => Ok(value),
+            Some(b) => Err(self.unexpected(b, "the end of the input")),
+        }
+    }
+
+    fn parse_value(&mut self) -> Result<Value, Error> {
+        self.skip_whitespace()?;
+        match self.peek()? {
+            Some(b'n') => self.literal("null", Value::Null),
+            Some(b't') => self.literal("true", Value::Bool(true)),
+            Some(b'f') => self.literal("false", Value::Bool(false)),
+            Some(b'"') => self.parse_string().map(Value::String),
+            Some(b'-' | b'0'..=b'9') => self.parse_number(),
+            Some(b'[') => self.nested(Parser::parse_array),
+            Some(b'{') => self.nested(Parser::parse_object),
+            Some(b) => Err(self.unexpected(b, "a value")),
+            None => Err(self.error("expected a value, found the end of the input")),
+        }
+    }
+
+    fn nested(&mut self, parse: fn(&mut Self) -> Result<Value, Error>) -> Result<Value, Error> {
+        if self.depth == MAX_DEPTH {
+            return Err(self.error(format!("arrays and objects nest deeper than {MAX_DEPTH}")));
+        }
+        self.depth += 1;
+        let value = parse(self);
+        self.depth -= 1;
+        value
+    }
+
+    fn parse_array(&mut self) -> Result<Value, Error> {
+        self.next()?;
+        let mut items = Vec::new();
+        self.skip_whitespace()?;
+        if self.eat(b']')? {
+            return Ok(Value::Array(items));
+        }
+        loop {
+            items.push(self.parse_value()?);
+            self.skip_whitespace()?;
+            match self.peek()? {
+                Some(b',') => self.next()?,
+                Some(b']') => {
+                    self.next()?;
+                    return Ok(Value::Array(items));
+                }
+                Some(b) => return Err(self.unexpected(b, "`,` or `]`")),
+                None => return Err(self.error("the array is never closed")),
+            };
+        }
+    }
+
+    fn parse_object(&mut self) -> Result<Value, Error> {
+        self.next()?;
+        let mut fields = BTreeMap::new();
+        self.skip_whitespace()?;
+        if self.eat(b'}')? {
+            return Ok(Value::Object(fields));
+        }
+        loop {
+            self.skip_whitespace()?;
+            let key = match self.peek()? {
+                Some(b'"') => self.parse_string()?,
+                Some(b) => return Err(self.unexpected(b, "a string key")),
+                None => return Err(self.error("the object is never closed")),
+            };
+            self.skip_whitespace()?;
+            match self.peek()? {
+                Some(b':') => self.next()?,
+                Some(b) => return Err(self.unexpected(b, "`:`")),
+                None => return Err(self.error("the object is never closed")),
+            };
+            // Like most parsers, the last of duplicate keys wins.
+            fields.insert(key, self.parse_value()?);
+
+            self.skip_whitespace()?;
+            match self.peek()? {
+                Some(b',') => self.next()?,
+                Some(b'}') => {
+                    self.next()?;
+                    return Ok(Value::Object(fields));
+                }
+                Some(b) => return Err(self.unexpected(b, "`,` or `}`")),
+                None => return Err(self.error("the object is never closed")),
+            };
+        }
+    }
+
+    fn parse_string(&mut self) -> Result<String, Error> {
+        self.next()?;
+        let mut bytes = Vec::new();
+        loop {
+            let (line, column) = (self.line, self.column);
+            match self.next()? {
+                Some(b'"') => break,
+                Some(b'\\') => {
+                    let c = self.parse_escape()?;
+                    bytes.extend_from_slice(c.encode_utf8(&mut [0; 4]).as_bytes());
+                }
+                Some(b) if b < 0x20 => {
+                    return Err(Error::syntax(
+                        line,
+                        column,
+                        "control characters in strings must be escaped",
+                    ))
+                }
+                Some(b) => bytes.push(b),
+                None => return Err(self.error("the string is never closed")),
+            }
+        }
+        String::from_utf8(bytes).map_err(|_| self.error("the string is not valid UTF-8"))
+    }
+
+    /// The character of an escape sequence, after its backslash.
+    fn parse_escape(&mut self) -> Result<char, Error> {
+        let c = match self.next()? {
+            Some(b'"') => '"',
+            Some(b'\\') => '\\',
+            Some(b'/') => '/',
+            Some(b'b') => '\u{8}',
+            Some(b'f') => '\u{c}',
+            Some(b'n') => '\n',
+            Some(b'r') => '\r',
+            Some(b't') => '\t',
+            Some(b'u') => {
+                let unit = self.parse_hex4()?;
+                if !(0xd800..0xdc00).contains(&unit) {
+                    return char::from_u32(unit)
+                        .ok_or_else(|| self.error("unpaired surrogate in a `\\u` escape"));
+                }
+                // A character outside the Basic Multilingual Plane, as a
+                // surrogate pair.
+                if !(self.eat(b'\\')? && self.eat(b'u')?) {
+                    return Err(self.error("unpaired surrogate in a `\\u` escape"));
+                }
+                let low = self.parse_hex4()?;
+                if !(0xdc00..0xe000).contains(&low) {
+                    return Err(self.error("unpaired surrogate in a `\\u` escape"));
+                }
+                char::from_u32(0x10000 + ((unit - 0xd800) << 10) + (low - 0xdc00)).unwrap()
+            }
+            Some(_) => return Err(self.error("unknown escape sequence")),
+            None => return Err(self.error("the string is never closed")),
+        };
+        Ok(c)
+    }
+
+    fn parse_hex4(&mut self) -> Result<u32, Error> {
+        let mut unit = 0;
+        for _ in 0..4 {
+            let digit = match self.peek()? {
+                Some(b) => (b as char).to_digit(16),
+                None => None,
+            };
+            let Some(digit) = digit else {
+                return Err(self.error("expected four hex digits after `\\u`"));
+            };
+            self.next()?;
+            unit = unit * 16 + digit;
+        }
+        Ok(unit)
+    }
+
+    fn parse_number(&mut self) -> Result<Value, Error> {
+        let (line, column) = (self.line, self.column);
+        let mut text = String::new();
+        if self.eat(b'-')? {
+            text.push('-');
+        }
+
+        // No leading zeros, and digits on both sides of the point.
+        match self.peek()? {
+            Some(b'0') => {
+                self.next()?;
+                text.push('0');
+            }
+            Some(b'1'..=b'9') => self.digits(&mut text)?,
+            _ => return Err(self.error("expected a digit")),
+        }
+        if self.eat(b'.')? {
+            text.push('.');
+            if !self.peek()?.is_some_and(|b| b.is_ascii_digit()) {
+                return Err(self.error("expected a digit after the decimal point"));
+            }
+            self.digits(&mut text)?;
+        }
+        if self.eat(b'e')? || self.eat(b'E')? {
+            text.push('e');
+            if let Some(sign @ (b'+' | b'-')) = self.peek()? {
+                self.next()?;
+                text.push(sign as char);
+            }
+            if !self.peek()?.is_some_and(|b| b.is_ascii_digit()) {
+                return Err(self.error("expected a digit in the exponent"));
+            }
+            self.digits(&mut text)?;
+        }
+
+        match text.parse::<f64>() {
+            Ok(n) if n.is_finite() => Ok(Value::Number(n)),
+            _ => Err(Error::syntax(line, column, "the number is out of range")),
+        }
+    }
+
+    fn digits(&mut self, text: &mut String) -> Result<(), Error> {
+        while let Some(b) = self.peek()?.filter(u8::is_ascii_digit) {
+            self.next()?;
+            text.push(b as char);
+        }
+        Ok(())
+    }
+
+    fn literal(&mut self, word: &str, value: Value) -> Result<Value, Error> {
+        for expected in word.bytes() {
+            if !self.eat(expected)? {
+                return Err(self.error(format!("expected `{word}`")));
+            }
+        }
+        Ok(value)
+    }
+
+    fn skip_whitespace(&mut self) -> Result<(), Error> {
+        while let Some(b' ' | b'\t' | b'\n' | b'\r') = self.peek()? {
+            self.next()?;
+        }
+        Ok(())
+    }
+
+    /// Consume the next byte if it's `expected`.
+    fn eat(&mut self, expected: u8) -> Result<bool, Error> {
+        if self.peek()? == Some(expected) {
+            self.next()?;
+            return Ok(true);
+        }
+        Ok(false)
+    }
+
+    fn peek(&mut self) -> Result<Option<u8>, Error> {
+        match self.bytes.peek() {
+            Some(Ok(b)) => Ok(Some(*b)),
+            Some(Err(_)) => Err(self.bytes.next().unwrap().unwrap_err().into()),
+            None => Ok(None),
+        }
+    }
+
+    fn next(&mut self) -> Result<Option<u8>, Error> {
+        let Some(b) = self.bytes.next().transpose()? else {
+            return Ok(None);
+        };
+        if b == b'\n' {
+            self.line += 1;
+            self.column = 1;
+        } else if b & 0xc0 != 0x80 {
+            // Columns count characters, not the bytes that continue them.
+            self.column += 1;
+        }
+        Ok(Some(b))
+    }
+
+    /// An error at the next byte.
+    fn error(&self, message: impl Into<String>) -> Error {
+        Error::syntax(self.line, self.column, message)
+    }
+
+    fn unexpected(&self, found: u8, expected: &str) -> Error {
+        let found = match found {
+            b'!'..=b'~' => format!("`{}`", found as char),
+            b'\n' => "a line break".to_string(),
+            _ => format!("byte 0x{found:02x}"),
+        };
+        self.error(format!("expected {expected}, found {found}"))
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(e: io::Error) -> Error {
+        Error::Io(e)
+    }
+}
@@ -1,54 +1,315 @@
-use echo::ThreadPool;
-
-use std::{
-    fs,
-    io::{prelude::*, BufReader},
-    net::{TcpListener, TcpStream},
-    thread,
-    time::Duration,
+use echo::{
+    access_log::AccessLog,
+    cgi::Cgi,
+    compress::Compress,
+    config::{self, Command, Config, CorsConfig, ListenAddr, SiteConfig},
+    files::StaticFiles,
+    http,
+    metrics::{Registry, ServerMetrics},
+    middleware::{BasicAuth, Cors, Pipeline, RateLimit, Recover, RequestId},
+    pool::{RejectionPolicy, ThreadPool},
+    proxy::Proxy,
+    server::Listener,
+    template::{Context, Templates},
+    vhost::VirtualHosts,
+    websocket::{self, Message, WebSocket},
+    Handler, Request, Response, Server,
 };
 
+use std::{env, error::Error, net::TcpListener, process, sync::Arc, thread, time::Duration};
+
 fn main() {
-    let listener = TcpListener::bind("127.0.0.1:7878").unwrap();
-    let pool = ThreadPool::new(4);
+    let config = match config::parse_args(env::args().skip(1)) {
+        Ok(Command::Run(config)) => config,
+        Ok(Command::CheckConfig(config)) => {
+            check_config(&config);
+            return;
+        }
+        Ok(Command::Help) => {
+            print!("{}", config::USAGE);
+            return;
+        }
+        Err(e) => fail(e),
+    };
+    if let Err(e) = config.validate() {
+        fail(e);
+    }
 
-    for stream in listener.incoming().take(10) {
-        let stream = stream.unwrap();
+    let listeners = listen(&config).unwrap_or_else(|e| fail(e));
 
-        println!("Connection established!");
+    let server = build_server(&config).unwrap_or_else(|e| fail(e));
 
-        pool.execute(|| {
-            handle_connection(stream);
-        });
+    for listener in &listeners {
+        println!("Listening on {listener} ({:?} backend)", config.backend);
     }
+    if let Err(e) = server.serve_all(listeners) {
+        fail(format!("Server error: {e}"));
+    }
+}
 
-    println!("Shutting down.");
+fn check_config(config: &Config) {
+    match config.validate() {
+        Ok(()) => print!("{config}\nThe configuration is valid.\n"),
+        Err(e) => fail(e),
+    }
 }
 
-fn handle_connection(mut stream: TcpStream) {
-    let buf_reader = BufReader::new(&mut stream);
-    let http_request: Vec<_> = buf_reader
-        .lines()
-        .map(|result| result.unwrap())
-        .take_while(|line| !line.is_empty())
-        .collect();
+/// The sockets systemd passed if it started the server, or else the
+/// configured ones.
+fn listen(config: &Config) -> Result<Vec<Listener>, String> {
+    #[cfg(target_os = "linux")]
+    {
+        let activated = Listener::from_env().map_err(|e| format!("socket activation: {e}"))?;
+        if !activated.is_empty() {
+            return Ok(activated);
+        }
+    }
+
+    config
+        .listen
+        .iter()
+        .map(|addr| {
+            let listener = match addr {
+                ListenAddr::Tcp(addr) => TcpListener::bind(addr).map(Listener::from),
+                #[cfg(unix)]
+                ListenAddr::Unix(path) => Listener::bind_unix(path, config.socket_mode),
+                #[cfg(not(unix))]
+                ListenAddr::Unix(_) => Err(std::io::Error::new(
+                    std::io::ErrorKind::Unsupported,
+                    "Unix sockets aren't supported on this platform",
+                )),
+            };
+            listener.map_err(|e| format!("{addr}: {e}"))
+        })
+        .collect()
+}
+
+fn build_server(config: &Config) -> Result<Server, Box<dyn Error>> {
+    let mut pool = ThreadPool::builder()
+        .size(config.workers)
+        .rejection_policy(RejectionPolicy::Reject)
+        .on_event(|event| println!("{event}"));
+    if let Some(capacity) = config.queue_capacity {
+        pool = pool.queue_capacity(capacity);
+    }
 
-    println!("Request: {:#?}", http_request);
+    let mut server = Server::builder().backend(config.backend).pool(pool.build());
+    let registry = Registry::new();
+    if config.metrics {
+        server = server.metrics(ServerMetrics::new(&registry).route(route(config)));
+    }
+    if let Some(log) = &config.access_log {
+        let mut access_log = AccessLog::builder()
+            .format(log.format)
+            .max_files(log.max_files);
+        if let Some(path) = &log.path {
+            access_log = access_log.path(path);
+        }
+        if let Some(max_size) = log.max_size {
+            access_log = access_log.max_size(max_size);
+        }
+        server = server.access_log(access_log.build()?);
+    }
+    if let Some(timeout) = config.read_timeout {
+        server = server.read_timeout(timeout);
+    }
+    if let Some(timeout) = config.write_timeout {
+        server = server.write_timeout(timeout);
+    }
+    if let Some(timeout) = config.header_timeout {
+        server = server.header_timeout(timeout);
+    }
+    server = server.limits(config.limits);
+    if let Some(max) = config.max_connections_per_ip {
+        server = server.max_connections_per_ip(max);
+    }
+    server = server.max_long_lived_streams(config.max_long_lived_streams);
 
-    let request_line = &http_request[0];
+    let mut hosts = VirtualHosts::new().default_host(build_site(config, &config.default_site()));
+    for site in &config.sites {
+        let patterns: Vec<&str> = site.hosts.iter().map(String::as_str).collect();
+        hosts = hosts.hosts(&patterns, build_site(config, site));
+    }
 
-    let (status_line, filename) = match &request_line[..] {
-        "GET / HTTP/1.1" => ("HTTP/1.1 200 OK", "hello.html"),
-        "GET /sleep HTTP/1.1" => {
-            thread::sleep(Duration::from_secs(5));
-            ("HTTP/1.1 200 OK", "hello.html")
+    let metrics = config.metrics;
+    let handler = move |request: &Request| {
+        if request.path() == "/metrics" && metrics {
+            return registry.response();
         }
-        _ => ("HTTP/1.1 404 NOT FOUND", "404.html"),
+        hosts.handle(request)
     };
 
-    let contents = fs::read_to_string(filename).unwrap();
-    let length = contents.len();
-    let response = format!("{status_line}\r\nContent-Length: {length}\r\n\r\n{contents}");
+    // The request ID goes outermost, so that even the error pages of panics
+    // carry it.
+    let mut pipeline = Pipeline::new(handler).with(RequestId::new());
+    if let Some(limit) = &config.rate_limit {
+        let mut rate_limit = RateLimit::new(limit.requests, limit.per);
+        if let Some(burst) = limit.burst {
+            rate_limit = rate_limit.burst(burst);
+        }
+        if let Some(header) = &limit.header {
+            rate_limit = rate_limit.key_header(header);
+        }
+        pipeline = pipeline.with(rate_limit);
+    }
+    if let Some(cors) = &config.cors {
+        pipeline = pipeline.with(build_cors(cors));
+    }
+    if let Some(auth) = &config.basic_auth {
+        let users = BasicAuth::from_file(&auth.users)
+            .map_err(|e| format!("{}: {e}", auth.users.display()))?;
+        pipeline = pipeline.with(users.realm(&auth.realm).prefix(&auth.prefix));
+    }
+
+    if config.compression {
+        Ok(server.build(Compress::new(pipeline)))
+    } else {
+        Ok(server.build(pipeline))
+    }
+}
+
+/// The handler of one site: its files, handlers and error pages.
+fn build_site(config: &Config, site: &SiteConfig) -> impl Handler {
+    let mut files = StaticFiles::new(&site.document_root)
+        .index("hello.html")
+        .precompress(config.compression);
+    if let Some(cache_control) = &site.cache_control {
+        files = files.cache_control(cache_control.clone());
+    }
+    let templates = Arc::new(Templates::new(
+        site.templates.as_ref().unwrap_or(&config.templates),
+    ));
+    let pages = Arc::clone(&templates);
+
+    let cgi = site.cgi.as_ref().map(|cgi| {
+        let handler = Cgi::new(&cgi.program)
+            .script_name(&cgi.prefix)
+            .timeout(cgi.timeout);
+        (cgi.prefix.clone(), handler)
+    });
+    let proxy = site.proxy.as_ref().map(|proxy| {
+        let handler = Proxy::new(proxy.upstream)
+            .strip_prefix(&proxy.prefix)
+            .timeout(proxy.timeout);
+        (proxy.prefix.clone(), handler)
+    });
+
+    let handler = move |request: &Request| {
+        if let Some((_, cgi)) = cgi.as_ref().filter(|(prefix, _)| under(request, prefix)) {
+            return cgi.serve(request);
+        }
+        if let Some((_, proxy)) = proxy.as_ref().filter(|(prefix, _)| under(request, prefix)) {
+            return proxy.serve(request);
+        }
+        handle(&files, &pages, request)
+    };
+    Pipeline::new(handler).with(Recover::new().response(move |request| {
+        error_page(
+            &templates,
+            request,
+            Response::text(500, "Internal Server Error\n"),
+        )
+    }))
+}
+
+/// The route of a request in the metrics: one per handler, of any site.
+fn route(config: &Config) -> impl Fn(&Request) -> String + Send + Sync + 'static {
+    let mut prefixes: Vec<String> = Vec::new();
+    for site in [config.default_site()].iter().chain(&config.sites) {
+        let handlers = [
+            site.cgi.as_ref().map(|cgi| &cgi.prefix),
+            site.proxy.as_ref().map(|proxy| &proxy.prefix),
+        ];
+        for prefix in handlers.into_iter().flatten() {
+            if !prefixes.contains(prefix) {
+                prefixes.push(prefix.clone());
+            }
+        }
+    }
+    move |request| match request.path() {
+        path @ ("/ws" | "/sleep" | "/metrics") => path.to_string(),
+        _ => match prefixes.iter().find(|prefix| under(request, prefix)) {
+            Some(prefix) => prefix.clone(),
+            None => "static".to_string(),
+        },
+    }
+}
+
+fn build_cors(config: &CorsConfig) -> Cors {
+    let mut cors = Cors::new().allow_credentials(config.allow_credentials);
+    for origin in &config.allow_origins {
+        cors = match origin.as_str() {
+            "*" => cors.allow_any_origin(),
+            origin => cors.allow_origin(origin),
+        };
+    }
+    for method in &config.allow_methods {
+        cors = cors.allow_method(method);
+    }
+    for name in &config.allow_headers {
+        cors = cors.allow_header(name);
+    }
+    for name in &config.expose_headers {
+        cors = cors.expose_header(name);
+    }
+    if let Some(max_age) = config.max_age {
+        cors = cors.max_age(max_age);
+    }
+    cors
+}
+
+fn handle(files: &StaticFiles, templates: &Templates, request: &Request) -> Response {
+    if request.path() == "/ws" {
+        return websocket::upgrade(request, echo_messages);
+    }
+    if request.path() == "/sleep" {
+        thread::sleep(Duration::from_secs(5));
+        let mut request = request.clone();
+        request.target = "/".to_string();
+        return files.serve(&request);
+    }
+
+    error_page(templates, request, files.serve(request))
+}
+
+/// The page rendered from the template named after the status of `response`,
+/// like `404.html`, if there's one for it; otherwise `response` itself.
+fn error_page(templates: &Templates, request: &Request, response: Response) -> Response {
+    let name = format!("{}.html", response.status);
+    if !matches!(response.status, 404 | 500) || !templates.exists(&name) {
+        return response;
+    }
+    let context = Context::for_request(request)
+        .with("status", response.status)
+        .with("reason", http::reason(response.status));
+    templates.response(response.status, &name, &context)
+}
+
+/// Whether the request's path, once normalized, is `prefix` or below it.
+/// Paths that can't be normalized are under none, and end up refused by the
+/// static files.
+fn under(request: &Request, prefix: &str) -> bool {
+    let Some(path) = request.normalized_path() else {
+        return false;
+    };
+    match path.strip_prefix(prefix.trim_end_matches('/')) {
+        Some(rest) => rest.is_empty() || rest.starts_with('/'),
+        None => false,
+    }
+}
+
+/// Send every message back to where it came from.
+fn echo_messages(mut socket: WebSocket) {
+    while let Ok(message) = socket.recv() {
+        if let Message::Text(_) | Message::Binary(_) = message {
+            if socket.send(&message).is_err() {
+                return;
+            }
+        }
+    }
+}
 
-    stream.write_all(response.as_bytes()).unwrap();
+fn fail(message: impl std::fmt::Display) -> ! {
+    eprintln!("echo: {message}");
+    process::exit(1);
 }
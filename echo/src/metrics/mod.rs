@@ -0,0 +1,506 @@
+//! Counters, gauges and histograms, exposed in the Prometheus text format.
+//!
+//! ```
+//! use echo::metrics::Registry;
+//!
+//! let registry = Registry::new();
+//! let jobs = registry.counter_family("jobs_total", "Jobs run.", &["queue"]);
+//! let queued = registry.gauge("jobs_queued", "Jobs waiting.");
+//!
+//! jobs.with(&["mail"]).inc();
+//! queued.set(3.0);
+//!
+//! let text = registry.render();
+//! assert!(text.contains("jobs_total{queue=\"mail\"} 1\n"));
+//! assert!(text.contains("jobs_queued 3\n"));
+//! ```
+//!
+//! Metric handles are cheap to clone, and all clones count together.
+
+mod server;
+
+use std::{
+    collections::BTreeMap,
+    fmt::Write,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+};
+
+pub use server::ServerMetrics;
+
+use crate::http::Response;
+
+/// The buckets of latency histograms, in seconds, as Prometheus clients
+/// have them by default.
+pub const DEFAULT_BUCKETS: [f64; 11] = [
+    0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0,
+];
+
+/// The content type of the text exposition format.
+pub const CONTENT_TYPE: &str = "text/plain; version=0.0.4; charset=utf-8";
+
+/// The metrics to expose, in the order they were registered.
+#[derive(Clone, Default)]
+pub struct Registry {
+    entries: Arc<Mutex<Vec<Entry>>>,
+}
+
+struct Entry {
+    name: String,
+    help: String,
+    kind: &'static str,
+    metric: Box<dyn Metric>,
+}
+
+impl Registry {
+    pub fn new() -> Registry {
+        Registry::default()
+    }
+
+    /// Add a metric.
+    ///
+    /// # Panics
+    ///
+    /// If there's a metric with the same name already.
+    fn register<M: Metric + Clone>(&self, name: &str, help: &str, metric: M) -> M {
+        let mut entries = self.entries.lock().unwrap();
+        assert!(
+            entries.iter().all(|entry| entry.name != name),
+            "metric `{name}` registered twice"
+        );
+        entries.push(Entry {
+            name: name.to_string(),
+            help: help.to_string(),
+            kind: metric.kind(),
+            metric: Box::new(metric.clone()),
+        });
+        metric
+    }
+
+    pub fn counter(&self, name: &str, help: &str) -> Counter {
+        self.register(name, help, Counter::default())
+    }
+
+    pub fn gauge(&self, name: &str, help: &str) -> Gauge {
+        self.register(name, help, Gauge::default())
+    }
+
+    /// A histogram counting observations up to each of the upper `bounds`.
+    pub fn histogram(&self, name: &str, help: &str, bounds: &[f64]) -> Histogram {
+        self.register(name, help, Histogram::new(bounds))
+    }
+
+    /// Counters told apart by the values of `labels`.
+    pub fn counter_family(&self, name: &str, help: &str, labels: &[&str]) -> Family<Counter> {
+        self.register(name, help, Family::new(labels, Counter::default))
+    }
+
+    pub fn gauge_family(&self, name: &str, help: &str, labels: &[&str]) -> Family<Gauge> {
+        self.register(name, help, Family::new(labels, Gauge::default))
+    }
+
+    pub fn histogram_family(
+        &self,
+        name: &str,
+        help: &str,
+        labels: &[&str],
+        bounds: &[f64],
+    ) -> Family<Histogram> {
+        let bounds = bounds.to_vec();
+        self.register(
+            name,
+            help,
+            Family::new(labels, move || Histogram::new(&bounds)),
+        )
+    }
+
+    /// A gauge whose value is read when the metrics are rendered, for
+    /// values kept elsewhere.
+    pub fn gauge_fn<F>(&self, name: &str, help: &str, read: F)
+    where
+        F: Fn() -> f64 + Send + Sync + 'static,
+    {
+        self.register(name, help, Read::new("gauge", read));
+    }
+
+    /// A counter whose value is read when the metrics are rendered.
+    pub fn counter_fn<F>(&self, name: &str, help: &str, read: F)
+    where
+        F: Fn() -> f64 + Send + Sync + 'static,
+    {
+        self.register(name, help, Read::new("counter", read));
+    }
+
+    /// Every metric, in the Prometheus text exposition format.
+    pub fn render(&self) -> String {
+        let mut out = String::new();
+        for entry in self.entries.lock().unwrap().iter() {
+            let help = entry.help.replace('\\', "\\\\").replace('\n', "\\n");
+            writeln!(out, "# HELP {} {help}", entry.name).unwrap();
+            writeln!(out, "# TYPE {} {}", entry.name, entry.kind).unwrap();
+            entry.metric.write(&entry.name, &[], &mut out);
+        }
+        out
+    }
+
+    /// A response with the rendered metrics, for a `/metrics` endpoint.
+    pub fn response(&self) -> Response {
+        Response::new(200)
+            .with_header("Content-Type", CONTENT_TYPE)
+            .with_body(self.render())
+    }
+}
+
+/// Something that writes samples of itself.
+trait Metric: Send + Sync + 'static {
+    fn kind(&self) -> &'static str;
+
+    /// Write the samples of the metric named `name`, with `labels`.
+    fn write(&self, name: &str, labels: &[(&str, &str)], out: &mut String);
+}
+
+/// A value that only goes up, like a number of requests.
+#[derive(Debug, Clone, Default)]
+pub struct Counter {
+    value: Arc<AtomicU64>,
+}
+
+impl Counter {
+    pub fn inc(&self) {
+        self.inc_by(1);
+    }
+
+    pub fn inc_by(&self, n: u64) {
+        self.value.fetch_add(n, Ordering::Relaxed);
+    }
+
+    pub fn get(&self) -> u64 {
+        self.value.load(Ordering::Relaxed)
+    }
+}
+
+impl Metric for Counter {
+    fn kind(&self) -> &'static str {
+        "counter"
+    }
+
+    fn write(&self, name: &str, labels: &[(&str, &str)], out: &mut String) {
+        sample(out, name, labels, self.get() as f64);
+    }
+}
+
+/// A value that goes up and down, like a number of open connections.
+#[derive(Debug, Clone, Default)]
+pub struct Gauge {
+    /// The bits of an `f64`.
+    value: Arc<AtomicU64>,
+}
+
+impl Gauge {
+    pub fn set(&self, value: f64) {
+        self.value.store(value.to_bits(), Ordering::Relaxed);
+    }
+
+    pub fn add(&self, delta: f64) {
+        add_f64(&self.value, delta);
+    }
+
+    pub fn inc(&self) {
+        self.add(1.0);
+    }
+
+    pub fn dec(&self) {
+        self.add(-1.0);
+    }
+
+    pub fn get(&self) -> f64 {
+        f64::from_bits(self.value.load(Ordering::Relaxed))
+    }
+}
+
+impl Metric for Gauge {
+    fn kind(&self) -> &'static str {
+        "gauge"
+    }
+
+    fn write(&self, name: &str, labels: &[(&str, &str)], out: &mut String) {
+        sample(out, name, labels, self.get());
+    }
+}
+
+/// Counts observations, like request durations, in buckets by size.
+#[derive(Debug, Clone)]
+pub struct Histogram {
+    inner: Arc<HistogramInner>,
+}
+
+#[derive(Debug)]
+struct HistogramInner {
+    bounds: Vec<f64>,
+    /// The observations in each bucket alone, the last one above every
+    /// bound.
+    counts: Vec<AtomicU64>,
+    /// The bits of an `f64`.
+    sum: AtomicU64,
+}
+
+impl Histogram {
+    fn new(bounds: &[f64]) -> Histogram {
+        let mut bounds = bounds.to_vec();
+        bounds.sort_by(f64::total_cmp);
+        bounds.dedup();
+        Histogram {
+            inner: Arc::new(HistogramInner {
+                counts: (0..=bounds.len()).map(|_| AtomicU64::new(0)).collect(),
+                bounds,
+                sum: AtomicU64::new(0),
+            }),
+        }
+    }
+
+    pub fn observe(&self, value: f64) {
+        let inner = &self.inner;
+        let bucket = inner.bounds.partition_point(|&bound| bound < value);
+        inner.counts[bucket].fetch_add(1, Ordering::Relaxed);
+        add_f64(&inner.sum, value);
+    }
+
+    /// The number of observations.
+    pub fn count(&self) -> u64 {
+        let counts = &self.inner.counts;
+        counts.iter().map(|c| c.load(Ordering::Relaxed)).sum()
+    }
+
+    /// The sum of the observations.
+    pub fn sum(&self) -> f64 {
+        f64::from_bits(self.inner.sum.load(Ordering::Relaxed))
+    }
+}
+
+impl Metric for Histogram {
+    fn kind(&self) -> &'static str {
+        "histogram"
+    }
+
+    fn write(&self, name: &str, labels: &[(&str, &str)], out: &mut String) {
+        let inner = &self.inner;
+        let bucket = format!("{name}_bucket");
+        let mut cumulative = 0;
+        let bounds = inner.bounds.iter().map(|b| format_value(*b));
+        for (count, bound) in inner.counts.iter().zip(bounds.chain(["+Inf".into()])) {
+            cumulative += count.load(Ordering::Relaxed);
+            let mut labels = labels.to_vec();
+            labels.push(("le", &bound));
+            sample(out, &bucket, &labels, cumulative as f64);
+        }
+        sample(out, &format!("{name}_sum"), labels, self.sum());
+        sample(out, &format!("{name}_count"), labels, cumulative as f64);
+    }
+}
+
+/// Metrics of one name, told apart by the values of their labels.
+pub struct Family<M> {
+    labels: Arc<[String]>,
+    metrics: Arc<Mutex<BTreeMap<Vec<String>, M>>>,
+    make: Arc<dyn Fn() -> M + Send + Sync>,
+}
+
+impl<M: Clone> Family<M> {
+    fn new(labels: &[&str], make: impl Fn() -> M + Send + Sync + 'static) -> Family<M> {
+        Family {
+            labels: labels.iter().map(|l| l.to_string()).collect(),
+            metrics: Arc::default(),
+            make: Arc::new(make),
+        }
+    }
+
+    /// The metric with these label values, in the order of the labels.
+    ///
+    /// # Panics
+    ///
+    /// If there are more or fewer values than labels.
+    pub fn with(&self, values: &[&str]) -> M {
+        assert_eq!(
+            values.len(),
+            self.labels.len(),
+            "expected values for {:?}",
+            self.labels
+        );
+        let key: Vec<String> = values.iter().map(|v| v.to_string()).collect();
+        let mut metrics = self.metrics.lock().unwrap();
+        metrics.entry(key).or_insert_with(|| (self.make)()).clone()
+    }
+}
+
+impl<M> Clone for Family<M> {
+    fn clone(&self) -> Family<M> {
+        Family {
+            labels: Arc::clone(&self.labels),
+            metrics: Arc::clone(&self.metrics),
+            make: Arc::clone(&self.make),
+        }
+    }
+}
+
+impl<M: Metric + Clone> Metric for Family<M> {
+    fn kind(&self) -> &'static str {
+        (self.make)().kind()
+    }
+
+    fn write(&self, name: &str, labels: &[(&str, &str)], out: &mut String) {
+        for (values, metric) in self.metrics.lock().unwrap().iter() {
+            let mut all = labels.to_vec();
+            all.extend(
+                self.labels
+                    .iter()
+                    .map(String::as_str)
+                    .zip(values.iter().map(String::as_str)),
+            );
+            metric.write(name, &all, out);
+        }
+    }
+}
+
+/// A value read from elsewhere when rendering.
+#[derive(Clone)]
+struct Read {
+    kind: &'static str,
+    read: Arc<dyn Fn() -> f64 + Send + Sync>,
+}
+
+impl Read {
+    fn new(kind: &'static str, read: impl Fn() -> f64 + Send + Sync + 'static) -> Read {
+        Read {
+            kind,
+            read: Arc::new(read),
+        }
+    }
+}
+
+impl Metric for Read {
+    fn kind(&self) -> &'static str {
+        self.kind
+    }
+
+    fn write(&self, name: &str, labels: &[(&str, &str)], out: &mut String) {
+        sample(out, name, labels, (self.read)());
+    }
+}
+
+/// Write a line like `name{label="value"} 1.5`.
+fn sample(out: &mut String, name: &str, labels: &[(&str, &str)], value: f64) {
+    out.push_str(name);
+    if !labels.is_empty() {
+        out.push('{');
+        for (i, (label, value)) in labels.iter().enumerate() {
+            if i > 0 {
+                out.push(',');
+            }
+            let value = value
+                .replace('\\', "\\\\")
+                .replace('"', "\\\"")
+                .replace('\n', "\\n");
+            write!(out, "{label}=\"{value}\"").unwrap();
+        }
+        out.push('}');
+    }
+    writeln!(out, " {}", format_value(value)).unwrap();
+}
+
+fn format_value(value: f64) -> String {
+    match value {
+        f64::INFINITY => "+Inf".to_string(),
+        f64::NEG_INFINITY => "-Inf".to_string(),
+        value => value.to_string(),
+    }
+}
+
+/// Add to an `f64` kept as bits in an atomic.
+fn add_f64(bits: &AtomicU64, delta: f64) {
+    let mut current = bits.load(Ordering::Relaxed);
+    loop {
+        let new = (f64::from_bits(current) + delta).to_bits();
+        match bits.compare_exchange_weak(current, new, Ordering::Relaxed, Ordering::Relaxed) {
+            Ok(_) => return,
+            Err(actual) => current = actual,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn histograms() {
+        let registry = Registry::new();
+        let durations = registry.histogram_family(
+            "request_seconds",
+            "How long requests took.",
+            &["route"],
+            &[1.0, 0.25],
+        );
+        let api = durations.with(&["/api"]);
+        for value in [0.125, 0.25, 0.5, 3.0] {
+            api.observe(value);
+        }
+        assert_eq!(api.count(), 4);
+        assert_eq!(durations.with(&["/api"]).sum(), 3.875);
+
+        assert_eq!(
+            registry.render(),
+            "# HELP request_seconds How long requests took.\n\
+             # TYPE request_seconds histogram\n\
+             request_seconds_bucket{route=\"/api\",le=\"0.25\"} 2\n\
+             request_seconds_bucket{route=\"/api\",le=\"1\"} 3\n\
+             request_seconds_bucket{route=\"/api\",le=\"+Inf\"} 4\n\
+             request_seconds_sum{route=\"/api\"} 3.875\n\
+             request_seconds_count{route=\"/api\"} 4\n"
+        );
+    }
+
+    #[test]
+    fn counters_and_gauges() {
+        let registry = Registry::new();
+        let requests = registry.counter_family("requests_total", "Requests.", &["path", "status"]);
+        let open = registry.gauge("open", "Open things.\nReally.");
+        registry.counter_fn("read_total", "Read elsewhere.", || 7.0);
+
+        requests.with(&["/a\"b\\", "200"]).inc();
+        requests.with(&["/", "404"]).inc_by(2);
+        requests.with(&["/", "200"]).inc();
+        assert_eq!(requests.with(&["/", "404"]).get(), 2);
+        open.inc();
+        open.inc();
+        open.dec();
+        open.add(0.5);
+
+        assert_eq!(
+            registry.render(),
+            "# HELP requests_total Requests.\n\
+             # TYPE requests_total counter\n\
+             requests_total{path=\"/\",status=\"200\"} 1\n\
+             requests_total{path=\"/\",status=\"404\"} 2\n\
+             requests_total{path=\"/a\\\"b\\\\\",status=\"200\"} 1\n\
+             # HELP open Open things.\\nReally.\n\
+             # TYPE open gauge\n\
+             open 1.5\n\
+             # HELP read_total Read elsewhere.\n\
+             # TYPE read_total counter\n\
+             read_total 7\n"
+        );
+
+        let response = registry.response();
+        assert_eq!(response.headers.get("Content-Type"), Some(CONTENT_TYPE));
+    }
+
+    #[test]
+    #[should_panic(expected = "registered twice")]
+    fn names_are_unique() {
+        let registry = Registry::new();
+        registry.counter("a", "");
+        registry.gauge("a", "");
+    }
+}
@@ -0,0 +1,187 @@
+use std::{sync::Arc, time::Duration};
+
+use super::{Counter, Family, Gauge, Histogram, Registry, DEFAULT_BUCKETS};
+use crate::{http::Request, pool::Stats};
+
+type Route = dyn Fn(&Request) -> String + Send + Sync;
+
+/// The metrics a `Server` keeps about its requests, connections and pool,
+/// given to it with `Builder::metrics`:
+///
+/// - `echo_http_requests_total`, by route and status
+/// - `echo_http_request_duration_seconds`, by route, from the end of the
+///   request to the response being ready
+/// - `echo_connections_open`
+/// - `echo_pool_workers`, `echo_pool_busy_workers`, `echo_pool_utilization`
+///   and `echo_pool_queued_jobs`
+/// - `echo_pool_jobs_completed_total` and `echo_pool_jobs_panicked_total`
+///
+/// Requests are grouped into routes by the first segment of their path,
+/// unless told otherwise with `route`. So that clients probing for paths
+/// can't make up as many routes as they like, requests answered 404 go in
+/// the `unmatched` route, as do all but successful and 304 answers when the
+/// routes are told by path.
+pub struct ServerMetrics {
+    registry: Registry,
+    requests: Family<Counter>,
+    durations: Family<Histogram>,
+    connections: Gauge,
+    /// `None` to group by the first segment of the path.
+    route: Option<Box<Route>>,
+}
+
+impl ServerMetrics {
+    /// Register the server's metrics in `registry`.
+    ///
+    /// # Panics
+    ///
+    /// If `registry` has server metrics already.
+    pub fn new(registry: &Registry) -> ServerMetrics {
+        ServerMetrics {
+            registry: registry.clone(),
+            requests: registry.counter_family(
+                "echo_http_requests_total",
+                "HTTP requests answered.",
+                &["route", "status"],
+            ),
+            durations: registry.histogram_family(
+                "echo_http_request_duration_seconds",
+                "How long handlers took to answer requests.",
+                &["route"],
+                &DEFAULT_BUCKETS,
+            ),
+            connections: registry.gauge("echo_connections_open", "Open client connections."),
+            route: None,
+        }
+    }
+
+    /// Name the route of a request, like `/users/:id` for `/users/42`. There
+    /// should be few different routes: each gets metrics of its own.
+    pub fn route<F>(mut self, route: F) -> ServerMetrics
+    where
+        F: Fn(&Request) -> String + Send + Sync + 'static,
+    {
+        self.route = Some(Box::new(route));
+        self
+    }
+
+    /// Count an answered request.
+    pub(crate) fn observe(&self, request: &Request, status: u16, duration: Duration) {
+        let route = match (&self.route, status) {
+            (_, 404) | (None, ..200 | 300..=303 | 305..) => "unmatched".to_string(),
+            (Some(route), _) => route(request),
+            (None, _) => first_segment(request.path()),
+        };
+        let status = status.to_string();
+        self.requests.with(&[&route, &status]).inc();
+        self.durations
+            .with(&[&route])
+            .observe(duration.as_secs_f64());
+    }
+
+    /// The gauge of open connections.
+    pub(crate) fn connections(&self) -> &Gauge {
+        &self.connections
+    }
+
+    /// Register the metrics of the server's pool, read from `stats`.
+    pub(crate) fn watch_pool(&self, stats: impl Fn() -> Stats + Send + Sync + 'static) {
+        let stats = Arc::new(stats);
+        let read = |f: fn(&Stats) -> f64| {
+            let stats = Arc::clone(&stats);
+            move || f(&stats())
+        };
+        let registry = &self.registry;
+        registry.gauge_fn(
+            "echo_pool_workers",
+            "Worker threads in the pool.",
+            read(|s| s.workers as f64),
+        );
+        registry.gauge_fn(
+            "echo_pool_busy_workers",
+            "Workers running a job.",
+            read(|s| s.running as f64),
+        );
+        registry.gauge_fn(
+            "echo_pool_utilization",
+            "The share of the workers that are busy, from 0 to 1.",
+            read(|s| s.running as f64 / s.workers.max(1) as f64),
+        );
+        registry.gauge_fn(
+            "echo_pool_queued_jobs",
+            "Jobs waiting for a worker.",
+            read(|s| s.queued as f64),
+        );
+        registry.counter_fn(
+            "echo_pool_jobs_completed_total",
+            "Jobs the pool ran to completion.",
+            read(|s| s.completed as f64),
+        );
+        registry.counter_fn(
+            "echo_pool_jobs_panicked_total",
+            "Jobs that panicked.",
+            read(|s| s.panicked as f64),
+        );
+    }
+}
+
+/// `/users` for `/users/42`.
+fn first_segment(path: &str) -> String {
+    let segment = path.trim_start_matches('/').split('/').next();
+    format!("/{}", segment.unwrap_or_default())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn routes() {
+        assert_eq!(first_segment("/users/42"), "/users");
+        assert_eq!(first_segment("/"), "/");
+        assert_eq!(first_segment("/metrics"), "/metrics");
+
+        let registry = Registry::new();
+        let metrics = ServerMetrics::new(&registry);
+        metrics.observe(
+            &Request::new("GET", "/users/1"),
+            200,
+            Duration::from_millis(3),
+        );
+        metrics.observe(
+            &Request::new("GET", "/users/2"),
+            200,
+            Duration::from_secs(2),
+        );
+        metrics.observe(&Request::new("GET", "/nope"), 404, Duration::ZERO);
+        for status in [301, 401, 405] {
+            metrics.observe(&Request::new("GET", "/probe"), status, Duration::ZERO);
+        }
+        metrics.observe(&Request::new("GET", "/docs/a"), 304, Duration::ZERO);
+
+        let rendered = registry.render();
+        for line in [
+            "echo_http_requests_total{route=\"/users\",status=\"200\"} 2\n",
+            "echo_http_requests_total{route=\"unmatched\",status=\"404\"} 1\n",
+            "echo_http_requests_total{route=\"unmatched\",status=\"401\"} 1\n",
+            "echo_http_requests_total{route=\"/docs\",status=\"304\"} 1\n",
+            "echo_http_request_duration_seconds_bucket{route=\"/users\",le=\"0.005\"} 1\n",
+            "echo_http_request_duration_seconds_bucket{route=\"/users\",le=\"2.5\"} 2\n",
+            "echo_http_request_duration_seconds_count{route=\"/users\"} 2\n",
+        ] {
+            assert!(rendered.contains(line), "{line} missing from\n{rendered}");
+        }
+        assert!(!rendered.contains("/probe"));
+
+        // Routes named otherwise keep all their answers but 404s.
+        let registry = Registry::new();
+        let metrics = ServerMetrics::new(&registry).route(|_| "app".to_string());
+        metrics.observe(&Request::new("GET", "/login"), 401, Duration::ZERO);
+        metrics.observe(&Request::new("GET", "/nope"), 404, Duration::ZERO);
+        let rendered = registry.render();
+        assert!(rendered.contains("echo_http_requests_total{route=\"app\",status=\"401\"} 1\n"));
+        assert!(
+            rendered.contains("echo_http_requests_total{route=\"unmatched\",status=\"404\"} 1\n")
+        );
+    }
+}
@@ -0,0 +1,285 @@
+use std::{collections::HashMap, error, fmt, fs, io, path::Path};
+
+use super::{under, Middleware, Next};
+use crate::{
+    base64,
+    http::{Request, Response},
+    md5::md5,
+    sha1::sha1,
+};
+
+/// HTTP Basic authentication (RFC 7617) against users from an htpasswd
+/// file. Requests without valid credentials get a 401 response asking for
+/// them.
+///
+/// Passwords may be hashed with `{SHA}` or with Apache's MD5 `$apr1$`, which
+/// is what `htpasswd -m` writes. Files with `bcrypt` or `crypt` hashes are
+/// rejected when loaded, rather than turning everyone away later.
+pub struct BasicAuth {
+    users: HashMap<String, Hash>,
+    realm: String,
+    prefix: String,
+}
+
+enum Hash {
+    Sha1([u8; 20]),
+    Apr1 { salt: String, hash: String },
+}
+
+impl Hash {
+    fn parse(s: &str) -> Result<Hash, String> {
+        if let Some(encoded) = s.strip_prefix("{SHA}") {
+            let digest = base64::decode(encoded).and_then(|d| <[u8; 20]>::try_from(d).ok());
+            return digest.map(Hash::Sha1).ok_or("invalid {SHA} hash".into());
+        }
+        if let Some(rest) = s.strip_prefix("$apr1$") {
+            return match rest.split_once('$') {
+                Some((salt, hash)) if salt.len() <= 8 && hash.len() == 22 => Ok(Hash::Apr1 {
+                    salt: salt.to_string(),
+                    hash: hash.to_string(),
+                }),
+                _ => Err("invalid $apr1$ hash".into()),
+            };
+        }
+        if s.starts_with("$2") {
+            Err("bcrypt hashes are not supported, use `htpasswd -m`".into())
+        } else {
+            Err("unsupported hash, use `htpasswd -m` or `htpasswd -s`".into())
+        }
+    }
+
+    fn matches(&self, password: &str) -> bool {
+        match self {
+            Hash::Sha1(digest) => constant_time_eq(&sha1(password.as_bytes()), digest),
+            Hash::Apr1 { salt, hash } => constant_time_eq(
+                apr1(password.as_bytes(), salt.as_bytes()).as_bytes(),
+                hash.as_bytes(),
+            ),
+        }
+    }
+}
+
+impl BasicAuth {
+    /// Load the users of an htpasswd file.
+    pub fn from_file(path: impl AsRef<Path>) -> Result<BasicAuth, LoadError> {
+        BasicAuth::parse(&fs::read_to_string(path)?)
+    }
+
+    /// Parse the `user:hash` lines of an htpasswd file. Blank lines and
+    /// lines starting with `#` are skipped.
+    pub fn parse(s: &str) -> Result<BasicAuth, LoadError> {
+        let mut users = HashMap::new();
+        for (i, line) in s.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let invalid = |message: String| LoadError::Invalid {
+                line: i + 1,
+                message,
+            };
+            let (user, hash) = line
+                .split_once(':')
+                .ok_or_else(|| invalid("expected `user:hash`".into()))?;
+            users.insert(user.to_string(), Hash::parse(hash).map_err(invalid)?);
+        }
+        Ok(BasicAuth {
+            users,
+            realm: "echo".into(),
+            prefix: String::new(),
+        })
+    }
+
+    /// The realm named in challenges, which browsers show when asking for a
+    /// password. Defaults to `echo`.
+    pub fn realm(mut self, realm: impl Into<String>) -> BasicAuth {
+        self.realm = realm.into();
+        self
+    }
+
+    /// Only ask for credentials below this path. Defaults to everything.
+    pub fn prefix(mut self, prefix: impl Into<String>) -> BasicAuth {
+        self.prefix = prefix.into();
+        self
+    }
+
+    /// The user name and password a request carries, if any.
+    pub fn credentials(request: &Request) -> Option<(String, String)> {
+        let (scheme, encoded) = request.header("Authorization")?.trim().split_once(' ')?;
+        if !scheme.eq_ignore_ascii_case("basic") {
+            return None;
+        }
+        let decoded = String::from_utf8(base64::decode(encoded.trim())?).ok()?;
+        let (user, password) = decoded.split_once(':')?;
+        Some((user.to_string(), password.to_string()))
+    }
+
+    fn authenticated(&self, request: &Request) -> bool {
+        match BasicAuth::credentials(request) {
+            Some((user, password)) => self
+                .users
+                .get(&user)
+                .is_some_and(|hash| hash.matches(&password)),
+            None => false,
+        }
+    }
+}
+
+impl Middleware for BasicAuth {
+    fn handle(&self, request: &Request, next: Next<'_>) -> Response {
+        if !under(request, &self.prefix) || self.authenticated(request) {
+            return next.run(request);
+        }
+        let realm = self.realm.replace(['\\', '"'], "");
+        Response::text(401, "Unauthorized\n").with_header(
+            "WWW-Authenticate",
+            format!("Basic realm=\"{realm}\", charset=\"UTF-8\""),
+        )
+    }
+}
+
+/// Why an htpasswd file couldn't be loaded.
+#[derive(Debug)]
+pub enum LoadError {
+    Io(io::Error),
+    Invalid { line: usize, message: String },
+}
+
+impl fmt::Display for LoadError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LoadError::Io(e) => e.fmt(f),
+            LoadError::Invalid { line, message } => write!(f, "line {line}: {message}"),
+        }
+    }
+}
+
+impl error::Error for LoadError {}
+
+impl From<io::Error> for LoadError {
+    fn from(e: io::Error) -> LoadError {
+        LoadError::Io(e)
+    }
+}
+
+/// Compare without returning early, so the time taken doesn't tell how much
+/// of a guess was right.
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |diff, (x, y)| diff | (x ^ y)) == 0
+}
+
+/// The hash part of an `$apr1$` hash: Apache's variant of the MD5-based
+/// crypt of FreeBSD, with its strange rounds and byte order.
+fn apr1(password: &[u8], salt: &[u8]) -> String {
+    let alternate = md5(&[password, salt, password].concat());
+
+    let mut input = [password, b"$apr1$", salt].concat();
+    input.extend(alternate.iter().cycle().take(password.len()));
+    let mut n = password.len();
+    while n > 0 {
+        input.push(if n & 1 == 1 { 0 } else { password[0] });
+        n >>= 1;
+    }
+    let mut digest = md5(&input);
+
+    for round in 0..1000 {
+        let mut input = Vec::new();
+        input.extend_from_slice(if round % 2 == 1 { password } else { &digest });
+        if round % 3 != 0 {
+            input.extend_from_slice(salt);
+        }
+        if round % 7 != 0 {
+            input.extend_from_slice(password);
+        }
+        input.extend_from_slice(if round % 2 == 1 { &digest } else { password });
+        digest = md5(&input);
+    }
+
+    const ALPHABET: &[u8] = b"./0123456789ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz";
+    let mut hash = String::with_capacity(22);
+    let mut push = |mut value: u32, chars: usize| {
+        for _ in 0..chars {
+            hash.push(ALPHABET[(value & 0x3f) as usize] as char);
+            value >>= 6;
+        }
+    };
+    for [a, b, c] in [[0, 6, 12], [1, 7, 13], [2, 8, 14], [3, 9, 15], [4, 10, 5]] {
+        let value = u32::from(digest[a]) << 16 | u32::from(digest[b]) << 8 | u32::from(digest[c]);
+        push(value, 4);
+    }
+    push(u32::from(digest[11]), 2);
+    hash
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::middleware::Pipeline;
+    use crate::server::Handler;
+
+    const HTPASSWD: &str = "\
+# made with htpasswd
+alice:$apr1$rOs3nzgF$PjF/PfVK7gIP7k4CLrFPk0
+
+bob:{SHA}5en6G6MezRroT3XKqkdPOmY/BfQ=
+";
+
+    fn login(user: &str, password: &str) -> Request {
+        let mut request = Request::new("GET", "/private/page");
+        let encoded = base64::encode(format!("{user}:{password}").as_bytes());
+        request
+            .headers
+            .insert("Authorization", format!("Basic {encoded}"));
+        request
+    }
+
+    #[test]
+    fn apr1_hashes() {
+        assert_eq!(apr1(b"secret", b"rOs3nzgF"), "PjF/PfVK7gIP7k4CLrFPk0");
+    }
+
+    #[test]
+    fn checks_credentials() {
+        let auth = BasicAuth::parse(HTPASSWD)
+            .unwrap()
+            .realm("Staff \"only\"")
+            .prefix("/private");
+        let pipeline = Pipeline::new(|_: &Request| Response::text(200, "secret stuff")).with(auth);
+
+        assert_eq!(pipeline.handle(&login("alice", "secret")).status, 200);
+        assert_eq!(pipeline.handle(&login("bob", "secret")).status, 200);
+        assert_eq!(pipeline.handle(&Request::new("GET", "/public")).status, 200);
+        assert_eq!(
+            pipeline.handle(&Request::new("GET", "/privateer")).status,
+            200
+        );
+
+        for request in [
+            login("alice", "wrong"),
+            login("carol", "secret"),
+            Request::new("GET", "/private"),
+            // The same path, spelled differently.
+            Request::new("GET", "//private/page"),
+            Request::new("GET", "/%70rivate/page"),
+            Request::new("GET", "/./private/page"),
+            Request::new("GET", "/public/../private/page"),
+        ] {
+            let response = pipeline.handle(&request);
+            assert_eq!(response.status, 401);
+            assert_eq!(
+                response.headers.get("WWW-Authenticate"),
+                Some("Basic realm=\"Staff only\", charset=\"UTF-8\"")
+            );
+        }
+    }
+
+    #[test]
+    fn rejects_unsupported_hashes() {
+        let err = BasicAuth::parse("a:{SHA}5en6G6MezRroT3XKqkdPOmY/BfQ=\nb:$2y$05$abc\n")
+            .err()
+            .unwrap();
+        assert!(matches!(err, LoadError::Invalid { line: 2, .. }), "{err}");
+        assert!(BasicAuth::parse("nocolon").is_err());
+        assert!(BasicAuth::parse("c:rlvZh5Q0sP3Gk").is_err());
+    }
+}
@@ -0,0 +1,283 @@
+use std::time::Duration;
+
+use super::{Middleware, Next};
+use crate::http::{Request, Response};
+
+/// Methods a cross-origin request may always use.
+const SAFE_METHODS: [&str; 3] = ["GET", "HEAD", "POST"];
+
+/// Request headers a cross-origin request may always send.
+const SAFE_HEADERS: [&str; 4] = [
+    "accept",
+    "accept-language",
+    "content-language",
+    "content-type",
+];
+
+/// Cross-origin resource sharing: lets scripts from other origins read
+/// responses, and answers their preflight requests.
+///
+/// Requests from origins that aren't allowed still get through, just without
+/// the headers that let the browser hand the response to the script.
+/// Preflights from them are refused with 403.
+#[derive(Debug, Clone, Default)]
+pub struct Cors {
+    any_origin: bool,
+    origins: Vec<String>,
+    methods: Vec<String>,
+    headers: Vec<String>,
+    expose: Vec<String>,
+    credentials: bool,
+    max_age: Option<Duration>,
+}
+
+impl Cors {
+    /// CORS allowing no origins yet.
+    pub fn new() -> Cors {
+        Cors::default()
+    }
+
+    /// Allow an origin, like `https://example.com`.
+    pub fn allow_origin(mut self, origin: impl Into<String>) -> Cors {
+        self.origins.push(origin.into());
+        self
+    }
+
+    /// Allow every origin.
+    pub fn allow_any_origin(mut self) -> Cors {
+        self.any_origin = true;
+        self
+    }
+
+    /// Allow a method besides `GET`, `HEAD` and `POST`.
+    pub fn allow_method(mut self, method: impl Into<String>) -> Cors {
+        self.methods.push(method.into());
+        self
+    }
+
+    /// Allow scripts to send a request header besides the safelisted ones.
+    pub fn allow_header(mut self, name: impl Into<String>) -> Cors {
+        self.headers.push(name.into());
+        self
+    }
+
+    /// Let scripts read a response header besides the safelisted ones.
+    pub fn expose_header(mut self, name: impl Into<String>) -> Cors {
+        self.expose.push(name.into());
+        self
+    }
+
+    /// Allow requests with cookies or HTTP authentication.
+    pub fn allow_credentials(mut self, allow: bool) -> Cors {
+        self.credentials = allow;
+        self
+    }
+
+    /// How long browsers may cache the answer to a preflight.
+    pub fn max_age(mut self, max_age: Duration) -> Cors {
+        self.max_age = Some(max_age);
+        self
+    }
+
+    fn allows_origin(&self, origin: &str) -> bool {
+        self.any_origin || self.origins.iter().any(|o| o == origin)
+    }
+
+    fn allows_method(&self, method: &str) -> bool {
+        SAFE_METHODS.contains(&method) || self.methods.iter().any(|m| m == method)
+    }
+
+    fn allows_header(&self, name: &str) -> bool {
+        SAFE_HEADERS.iter().any(|h| h.eq_ignore_ascii_case(name))
+            || self.headers.iter().any(|h| h.eq_ignore_ascii_case(name))
+    }
+
+    /// The `Access-Control-Allow-Origin` value for an allowed origin. A
+    /// wildcard isn't allowed with credentials, so those get the origin back.
+    fn allow_origin_value<'a>(&self, origin: &'a str) -> &'a str {
+        if self.any_origin && !self.credentials {
+            "*"
+        } else {
+            origin
+        }
+    }
+
+    fn with_origin_headers(&self, mut response: Response, origin: &str) -> Response {
+        response.headers.insert(
+            "Access-Control-Allow-Origin",
+            self.allow_origin_value(origin),
+        );
+        if self.credentials {
+            response
+                .headers
+                .insert("Access-Control-Allow-Credentials", "true");
+        }
+        response
+    }
+
+    fn preflight(&self, origin: &str, method: &str, headers: Option<&str>) -> Response {
+        let requested = headers
+            .unwrap_or_default()
+            .split(',')
+            .map(str::trim)
+            .filter(|h| !h.is_empty());
+        let allowed = self.allows_origin(origin)
+            && self.allows_method(method)
+            && requested.clone().all(|h| self.allows_header(h));
+        if !allowed {
+            return Response::new(403).with_header("Vary", "Origin");
+        }
+
+        let mut response = self
+            .with_origin_headers(Response::new(204), origin)
+            .with_header("Vary", "Origin")
+            .with_header("Access-Control-Allow-Methods", method);
+        let requested = requested.collect::<Vec<_>>();
+        if !requested.is_empty() {
+            response
+                .headers
+                .insert("Access-Control-Allow-Headers", requested.join(", "));
+        }
+        if let Some(max_age) = self.max_age {
+            response
+                .headers
+                .insert("Access-Control-Max-Age", max_age.as_secs().to_string());
+        }
+        response
+    }
+}
+
+impl Middleware for Cors {
+    fn handle(&self, request: &Request, next: Next<'_>) -> Response {
+        let Some(origin) = request.header("Origin") else {
+            return next.run(request);
+        };
+        if request.method == "OPTIONS" {
+            if let Some(method) = request.header("Access-Control-Request-Method") {
+                let headers = request.header("Access-Control-Request-Headers");
+                return self.preflight(origin, method, headers);
+            }
+        }
+
+        let mut response = next.run(request);
+        if self.allow_origin_value(origin) != "*" {
+            response.headers.append("Vary", "Origin");
+        }
+        if !self.allows_origin(origin) {
+            return response;
+        }
+        if !self.expose.is_empty() {
+            response
+                .headers
+                .insert("Access-Control-Expose-Headers", self.expose.join(", "));
+        }
+        self.with_origin_headers(response, origin)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{middleware::Pipeline, server::Handler};
+
+    fn request(method: &str, origin: &str, headers: &[(&str, &str)]) -> Request {
+        let mut request = Request::new(method, "/api");
+        request.headers.insert("Origin", origin);
+        for (name, value) in headers {
+            request.headers.insert(*name, *value);
+        }
+        request
+    }
+
+    fn pipeline(cors: Cors) -> Pipeline {
+        Pipeline::new(|_: &Request| Response::text(200, "data")).with(cors)
+    }
+
+    #[test]
+    fn preflights() {
+        let pipeline = pipeline(
+            Cors::new()
+                .allow_origin("https://app.example")
+                .allow_method("PUT")
+                .allow_header("X-Token")
+                .allow_credentials(true)
+                .max_age(Duration::from_secs(600)),
+        );
+
+        let preflight = |origin: &str, method: &str, headers: &str| {
+            pipeline.handle(&request(
+                "OPTIONS",
+                origin,
+                &[
+                    ("Access-Control-Request-Method", method),
+                    ("Access-Control-Request-Headers", headers),
+                ],
+            ))
+        };
+
+        let response = preflight("https://app.example", "PUT", "x-token, content-type");
+        assert_eq!(response.status, 204);
+        let header = |name| response.headers.get(name);
+        assert_eq!(
+            header("Access-Control-Allow-Origin"),
+            Some("https://app.example")
+        );
+        assert_eq!(header("Access-Control-Allow-Methods"), Some("PUT"));
+        assert_eq!(
+            header("Access-Control-Allow-Headers"),
+            Some("x-token, content-type")
+        );
+        assert_eq!(header("Access-Control-Allow-Credentials"), Some("true"));
+        assert_eq!(header("Access-Control-Max-Age"), Some("600"));
+
+        assert_eq!(preflight("https://evil.example", "PUT", "").status, 403);
+        assert_eq!(preflight("https://app.example", "DELETE", "").status, 403);
+        assert_eq!(
+            preflight("https://app.example", "GET", "X-Other").status,
+            403
+        );
+
+        // Without the preflight header it's an ordinary OPTIONS request.
+        let response = pipeline.handle(&request("OPTIONS", "https://app.example", &[]));
+        assert_eq!(response.status, 200);
+    }
+
+    #[test]
+    fn actual_requests() {
+        let listed = pipeline(
+            Cors::new()
+                .allow_origin("https://app.example")
+                .expose_header("X-Request-Id"),
+        );
+
+        let response = listed.handle(&request("GET", "https://app.example", &[]));
+        assert_eq!(response.body, b"data");
+        assert_eq!(
+            response.headers.get("Access-Control-Allow-Origin"),
+            Some("https://app.example")
+        );
+        assert_eq!(
+            response.headers.get("Access-Control-Expose-Headers"),
+            Some("X-Request-Id")
+        );
+        assert_eq!(response.headers.get("Vary"), Some("Origin"));
+        assert!(!response
+            .headers
+            .contains("Access-Control-Allow-Credentials"));
+
+        let response = listed.handle(&request("GET", "https://evil.example", &[]));
+        assert_eq!(response.status, 200);
+        assert!(!response.headers.contains("Access-Control-Allow-Origin"));
+
+        let response = listed.handle(&Request::new("GET", "/"));
+        assert!(!response.headers.contains("Vary"));
+
+        let any = pipeline(Cors::new().allow_any_origin());
+        let response = any.handle(&request("GET", "https://anywhere.example", &[]));
+        assert_eq!(
+            response.headers.get("Access-Control-Allow-Origin"),
+            Some("*")
+        );
+        assert!(!response.headers.contains("Vary"));
+    }
+}
@@ -0,0 +1,189 @@
+//! Behavior around a handler that isn't its business: authentication, CORS,
+//! rate limiting, request IDs, catching panics.
+//!
+//! A middleware gets each request before the handler, and the `Next` step
+//! of the pipeline to pass it on to. It may answer the request itself,
+//! change it before passing it on, or change the response on its way back.
+//!
+//! ```no_run
+//! use echo::{
+//!     middleware::{Cors, Next, Pipeline, Recover, RequestId},
+//!     Request, Response, Server,
+//! };
+//! use std::net::TcpListener;
+//!
+//! let handler = |_: &Request| Response::text(200, "Hello!\n");
+//! let pipeline = Pipeline::new(handler)
+//!     .with(RequestId::new())
+//!     .with(Recover::new())
+//!     .with(Cors::new().allow_origin("https://example.com"))
+//!     .with(|request: &Request, next: Next| {
+//!         let started = std::time::Instant::now();
+//!         let response = next.run(request);
+//!         println!("{} took {:?}", request.target, started.elapsed());
+//!         response
+//!     });
+//! Server::new(pipeline).serve(TcpListener::bind("127.0.0.1:7878").unwrap()).unwrap();
+//! ```
+
+mod auth;
+mod cors;
+mod rate_limit;
+mod recover;
+mod request_id;
+
+pub use auth::{BasicAuth, LoadError};
+pub use cors::Cors;
+pub use rate_limit::RateLimit;
+pub use recover::Recover;
+pub use request_id::RequestId;
+
+use crate::{
+    http::{Request, Response},
+    server::Handler,
+};
+
+/// Something wrapped around a handler. Any
+/// `Fn(&Request, Next) -> Response` closure is a middleware.
+pub trait Middleware: Send + Sync + 'static {
+    fn handle(&self, request: &Request, next: Next<'_>) -> Response;
+}
+
+impl<F> Middleware for F
+where
+    F: Fn(&Request, Next<'_>) -> Response + Send + Sync + 'static,
+{
+    fn handle(&self, request: &Request, next: Next<'_>) -> Response {
+        self(request, next)
+    }
+}
+
+/// The rest of a pipeline: the middlewares after the current one, then the
+/// handler.
+pub struct Next<'a> {
+    middlewares: &'a [Box<dyn Middleware>],
+    handler: &'a dyn Handler,
+}
+
+impl Next<'_> {
+    /// Pass the request on and get the response.
+    pub fn run(self, request: &Request) -> Response {
+        match self.middlewares.split_first() {
+            Some((middleware, rest)) => middleware.handle(
+                request,
+                Next {
+                    middlewares: rest,
+                    handler: self.handler,
+                },
+            ),
+            None => self.handler.handle(request),
+        }
+    }
+}
+
+/// A handler with middlewares around it. The first one added is the
+/// outermost: it sees requests first and responses last.
+pub struct Pipeline {
+    middlewares: Vec<Box<dyn Middleware>>,
+    handler: Box<dyn Handler>,
+}
+
+impl Pipeline {
+    pub fn new<H: Handler>(handler: H) -> Pipeline {
+        Pipeline {
+            middlewares: Vec::new(),
+            handler: Box::new(handler),
+        }
+    }
+
+    /// Add a middleware inside those added before.
+    pub fn with<M: Middleware>(mut self, middleware: M) -> Pipeline {
+        self.middlewares.push(Box::new(middleware));
+        self
+    }
+}
+
+impl Handler for Pipeline {
+    fn handle(&self, request: &Request) -> Response {
+        Next {
+            middlewares: &self.middlewares,
+            handler: &*self.handler,
+        }
+        .run(request)
+    }
+}
+
+/// Whether the request's path, once normalized, is `prefix` or below it.
+/// An empty prefix is the root, with everything below it. Paths that can't
+/// be normalized count as under every prefix, so that they're refused
+/// rather than let through.
+pub(crate) fn under(request: &Request, prefix: &str) -> bool {
+    let Some(path) = request.normalized_path() else {
+        return true;
+    };
+    match path.strip_prefix(prefix.trim_end_matches('/')) {
+        Some(rest) => rest.is_empty() || rest.starts_with('/'),
+        None => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::{Arc, Mutex};
+
+    #[test]
+    fn runs_in_order() {
+        let calls = Arc::new(Mutex::new(Vec::new()));
+        let record = |name: &'static str| {
+            let calls = Arc::clone(&calls);
+            move |request: &Request, next: Next| {
+                calls
+                    .lock()
+                    .unwrap()
+                    .push(format!("{name} {}", request.target));
+                let mut request = request.clone();
+                request.target.push_str(name);
+                let response = next.run(&request);
+                calls
+                    .lock()
+                    .unwrap()
+                    .push(format!("{name} {}", response.status));
+                response.with_header("X-Seen", name)
+            }
+        };
+        let handler = {
+            let calls = Arc::clone(&calls);
+            move |request: &Request| {
+                calls
+                    .lock()
+                    .unwrap()
+                    .push(format!("handler {}", request.target));
+                Response::new(200)
+            }
+        };
+        let pipeline = Pipeline::new(handler)
+            .with(record("a"))
+            .with(record("b"))
+            .with(|request: &Request, next: Next| {
+                if request.path().starts_with("/stop") {
+                    return Response::new(403);
+                }
+                next.run(request)
+            });
+
+        let response = pipeline.handle(&Request::new("GET", "/"));
+        assert_eq!(response.headers.get("X-Seen"), Some("a"));
+        assert_eq!(
+            *calls.lock().unwrap(),
+            ["a /", "b /a", "handler /ab", "b 200", "a 200"]
+        );
+
+        calls.lock().unwrap().clear();
+        assert_eq!(pipeline.handle(&Request::new("GET", "/stop")).status, 403);
+        assert_eq!(
+            *calls.lock().unwrap(),
+            ["a /stop", "b /stopa", "b 403", "a 403"]
+        );
+    }
+}
@@ -0,0 +1,334 @@
+use std::{
+    collections::HashMap,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+use super::{Middleware, Next};
+use crate::http::{Request, Response};
+
+/// Limits how often each client may make requests, with a token bucket per
+/// client: a bucket holds up to `burst` tokens, refills at a steady rate,
+/// and each request takes a token. Requests finding the bucket empty get a
+/// 429 response saying when to try again.
+///
+/// Clients are told by their address, or by a header such as an API key;
+/// requests with neither, like those on a Unix socket, share a bucket. Past
+/// `max_clients` buckets, new clients share one too, so that clients making
+/// up keys can't grow the table without bound. Every response carries
+/// `RateLimit-Limit`, `RateLimit-Remaining` and `RateLimit-Reset`, so that
+/// well-behaved clients can slow down on their own.
+pub struct RateLimit {
+    /// Tokens added a second.
+    rate: f64,
+    burst: f64,
+    header: Option<String>,
+    max_clients: usize,
+    state: Mutex<State>,
+}
+
+/// The most clients with buckets of their own, by default.
+const DEFAULT_MAX_CLIENTS: usize = 10_000;
+
+/// How long a full table waits between sweeps for room, so that clients
+/// making up keys can't have it swept on every request.
+const SWEEP_GAP: Duration = Duration::from_secs(1);
+
+/// The bucket of requests with nothing to tell their client by.
+const ANONYMOUS: &str = "-";
+/// The bucket of new clients once there are `max_clients` buckets.
+const OVERFLOW: &str = "*";
+
+struct State {
+    buckets: HashMap<String, Bucket>,
+    last_sweep: Instant,
+}
+
+struct Bucket {
+    tokens: f64,
+    updated: Instant,
+}
+
+/// What a bucket said to a request.
+#[derive(Debug)]
+enum Decision {
+    Allowed { remaining: u64, reset: Duration },
+    Limited { retry_after: Duration },
+}
+
+impl RateLimit {
+    /// Allow `requests` requests every `per`, in bursts of as many.
+    pub fn new(requests: u32, per: Duration) -> RateLimit {
+        assert!(requests > 0, "a rate limit must allow some requests");
+        assert!(!per.is_zero(), "a rate limit needs a period");
+        RateLimit {
+            rate: f64::from(requests) / per.as_secs_f64(),
+            burst: f64::from(requests),
+            header: None,
+            max_clients: DEFAULT_MAX_CLIENTS,
+            state: Mutex::new(State {
+                buckets: HashMap::new(),
+                last_sweep: Instant::now(),
+            }),
+        }
+    }
+
+    /// How many requests a client may make at once, after being quiet.
+    pub fn burst(mut self, burst: u32) -> RateLimit {
+        assert!(burst > 0, "the burst must allow some requests");
+        self.burst = f64::from(burst);
+        self
+    }
+
+    /// Tell clients apart by this header rather than by their address.
+    /// Requests without it fall back to their address.
+    pub fn key_header(mut self, name: impl Into<String>) -> RateLimit {
+        self.header = Some(name.into());
+        self
+    }
+
+    /// How many clients may have buckets of their own. Defaults to 10,000.
+    pub fn max_clients(mut self, max: usize) -> RateLimit {
+        assert!(max > 0, "a rate limit must allow some clients");
+        self.max_clients = max;
+        self
+    }
+
+    /// The bucket a request takes from.
+    fn key(&self, request: &Request) -> String {
+        let header = self.header.as_deref().and_then(|name| request.header(name));
+        match (header, request.peer) {
+            (Some(value), _) => format!("h:{value}"),
+            (None, Some(ip)) => ip.to_string(),
+            (None, None) => ANONYMOUS.to_string(),
+        }
+    }
+
+    /// How long an untouched bucket takes to fill up, after which it's no
+    /// different from a new one and can go.
+    fn fill_time(&self) -> Duration {
+        Duration::from_secs_f64(self.burst / self.rate)
+    }
+
+    fn take(&self, mut key: String, now: Instant) -> Decision {
+        let mut state = self.state.lock().unwrap_or_else(|e| e.into_inner());
+        let full = |state: &State| {
+            state.buckets.len() >= self.max_clients && !state.buckets.contains_key(&key)
+        };
+        let since_sweep = now.saturating_duration_since(state.last_sweep);
+        if since_sweep >= self.fill_time() || (full(&state) && since_sweep >= SWEEP_GAP) {
+            self.sweep(&mut state.buckets, now);
+            state.last_sweep = now;
+        }
+        if full(&state) {
+            key = OVERFLOW.to_string();
+        }
+
+        let bucket = state.buckets.entry(key).or_insert(Bucket {
+            tokens: self.burst,
+            updated: now,
+        });
+        bucket.tokens = self.refilled(bucket, now);
+        bucket.updated = now;
+
+        if bucket.tokens >= 1.0 {
+            bucket.tokens -= 1.0;
+            Decision::Allowed {
+                remaining: bucket.tokens as u64,
+                reset: Duration::from_secs_f64((self.burst - bucket.tokens) / self.rate),
+            }
+        } else {
+            Decision::Limited {
+                retry_after: Duration::from_secs_f64((1.0 - bucket.tokens) / self.rate),
+            }
+        }
+    }
+
+    fn refilled(&self, bucket: &Bucket, now: Instant) -> f64 {
+        let elapsed = now.saturating_duration_since(bucket.updated).as_secs_f64();
+        (bucket.tokens + elapsed * self.rate).min(self.burst)
+    }
+
+    /// Drop the buckets that have filled up.
+    fn sweep(&self, buckets: &mut HashMap<String, Bucket>, now: Instant) {
+        buckets.retain(|_, bucket| self.refilled(bucket, now) < self.burst);
+    }
+}
+
+/// Whole seconds, rounded up, as rate limit headers have them.
+fn seconds(duration: Duration) -> String {
+    duration.as_secs_f64().ceil().to_string()
+}
+
+impl Middleware for RateLimit {
+    fn handle(&self, request: &Request, next: Next<'_>) -> Response {
+        let key = self.key(request);
+        let limit = (self.burst as u64).to_string();
+        match self.take(key, Instant::now()) {
+            Decision::Allowed { remaining, reset } => next
+                .run(request)
+                .with_header("RateLimit-Limit", limit)
+                .with_header("RateLimit-Remaining", remaining.to_string())
+                .with_header("RateLimit-Reset", seconds(reset)),
+            Decision::Limited { retry_after } => Response::text(429, "Too Many Requests\n")
+                .with_header("Retry-After", seconds(retry_after))
+                .with_header("RateLimit-Limit", limit)
+                .with_header("RateLimit-Remaining", "0")
+                .with_header("RateLimit-Reset", seconds(retry_after)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{middleware::Pipeline, server::Handler};
+
+    #[test]
+    fn buckets_refill() {
+        // A token every 100ms, 3 at once.
+        let limit = RateLimit::new(10, Duration::from_secs(1)).burst(3);
+        let start = Instant::now();
+        let at = |ms| start + Duration::from_millis(ms);
+
+        for remaining in [2, 1, 0] {
+            assert!(matches!(
+                limit.take("a".into(), start),
+                Decision::Allowed { remaining: r, .. } if r == remaining
+            ));
+        }
+        let Decision::Limited { retry_after } = limit.take("a".into(), at(50)) else {
+            panic!("the bucket should be empty");
+        };
+        assert!((retry_after.as_secs_f64() - 0.05).abs() < 1e-9);
+        // Another client has its own bucket.
+        assert!(matches!(
+            limit.take("b".into(), at(50)),
+            Decision::Allowed { .. }
+        ));
+
+        assert!(matches!(
+            limit.take("a".into(), at(150)),
+            Decision::Allowed { remaining: 0, .. }
+        ));
+        assert!(matches!(
+            limit.take("a".into(), at(160)),
+            Decision::Limited { .. }
+        ));
+    }
+
+    #[test]
+    fn idle_buckets_are_dropped() {
+        let limit = RateLimit::new(10, Duration::from_secs(1)).burst(2);
+        let start = Instant::now();
+        limit.take("a".into(), start);
+        limit.take("b".into(), start + Duration::from_millis(150));
+        limit.take("b".into(), start + Duration::from_millis(150));
+
+        // By now `a` has filled up again, but `b` hasn't.
+        limit.take("c".into(), start + Duration::from_millis(250));
+        let state = limit.state.lock().unwrap();
+        let mut keys: Vec<_> = state.buckets.keys().map(String::as_str).collect();
+        keys.sort();
+        assert_eq!(keys, ["b", "c"]);
+    }
+
+    #[test]
+    fn responses() {
+        let pipeline = Pipeline::new(|_: &Request| Response::new(200))
+            .with(RateLimit::new(1, Duration::from_secs(60)).key_header("X-Api-Key"));
+        let request = |ip: [u8; 4], key: Option<&str>| {
+            let mut request = Request::new("GET", "/");
+            request.peer = Some(ip.into());
+            if let Some(key) = key {
+                request.headers.insert("X-Api-Key", key);
+            }
+            request
+        };
+
+        let response = pipeline.handle(&request([10, 0, 0, 1], None));
+        assert_eq!(response.status, 200);
+        assert_eq!(response.headers.get("RateLimit-Limit"), Some("1"));
+        assert_eq!(response.headers.get("RateLimit-Remaining"), Some("0"));
+        assert_eq!(response.headers.get("RateLimit-Reset"), Some("60"));
+
+        let response = pipeline.handle(&request([10, 0, 0, 1], None));
+        assert_eq!(response.status, 429);
+        let retry_after: u64 = response
+            .headers
+            .get("Retry-After")
+            .unwrap()
+            .parse()
+            .unwrap();
+        assert!((59..=60).contains(&retry_after));
+
+        // The same address with a key, and the key from elsewhere.
+        assert_eq!(
+            pipeline.handle(&request([10, 0, 0, 1], Some("k"))).status,
+            200
+        );
+        assert_eq!(
+            pipeline.handle(&request([10, 0, 0, 2], Some("k"))).status,
+            429
+        );
+        assert_eq!(pipeline.handle(&request([10, 0, 0, 2], None)).status, 200);
+
+        // Nothing to tell the client by, so a bucket shared with others
+        // like it.
+        let anonymous = Request::new("GET", "/");
+        assert_eq!(pipeline.handle(&anonymous).status, 200);
+        assert_eq!(pipeline.handle(&anonymous).status, 429);
+    }
+
+    #[test]
+    fn caps_clients() {
+        let limit = RateLimit::new(10, Duration::from_secs(1))
+            .burst(1)
+            .max_clients(2);
+        let start = Instant::now();
+        limit.take("a".into(), start);
+        limit.take("b".into(), start);
+
+        // New clients share a bucket while the others are still filling.
+        assert!(matches!(
+            limit.take("c".into(), start),
+            Decision::Allowed { .. }
+        ));
+        assert!(matches!(
+            limit.take("d".into(), start),
+            Decision::Limited { .. }
+        ));
+        assert!(matches!(
+            limit.take("a".into(), start + Duration::from_millis(100)),
+            Decision::Allowed { .. }
+        ));
+
+        // Once they've filled up, they make room for new ones.
+        assert!(matches!(
+            limit.take("d".into(), start + Duration::from_millis(300)),
+            Decision::Allowed { .. }
+        ));
+        let state = limit.state.lock().unwrap();
+        assert!(state.buckets.contains_key("d"));
+        assert!(state.buckets.len() <= 2);
+    }
+
+    #[test]
+    fn full_tables_are_swept_sparingly() {
+        // Buckets take a minute to fill up.
+        let limit = RateLimit::new(1, Duration::from_secs(60)).max_clients(2);
+        let start = Instant::now();
+        limit.take("a".into(), start);
+        limit.take("b".into(), start);
+        let swept = limit.state.lock().unwrap().last_sweep;
+
+        // New keys go to the shared bucket without a sweep in between.
+        for ms in [100, 200, 300] {
+            limit.take(format!("new-{ms}"), start + Duration::from_millis(ms));
+        }
+        let state = limit.state.lock().unwrap();
+        assert_eq!(state.last_sweep, swept);
+        assert!(state.buckets.contains_key(OVERFLOW));
+        assert_eq!(state.buckets.len(), 3);
+    }
+}
@@ -0,0 +1,71 @@
+use std::panic::{self, AssertUnwindSafe};
+
+use super::{Middleware, Next};
+use crate::http::{Request, Response};
+
+type Respond = dyn Fn(&Request) -> Response + Send + Sync;
+
+/// Turns a panic further down the pipeline into a 500 response, instead of
+/// a dropped connection. The panic message is printed to stderr, as usual.
+pub struct Recover {
+    respond: Box<Respond>,
+}
+
+impl Recover {
+    pub fn new() -> Recover {
+        Recover {
+            respond: Box::new(|_| Response::text(500, "Internal Server Error\n")),
+        }
+    }
+
+    /// Make the response to send after a panic, like an error page.
+    pub fn response<F>(mut self, respond: F) -> Recover
+    where
+        F: Fn(&Request) -> Response + Send + Sync + 'static,
+    {
+        self.respond = Box::new(respond);
+        self
+    }
+}
+
+impl Default for Recover {
+    fn default() -> Recover {
+        Recover::new()
+    }
+}
+
+impl Middleware for Recover {
+    fn handle(&self, request: &Request, next: Next<'_>) -> Response {
+        // Nothing the handler had borrowed is used after a panic, so any
+        // broken state it left behind can't be seen.
+        match panic::catch_unwind(AssertUnwindSafe(|| next.run(request))) {
+            Ok(response) => response,
+            Err(_) => {
+                eprintln!("Handler panicked on {} {}", request.method, request.target);
+                (self.respond)(request)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{middleware::Pipeline, server::Handler};
+
+    #[test]
+    fn recovers() {
+        let handler = |request: &Request| match request.path() {
+            "/panic" => panic!("boom"),
+            _ => Response::new(200),
+        };
+        let pipeline = Pipeline::new(handler).with(Recover::new());
+        assert_eq!(pipeline.handle(&Request::new("GET", "/")).status, 200);
+        assert_eq!(pipeline.handle(&Request::new("GET", "/panic")).status, 500);
+
+        let pipeline = Pipeline::new(handler)
+            .with(Recover::new().response(|request| Response::text(503, request.target.clone())));
+        let response = pipeline.handle(&Request::new("GET", "/panic"));
+        assert_eq!((response.status, response.body), (503, b"/panic".to_vec()));
+    }
+}
@@ -0,0 +1,119 @@
+use std::{
+    collections::hash_map::RandomState,
+    hash::{BuildHasher, Hasher},
+    sync::atomic::{AtomicU64, Ordering},
+    time::SystemTime,
+};
+
+use super::{Middleware, Next};
+use crate::http::{Request, Response};
+
+/// Gives each request an ID, so that log lines and error reports about it
+/// can be tied together. The ID is set on the request the handler sees and
+/// on the response.
+///
+/// An ID the client or a proxy in front already sent is kept, if it looks
+/// sane: up to 128 visible ASCII characters.
+pub struct RequestId {
+    header: String,
+    keys: RandomState,
+    counter: AtomicU64,
+}
+
+impl RequestId {
+    /// Request IDs in `X-Request-Id`.
+    pub fn new() -> RequestId {
+        RequestId {
+            header: "X-Request-Id".into(),
+            keys: RandomState::new(),
+            counter: AtomicU64::new(0),
+        }
+    }
+
+    /// Use another header, like `X-Correlation-Id`.
+    pub fn header(mut self, name: impl Into<String>) -> RequestId {
+        self.header = name.into();
+        self
+    }
+
+    /// The ID of a request that went through this middleware.
+    pub fn get<'a>(&self, request: &'a Request) -> Option<&'a str> {
+        request.header(&self.header)
+    }
+
+    /// A new random ID of 32 hex digits. `RandomState` is seeded randomly
+    /// for each process, and the counter keeps IDs within one apart.
+    fn generate(&self) -> String {
+        let count = self.counter.fetch_add(1, Ordering::Relaxed);
+        let now = SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_nanos();
+        let half = |salt: u64| {
+            let mut hasher = self.keys.build_hasher();
+            hasher.write_u64(salt);
+            hasher.write_u64(count);
+            hasher.write_u128(now);
+            hasher.finish()
+        };
+        format!("{:016x}{:016x}", half(0), half(1))
+    }
+}
+
+impl Default for RequestId {
+    fn default() -> RequestId {
+        RequestId::new()
+    }
+}
+
+fn is_valid(id: &str) -> bool {
+    !id.is_empty() && id.len() <= 128 && id.bytes().all(|b| b.is_ascii_graphic())
+}
+
+impl Middleware for RequestId {
+    fn handle(&self, request: &Request, next: Next<'_>) -> Response {
+        let id = match request.header(&self.header) {
+            Some(id) if is_valid(id) => id.to_string(),
+            _ => self.generate(),
+        };
+        // The copy shares the body, only the head is copied.
+        let mut request = request.clone();
+        request.headers.insert(self.header.as_str(), id.as_str());
+
+        let mut response = next.run(&request);
+        response.headers.insert(self.header.as_str(), id);
+        response
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{middleware::Pipeline, server::Handler};
+
+    #[test]
+    fn sets_ids() {
+        let pipeline = Pipeline::new(|request: &Request| {
+            Response::text(200, request.header("X-Request-Id").unwrap_or_default())
+        })
+        .with(RequestId::new());
+
+        let first = pipeline.handle(&Request::new("GET", "/"));
+        let id = first.headers.get("X-Request-Id").unwrap();
+        assert_eq!(id.len(), 32);
+        assert!(id.bytes().all(|b| b.is_ascii_hexdigit()));
+        assert_eq!(first.body, id.as_bytes());
+
+        let second = pipeline.handle(&Request::new("GET", "/"));
+        assert_ne!(second.headers.get("X-Request-Id"), Some(id));
+
+        let mut request = Request::new("GET", "/");
+        request.headers.insert("X-Request-Id", "upstream-42");
+        let response = pipeline.handle(&request);
+        assert_eq!(response.headers.get("X-Request-Id"), Some("upstream-42"));
+
+        request.headers.insert("X-Request-Id", "has spaces");
+        let response = pipeline.handle(&request);
+        assert_eq!(response.headers.get("X-Request-Id").unwrap().len(), 32);
+    }
+}
@@ -0,0 +1,777 @@
+mod queue;
+mod scope;
+mod stats;
+mod timer;
+mod worker;
+
+use std::{
+    cell::RefCell,
+    collections::HashMap,
+    error::Error,
+    fmt, mem,
+    sync::{
+        atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering},
+        Arc, Condvar, Mutex, RwLock,
+    },
+    thread,
+    time::{Duration, Instant},
+};
+
+use queue::{Injector, Job};
+use timer::Timers;
+use worker::Slot;
+
+pub use scope::Scope;
+pub use stats::{Event, Stats, WorkerStats};
+pub use timer::TimerHandle;
+
+/// What `ThreadPool::execute` does when the job queue is full.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum RejectionPolicy {
+    /// Block the caller until a worker takes a job off the queue.
+    #[default]
+    Block,
+    /// Refuse the job and return `ExecuteError::QueueFull`.
+    Reject,
+    /// Run the job right away on the thread that called `execute`.
+    CallerRuns,
+    /// Drop the oldest queued job to make room for the new one.
+    DropOldest,
+}
+
+/// The error returned by `ThreadPool::execute` when a job is not accepted.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExecuteError {
+    /// The queue is full and the pool uses `RejectionPolicy::Reject`.
+    QueueFull,
+}
+
+impl fmt::Display for ExecuteError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ExecuteError::QueueFull => write!(f, "thread pool queue is full"),
+        }
+    }
+}
+
+impl Error for ExecuteError {}
+
+type Hook = Arc<dyn Fn(&Event) + Send + Sync + 'static>;
+
+thread_local! {
+    /// The pool and slot of the worker running on the current thread, if any.
+    static WORKER: RefCell<Option<(usize, Arc<Slot>)>> = const { RefCell::new(None) };
+}
+
+/// Bounds for the auto-scaler, see `Builder::autoscale`.
+#[derive(Debug, Clone, Copy)]
+struct Autoscale {
+    min: usize,
+    max: usize,
+    idle_timeout: Duration,
+}
+
+/// What a parked worker should do next.
+enum Wait {
+    Work,
+    /// Nothing came up for the auto-scaler's idle timeout.
+    Idle,
+    Shutdown,
+}
+
+/// The scheduler state shared between the pool and its workers.
+///
+/// Jobs submitted from outside the pool go through the global injector
+/// queue. Each worker moves a batch of them into its own deque and, once both
+/// are empty, steals from the other workers' deques.
+struct Shared {
+    injector: Injector,
+    workers: RwLock<Vec<Arc<Slot>>>,
+    threads: Mutex<HashMap<usize, thread::JoinHandle<()>>>,
+    /// The number of workers, not counting those asked to stop. Only changed
+    /// with `threads` locked.
+    size: AtomicUsize,
+    next_id: AtomicUsize,
+    autoscale: Option<Autoscale>,
+    hook: Option<Hook>,
+    /// The number of jobs waiting in the injector or in any local deque.
+    pending: AtomicUsize,
+    running: AtomicUsize,
+    completed: AtomicU64,
+    panicked: AtomicU64,
+    /// The number of parked workers, so producers can skip the wake-up lock
+    /// when everybody is busy.
+    sleeping: AtomicUsize,
+    sleep: Mutex<()>,
+    wakeup: Condvar,
+    shutdown: AtomicBool,
+}
+
+impl Shared {
+    fn id(&self) -> usize {
+        self as *const Shared as usize
+    }
+
+    fn emit(&self, event: Event) {
+        if let Some(hook) = &self.hook {
+            hook(&event);
+        }
+    }
+
+    /// The slot of the worker running on the current thread, if it belongs
+    /// to this pool.
+    fn current_worker(&self) -> Option<Arc<Slot>> {
+        WORKER.with_borrow(|worker| match worker {
+            Some((pool, slot)) if *pool == self.id() => Some(Arc::clone(slot)),
+            _ => None,
+        })
+    }
+
+    fn push(self: &Arc<Self>, job: Job) -> Result<(), Job> {
+        let job = WORKER.with_borrow(|worker| match worker {
+            Some((pool, slot)) if *pool == self.id() => {
+                self.pending.fetch_add(1, Ordering::SeqCst);
+                slot.local.push(job);
+                None
+            }
+            _ => Some(job),
+        });
+        if let Some(job) = job {
+            self.injector.push(job, &self.pending)?;
+        }
+
+        if self.sleeping.load(Ordering::SeqCst) > 0 {
+            let _guard = self.sleep.lock().unwrap();
+            self.wakeup.notify_one();
+        }
+        if let Some(autoscale) = self.autoscale {
+            self.grow_if_busy(autoscale.max);
+        }
+        Ok(())
+    }
+
+    /// Look for a job in the worker's own deque, then in the injector, and
+    /// finally in the other workers' deques.
+    fn find_job(&self, slot: &Slot) -> Option<Job> {
+        let job = slot
+            .local
+            .pop()
+            .or_else(|| {
+                let workers = self.size.load(Ordering::SeqCst).max(1);
+                self.injector.take_batch(&slot.local, workers)
+            })
+            .or_else(|| self.steal(slot))?;
+
+        self.pending.fetch_sub(1, Ordering::SeqCst);
+        Some(job)
+    }
+
+    fn steal(&self, thief: &Slot) -> Option<Job> {
+        let workers = self.workers.read().unwrap();
+        let start = workers.iter().position(|slot| slot.id == thief.id)?;
+        let n = workers.len();
+
+        (1..n)
+            .map(|i| &workers[(start + i) % n])
+            .find_map(|victim| victim.local.steal_into(&thief.local))
+    }
+
+    /// Park the calling worker until there are jobs to run.
+    fn wait(&self, slot: &Slot) -> Wait {
+        let mut guard = self.sleep.lock().unwrap();
+        self.sleeping.fetch_add(1, Ordering::SeqCst);
+        let idle_since = Instant::now();
+
+        let wait = loop {
+            if self.pending.load(Ordering::SeqCst) > 0 || slot.is_retiring() {
+                break Wait::Work;
+            }
+            if self.shutdown.load(Ordering::SeqCst) {
+                break Wait::Shutdown;
+            }
+            match self.autoscale {
+                Some(autoscale) => {
+                    let idle = idle_since.elapsed();
+                    if idle >= autoscale.idle_timeout {
+                        break Wait::Idle;
+                    }
+                    let timeout = autoscale.idle_timeout - idle;
+                    guard = self.wakeup.wait_timeout(guard, timeout).unwrap().0;
+                }
+                None => guard = self.wakeup.wait(guard).unwrap(),
+            }
+        };
+
+        self.sleeping.fetch_sub(1, Ordering::SeqCst);
+        wait
+    }
+
+    fn wake_all(&self) {
+        let _guard = self.sleep.lock().unwrap();
+        self.wakeup.notify_all();
+    }
+
+    fn spawn_worker(self: &Arc<Self>, threads: &mut HashMap<usize, thread::JoinHandle<()>>) {
+        let slot = Arc::new(Slot::new(self.next_id.fetch_add(1, Ordering::SeqCst)));
+        self.workers.write().unwrap().push(Arc::clone(&slot));
+        self.size.fetch_add(1, Ordering::SeqCst);
+
+        let id = slot.id;
+        threads.insert(id, worker::spawn(self, slot));
+    }
+
+    fn resize(self: &Arc<Self>, size: usize) {
+        let mut threads = self.threads.lock().unwrap();
+        if self.shutdown.load(Ordering::SeqCst) {
+            return;
+        }
+
+        let from = self.size.load(Ordering::SeqCst);
+        if size > from {
+            for _ in from..size {
+                self.spawn_worker(&mut threads);
+            }
+        } else if size < from {
+            let workers = self.workers.read().unwrap();
+            let retiring = workers
+                .iter()
+                .rev()
+                .filter(|slot| !slot.is_retiring())
+                .take(from - size);
+            for slot in retiring {
+                slot.retire();
+            }
+            drop(workers);
+
+            self.size.store(size, Ordering::SeqCst);
+            self.wake_all();
+        } else {
+            return;
+        }
+        drop(threads);
+
+        self.emit(Event::Resized { from, to: size });
+    }
+
+    /// Add a worker if there are more jobs, running or queued, than workers.
+    fn grow_if_busy(self: &Arc<Self>, max: usize) {
+        let busy = || {
+            let size = self.size.load(Ordering::SeqCst);
+            let jobs = self.running.load(Ordering::Relaxed) + self.pending.load(Ordering::SeqCst);
+            size < max && jobs > size
+        };
+
+        if busy() {
+            let mut threads = self.threads.lock().unwrap();
+            if busy() && !self.shutdown.load(Ordering::SeqCst) {
+                let from = self.size.load(Ordering::SeqCst);
+                self.spawn_worker(&mut threads);
+                drop(threads);
+                self.emit(Event::Resized { from, to: from + 1 });
+            }
+        }
+    }
+
+    /// Retire a worker that has been idle for the auto-scaler's timeout,
+    /// unless the pool is already at its minimum size.
+    fn retire_idle(&self, slot: &Slot) -> bool {
+        let Some(autoscale) = self.autoscale else {
+            return false;
+        };
+
+        let _threads = self.threads.lock().unwrap();
+        let from = self.size.load(Ordering::SeqCst);
+        if from <= autoscale.min || slot.is_retiring() {
+            return false;
+        }
+        slot.retire();
+        self.size.store(from - 1, Ordering::SeqCst);
+        drop(_threads);
+
+        self.emit(Event::Resized { from, to: from - 1 });
+        true
+    }
+
+    /// Unregister a stopping worker, handing its queued jobs to the others.
+    fn remove_worker(&self, slot: &Slot) {
+        if let Some(thread) = self.threads.lock().unwrap().remove(&slot.id) {
+            // A worker can't join itself, let it go instead.
+            drop(thread);
+        }
+        self.workers
+            .write()
+            .unwrap()
+            .retain(|other| other.id != slot.id);
+
+        let jobs = slot.local.take_all();
+        if !jobs.is_empty() {
+            self.injector.push_all(jobs);
+            self.wake_all();
+        }
+    }
+
+    fn stats(&self) -> Stats {
+        let per_worker: Vec<WorkerStats> = self
+            .workers
+            .read()
+            .unwrap()
+            .iter()
+            .filter(|slot| !slot.is_retiring())
+            .map(|slot| slot.stats())
+            .collect();
+
+        Stats {
+            workers: per_worker.len(),
+            queued: self.pending.load(Ordering::SeqCst),
+            running: self.running.load(Ordering::Relaxed),
+            completed: self.completed.load(Ordering::Relaxed),
+            panicked: self.panicked.load(Ordering::Relaxed),
+            per_worker,
+        }
+    }
+
+    fn shutdown(&self) {
+        self.shutdown.store(true, Ordering::SeqCst);
+        self.wake_all();
+    }
+}
+
+/// Configures and creates a `ThreadPool`.
+///
+/// ```
+/// use echo::pool::{RejectionPolicy, ThreadPool};
+///
+/// let pool = ThreadPool::builder()
+///     .size(2)
+///     .queue_capacity(8)
+///     .rejection_policy(RejectionPolicy::Reject)
+///     .on_event(|event| println!("{event}"))
+///     .build();
+///
+/// pool.execute(|| println!("hello")).unwrap();
+/// ```
+#[derive(Clone)]
+pub struct Builder {
+    size: usize,
+    queue_capacity: Option<usize>,
+    policy: RejectionPolicy,
+    autoscale: Option<Autoscale>,
+    hook: Option<Hook>,
+}
+
+impl Builder {
+    /// Create a builder with one worker per available CPU and an unbounded
+    /// queue.
+    pub fn new() -> Builder {
+        Builder {
+            size: thread::available_parallelism().map_or(1, |n| n.get()),
+            queue_capacity: None,
+            policy: RejectionPolicy::default(),
+            autoscale: None,
+            hook: None,
+        }
+    }
+
+    /// The number of threads in the pool.
+    pub fn size(mut self, size: usize) -> Builder {
+        self.size = size;
+        self
+    }
+
+    /// The maximum number of jobs waiting for a worker. Once the queue is
+    /// full, the rejection policy decides what happens to new jobs.
+    pub fn queue_capacity(mut self, capacity: usize) -> Builder {
+        self.queue_capacity = Some(capacity);
+        self
+    }
+
+    /// What to do with new jobs when the queue is full.
+    pub fn rejection_policy(mut self, policy: RejectionPolicy) -> Builder {
+        self.policy = policy;
+        self
+    }
+
+    /// Let the pool size itself between `min` and `max` workers, instead of
+    /// using a fixed `size`.
+    ///
+    /// The pool starts with `min` workers. A worker is added when a job is
+    /// queued while every worker is busy, and a worker that found nothing to
+    /// do for `idle_timeout` stops.
+    pub fn autoscale(mut self, min: usize, max: usize, idle_timeout: Duration) -> Builder {
+        self.autoscale = Some(Autoscale {
+            min,
+            max,
+            idle_timeout,
+        });
+        self
+    }
+
+    /// Install a hook called for every `Event` in the pool, e.g. to log them.
+    /// The hook runs on the thread where the event happened.
+    pub fn on_event<F>(mut self, hook: F) -> Builder
+    where
+        F: Fn(&Event) + Send + Sync + 'static,
+    {
+        self.hook = Some(Arc::new(hook));
+        self
+    }
+
+    /// Create the ThreadPool.
+    ///
+    /// # Panics
+    ///
+    /// The `build` function will panic if the size or the queue capacity is
+    /// zero, or if the auto-scaler's `min` is zero or greater than `max`.
+    pub fn build(self) -> ThreadPool {
+        let size = match self.autoscale {
+            Some(autoscale) => {
+                assert!(autoscale.min > 0 && autoscale.min <= autoscale.max);
+                autoscale.min
+            }
+            None => self.size,
+        };
+        assert!(size > 0);
+        assert!(self.queue_capacity != Some(0));
+
+        let shared = Arc::new(Shared {
+            injector: Injector::new(self.queue_capacity, self.policy),
+            workers: RwLock::new(Vec::with_capacity(size)),
+            threads: Mutex::new(HashMap::new()),
+            size: AtomicUsize::new(0),
+            next_id: AtomicUsize::new(0),
+            autoscale: self.autoscale,
+            hook: self.hook,
+            pending: AtomicUsize::new(0),
+            running: AtomicUsize::new(0),
+            completed: AtomicU64::new(0),
+            panicked: AtomicU64::new(0),
+            sleeping: AtomicUsize::new(0),
+            sleep: Mutex::new(()),
+            wakeup: Condvar::new(),
+            shutdown: AtomicBool::new(false),
+        });
+
+        let mut threads = shared.threads.lock().unwrap();
+        for _ in 0..size {
+            shared.spawn_worker(&mut threads);
+        }
+        drop(threads);
+
+        ThreadPool {
+            shared,
+            timers: Timers::new(),
+        }
+    }
+}
+
+impl Default for Builder {
+    fn default() -> Builder {
+        Builder::new()
+    }
+}
+
+impl fmt::Debug for Builder {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Builder")
+            .field("size", &self.size)
+            .field("queue_capacity", &self.queue_capacity)
+            .field("policy", &self.policy)
+            .field("autoscale", &self.autoscale)
+            .finish_non_exhaustive()
+    }
+}
+
+pub struct ThreadPool {
+    shared: Arc<Shared>,
+    timers: Timers,
+}
+
+impl ThreadPool {
+    /// Create a new ThreadPool with an unbounded queue.
+    ///
+    /// The size is the number of threads in the pool.
+    ///
+    /// # Panics
+    ///
+    /// The `new` function will panic if the size is zero.
+    pub fn new(size: usize) -> ThreadPool {
+        Builder::new().size(size).build()
+    }
+
+    /// Start configuring a ThreadPool with a bounded queue, a different
+    /// rejection policy, auto-scaling or an event hook.
+    pub fn builder() -> Builder {
+        Builder::new()
+    }
+
+    /// Queue a job to be run by one of the workers.
+    ///
+    /// With a bounded queue, a full queue is handled according to the pool's
+    /// `RejectionPolicy`; only `RejectionPolicy::Reject` returns an error.
+    /// Jobs queued by another job of the same pool go straight to the
+    /// worker's own deque and are never rejected.
+    pub fn execute<F>(&self, f: F) -> Result<(), ExecuteError>
+    where
+        F: FnOnce() + Send + 'static,
+    {
+        let job = Box::new(f);
+
+        self.shared.push(job).map_err(|_| ExecuteError::QueueFull)
+    }
+
+    /// The number of workers.
+    pub fn size(&self) -> usize {
+        self.shared.size.load(Ordering::SeqCst)
+    }
+
+    /// Grow or shrink the pool to `size` workers.
+    ///
+    /// New workers start right away. When shrinking, busy workers stop after
+    /// their current job and hand their queued jobs over to the others. With
+    /// auto-scaling, the pool keeps sizing itself from there.
+    ///
+    /// # Panics
+    ///
+    /// The `resize` function will panic if the size is zero.
+    pub fn resize(&self, size: usize) {
+        assert!(size > 0);
+
+        self.shared.resize(size);
+    }
+
+    /// A snapshot of the queue and worker counters.
+    pub fn stats(&self) -> Stats {
+        self.shared.stats()
+    }
+
+    /// `stats` as a function of its own, for whoever reports on the pool
+    /// without owning it.
+    pub(crate) fn stats_fn(&self) -> impl Fn() -> Stats + Send + Sync + 'static {
+        let shared = Arc::clone(&self.shared);
+        move || shared.stats()
+    }
+
+    /// `execute` as a function of its own, for whoever queues jobs without
+    /// owning the pool. Jobs queued once the pool is dropped never run.
+    pub(crate) fn execute_fn(&self) -> impl Fn(Job) -> Result<(), ExecuteError> + Send + Sync {
+        let shared = Arc::clone(&self.shared);
+        move |job| shared.push(job).map_err(|_| ExecuteError::QueueFull)
+    }
+}
+
+impl Drop for ThreadPool {
+    fn drop(&mut self) {
+        self.timers.shutdown();
+        self.shared.shutdown();
+
+        let threads = mem::take(&mut *self.shared.threads.lock().unwrap());
+        for (_, thread) in threads {
+            thread.join().unwrap();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::mpsc;
+
+    /// A single-worker pool with a queue of one, whose worker is kept busy
+    /// until the returned sender is dropped.
+    fn busy_pool(policy: RejectionPolicy) -> (ThreadPool, mpsc::Sender<()>) {
+        let pool = ThreadPool::builder()
+            .size(1)
+            .queue_capacity(1)
+            .rejection_policy(policy)
+            .build();
+
+        let (started_tx, started_rx) = mpsc::channel();
+        let (release_tx, release_rx) = mpsc::channel::<()>();
+        pool.execute(move || {
+            started_tx.send(()).unwrap();
+            let _ = release_rx.recv();
+        })
+        .unwrap();
+        started_rx.recv().unwrap();
+
+        (pool, release_tx)
+    }
+
+    #[test]
+    fn reject_when_full() {
+        let (pool, release) = busy_pool(RejectionPolicy::Reject);
+
+        assert_eq!(pool.execute(|| {}), Ok(()));
+        assert_eq!(pool.execute(|| {}), Err(ExecuteError::QueueFull));
+
+        drop(release);
+    }
+
+    #[test]
+    fn caller_runs_when_full() {
+        let (pool, release) = busy_pool(RejectionPolicy::CallerRuns);
+        pool.execute(|| {}).unwrap();
+
+        let caller = thread::current().id();
+        let (tx, rx) = mpsc::channel();
+        pool.execute(move || tx.send(thread::current().id()).unwrap())
+            .unwrap();
+        assert_eq!(rx.recv().unwrap(), caller);
+
+        drop(release);
+    }
+
+    #[test]
+    fn drop_oldest_when_full() {
+        let (pool, release) = busy_pool(RejectionPolicy::DropOldest);
+
+        let (tx, rx) = mpsc::channel();
+        for i in 0..3 {
+            let tx = tx.clone();
+            pool.execute(move || tx.send(i).unwrap()).unwrap();
+        }
+        drop(tx);
+        drop(release);
+        drop(pool);
+
+        assert_eq!(rx.iter().collect::<Vec<_>>(), vec![2]);
+    }
+
+    #[test]
+    fn runs_every_job() {
+        let pool = ThreadPool::new(4);
+        let (tx, rx) = mpsc::channel();
+
+        for _ in 0..100 {
+            let tx = tx.clone();
+            pool.execute(move || tx.send(()).unwrap()).unwrap();
+        }
+        drop(tx);
+        drop(pool);
+
+        assert_eq!(rx.iter().count(), 100);
+    }
+
+    #[test]
+    fn nested_jobs_bypass_the_queue_limit() {
+        let pool = Arc::new(
+            ThreadPool::builder()
+                .size(2)
+                .queue_capacity(1)
+                .rejection_policy(RejectionPolicy::Reject)
+                .build(),
+        );
+        let (tx, rx) = mpsc::channel();
+
+        let inner = Arc::clone(&pool);
+        pool.execute(move || {
+            for _ in 0..10 {
+                let tx = tx.clone();
+                inner.execute(move || tx.send(()).unwrap()).unwrap();
+            }
+            // The test must hold the last reference, a worker can't join
+            // itself when the pool is dropped.
+            drop(inner);
+            tx.send(()).unwrap();
+        })
+        .unwrap();
+
+        assert_eq!(rx.iter().take(11).count(), 11);
+    }
+
+    #[test]
+    fn resize_keeps_running_jobs() {
+        let pool = ThreadPool::new(2);
+
+        pool.resize(4);
+        assert_eq!(pool.size(), 4);
+        assert_eq!(pool.stats().workers, 4);
+
+        pool.resize(1);
+        assert_eq!(pool.size(), 1);
+        assert_eq!(pool.stats().workers, 1);
+
+        let (tx, rx) = mpsc::channel();
+        for _ in 0..10 {
+            let tx = tx.clone();
+            pool.execute(move || tx.send(()).unwrap()).unwrap();
+        }
+        assert_eq!(rx.iter().take(10).count(), 10);
+    }
+
+    #[test]
+    fn stats_and_events() {
+        let (events_tx, events_rx) = mpsc::channel();
+        let events_tx = Mutex::new(events_tx);
+        let pool = ThreadPool::builder()
+            .size(2)
+            .on_event(move |event| events_tx.lock().unwrap().send(event.clone()).unwrap())
+            .build();
+
+        for _ in 0..5 {
+            pool.execute(|| {}).unwrap();
+        }
+        pool.execute(|| panic!("boom")).unwrap();
+
+        let panicked = events_rx
+            .iter()
+            .find(|event| matches!(event, Event::JobPanicked { .. }));
+        assert!(matches!(
+            panicked,
+            Some(Event::JobPanicked { message, .. }) if message == "boom"
+        ));
+
+        while pool.stats().completed < 5 {
+            thread::yield_now();
+        }
+        let stats = pool.stats();
+        assert_eq!(stats.workers, 2);
+        assert_eq!(stats.panicked, 1);
+        assert_eq!(stats.per_worker.iter().map(|w| w.completed).sum::<u64>(), 5);
+    }
+
+    #[test]
+    fn autoscale_between_min_and_max() {
+        let pool = ThreadPool::builder()
+            .autoscale(1, 4, Duration::from_millis(20))
+            .build();
+        assert_eq!(pool.size(), 1);
+
+        // Every job waits for all of them to be running, which takes four
+        // workers.
+        let barrier = Arc::new(std::sync::Barrier::new(5));
+        for _ in 0..4 {
+            let barrier = Arc::clone(&barrier);
+            pool.execute(move || {
+                barrier.wait();
+            })
+            .unwrap();
+        }
+        barrier.wait();
+        assert_eq!(pool.size(), 4);
+
+        let start = Instant::now();
+        while pool.size() > 1 {
+            assert!(start.elapsed() < Duration::from_secs(5));
+            thread::sleep(Duration::from_millis(5));
+        }
+    }
+
+    #[test]
+    fn block_until_space() {
+        let (pool, release) = busy_pool(RejectionPolicy::Block);
+        pool.execute(|| {}).unwrap();
+
+        let pool = Arc::new(pool);
+        let (tx, rx) = mpsc::channel();
+        let producer = {
+            let pool = Arc::clone(&pool);
+            thread::spawn(move || pool.execute(move || tx.send(()).unwrap()))
+        };
+
+        assert!(rx.try_recv().is_err());
+        drop(release);
+        assert_eq!(producer.join().unwrap(), Ok(()));
+        rx.recv().unwrap();
+    }
+}
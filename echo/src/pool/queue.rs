@@ -0,0 +1,149 @@
+use std::{
+    collections::VecDeque,
+    mem,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Condvar, Mutex,
+    },
+};
+
+use super::RejectionPolicy;
+
+pub(super) type Job = Box<dyn FnOnce() + Send + 'static>;
+
+/// The most jobs a worker moves from the injector to its own deque at once.
+const MAX_BATCH: usize = 32;
+
+/// The global queue for jobs submitted from outside the pool.
+///
+/// It is the only bounded queue, so this is where the rejection policy
+/// applies. Jobs spawned by a worker go to that worker's own deque instead,
+/// which means a job can never block on the pool it is running in.
+pub(super) struct Injector {
+    jobs: Mutex<VecDeque<Job>>,
+    capacity: Option<usize>,
+    policy: RejectionPolicy,
+    /// Signalled when jobs are taken off the queue, waking up blocked
+    /// producers.
+    space: Condvar,
+}
+
+impl Injector {
+    pub(super) fn new(capacity: Option<usize>, policy: RejectionPolicy) -> Injector {
+        Injector {
+            jobs: Mutex::new(VecDeque::new()),
+            capacity,
+            policy,
+            space: Condvar::new(),
+        }
+    }
+
+    /// Queue a job, applying the rejection policy if the queue is full. A
+    /// rejected job is handed back to the caller.
+    ///
+    /// `pending` is incremented before the job becomes visible to workers.
+    pub(super) fn push(&self, job: Job, pending: &AtomicUsize) -> Result<(), Job> {
+        let mut jobs = self.jobs.lock().unwrap();
+        let mut dropped = None;
+
+        if let Some(capacity) = self.capacity {
+            while jobs.len() >= capacity {
+                match self.policy {
+                    RejectionPolicy::Block => jobs = self.space.wait(jobs).unwrap(),
+                    RejectionPolicy::Reject => return Err(job),
+                    RejectionPolicy::CallerRuns => {
+                        drop(jobs);
+                        job();
+                        return Ok(());
+                    }
+                    RejectionPolicy::DropOldest => {
+                        dropped = jobs.pop_front();
+                        pending.fetch_sub(1, Ordering::SeqCst);
+                    }
+                }
+            }
+        }
+
+        pending.fetch_add(1, Ordering::SeqCst);
+        jobs.push_back(job);
+        drop(jobs);
+
+        // Whatever the dropped job owns is released outside of the lock.
+        drop(dropped);
+        Ok(())
+    }
+
+    /// Queue jobs regardless of the capacity, for jobs that were already
+    /// accepted once. `pending` already counts them.
+    pub(super) fn push_all(&self, jobs: VecDeque<Job>) {
+        self.jobs.lock().unwrap().extend(jobs);
+    }
+
+    /// Take a fair share of the queued jobs, moving all but the first one
+    /// into `local`.
+    pub(super) fn take_batch(&self, local: &Local, workers: usize) -> Option<Job> {
+        let mut jobs = self.jobs.lock().unwrap();
+        let first = jobs.pop_front()?;
+
+        let batch = (jobs.len() / workers).min(MAX_BATCH);
+        let rest: Vec<Job> = jobs.drain(..batch).collect();
+        drop(jobs);
+
+        if rest.is_empty() {
+            self.space.notify_one();
+        } else {
+            self.space.notify_all();
+            local.extend(rest);
+        }
+
+        Some(first)
+    }
+}
+
+/// A worker's own deque.
+///
+/// The owner takes jobs from the front, so jobs keep their submission order,
+/// while thieves take from the back. Each deque has its own lock, so workers
+/// only contend with each other when one of them runs out of work.
+pub(super) struct Local {
+    jobs: Mutex<VecDeque<Job>>,
+}
+
+impl Local {
+    pub(super) fn new() -> Local {
+        Local {
+            jobs: Mutex::new(VecDeque::new()),
+        }
+    }
+
+    pub(super) fn push(&self, job: Job) {
+        self.jobs.lock().unwrap().push_back(job);
+    }
+
+    fn extend(&self, jobs: impl IntoIterator<Item = Job>) {
+        self.jobs.lock().unwrap().extend(jobs);
+    }
+
+    pub(super) fn pop(&self) -> Option<Job> {
+        self.jobs.lock().unwrap().pop_front()
+    }
+
+    pub(super) fn take_all(&self) -> VecDeque<Job> {
+        mem::take(&mut *self.jobs.lock().unwrap())
+    }
+
+    /// Steal half of the jobs in this deque (rounding up), running the first
+    /// one and moving the rest into `thief`.
+    pub(super) fn steal_into(&self, thief: &Local) -> Option<Job> {
+        let mut jobs = self.jobs.lock().unwrap();
+        let keep = jobs.len() / 2;
+        let mut stolen = jobs.split_off(keep);
+        drop(jobs);
+
+        let first = stolen.pop_front()?;
+        if !stolen.is_empty() {
+            thief.extend(stolen);
+        }
+        Some(first)
+    }
+}
@@ -0,0 +1,228 @@
+use std::{
+    marker::PhantomData,
+    mem,
+    panic::{self, AssertUnwindSafe},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Condvar, Mutex,
+    },
+    time::Duration,
+};
+
+use super::{Job, Shared, ThreadPool};
+
+/// How long a worker waiting on its scope sleeps between looking for other
+/// jobs to help with.
+const HELP_INTERVAL: Duration = Duration::from_millis(1);
+
+/// A scope to queue jobs that borrow from the caller, see
+/// `ThreadPool::scope`.
+pub struct Scope<'scope, 'env: 'scope> {
+    shared: Arc<Shared>,
+    state: Arc<ScopeState>,
+    scope: PhantomData<&'scope mut &'scope ()>,
+    env: PhantomData<&'env mut &'env ()>,
+}
+
+struct ScopeState {
+    running: Mutex<usize>,
+    finished: Condvar,
+    panicked: AtomicBool,
+}
+
+impl ScopeState {
+    fn finish(&self) {
+        let mut running = self.running.lock().unwrap();
+        *running -= 1;
+        if *running == 0 {
+            self.finished.notify_all();
+        }
+    }
+}
+
+/// A job queued in a scope.
+///
+/// The job runs when it is dropped, so even a job evicted from the queue by
+/// `RejectionPolicy::DropOldest` still runs (on the evicting thread) before
+/// the scope is allowed to end.
+struct ScopedJob<'scope> {
+    f: Option<Box<dyn FnOnce() + Send + 'scope>>,
+    state: Arc<ScopeState>,
+}
+
+impl Drop for ScopedJob<'_> {
+    fn drop(&mut self) {
+        if let Some(f) = self.f.take() {
+            if panic::catch_unwind(AssertUnwindSafe(f)).is_err() {
+                self.state.panicked.store(true, Ordering::SeqCst);
+            }
+        }
+        self.state.finish();
+    }
+}
+
+impl<'scope> Scope<'scope, '_> {
+    /// Queue a job on the pool. Unlike `ThreadPool::execute`, the job may
+    /// borrow anything that outlives the scope.
+    ///
+    /// Scoped jobs are never rejected: if the queue is full, the job runs
+    /// right away on the calling thread.
+    pub fn execute<F>(&'scope self, f: F)
+    where
+        F: FnOnce() + Send + 'scope,
+    {
+        *self.state.running.lock().unwrap() += 1;
+
+        let scoped = ScopedJob {
+            f: Some(Box::new(f)),
+            state: Arc::clone(&self.state),
+        };
+        let job: Box<dyn FnOnce() + Send + 'scope> = Box::new(move || drop(scoped));
+
+        // SAFETY: `ThreadPool::scope` doesn't return before every job queued
+        // in the scope has been dropped (which is when it runs), so nothing
+        // the job borrows can go away while the pool holds on to it.
+        let job = unsafe { mem::transmute::<Box<dyn FnOnce() + Send + 'scope>, Job>(job) };
+
+        if let Err(job) = self.shared.push(job) {
+            job();
+        }
+    }
+
+    /// Wait for every job in the scope. A worker of the same pool runs other
+    /// jobs in the meantime, instead of holding up a thread the scope's own
+    /// jobs may be waiting for.
+    fn wait(&self) {
+        let worker = self.shared.current_worker();
+        let mut running = self.state.running.lock().unwrap();
+
+        while *running > 0 {
+            match &worker {
+                Some(slot) => {
+                    drop(running);
+                    if let Some(job) = self.shared.find_job(slot) {
+                        slot.run(&self.shared, job);
+                    }
+                    running = self.state.running.lock().unwrap();
+                    if *running > 0 {
+                        running = self
+                            .state
+                            .finished
+                            .wait_timeout(running, HELP_INTERVAL)
+                            .unwrap()
+                            .0;
+                    }
+                }
+                None => running = self.state.finished.wait(running).unwrap(),
+            }
+        }
+    }
+}
+
+impl ThreadPool {
+    /// Run `f` with a scope in which jobs can borrow non-`'static` data, like
+    /// `std::thread::scope` but running the jobs on the pool's workers.
+    ///
+    /// All jobs queued in the scope have finished by the time `scope`
+    /// returns.
+    ///
+    /// ```
+    /// use echo::ThreadPool;
+    /// use std::sync::Mutex;
+    ///
+    /// let pool = ThreadPool::new(4);
+    /// let contents = "Rust:\nsafe, fast, productive.\nPick three.\nTrust me.";
+    /// let lines: Vec<&str> = contents.lines().collect();
+    /// let results: Mutex<Vec<&str>> = Mutex::new(Vec::new());
+    ///
+    /// pool.scope(|s| {
+    ///     for chunk in lines.chunks(2) {
+    ///         let results = &results;
+    ///         s.execute(move || {
+    ///             let found = chunk.iter().filter(|line| line.contains("ust"));
+    ///             results.lock().unwrap().extend(found.copied());
+    ///         });
+    ///     }
+    /// });
+    ///
+    /// let mut results = results.into_inner().unwrap();
+    /// results.sort();
+    /// assert_eq!(results, vec!["Rust:", "Trust me."]);
+    /// ```
+    ///
+    /// # Panics
+    ///
+    /// If `f` panics, the panic is resumed once every job has finished. If
+    /// `f` returns but one of the jobs panicked, `scope` panics as well.
+    pub fn scope<'env, F, T>(&self, f: F) -> T
+    where
+        F: for<'scope> FnOnce(&'scope Scope<'scope, 'env>) -> T,
+    {
+        let scope = Scope {
+            shared: Arc::clone(&self.shared),
+            state: Arc::new(ScopeState {
+                running: Mutex::new(0),
+                finished: Condvar::new(),
+                panicked: AtomicBool::new(false),
+            }),
+            scope: PhantomData,
+            env: PhantomData,
+        };
+
+        let result = panic::catch_unwind(AssertUnwindSafe(|| f(&scope)));
+        scope.wait();
+
+        match result {
+            Err(e) => panic::resume_unwind(e),
+            Ok(_) if scope.state.panicked.load(Ordering::SeqCst) => {
+                panic!("a scoped job panicked")
+            }
+            Ok(result) => result,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn jobs_borrow_from_the_stack() {
+        let pool = ThreadPool::new(4);
+        let mut numbers = vec![1; 100];
+
+        pool.scope(|s| {
+            for chunk in numbers.chunks_mut(10) {
+                s.execute(move || chunk.iter_mut().for_each(|n| *n *= 2));
+            }
+        });
+
+        assert!(numbers.iter().all(|&n| n == 2));
+    }
+
+    #[test]
+    fn nested_scope_on_a_single_worker() {
+        let pool = ThreadPool::new(1);
+        let total = Mutex::new(0);
+
+        pool.scope(|s| {
+            s.execute(|| {
+                pool.scope(|s| {
+                    for i in 1..=10 {
+                        let total = &total;
+                        s.execute(move || *total.lock().unwrap() += i);
+                    }
+                });
+            });
+        });
+
+        assert_eq!(total.into_inner().unwrap(), 55);
+    }
+
+    #[test]
+    #[should_panic(expected = "a scoped job panicked")]
+    fn job_panics_are_propagated() {
+        let pool = ThreadPool::new(2);
+        pool.scope(|s| s.execute(|| panic!("boom")));
+    }
+}
@@ -0,0 +1,67 @@
+use std::{fmt, time::Duration};
+
+/// A snapshot of the pool's counters, see `ThreadPool::stats`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Stats {
+    /// The number of workers, not counting those asked to stop.
+    pub workers: usize,
+    /// Jobs waiting for a worker.
+    pub queued: usize,
+    /// Jobs being run right now.
+    pub running: usize,
+    /// Jobs that ran to completion, including those of stopped workers.
+    pub completed: u64,
+    /// Jobs that panicked, including those of stopped workers.
+    pub panicked: u64,
+    /// One entry per live worker, ordered by id.
+    pub per_worker: Vec<WorkerStats>,
+}
+
+/// The counters of a single worker.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct WorkerStats {
+    pub id: usize,
+    /// Whether the worker is running a job right now.
+    pub busy: bool,
+    /// The total time spent running jobs.
+    pub busy_time: Duration,
+    pub completed: u64,
+    pub panicked: u64,
+}
+
+/// Something that happened in the pool, passed to the hook installed with
+/// `Builder::on_event`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum Event {
+    WorkerStarted {
+        id: usize,
+    },
+    WorkerStopped {
+        id: usize,
+    },
+    /// A job panicked. The worker caught the panic and carries on.
+    JobPanicked {
+        worker: usize,
+        message: String,
+    },
+    /// The number of workers changed, either through `ThreadPool::resize`
+    /// or the auto-scaler.
+    Resized {
+        from: usize,
+        to: usize,
+    },
+}
+
+impl fmt::Display for Event {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Event::WorkerStarted { id } => write!(f, "Worker {id} started."),
+            Event::WorkerStopped { id } => write!(f, "Worker {id} disconnected; shutting down."),
+            Event::JobPanicked { worker, message } => {
+                write!(f, "Worker {worker} caught a panic: {message}")
+            }
+            Event::Resized { from, to } => write!(f, "Resized from {from} to {to} workers."),
+        }
+    }
+}
@@ -0,0 +1,297 @@
+use std::{
+    cmp::Ordering,
+    collections::BinaryHeap,
+    sync::{
+        atomic::{self, AtomicBool},
+        Arc, Condvar, Mutex,
+    },
+    thread,
+    time::{Duration, Instant},
+};
+
+use super::{Shared, ThreadPool};
+
+/// A handle to a job scheduled with `ThreadPool::schedule_after` or
+/// `ThreadPool::schedule_every`.
+///
+/// Dropping the handle does not cancel the job.
+#[derive(Debug, Clone)]
+pub struct TimerHandle {
+    cancelled: Arc<AtomicBool>,
+}
+
+impl TimerHandle {
+    /// Cancel the job. A job that is already running is not interrupted, but
+    /// a periodic job won't be queued again.
+    pub fn cancel(&self) {
+        self.cancelled.store(true, atomic::Ordering::SeqCst);
+    }
+
+    /// Whether the job was cancelled, either through the handle or because
+    /// the pool shut down before it was due.
+    pub fn is_cancelled(&self) -> bool {
+        self.cancelled.load(atomic::Ordering::SeqCst)
+    }
+}
+
+enum Task {
+    Once(Box<dyn FnOnce() + Send + 'static>),
+    Every(Duration, Arc<dyn Fn() + Send + Sync + 'static>),
+}
+
+struct Entry {
+    deadline: Instant,
+    /// Keeps timers that are due at the same instant in scheduling order.
+    seq: u64,
+    task: Task,
+    cancelled: Arc<AtomicBool>,
+}
+
+// `BinaryHeap` is a max-heap, so entries compare in reverse to pop the
+// earliest deadline first.
+impl Ord for Entry {
+    fn cmp(&self, other: &Entry) -> Ordering {
+        (other.deadline, other.seq).cmp(&(self.deadline, self.seq))
+    }
+}
+
+impl PartialOrd for Entry {
+    fn partial_cmp(&self, other: &Entry) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl PartialEq for Entry {
+    fn eq(&self, other: &Entry) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for Entry {}
+
+struct State {
+    heap: BinaryHeap<Entry>,
+    seq: u64,
+    shutdown: bool,
+}
+
+struct Queue {
+    state: Mutex<State>,
+    /// Signalled when a timer is added or the pool shuts down.
+    changed: Condvar,
+}
+
+/// The pool's timers: a min-heap of deadlines served by a thread that is
+/// started the first time a job is scheduled.
+pub(super) struct Timers {
+    queue: Arc<Queue>,
+    thread: Mutex<Option<thread::JoinHandle<()>>>,
+}
+
+impl Timers {
+    pub(super) fn new() -> Timers {
+        Timers {
+            queue: Arc::new(Queue {
+                state: Mutex::new(State {
+                    heap: BinaryHeap::new(),
+                    seq: 0,
+                    shutdown: false,
+                }),
+                changed: Condvar::new(),
+            }),
+            thread: Mutex::new(None),
+        }
+    }
+
+    fn schedule(&self, shared: &Arc<Shared>, deadline: Instant, task: Task) -> TimerHandle {
+        let cancelled = Arc::new(AtomicBool::new(false));
+
+        let mut state = self.queue.state.lock().unwrap();
+        state.seq += 1;
+        let entry = Entry {
+            deadline,
+            seq: state.seq,
+            task,
+            cancelled: Arc::clone(&cancelled),
+        };
+        state.heap.push(entry);
+        drop(state);
+        self.queue.changed.notify_one();
+
+        let mut thread = self.thread.lock().unwrap();
+        if thread.is_none() {
+            let queue = Arc::clone(&self.queue);
+            let shared = Arc::clone(shared);
+            *thread = Some(thread::spawn(move || run(&queue, &shared)));
+        }
+
+        TimerHandle { cancelled }
+    }
+
+    /// Cancel every pending timer and stop the timer thread.
+    pub(super) fn shutdown(&self) {
+        let mut state = self.queue.state.lock().unwrap();
+        state.shutdown = true;
+        for entry in state.heap.drain() {
+            entry.cancelled.store(true, atomic::Ordering::SeqCst);
+        }
+        drop(state);
+        self.queue.changed.notify_one();
+
+        if let Some(thread) = self.thread.lock().unwrap().take() {
+            thread.join().unwrap();
+        }
+    }
+}
+
+/// The timer thread: sleep until the earliest deadline, then queue the job
+/// on the pool. A firing the pool rejects is skipped.
+fn run(queue: &Queue, shared: &Arc<Shared>) {
+    let mut state = queue.state.lock().unwrap();
+
+    while !state.shutdown {
+        let now = Instant::now();
+        let Some(next) = state.heap.peek() else {
+            state = queue.changed.wait(state).unwrap();
+            continue;
+        };
+        if next.deadline > now {
+            let timeout = next.deadline - now;
+            state = queue.changed.wait_timeout(state, timeout).unwrap().0;
+            continue;
+        }
+
+        let mut entry = state.heap.pop().unwrap();
+        if entry.cancelled.load(atomic::Ordering::SeqCst) {
+            continue;
+        }
+
+        match entry.task {
+            Task::Once(job) => {
+                drop(state);
+                let _ = shared.push(job);
+            }
+            Task::Every(interval, ref job) => {
+                let job = Arc::clone(job);
+
+                // Skip the firings we're too late for instead of queueing a
+                // burst of them.
+                while entry.deadline <= now {
+                    entry.deadline += interval;
+                }
+                state.seq += 1;
+                entry.seq = state.seq;
+                state.heap.push(entry);
+                drop(state);
+
+                let _ = shared.push(Box::new(move || job()));
+            }
+        }
+
+        state = queue.state.lock().unwrap();
+    }
+}
+
+impl ThreadPool {
+    /// Run a job on the pool once `delay` has elapsed.
+    pub fn schedule_after<F>(&self, delay: Duration, f: F) -> TimerHandle
+    where
+        F: FnOnce() + Send + 'static,
+    {
+        let deadline = Instant::now() + delay;
+        self.timers
+            .schedule(&self.shared, deadline, Task::Once(Box::new(f)))
+    }
+
+    /// Run a job on the pool every `interval`, starting one `interval` from
+    /// now, until the returned handle is cancelled or the pool shuts down.
+    ///
+    /// A run is queued on time even if the previous one hasn't finished, so
+    /// jobs slower than the interval may overlap.
+    ///
+    /// # Panics
+    ///
+    /// The `schedule_every` function will panic if the interval is zero.
+    pub fn schedule_every<F>(&self, interval: Duration, f: F) -> TimerHandle
+    where
+        F: Fn() + Send + Sync + 'static,
+    {
+        assert!(!interval.is_zero());
+
+        let deadline = Instant::now() + interval;
+        self.timers
+            .schedule(&self.shared, deadline, Task::Every(interval, Arc::new(f)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::mpsc;
+
+    #[test]
+    fn runs_after_the_delay() {
+        let pool = ThreadPool::new(2);
+        let (tx, rx) = mpsc::channel();
+
+        let start = Instant::now();
+        pool.schedule_after(Duration::from_millis(50), move || tx.send(()).unwrap());
+
+        rx.recv().unwrap();
+        assert!(start.elapsed() >= Duration::from_millis(50));
+    }
+
+    #[test]
+    fn runs_in_deadline_order() {
+        let pool = ThreadPool::new(1);
+        let (tx, rx) = mpsc::channel();
+
+        for delay in [30, 10, 20] {
+            let tx = tx.clone();
+            pool.schedule_after(Duration::from_millis(delay), move || {
+                tx.send(delay).unwrap()
+            });
+        }
+
+        assert_eq!(rx.iter().take(3).collect::<Vec<_>>(), vec![10, 20, 30]);
+    }
+
+    #[test]
+    fn cancelled_jobs_dont_run() {
+        let pool = ThreadPool::new(2);
+        let (tx, rx) = mpsc::channel();
+
+        let handle = pool.schedule_after(Duration::from_millis(20), move || tx.send(()).unwrap());
+        handle.cancel();
+
+        assert!(rx.recv_timeout(Duration::from_millis(100)).is_err());
+    }
+
+    #[test]
+    fn periodic_jobs_repeat_until_cancelled() {
+        let pool = ThreadPool::new(2);
+        let (tx, rx) = mpsc::channel();
+
+        let handle = pool.schedule_every(Duration::from_millis(5), move || {
+            let _ = tx.send(());
+        });
+        for _ in 0..3 {
+            rx.recv().unwrap();
+        }
+        handle.cancel();
+
+        // At most a run that was already queued can still come through.
+        thread::sleep(Duration::from_millis(20));
+        while rx.try_recv().is_ok() {}
+        assert!(rx.recv_timeout(Duration::from_millis(50)).is_err());
+    }
+
+    #[test]
+    fn shutdown_cancels_pending_timers() {
+        let pool = ThreadPool::new(1);
+        let handle = pool.schedule_after(Duration::from_secs(60), || {});
+
+        drop(pool);
+        assert!(handle.is_cancelled());
+    }
+}
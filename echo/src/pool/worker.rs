@@ -0,0 +1,144 @@
+use std::{
+    any::Any,
+    panic::{self, AssertUnwindSafe},
+    sync::{
+        atomic::{AtomicBool, AtomicU64, Ordering},
+        Arc,
+    },
+    thread,
+    time::{Duration, Instant},
+};
+
+use super::{
+    queue::{Job, Local},
+    Event, Shared, Wait, WorkerStats, WORKER,
+};
+
+/// How many times an idle worker looks for jobs before parking.
+const IDLE_SPINS: u32 = 16;
+
+/// A worker's deque and counters, shared with the rest of the pool so that
+/// other workers can steal from it and `ThreadPool::stats` can read it.
+pub(super) struct Slot {
+    pub(super) id: usize,
+    pub(super) local: Local,
+    /// Set when the pool shrinks, the worker stops after its current job.
+    retiring: AtomicBool,
+    busy: AtomicBool,
+    busy_nanos: AtomicU64,
+    completed: AtomicU64,
+    panicked: AtomicU64,
+}
+
+impl Slot {
+    pub(super) fn new(id: usize) -> Slot {
+        Slot {
+            id,
+            local: Local::new(),
+            retiring: AtomicBool::new(false),
+            busy: AtomicBool::new(false),
+            busy_nanos: AtomicU64::new(0),
+            completed: AtomicU64::new(0),
+            panicked: AtomicU64::new(0),
+        }
+    }
+
+    pub(super) fn retire(&self) {
+        self.retiring.store(true, Ordering::SeqCst);
+    }
+
+    pub(super) fn is_retiring(&self) -> bool {
+        self.retiring.load(Ordering::SeqCst)
+    }
+
+    /// Run a job, keeping count of the time it took and whether it panicked.
+    pub(super) fn run(&self, shared: &Shared, job: Job) {
+        self.busy.store(true, Ordering::Relaxed);
+        shared.running.fetch_add(1, Ordering::Relaxed);
+        let start = Instant::now();
+
+        let result = panic::catch_unwind(AssertUnwindSafe(job));
+
+        let elapsed = start.elapsed().as_nanos() as u64;
+        self.busy_nanos.fetch_add(elapsed, Ordering::Relaxed);
+        shared.running.fetch_sub(1, Ordering::Relaxed);
+        self.busy.store(false, Ordering::Relaxed);
+
+        match result {
+            Ok(()) => {
+                self.completed.fetch_add(1, Ordering::Relaxed);
+                shared.completed.fetch_add(1, Ordering::Relaxed);
+            }
+            Err(payload) => {
+                self.panicked.fetch_add(1, Ordering::Relaxed);
+                shared.panicked.fetch_add(1, Ordering::Relaxed);
+                shared.emit(Event::JobPanicked {
+                    worker: self.id,
+                    message: panic_message(&*payload),
+                });
+            }
+        }
+    }
+
+    pub(super) fn stats(&self) -> WorkerStats {
+        WorkerStats {
+            id: self.id,
+            busy: self.busy.load(Ordering::Relaxed),
+            busy_time: Duration::from_nanos(self.busy_nanos.load(Ordering::Relaxed)),
+            completed: self.completed.load(Ordering::Relaxed),
+            panicked: self.panicked.load(Ordering::Relaxed),
+        }
+    }
+}
+
+fn panic_message(payload: &(dyn Any + Send)) -> String {
+    if let Some(message) = payload.downcast_ref::<&str>() {
+        message.to_string()
+    } else if let Some(message) = payload.downcast_ref::<String>() {
+        message.clone()
+    } else {
+        "Box<dyn Any>".to_string()
+    }
+}
+
+/// Start a worker thread for `slot`.
+pub(super) fn spawn(shared: &Arc<Shared>, slot: Arc<Slot>) -> thread::JoinHandle<()> {
+    let shared = Arc::clone(shared);
+
+    thread::Builder::new()
+        .name(format!("echo-worker-{}", slot.id))
+        .spawn(move || run(&shared, &slot))
+        .unwrap()
+}
+
+fn run(shared: &Arc<Shared>, slot: &Arc<Slot>) {
+    WORKER.set(Some((shared.id(), Arc::clone(slot))));
+    shared.emit(Event::WorkerStarted { id: slot.id });
+
+    let mut idle = 0;
+    while !slot.is_retiring() {
+        if let Some(job) = shared.find_job(slot) {
+            idle = 0;
+            slot.run(shared, job);
+        } else if idle < IDLE_SPINS {
+            // More jobs usually arrive shortly, parking and being woken up
+            // again costs more than looking a few times.
+            idle += 1;
+            thread::yield_now();
+        } else {
+            match shared.wait(slot) {
+                Wait::Work => {}
+                Wait::Idle => {
+                    if shared.retire_idle(slot) {
+                        break;
+                    }
+                }
+                Wait::Shutdown => break,
+            }
+        }
+    }
+
+    shared.remove_worker(slot);
+    WORKER.set(None);
+    shared.emit(Event::WorkerStopped { id: slot.id });
+}
@@ -0,0 +1,554 @@
+//! Forwarding requests to another HTTP server.
+
+use std::{
+    fmt,
+    io::{self, BufRead, BufReader, Read, Write},
+    net::{SocketAddr, TcpStream},
+    time::Duration,
+};
+
+use crate::{
+    http::{
+        has_body, is_interim, parse_response_head, response_framing, Framing, Headers, Request,
+        Response,
+    },
+    server::Handler,
+};
+
+/// The largest response head, or chunk line, taken from the upstream.
+const MAX_HEAD: usize = 64 * 1024;
+
+/// Headers that describe a single connection rather than the message, and
+/// so stop at the proxy (RFC 9110 section 7.6.1).
+const HOP_BY_HOP: [&str; 8] = [
+    "Connection",
+    "Keep-Alive",
+    "Proxy-Connection",
+    "Proxy-Authorization",
+    "TE",
+    "Trailer",
+    "Transfer-Encoding",
+    "Upgrade",
+];
+
+/// A reverse proxy: a handler passing requests on to an upstream server, such
+/// as an application server listening on a local port, and its responses
+/// back.
+///
+/// The upstream sees the client's address in `X-Forwarded-For`, and the host
+/// and scheme the client used in `X-Forwarded-Host` and `X-Forwarded-Proto`.
+/// Every request is sent on a fresh connection, and the response body is
+/// passed on as it comes rather than read up front.
+#[derive(Debug, Clone)]
+pub struct Proxy {
+    upstream: SocketAddr,
+    connect_timeout: Duration,
+    timeout: Option<Duration>,
+    strip_prefix: Option<String>,
+    set_headers: Vec<(String, String)>,
+    remove_headers: Vec<String>,
+    preserve_host: bool,
+}
+
+impl Proxy {
+    pub fn new(upstream: SocketAddr) -> Proxy {
+        Proxy {
+            upstream,
+            connect_timeout: Duration::from_secs(5),
+            timeout: Some(Duration::from_secs(30)),
+            strip_prefix: None,
+            set_headers: Vec::new(),
+            remove_headers: Vec::new(),
+            preserve_host: false,
+        }
+    }
+
+    /// How long to wait for the upstream to accept a connection. The default
+    /// is 5 seconds.
+    pub fn connect_timeout(mut self, timeout: Duration) -> Proxy {
+        self.connect_timeout = timeout;
+        self
+    }
+
+    /// How long the upstream may go quiet while sending its response, or
+    /// take to accept the request: 30 seconds by default, `None` to wait as
+    /// long as it takes.
+    pub fn timeout(mut self, timeout: Option<Duration>) -> Proxy {
+        self.timeout = timeout;
+        self
+    }
+
+    /// Remove `prefix` from the paths sent upstream, so that `/api/users`
+    /// goes up as `/users` with `/api`. Redirects to paths on the upstream
+    /// get it back.
+    pub fn strip_prefix(mut self, prefix: impl Into<String>) -> Proxy {
+        let prefix = prefix.into().trim_end_matches('/').to_string();
+        self.strip_prefix = Some(prefix).filter(|prefix| !prefix.is_empty());
+        self
+    }
+
+    /// Set a header on the requests sent upstream, replacing the client's.
+    pub fn set_header(mut self, name: impl Into<String>, value: impl Into<String>) -> Proxy {
+        self.set_headers.push((name.into(), value.into()));
+        self
+    }
+
+    /// Don't pass the client's `name` header upstream.
+    pub fn remove_header(mut self, name: impl Into<String>) -> Proxy {
+        self.remove_headers.push(name.into());
+        self
+    }
+
+    /// Pass the client's `Host` header upstream, rather than the upstream's
+    /// own address.
+    pub fn preserve_host(mut self, preserve: bool) -> Proxy {
+        self.preserve_host = preserve;
+        self
+    }
+
+    /// Forward `request` and return the upstream's response. Answers 502 if
+    /// the upstream can't be reached or its response is malformed, and 504
+    /// if it times out.
+    pub fn serve(&self, request: &Request) -> Response {
+        let Some(upstream_request) = self.upstream_request(request) else {
+            return Response::text(404, "Not Found\n");
+        };
+
+        match self.forward(&upstream_request) {
+            Ok(response) => self.downstream_response(request, response),
+            Err(e) if is_timeout(&e) => {
+                eprintln!("Upstream {} timed out: {e}", self.upstream);
+                Response::text(504, "Gateway Timeout\n")
+            }
+            Err(e) => {
+                eprintln!("Upstream {} failed: {e}", self.upstream);
+                Response::text(502, "Bad Gateway\n")
+            }
+        }
+    }
+
+    /// The request to send upstream, or `None` if `request` isn't under the
+    /// stripped prefix.
+    fn upstream_request(&self, request: &Request) -> Option<Request> {
+        let target = match &self.strip_prefix {
+            Some(prefix) => {
+                let rest = request.target.strip_prefix(prefix.as_str())?;
+                match rest.chars().next() {
+                    None => "/".to_string(),
+                    Some('?') => format!("/{rest}"),
+                    Some('/') => rest.to_string(),
+                    Some(_) => return None,
+                }
+            }
+            None => request.target.clone(),
+        };
+
+        let mut upstream = Request::new(request.method.clone(), target);
+        upstream.body = request.body.clone();
+        upstream.headers = request.headers.clone();
+        remove_hop_by_hop(&mut upstream.headers);
+        upstream.headers.remove("Content-Length");
+        for name in &self.remove_headers {
+            upstream.headers.remove(name);
+        }
+
+        let forwarded_for = match (request.header("X-Forwarded-For"), request.peer) {
+            (Some(previous), Some(peer)) => Some(format!("{previous}, {peer}")),
+            (None, Some(peer)) => Some(peer.to_string()),
+            (previous, None) => previous.map(str::to_string),
+        };
+        if let Some(forwarded_for) = forwarded_for {
+            upstream.headers.insert("X-Forwarded-For", forwarded_for);
+        }
+        if !upstream.headers.contains("X-Forwarded-Proto") {
+            upstream.headers.insert("X-Forwarded-Proto", "http");
+        }
+        if let Some(host) = request.header("Host") {
+            upstream.headers.insert("X-Forwarded-Host", host);
+        }
+        if !self.preserve_host || !upstream.headers.contains("Host") {
+            upstream.headers.insert("Host", self.upstream.to_string());
+        }
+
+        for (name, value) in &self.set_headers {
+            upstream.headers.insert(name, value);
+        }
+        // One request per connection, so the response ends with it.
+        upstream.headers.insert("Connection", "close");
+        if !upstream.body.is_empty() || request.headers.contains("Content-Length") {
+            let length = upstream.body.len();
+            upstream
+                .headers
+                .insert("Content-Length", length.to_string());
+        }
+        Some(upstream)
+    }
+
+    /// Send `request` upstream and read the head of its response. The body
+    /// is left to stream from the upstream as the response is sent on.
+    fn forward(&self, request: &Request) -> io::Result<Response> {
+        let mut stream = TcpStream::connect_timeout(&self.upstream, self.connect_timeout)?;
+        stream.set_read_timeout(self.timeout)?;
+        stream.set_write_timeout(self.timeout)?;
+
+        let mut head = format!("{} {} HTTP/1.1\r\n", request.method, request.target);
+        for (name, value) in request.headers.iter() {
+            head.push_str(&format!("{name}: {value}\r\n"));
+        }
+        head.push_str("\r\n");
+        stream.write_all(head.as_bytes())?;
+        stream.write_all(&request.body)?;
+
+        let mut upstream = BufReader::new(stream);
+        let response = loop {
+            let head = read_head(&mut upstream)?;
+            let response = parse_response_head(&head).map_err(invalid_data)?;
+            if !is_interim(response.status) {
+                break response;
+            }
+        };
+        if !has_body(&response, &request.method) {
+            return Ok(response);
+        }
+
+        // The body is decoded; the server frames it anew, by its length if
+        // the upstream gave one.
+        Ok(
+            match response_framing(&response.headers).map_err(invalid_data)? {
+                Some(Framing::Length(length)) => response
+                    .with_header("Content-Length", length.to_string())
+                    .with_stream(upstream.take(length as u64)),
+                Some(Framing::Chunked) => response.with_stream(Dechunked::new(upstream)),
+                None => response.with_stream(upstream),
+            },
+        )
+    }
+
+    /// The upstream's response, fit for the client.
+    fn downstream_response(&self, request: &Request, mut response: Response) -> Response {
+        remove_hop_by_hop(&mut response.headers);
+        if let Some(location) = response.headers.get("Location") {
+            if let Some(location) = self.public_location(request, location) {
+                response.headers.insert("Location", location);
+            }
+        }
+        response
+    }
+
+    /// Where a redirect to `location` on the upstream leads through the
+    /// proxy, or `None` if it leads elsewhere.
+    fn public_location(&self, request: &Request, location: &str) -> Option<String> {
+        let prefix = self.strip_prefix.as_deref().unwrap_or("");
+        let upstream_origin = format!("http://{}", self.upstream);
+
+        if let Some(path) = location.strip_prefix(&upstream_origin) {
+            if !path.is_empty() && !path.starts_with('/') {
+                return None;
+            }
+            let host = request.header("Host")?;
+            return Some(format!("http://{host}{prefix}{path}"));
+        }
+        if location.starts_with('/') && !location.starts_with("//") && !prefix.is_empty() {
+            return Some(format!("{prefix}{location}"));
+        }
+        None
+    }
+}
+
+impl Handler for Proxy {
+    fn handle(&self, request: &Request) -> Response {
+        self.serve(request)
+    }
+}
+
+/// Remove the hop-by-hop headers, including those the `Connection` header
+/// names.
+fn remove_hop_by_hop(headers: &mut Headers) {
+    let named: Vec<String> = headers
+        .get_all("Connection")
+        .flat_map(|value| value.split(','))
+        .map(|name| name.trim().to_string())
+        .filter(|name| !name.is_empty())
+        .collect();
+    for name in HOP_BY_HOP
+        .iter()
+        .copied()
+        .chain(named.iter().map(String::as_str))
+    {
+        headers.remove(name);
+    }
+}
+
+/// Read a response head from the upstream, up to the empty line that ends
+/// it.
+fn read_head(upstream: &mut impl BufRead) -> io::Result<Vec<u8>> {
+    let mut head = Vec::new();
+    loop {
+        let start = head.len();
+        let n = upstream
+            .take((MAX_HEAD - start) as u64 + 1)
+            .read_until(b'\n', &mut head)?;
+        if n == 0 {
+            return Err(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                "the upstream closed the connection before the end of the head",
+            ));
+        }
+        if head.len() > MAX_HEAD {
+            return Err(invalid_data("the response head is too large"));
+        }
+        if matches!(&head[start..], b"\r\n" | b"\n") {
+            return Ok(head);
+        }
+    }
+}
+
+/// A chunked body, decoded as it's read. Trailer fields are skipped.
+struct Dechunked<R> {
+    inner: R,
+    /// What's left of the current chunk.
+    remaining: u64,
+    done: bool,
+}
+
+impl<R: BufRead> Dechunked<R> {
+    fn new(inner: R) -> Dechunked<R> {
+        Dechunked {
+            inner,
+            remaining: 0,
+            done: false,
+        }
+    }
+
+    /// The next line, without its line break.
+    fn line(&mut self) -> io::Result<String> {
+        let mut line = Vec::new();
+        (&mut self.inner)
+            .take(MAX_HEAD as u64)
+            .read_until(b'\n', &mut line)?;
+        if !line.ends_with(b"\n") {
+            return Err(invalid_data("truncated chunked body"));
+        }
+        let line = String::from_utf8(line).map_err(|_| invalid_data("invalid chunk line"))?;
+        Ok(line.trim_end_matches(['\r', '\n']).to_string())
+    }
+}
+
+impl<R: BufRead> Read for Dechunked<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.done || buf.is_empty() {
+            return Ok(0);
+        }
+        if self.remaining == 0 {
+            let line = self.line()?;
+            let size = line.split(';').next().unwrap_or_default().trim();
+            let size =
+                u64::from_str_radix(size, 16).map_err(|_| invalid_data("invalid chunk size"))?;
+            if size == 0 {
+                while !self.line()?.is_empty() {}
+                self.done = true;
+                return Ok(0);
+            }
+            self.remaining = size;
+        }
+
+        let max = buf
+            .len()
+            .min(usize::try_from(self.remaining).unwrap_or(usize::MAX));
+        let n = self.inner.read(&mut buf[..max])?;
+        if n == 0 {
+            return Err(invalid_data("truncated chunked body"));
+        }
+        self.remaining -= n as u64;
+        if self.remaining == 0 && !self.line()?.is_empty() {
+            return Err(invalid_data("missing line break after chunk"));
+        }
+        Ok(n)
+    }
+}
+
+fn invalid_data(e: impl fmt::Display) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, e.to_string())
+}
+
+fn is_timeout(e: &io::Error) -> bool {
+    matches!(
+        e.kind(),
+        io::ErrorKind::TimedOut | io::ErrorKind::WouldBlock
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{io::BufRead, io::BufReader, net::TcpListener, thread};
+
+    /// An upstream answering one connection with `response`, and returning
+    /// the request it got.
+    fn upstream(response: &'static str) -> (SocketAddr, thread::JoinHandle<String>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let handle = thread::spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            let mut reader = BufReader::new(stream);
+            let mut request = String::new();
+            loop {
+                let mut line = String::new();
+                reader.read_line(&mut line).unwrap();
+                request.push_str(&line);
+                if line == "\r\n" || line.is_empty() {
+                    break;
+                }
+            }
+            if let Some(length) = request
+                .lines()
+                .find_map(|line| line.strip_prefix("Content-Length: "))
+            {
+                let mut body = vec![0; length.parse().unwrap()];
+                reader.read_exact(&mut body).unwrap();
+                request.push_str(&String::from_utf8(body).unwrap());
+            }
+            reader.get_mut().write_all(response.as_bytes()).unwrap();
+            request
+        });
+        (addr, handle)
+    }
+
+    #[test]
+    fn forwards_requests() {
+        let (addr, upstream) = upstream(
+            "HTTP/1.1 200 OK\r\nContent-Type: text/plain\r\nTransfer-Encoding: chunked\r\n\
+             Connection: close, X-Private\r\nX-Private: 1\r\n\r\n5\r\nhello\r\n0\r\n\r\n",
+        );
+        let proxy = Proxy::new(addr)
+            .strip_prefix("/api/")
+            .set_header("X-Set", "set")
+            .remove_header("Cookie");
+
+        let mut request = Request::new("POST", "/api/users?page=2");
+        request.headers.insert("Host", "example.com");
+        request.headers.insert("Cookie", "secret");
+        request.headers.insert("Connection", "keep-alive, X-Hop");
+        request.headers.insert("X-Hop", "hop");
+        request.headers.insert("X-Forwarded-For", "10.0.0.1");
+        request.peer = Some([10, 0, 0, 2].into());
+        request.body = b"body".to_vec().into();
+
+        let response = proxy.serve(&request);
+        assert_eq!(response.status, 200);
+        assert_eq!(body(&response).unwrap(), b"hello");
+        assert_eq!(response.headers.get("Content-Type"), Some("text/plain"));
+        for name in [
+            "Transfer-Encoding",
+            "Content-Length",
+            "Connection",
+            "X-Private",
+        ] {
+            assert!(!response.headers.contains(name), "{name}");
+        }
+
+        let sent = upstream.join().unwrap();
+        assert!(
+            sent.starts_with("POST /users?page=2 HTTP/1.1\r\n"),
+            "{sent}"
+        );
+        for line in [
+            format!("Host: {addr}"),
+            "X-Forwarded-For: 10.0.0.1, 10.0.0.2".to_string(),
+            "X-Forwarded-Host: example.com".to_string(),
+            "X-Forwarded-Proto: http".to_string(),
+            "X-Set: set".to_string(),
+            "Connection: close".to_string(),
+            "Content-Length: 4".to_string(),
+        ] {
+            assert!(sent.contains(&format!("{line}\r\n")), "{line} in {sent}");
+        }
+        assert!(
+            !sent.contains("Cookie") && !sent.contains("X-Hop"),
+            "{sent}"
+        );
+        assert!(sent.ends_with("\r\n\r\nbody"));
+
+        assert_eq!(proxy.serve(&Request::new("GET", "/apix")).status, 404);
+    }
+
+    #[test]
+    fn streams_bodies() {
+        let get = |response| {
+            let (addr, _upstream) = upstream(response);
+            Proxy::new(addr).serve(&Request::new("GET", "/"))
+        };
+
+        let response = get("HTTP/1.1 200 OK\r\nContent-Length: 5\r\n\r\nhello, and more");
+        assert_eq!(response.headers.get("Content-Length"), Some("5"));
+        assert_eq!(body(&response).unwrap(), b"hello");
+
+        let response = get("HTTP/1.1 100 Continue\r\n\r\nHTTP/1.1 200 OK\r\n\r\nuntil the end");
+        assert_eq!(response.status, 200);
+        assert_eq!(body(&response).unwrap(), b"until the end");
+
+        let response = get("HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\r\n\
+             3;ext=1\r\nabc\r\na\r\n0123456789\r\n0\r\nX-Trailer: 1\r\n\r\n");
+        assert_eq!(body(&response).unwrap(), b"abc0123456789");
+
+        // A body cut short is an error, which cuts the response short too.
+        let response = get("HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\r\n5\r\nhel");
+        assert!(body(&response).is_err());
+
+        // A response to HEAD has no body, whatever its length says.
+        let (addr, _upstream) = upstream("HTTP/1.1 200 OK\r\nContent-Length: 5\r\n\r\n");
+        let response = Proxy::new(addr).serve(&Request::new("HEAD", "/"));
+        assert_eq!(response.headers.get("Content-Length"), Some("5"));
+        assert!(response.stream.is_none());
+    }
+
+    /// The body of a response, read from the upstream.
+    fn body(response: &Response) -> io::Result<Vec<u8>> {
+        let mut body = response.body.clone();
+        if let Some(stream) = &response.stream {
+            stream.take().unwrap().read_to_end(&mut body)?;
+        }
+        Ok(body)
+    }
+
+    #[test]
+    fn rewrites_redirects() {
+        let proxy = Proxy::new(SocketAddr::from(([127, 0, 0, 1], 3000))).strip_prefix("/app");
+        let mut request = Request::new("GET", "/app/");
+        request.headers.insert("Host", "example.com");
+
+        let location = |location| proxy.public_location(&request, location);
+        assert_eq!(
+            location("http://127.0.0.1:3000/login").as_deref(),
+            Some("http://example.com/app/login")
+        );
+        assert_eq!(location("/login").as_deref(), Some("/app/login"));
+        assert_eq!(location("http://127.0.0.1:30000/"), None);
+        assert_eq!(location("https://elsewhere.org/"), None);
+    }
+
+    #[test]
+    fn upstream_failures() {
+        // Nothing listens on a port that was just released.
+        let addr = TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap();
+        assert_eq!(
+            Proxy::new(addr).serve(&Request::new("GET", "/")).status,
+            502
+        );
+
+        let (addr, _upstream) = upstream("not http\r\n\r\n");
+        assert_eq!(
+            Proxy::new(addr).serve(&Request::new("GET", "/")).status,
+            502
+        );
+
+        // An upstream that accepts the request and never answers.
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let proxy =
+            Proxy::new(listener.local_addr().unwrap()).timeout(Some(Duration::from_millis(100)));
+        assert_eq!(proxy.serve(&Request::new("GET", "/")).status, 504);
+    }
+}
@@ -0,0 +1,501 @@
+use std::{
+    collections::HashMap,
+    io::{self, Read, Write},
+    net::IpAddr,
+    panic::{self, AssertUnwindSafe},
+    sync::{mpsc, Arc},
+    time::{Duration, Instant},
+};
+
+use super::{
+    hand_over, http2, parse_error, request_timeout,
+    sys::{self, Epoll, Waker, EPOLLIN, EPOLLOUT, EPOLLRDHUP},
+    too_many_connections, unavailable, Listener, PeerSlot, Reply, Server, Stream, StreamedBody,
+};
+use crate::{
+    http::{Request, RequestParser, Upgrade},
+    http2::{preface, Preface},
+};
+
+const LISTENER: u64 = 0;
+const WAKER: u64 = 1;
+
+enum State {
+    /// Waiting for a complete request.
+    Reading,
+    /// A worker is running the handler.
+    Handling,
+    /// Sending the response.
+    Writing,
+}
+
+struct Connection {
+    stream: Stream,
+    peer: Option<IpAddr>,
+    state: State,
+    /// The events the connection is registered for.
+    interest: u32,
+    input: Vec<u8>,
+    /// The request coming in on `input`, as far as it's parsed.
+    parser: RequestParser,
+    output: Vec<u8>,
+    written: usize,
+    keep_alive: bool,
+    /// Where the connection goes once the response is sent.
+    upgrade: Option<Upgrade>,
+    /// The rest of the body, read on the pool once `output` is sent.
+    body: Option<StreamedBody>,
+    /// The client shut down its side of the connection.
+    eof: bool,
+    /// When to give up on a client that doesn't send or receive anything.
+    deadline: Option<Instant>,
+    /// When the head of the next request must be in.
+    header_deadline: Option<Instant>,
+    /// `None` if the client had too many connections already.
+    slot: Option<PeerSlot>,
+}
+
+impl Connection {
+    fn new(stream: Stream, peer: Option<IpAddr>, slot: Option<PeerSlot>) -> Connection {
+        Connection {
+            stream,
+            peer,
+            state: State::Reading,
+            interest: EPOLLIN | EPOLLRDHUP,
+            input: Vec::new(),
+            parser: RequestParser::default(),
+            output: Vec::new(),
+            written: 0,
+            keep_alive: true,
+            upgrade: None,
+            body: None,
+            eof: false,
+            deadline: None,
+            header_deadline: None,
+            slot,
+        }
+    }
+
+    /// Read what the socket has to offer, until the input holds `max`
+    /// bytes.
+    fn fill(&mut self, max: usize) -> io::Result<()> {
+        let mut chunk = [0; 4096];
+        while self.input.len() < max {
+            match self.stream.read(&mut chunk) {
+                Ok(0) => {
+                    self.eof = true;
+                    return Ok(());
+                }
+                Ok(n) => self.input.extend_from_slice(&chunk[..n]),
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => return Ok(()),
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+                Err(e) => return Err(e),
+            }
+        }
+        Ok(())
+    }
+
+    /// Write as much of the response as the socket takes.
+    fn flush(&mut self) -> io::Result<()> {
+        while self.written < self.output.len() {
+            match self.stream.write(&self.output[self.written..]) {
+                Ok(0) => return Err(io::ErrorKind::WriteZero.into()),
+                Ok(n) => self.written += n,
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => return Ok(()),
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+                Err(e) => return Err(e),
+            }
+        }
+        Ok(())
+    }
+
+    fn start_writing(&mut self, response: Vec<u8>, keep_alive: bool) {
+        self.state = State::Writing;
+        self.output = response;
+        self.written = 0;
+        self.keep_alive = keep_alive;
+    }
+
+    fn start_replying(&mut self, reply: Reply) {
+        self.start_writing(reply.bytes, reply.keep_alive);
+        self.upgrade = reply.upgrade;
+        self.body = reply.stream;
+    }
+
+    /// The events the connection should be registered for in its state.
+    /// `max_pipelined` is how much input may wait while a request is being
+    /// handled.
+    fn wanted_interest(&self, max_pipelined: usize) -> u32 {
+        match self.state {
+            // After the client shut down its side, a level-triggered read
+            // event would fire on every wait, while there's nothing left to
+            // read.
+            State::Reading | State::Handling if self.eof => 0,
+            State::Handling if self.input.len() >= max_pipelined => 0,
+            State::Reading | State::Handling => EPOLLIN | EPOLLRDHUP,
+            State::Writing => EPOLLOUT,
+        }
+    }
+}
+
+/// A response produced by a worker for the connection with `token`, or
+/// `None` if the handler panicked.
+struct Completion {
+    token: u64,
+    reply: Option<Reply>,
+}
+
+struct EventLoop<'a> {
+    server: &'a Server,
+    epoll: Epoll,
+    waker: Arc<Waker>,
+    sender: mpsc::Sender<Completion>,
+    completions: mpsc::Receiver<Completion>,
+    connections: HashMap<u64, Connection>,
+    next_token: u64,
+}
+
+pub(super) fn serve(server: &Server, listener: Listener) -> io::Result<()> {
+    listener.set_nonblocking(true)?;
+
+    let epoll = Epoll::new()?;
+    let waker = Arc::new(Waker::new()?);
+    epoll.add(&listener, LISTENER, EPOLLIN)?;
+    epoll.add(&*waker, WAKER, EPOLLIN)?;
+
+    let (sender, completions) = mpsc::channel();
+    let mut event_loop = EventLoop {
+        server,
+        epoll,
+        waker,
+        sender,
+        completions,
+        connections: HashMap::new(),
+        next_token: WAKER + 1,
+    };
+
+    event_loop.run(&listener)
+}
+
+impl EventLoop<'_> {
+    fn run(&mut self, listener: &Listener) -> io::Result<()> {
+        let mut events = Vec::new();
+        let mut timeout = None;
+
+        loop {
+            self.epoll.wait(&mut events, timeout)?;
+
+            for event in &events {
+                match event.token {
+                    LISTENER => self.accept(listener),
+                    WAKER => self.complete()?,
+                    token => self.ready(token, *event),
+                }
+            }
+
+            timeout = self.expire();
+        }
+    }
+
+    /// Close the connections whose deadline has passed. Returns how long
+    /// until the next deadline.
+    fn expire(&mut self) -> Option<Duration> {
+        let now = Instant::now();
+        let expired: Vec<u64> = self
+            .connections
+            .iter()
+            .filter(|(_, connection)| connection.deadline.is_some_and(|d| d <= now))
+            .map(|(&token, _)| token)
+            .collect();
+
+        for token in expired {
+            let Some(mut connection) = self.connections.remove(&token) else {
+                continue;
+            };
+            // A client that sent part of a request learns why it's cut off.
+            let open = if matches!(connection.state, State::Reading) && !connection.input.is_empty()
+            {
+                connection.start_writing(request_timeout(), false);
+                self.advance(token, &mut connection)
+            } else {
+                Ok(false)
+            };
+            self.keep_or_close(token, connection, open);
+        }
+
+        self.connections
+            .values()
+            .filter_map(|connection| connection.deadline)
+            .min()
+            .map(|next| next.saturating_duration_since(now))
+    }
+
+    fn accept(&mut self, listener: &Listener) {
+        loop {
+            match listener.accept() {
+                Ok((stream, peer)) => {
+                    let slot = self.server.admit(peer);
+                    if let Err(e) = self.register(stream, peer, slot) {
+                        eprintln!("Failed to register a connection: {e}");
+                    }
+                }
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => return,
+                Err(e) => {
+                    eprintln!("Failed to accept a connection: {e}");
+                    return;
+                }
+            }
+        }
+    }
+
+    fn register(
+        &mut self,
+        stream: Stream,
+        peer: Option<IpAddr>,
+        slot: Option<PeerSlot>,
+    ) -> io::Result<()> {
+        stream.set_nonblocking(true)?;
+
+        let token = self.next_token;
+        self.next_token += 1;
+
+        let mut connection = Connection::new(stream, peer, slot);
+        self.epoll
+            .add(&connection.stream, token, connection.interest)?;
+        if connection.slot.is_some() {
+            self.start_reading(&mut connection);
+        } else {
+            connection.start_writing(too_many_connections(), false);
+        }
+
+        let open = self.advance(token, &mut connection);
+        self.keep_or_close(token, connection, open);
+        Ok(())
+    }
+
+    /// Wait for the next request, and start the clock on its head.
+    fn start_reading(&self, connection: &mut Connection) {
+        connection.state = State::Reading;
+        connection.header_deadline = self
+            .server
+            .service
+            .timeouts
+            .header
+            .map(|timeout| Instant::now() + timeout);
+    }
+
+    fn ready(&mut self, token: u64, event: sys::Event) {
+        let Some(mut connection) = self.connections.remove(&token) else {
+            return;
+        };
+
+        let result = (|| {
+            match connection.state {
+                State::Reading | State::Handling if event.is_readable() => {
+                    connection.fill(self.max_pipelined())?;
+                }
+                State::Writing if event.is_writable() => connection.flush()?,
+                _ => {}
+            }
+            self.advance(token, &mut connection)
+        })();
+
+        self.keep_or_close(token, connection, result);
+    }
+
+    /// Hand the responses finished by the workers to their connections.
+    fn complete(&mut self) -> io::Result<()> {
+        self.waker.drain()?;
+
+        while let Ok(completion) = self.completions.try_recv() {
+            // The connection is gone if the client hung up in the meantime.
+            let token = completion.token;
+            let Some(mut connection) = self.connections.remove(&token) else {
+                continue;
+            };
+
+            let result = match completion.reply {
+                Some(reply) => {
+                    connection.start_replying(reply);
+                    self.advance(token, &mut connection)
+                }
+                None => Ok(false),
+            };
+            self.keep_or_close(token, connection, result);
+        }
+
+        Ok(())
+    }
+
+    /// Move the connection's state machine as far as it goes without
+    /// blocking. Returns whether the connection stays open.
+    fn advance(&mut self, token: u64, connection: &mut Connection) -> io::Result<bool> {
+        loop {
+            match connection.state {
+                State::Reading => match preface(&connection.input) {
+                    Preface::Complete => {
+                        let upgrade = http2::upgrade(Arc::clone(&self.server.service), None);
+                        return self.hand_over(connection, upgrade);
+                    }
+                    Preface::Partial if connection.eof => return Ok(false),
+                    Preface::Partial => {}
+                    Preface::Absent => {
+                        let limits = &self.server.service.limits;
+                        match connection.parser.parse(&connection.input, limits) {
+                            Ok(Some((request, len))) => {
+                                connection.input.drain(..len);
+                                connection.state = State::Handling;
+                                if !self.dispatch(token, request, connection.peer) {
+                                    connection.start_writing(unavailable(), false);
+                                    continue;
+                                }
+                            }
+                            Ok(None) if connection.eof => return Ok(false),
+                            Ok(None) => {}
+                            Err(e) => {
+                                connection.start_writing(parse_error(&e), false);
+                                continue;
+                            }
+                        }
+                    }
+                },
+                State::Handling => {}
+                State::Writing => {
+                    connection.flush()?;
+                    if connection.written == connection.output.len() {
+                        if let Some(body) = connection.body.take() {
+                            connection.state = State::Handling;
+                            if !self.send_more(token, body, connection.keep_alive) {
+                                return Ok(false);
+                            }
+                            continue;
+                        }
+                        if let Some(upgrade) = connection.upgrade.take() {
+                            return self.hand_over(connection, upgrade);
+                        }
+                        if !connection.keep_alive || connection.eof {
+                            return Ok(false);
+                        }
+                        // Look for a pipelined request that's already here.
+                        self.start_reading(connection);
+                        connection.output.clear();
+                        continue;
+                    }
+                }
+            }
+
+            let timeouts = self.server.service.timeouts;
+            let now = Instant::now();
+            connection.deadline = match connection.state {
+                State::Reading => {
+                    let read = timeouts.read.map(|timeout| now + timeout);
+                    let header = connection
+                        .header_deadline
+                        .filter(|_| !connection.parser.has_head());
+                    match (read, header) {
+                        (Some(read), Some(header)) => Some(read.min(header)),
+                        (read, header) => read.or(header),
+                    }
+                }
+                State::Handling => None,
+                State::Writing => timeouts.write.map(|timeout| now + timeout),
+            };
+
+            let interest = connection.wanted_interest(self.max_pipelined());
+            if interest != connection.interest {
+                self.epoll.modify(&connection.stream, token, interest)?;
+                connection.interest = interest;
+            }
+            return Ok(true);
+        }
+    }
+
+    /// How much input a connection may hold: about a request's worth. That
+    /// much is either a whole request or too large for one, so a connection
+    /// is read no further while it's being parsed, and while its request is
+    /// being handled, until the response is sent.
+    fn max_pipelined(&self) -> usize {
+        let limits = &self.server.service.limits;
+        limits.head.saturating_add(limits.body)
+    }
+
+    /// Give the connection to the protocol it's upgraded to; it leaves the
+    /// event loop for good. Returns `false`, as the connection is no longer
+    /// the event loop's to keep open.
+    fn hand_over(&self, connection: &mut Connection, upgrade: Upgrade) -> io::Result<bool> {
+        self.epoll.delete(&connection.stream)?;
+        let input = std::mem::take(&mut connection.input);
+        let stream = connection.stream.try_clone()?;
+        let slot = connection.slot.take().unwrap_or_else(PeerSlot::uncounted);
+        hand_over(upgrade, stream, input, self.server.service.timeouts, slot)?;
+        Ok(false)
+    }
+
+    /// Run the handler on the pool. Returns `false` if the pool rejected the
+    /// job.
+    fn dispatch(&self, token: u64, mut request: Request, peer: Option<IpAddr>) -> bool {
+        let service = Arc::clone(&self.server.service);
+        let received = Instant::now();
+        request.peer = peer;
+        self.spawn(token, move || service.respond(&request, received, true))
+    }
+
+    /// Read the next piece of a streamed body on the pool, as the reader may
+    /// block. Returns `false` if the pool rejected the job.
+    fn send_more(&self, token: u64, mut body: StreamedBody, keep_alive: bool) -> bool {
+        self.spawn(token, move || match body.next_chunk() {
+            Ok((bytes, done)) => Reply {
+                bytes,
+                keep_alive,
+                upgrade: None,
+                stream: (!done).then_some(body),
+            },
+            // The response can't be finished, only cut short.
+            Err(_) => Reply {
+                bytes: Vec::new(),
+                keep_alive: false,
+                upgrade: None,
+                stream: None,
+            },
+        })
+    }
+
+    /// Run `job` on the pool and hand its reply to the connection with
+    /// `token`. Returns `false` if the pool rejected the job.
+    fn spawn<F>(&self, token: u64, job: F) -> bool
+    where
+        F: FnOnce() -> Reply + Send + 'static,
+    {
+        let sender = self.sender.clone();
+        let waker = Arc::clone(&self.waker);
+
+        let result = self.server.pool.execute(move || {
+            let result = panic::catch_unwind(AssertUnwindSafe(job));
+            let (reply, panic) = match result {
+                Ok(reply) => (Some(reply), None),
+                Err(payload) => (None, Some(payload)),
+            };
+
+            if sender.send(Completion { token, reply }).is_ok() {
+                let _ = waker.wake();
+            }
+            // Let the pool account for the panic.
+            if let Some(payload) = panic {
+                panic::resume_unwind(payload);
+            }
+        });
+
+        result.is_ok()
+    }
+
+    fn keep_or_close(&mut self, token: u64, connection: Connection, open: io::Result<bool>) {
+        match open {
+            Ok(true) => {
+                self.connections.insert(token, connection);
+            }
+            Ok(false) | Err(_) => {
+                let _ = self.epoll.delete(&connection.stream);
+            }
+        }
+    }
+}
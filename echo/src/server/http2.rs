@@ -0,0 +1,1069 @@
+//! Serving connections that speak HTTP/2.
+//!
+//! A connection's frames are read on the thread it was handed over to, and
+//! the request of each stream is answered on the pool, so a slow handler
+//! only holds up its own stream. Workers write their responses themselves,
+//! taking turns on the socket, and wait for the client's flow control
+//! windows when they run out of room.
+
+use std::{
+    collections::{HashMap, HashSet},
+    io::{self, Read, Write},
+    net::{IpAddr, Shutdown},
+    panic::{self, AssertUnwindSafe},
+    sync::{Arc, Condvar, Mutex, MutexGuard},
+    thread,
+    time::{Duration, Instant},
+};
+
+use super::{Service, Stream, CHUNK_SIZE};
+use crate::{
+    http::{BodyStream, Exact, Request, Response, Upgrade, Upgraded},
+    http2::{
+        frame::{frame_len, parse_frame, Error, ErrorCode, Frame, DEFAULT_MAX_FRAME_SIZE},
+        hpack::{DecodeError, Decoder, Encoder},
+        request_from_fields, Settings, CONNECTION_SPECIFIC, DEFAULT_WINDOW_SIZE, MAX_WINDOW_SIZE,
+        PREFACE,
+    },
+};
+
+/// The most streams a client may have open at once. A stream the client
+/// reset counts until its handler is done with it.
+const MAX_CONCURRENT_STREAMS: u32 = 100;
+
+/// How many streams a client may reset in `RESET_PERIOD`. Opening streams
+/// and resetting them right away has the server start requests for nothing
+/// (the "rapid reset" attack), so a client resetting more gets the
+/// connection closed with ENHANCE_YOUR_CALM.
+const MAX_RESETS: u32 = 2 * MAX_CONCURRENT_STREAMS;
+const RESET_PERIOD: Duration = Duration::from_secs(10);
+
+/// How much of its request bodies a client may send ahead, on each stream
+/// and on the connection as a whole. Bodies are read into memory as they
+/// come, so the windows are replenished right away.
+const WINDOW_SIZE: u32 = 1 << 20;
+
+/// A request sent over HTTP/1.1 with `Upgrade: h2c`, answered on stream 1
+/// once the connection speaks HTTP/2.
+pub(super) struct Switched {
+    request: Request,
+    settings: Vec<(u16, u32)>,
+    received: Instant,
+}
+
+impl Switched {
+    pub(super) fn new(request: &Request, settings: Vec<(u16, u32)>, received: Instant) -> Switched {
+        let mut request = request.clone();
+        // These were for the switch, not for the handler.
+        for name in ["Connection", "Upgrade", "HTTP2-Settings"] {
+            request.headers.remove(name);
+        }
+        Switched {
+            request,
+            settings,
+            received,
+        }
+    }
+}
+
+/// What serves a connection over HTTP/2 once it's handed over, starting
+/// with the `switched` request if it's upgraded from HTTP/1.1.
+pub(super) fn upgrade(service: Arc<Service>, switched: Option<Switched>) -> Upgrade {
+    Upgrade::new(move |upgraded| serve(upgraded, service, switched))
+}
+
+fn serve(upgraded: Upgraded, service: Arc<Service>, switched: Option<Switched>) {
+    let Ok(stream) = upgraded.stream().try_clone() else {
+        return;
+    };
+    // The read timeout is how long the connection may stay idle. Frames
+    // are written whole, and each should go out as soon as it's written.
+    let configured = upgraded
+        .stream()
+        .set_read_timeout(service.timeouts.read)
+        .and_then(|()| stream.set_nodelay(true));
+    if configured.is_err() {
+        return;
+    }
+    let peer = upgraded.stream().peer_addr().ok().map(|addr| addr.ip());
+    let shared = Arc::new(Shared {
+        output: Mutex::new(Output {
+            stream,
+            encoder: Encoder::new(),
+            settings: Settings::default(),
+            window: i64::from(DEFAULT_WINDOW_SIZE),
+            streams: HashMap::new(),
+            answering: HashSet::new(),
+            closed: false,
+        }),
+        changed: Condvar::new(),
+        write_timeout: service.timeouts.write,
+    });
+
+    let decoder = Decoder::new().max_list_size(service.limits.head);
+    let mut connection = Connection {
+        upgraded,
+        service,
+        shared: Arc::clone(&shared),
+        peer,
+        buf: Vec::new(),
+        decoder,
+        incoming: HashMap::new(),
+        headers: None,
+        last_stream: 0,
+        going_away: false,
+        reset_period: Instant::now(),
+        resets: 0,
+    };
+    if let Close::GoAway(error, reason) = connection.run(switched) {
+        shared.send(&[Frame::GoAway {
+            last_stream: connection.last_stream,
+            error,
+            debug: reason.as_bytes().to_vec(),
+        }]);
+    }
+    shared.close();
+}
+
+/// Why a connection ends.
+enum Close {
+    /// The client hung up, or the socket failed: there's nobody to tell.
+    Gone,
+    /// Telling the client with a GOAWAY frame.
+    GoAway(ErrorCode, &'static str),
+}
+
+/// The reading half of a connection.
+struct Connection {
+    upgraded: Upgraded,
+    service: Arc<Service>,
+    shared: Arc<Shared>,
+    peer: Option<IpAddr>,
+    buf: Vec<u8>,
+    decoder: Decoder,
+    /// The streams whose request is still coming in.
+    incoming: HashMap<u32, Incoming>,
+    /// The header block being received, until its last CONTINUATION frame.
+    headers: Option<HeaderBlock>,
+    /// The highest stream the client opened.
+    last_stream: u32,
+    /// The client sent GOAWAY, it opens no more streams.
+    going_away: bool,
+    /// When the current `RESET_PERIOD` began, and how many streams the
+    /// client reset since.
+    reset_period: Instant,
+    resets: u32,
+}
+
+struct HeaderBlock {
+    stream: u32,
+    end_stream: bool,
+    block: Vec<u8>,
+}
+
+/// A request whose body is still coming in.
+struct Incoming {
+    request: Request,
+    received: Instant,
+    /// When the client last sent some of the request.
+    active: Instant,
+    /// How much more DATA the client may send on the stream.
+    window: i64,
+}
+
+impl Connection {
+    fn run(&mut self, switched: Option<Switched>) -> Close {
+        let max_head = u32::try_from(self.service.limits.head).unwrap_or(u32::MAX);
+        let settings = Settings {
+            max_concurrent_streams: Some(MAX_CONCURRENT_STREAMS),
+            initial_window_size: WINDOW_SIZE,
+            max_header_list_size: Some(max_head),
+            ..Settings::default()
+        };
+        self.shared.send(&[
+            Frame::Settings {
+                ack: false,
+                settings: settings.to_params(),
+            },
+            Frame::WindowUpdate {
+                stream: 0,
+                increment: WINDOW_SIZE - DEFAULT_WINDOW_SIZE,
+            },
+        ]);
+
+        // The upgrading request is stream 1, half closed by the client.
+        if let Some(switched) = switched {
+            if let Err(Error::Connection(error, reason)) =
+                self.shared.apply_settings(&switched.settings, false)
+            {
+                return Close::GoAway(error, reason);
+            }
+            self.last_stream = 1;
+            self.shared.open(1);
+            if let Err(e) = self.dispatch(1, switched.request, switched.received) {
+                self.fail_stream(e);
+            }
+        }
+
+        while self.buf.len() < PREFACE.len() {
+            if let Err(close) = self.fill() {
+                return close;
+            }
+        }
+        if !self.buf.starts_with(PREFACE) {
+            return Close::GoAway(ErrorCode::ProtocolError, "expected the connection preface");
+        }
+        self.buf.drain(..PREFACE.len());
+
+        let mut settled = false;
+        loop {
+            let result = match parse_frame(&self.buf, DEFAULT_MAX_FRAME_SIZE) {
+                Ok(Some((frame, len))) => {
+                    self.buf.drain(..len);
+                    // The preface ends with the client's settings.
+                    if !settled && !matches!(frame, Frame::Settings { ack: false, .. }) {
+                        return Close::GoAway(
+                            ErrorCode::ProtocolError,
+                            "expected SETTINGS after the preface",
+                        );
+                    }
+                    settled = true;
+                    self.handle(frame)
+                }
+                Ok(None) => {
+                    if let Err(close) = self.fill() {
+                        return close;
+                    }
+                    continue;
+                }
+                Err(e) => {
+                    if let Some(len) = frame_len(&self.buf) {
+                        self.buf.drain(..len);
+                    }
+                    Err(e)
+                }
+            };
+
+            match result {
+                Ok(()) => {}
+                Err(Error::Connection(error, reason)) => return Close::GoAway(error, reason),
+                Err(e) => self.fail_stream(e),
+            }
+            if self.going_away && self.idle() {
+                return Close::GoAway(ErrorCode::NoError, "");
+            }
+        }
+    }
+
+    /// Read more from the client, until it's gone or the connection stays
+    /// idle for the read timeout.
+    fn fill(&mut self) -> Result<(), Close> {
+        let mut chunk = [0; 16 * 1024];
+        loop {
+            match self.upgraded.read(&mut chunk) {
+                Ok(0) => return Err(Close::Gone),
+                Ok(n) => {
+                    self.buf.extend_from_slice(&chunk[..n]);
+                    self.expire_incoming();
+                    return Ok(());
+                }
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+                Err(e) if is_timeout(&e) => {
+                    // Streams being answered keep the connection open, but
+                    // not a header block that stopped halfway.
+                    if self.idle() {
+                        return Err(Close::GoAway(ErrorCode::NoError, "idle"));
+                    }
+                    if self.headers.is_some() {
+                        return Err(Close::GoAway(ErrorCode::NoError, "header block timed out"));
+                    }
+                    self.expire_incoming();
+                }
+                Err(_) => return Err(Close::Gone),
+            }
+        }
+    }
+
+    /// Answer 408 on the streams whose request the client sent none of for
+    /// the read timeout, however busy it keeps the connection otherwise.
+    fn expire_incoming(&mut self) {
+        let Some(timeout) = self.service.timeouts.read else {
+            return;
+        };
+        let expired: Vec<u32> = self
+            .incoming
+            .iter()
+            .filter(|(_, incoming)| incoming.active.elapsed() >= timeout)
+            .map(|(&id, _)| id)
+            .collect();
+        for id in expired {
+            self.incoming.remove(&id);
+            if let Err(e) = self.refuse(id, 408, "request timeout") {
+                self.fail_stream(e);
+            }
+        }
+    }
+
+    /// Whether no stream is open.
+    fn idle(&self) -> bool {
+        let output = self.shared.lock();
+        self.incoming.is_empty() && output.streams.is_empty() && output.answering.is_empty()
+    }
+
+    /// Count a stream the client reset, and close the connection if that's
+    /// more than `MAX_RESETS` in the current period.
+    fn count_reset(&mut self) -> Result<(), Error> {
+        if self.reset_period.elapsed() >= RESET_PERIOD {
+            self.reset_period = Instant::now();
+            self.resets = 0;
+        }
+        self.resets += 1;
+        if self.resets > MAX_RESETS {
+            return Err(Error::Connection(
+                ErrorCode::EnhanceYourCalm,
+                "too many streams reset",
+            ));
+        }
+        Ok(())
+    }
+
+    fn handle(&mut self, frame: Frame) -> Result<(), Error> {
+        use ErrorCode::ProtocolError;
+
+        // A header block comes in one piece (RFC 9113 section 6.10).
+        if let Some(headers) = &mut self.headers {
+            let Frame::Continuation {
+                stream,
+                block,
+                end_headers,
+            } = frame
+            else {
+                return Err(Error::Connection(ProtocolError, "expected CONTINUATION"));
+            };
+            if stream != headers.stream {
+                return Err(Error::Connection(ProtocolError, "expected CONTINUATION"));
+            }
+            headers.block.extend_from_slice(&block);
+            if headers.block.len() > self.service.limits.head {
+                return Err(Error::Connection(
+                    ErrorCode::EnhanceYourCalm,
+                    "header block is too large",
+                ));
+            }
+            return match end_headers {
+                true => self.end_headers(),
+                false => Ok(()),
+            };
+        }
+
+        let flow_controlled = frame.flow_controlled_len() as u32;
+        match frame {
+            Frame::Data {
+                stream,
+                data,
+                end_stream,
+                ..
+            } => self.data(stream, data, end_stream, flow_controlled),
+            Frame::Headers {
+                stream,
+                block,
+                end_stream,
+                end_headers,
+                ..
+            } => {
+                self.headers = Some(HeaderBlock {
+                    stream,
+                    end_stream,
+                    block,
+                });
+                match end_headers {
+                    true => self.end_headers(),
+                    false => Ok(()),
+                }
+            }
+            Frame::RstStream { stream, .. } => {
+                if stream > self.last_stream {
+                    return Err(Error::Connection(
+                        ProtocolError,
+                        "RST_STREAM on an idle stream",
+                    ));
+                }
+                self.count_reset()?;
+                self.incoming.remove(&stream);
+                self.shared.cancel(stream);
+                Ok(())
+            }
+            Frame::Settings {
+                ack: false,
+                settings,
+            } => self.shared.apply_settings(&settings, true),
+            Frame::Ping { ack: false, data } => {
+                self.shared.send(&[Frame::Ping { ack: true, data }]);
+                Ok(())
+            }
+            Frame::GoAway { .. } => {
+                self.going_away = true;
+                Ok(())
+            }
+            Frame::WindowUpdate { stream, increment } => {
+                if stream > self.last_stream {
+                    return Err(Error::Connection(
+                        ProtocolError,
+                        "WINDOW_UPDATE on an idle stream",
+                    ));
+                }
+                self.shared.window_update(stream, increment)
+            }
+            Frame::PushPromise { .. } => {
+                Err(Error::Connection(ProtocolError, "clients can't push"))
+            }
+            Frame::Continuation { .. } => Err(Error::Connection(
+                ProtocolError,
+                "CONTINUATION without HEADERS",
+            )),
+            // Acknowledgements need no answer, priorities are only hints and
+            // unknown frames are ignored (section 5.5).
+            Frame::Settings { ack: true, .. }
+            | Frame::Ping { ack: true, .. }
+            | Frame::Priority { .. }
+            | Frame::Unknown { .. } => Ok(()),
+        }
+    }
+
+    /// Act on a complete header block: open a stream, or end one with its
+    /// trailers.
+    fn end_headers(&mut self) -> Result<(), Error> {
+        use ErrorCode::ProtocolError;
+
+        let HeaderBlock {
+            stream: id,
+            end_stream,
+            block,
+        } = self
+            .headers
+            .take()
+            .expect("a header block is being received");
+
+        // Every block is decoded, whatever becomes of its stream, to keep
+        // the decoder's table in step with the client's.
+        let fields = match self.decoder.decode(&block) {
+            Ok(fields) => Some(fields),
+            Err(DecodeError::ListTooLarge) => None,
+            Err(_) => {
+                return Err(Error::Connection(
+                    ErrorCode::CompressionError,
+                    "header block can't be decoded",
+                ))
+            }
+        };
+
+        // Trailers, which the handler doesn't get to see.
+        if let Some(incoming) = self.incoming.remove(&id) {
+            if !end_stream {
+                return Err(Error::Stream(
+                    id,
+                    ProtocolError,
+                    "trailers must end the stream",
+                ));
+            }
+            return self.dispatch(id, incoming.request, incoming.received);
+        }
+
+        if id % 2 == 0 {
+            return Err(Error::Connection(
+                ProtocolError,
+                "clients open odd-numbered streams",
+            ));
+        }
+        // Trailers still in flight on a stream that's no longer incoming.
+        if id <= self.last_stream {
+            return Ok(());
+        }
+        self.last_stream = id;
+        if self.going_away {
+            return Ok(());
+        }
+        let open = self.incoming.len() + self.shared.lock().answering.len();
+        if open >= MAX_CONCURRENT_STREAMS as usize {
+            return Err(Error::Stream(
+                id,
+                ErrorCode::RefusedStream,
+                "too many open streams",
+            ));
+        }
+        self.shared.open(id);
+
+        let received = Instant::now();
+        let Some(fields) = fields else {
+            return self.refuse(id, 431, "request header fields too large");
+        };
+        let request = request_from_fields(fields)
+            .map_err(|reason| Error::Stream(id, ProtocolError, reason))?;
+        if end_stream {
+            return self.dispatch(id, request, received);
+        }
+        self.incoming.insert(
+            id,
+            Incoming {
+                request,
+                received,
+                active: received,
+                window: i64::from(WINDOW_SIZE),
+            },
+        );
+        Ok(())
+    }
+
+    fn data(
+        &mut self,
+        id: u32,
+        data: Vec<u8>,
+        end_stream: bool,
+        flow_controlled: u32,
+    ) -> Result<(), Error> {
+        if id > self.last_stream {
+            return Err(Error::Connection(
+                ErrorCode::ProtocolError,
+                "DATA on an idle stream",
+            ));
+        }
+        // The connection's window is replenished whatever becomes of the
+        // data.
+        if flow_controlled > 0 {
+            self.shared.send(&[Frame::WindowUpdate {
+                stream: 0,
+                increment: flow_controlled,
+            }]);
+        }
+
+        // Frames still in flight on a stream that was reset, or answered
+        // before its request was complete, are dropped.
+        let Some(incoming) = self.incoming.get_mut(&id) else {
+            return Ok(());
+        };
+        incoming.active = Instant::now();
+        incoming.window -= i64::from(flow_controlled);
+        if incoming.window < 0 {
+            return Err(Error::Stream(
+                id,
+                ErrorCode::FlowControlError,
+                "DATA beyond the window",
+            ));
+        }
+        if incoming.request.body.len() + data.len() > self.service.limits.body {
+            self.incoming.remove(&id);
+            return self.refuse(id, 413, "request body too large");
+        }
+        incoming.request.body.extend_from_slice(&data);
+
+        if end_stream {
+            let incoming = self.incoming.remove(&id).expect("the stream is incoming");
+            return self.dispatch(id, incoming.request, incoming.received);
+        }
+        if flow_controlled > 0 {
+            incoming.window += i64::from(flow_controlled);
+            self.shared.send(&[Frame::WindowUpdate {
+                stream: id,
+                increment: flow_controlled,
+            }]);
+        }
+        Ok(())
+    }
+
+    /// Answer the complete request on stream `id` on the pool.
+    fn dispatch(&self, id: u32, mut request: Request, received: Instant) -> Result<(), Error> {
+        if let Some(length) = request.header("Content-Length") {
+            if length.parse::<usize>().ok() != Some(request.body.len()) {
+                return Err(Error::Stream(
+                    id,
+                    ErrorCode::ProtocolError,
+                    "body doesn't match its Content-Length",
+                ));
+            }
+        }
+        request.peer = self.peer;
+
+        let service = Arc::clone(&self.service);
+        let shared = Arc::clone(&self.shared);
+        self.spawn(id, move || {
+            answer(&service, &shared, id, &request, received)
+        })
+    }
+
+    /// Answer stream `id` with an error before its request is complete, and
+    /// ask the client to stop sending it (section 8.1).
+    fn refuse(&self, id: u32, status: u16, reason: &str) -> Result<(), Error> {
+        let shared = Arc::clone(&self.shared);
+        let response = Response::text(status, format!("{reason}\n"));
+        self.spawn(id, move || {
+            let (response, _) = prepare(response, false);
+            if send_response(&shared, id, &response, None) {
+                shared.reset(id, ErrorCode::NoError);
+            }
+            shared.finish(id);
+        })
+    }
+
+    /// Run `job` for stream `id` on the pool. The stream counts as open
+    /// until the job calls `Shared::finish`.
+    fn spawn(&self, id: u32, job: impl FnOnce() + Send + 'static) -> Result<(), Error> {
+        self.shared.lock().answering.insert(id);
+        (self.service.spawn)(Box::new(job)).map_err(|_| {
+            self.shared.lock().answering.remove(&id);
+            Error::Stream(id, ErrorCode::RefusedStream, "the server is busy")
+        })
+    }
+
+    fn fail_stream(&mut self, error: Error) {
+        if let Error::Stream(id, code, _) = error {
+            self.incoming.remove(&id);
+            self.shared.reset(id, code);
+        }
+    }
+}
+
+/// Run the handler for stream `id` and send its response.
+fn answer(service: &Service, shared: &Arc<Shared>, id: u32, request: &Request, received: Instant) {
+    let response = match panic::catch_unwind(AssertUnwindSafe(|| service.handler.handle(request))) {
+        Ok(response) => response,
+        Err(payload) => {
+            shared.reset(id, ErrorCode::InternalError);
+            shared.finish(id);
+            // Let the pool account for the panic, like for any job.
+            panic::resume_unwind(payload);
+        }
+    };
+
+    let long_lived = response
+        .stream
+        .as_ref()
+        .is_some_and(BodyStream::is_long_lived);
+    let (response, stream) = prepare(response, request.method == "HEAD");
+
+    // A long-lived body is sent from a thread of its own, so that it
+    // doesn't hold up the worker, if there's room for one more.
+    if long_lived && stream.is_some() {
+        let Some(slot) = service.long_lived.acquire() else {
+            shared.reset(id, ErrorCode::RefusedStream);
+            shared.finish(id);
+            return;
+        };
+        let stream = service.record(
+            request,
+            response.status,
+            response.body.len(),
+            stream,
+            received,
+        );
+        let sender = Arc::clone(shared);
+        let spawned = thread::Builder::new()
+            .name("echo-stream".to_string())
+            .spawn(move || {
+                let _slot = slot;
+                send_response(&sender, id, &response, stream);
+                sender.finish(id);
+            });
+        if spawned.is_err() {
+            shared.reset(id, ErrorCode::InternalError);
+            shared.finish(id);
+        }
+        return;
+    }
+    let stream = service.record(
+        request,
+        response.status,
+        response.body.len(),
+        stream,
+        received,
+    );
+    send_response(shared, id, &response, stream);
+    shared.finish(id);
+}
+
+/// Fit a handler's response for HTTP/2, and take out the rest of its body
+/// if it's streamed.
+fn prepare(mut response: Response, head: bool) -> (Response, Option<Box<dyn Read + Send>>) {
+    // There are no interim responses to send in place of the final one, and
+    // no switching protocols (section 8.6).
+    if response.status < 200 {
+        response = Response::text(500, "Internal Server Error\n");
+    }
+
+    // These responses never have a body (RFC 9110 section 6.4.1).
+    let bodiless = response.status == 204 || response.status == 304;
+    let stream = response.stream.take().filter(|_| !bodiless);
+    let length = stream
+        .as_ref()
+        .and_then(|_| response.headers.get("Content-Length"))
+        .and_then(|length| length.parse::<u64>().ok());
+    if bodiless {
+        response.body.clear();
+    } else if stream.is_some() && length.is_none() {
+        response.headers.remove("Content-Length");
+    } else if !response.headers.contains("Content-Length") {
+        let length = response.body.len();
+        response
+            .headers
+            .insert("Content-Length", length.to_string());
+    }
+
+    if head {
+        response.body.clear();
+        return (response, None);
+    }
+    let stream = stream.and_then(|stream| stream.take());
+    let stream = match (stream, length) {
+        (Some(reader), Some(length)) => {
+            let length = length.saturating_sub(response.body.len() as u64);
+            Some(Box::new(Exact::new(reader, length)) as Box<dyn Read + Send>)
+        }
+        (stream, _) => stream,
+    };
+    (response, stream)
+}
+
+/// Send a response on stream `id`: its head, then its body as the client's
+/// windows allow. Returns whether all of it was sent.
+fn send_response(
+    shared: &Shared,
+    id: u32,
+    response: &Response,
+    stream: Option<Box<dyn Read + Send>>,
+) -> bool {
+    let mut fields = vec![(":status".to_string(), response.status.to_string())];
+    for (name, value) in response.headers.iter() {
+        let name = name.to_ascii_lowercase();
+        if !CONNECTION_SPECIFIC.contains(&name.as_str()) {
+            fields.push((name, value.to_string()));
+        }
+    }
+
+    let end_stream = response.body.is_empty() && stream.is_none();
+    if !shared.send_headers(id, &fields, end_stream) {
+        return false;
+    }
+    if end_stream {
+        return true;
+    }
+    if !response.body.is_empty() && !shared.send_data(id, &response.body, stream.is_none()) {
+        return false;
+    }
+    let Some(mut reader) = stream else {
+        return true;
+    };
+
+    let mut buf = vec![0; CHUNK_SIZE];
+    loop {
+        match reader.read(&mut buf) {
+            Ok(0) => return shared.send_data(id, &[], true),
+            Ok(n) => {
+                if !shared.send_data(id, &buf[..n], false) {
+                    return false;
+                }
+            }
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+            Err(_) => {
+                shared.reset(id, ErrorCode::InternalError);
+                return false;
+            }
+        }
+    }
+}
+
+/// The writing half of a connection, shared by the thread reading it and
+/// the workers answering its streams.
+struct Shared {
+    output: Mutex<Output>,
+    /// Signalled when a window grows, a stream ends or the connection
+    /// closes.
+    changed: Condvar,
+    /// How long a worker waits for the client to open a window.
+    write_timeout: Option<Duration>,
+}
+
+struct Output {
+    stream: Stream,
+    /// Header blocks must be encoded in the order they're sent, so the
+    /// encoder goes along with the socket.
+    encoder: Encoder,
+    /// The client's settings.
+    settings: Settings,
+    /// How much more DATA the client takes on the connection as a whole.
+    window: i64,
+    /// The open streams, with how much more DATA the client takes on each.
+    streams: HashMap<u32, i64>,
+    /// The streams whose handler is running, whether or not they're still
+    /// open.
+    answering: HashSet<u32>,
+    /// Nothing more can be sent.
+    closed: bool,
+}
+
+impl Output {
+    /// Send `frames` in one go. Returns `false` if they couldn't be sent.
+    fn write(&mut self, frames: &[Frame]) -> bool {
+        if self.closed {
+            return false;
+        }
+        let bytes: Vec<u8> = frames.iter().flat_map(Frame::encode).collect();
+        if (&self.stream).write_all(&bytes).is_err() {
+            self.closed = true;
+        }
+        !self.closed
+    }
+
+    fn reset(&mut self, id: u32, error: ErrorCode) {
+        self.streams.remove(&id);
+        self.write(&[Frame::RstStream { stream: id, error }]);
+    }
+}
+
+impl Shared {
+    fn lock(&self) -> MutexGuard<'_, Output> {
+        self.output
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    fn send(&self, frames: &[Frame]) {
+        self.lock().write(frames);
+    }
+
+    fn open(&self, id: u32) {
+        let mut output = self.lock();
+        let window = i64::from(output.settings.initial_window_size);
+        output.streams.insert(id, window);
+    }
+
+    /// Be done with stream `id`, without telling the client. Called once
+    /// its handler is done with it.
+    fn finish(&self, id: u32) {
+        let mut output = self.lock();
+        output.streams.remove(&id);
+        output.answering.remove(&id);
+        self.changed.notify_all();
+    }
+
+    /// The client reset stream `id`: nothing more is sent on it, but it
+    /// counts as open until its handler is done.
+    fn cancel(&self, id: u32) {
+        self.lock().streams.remove(&id);
+        self.changed.notify_all();
+    }
+
+    /// Close stream `id` with a RST_STREAM frame.
+    fn reset(&self, id: u32, error: ErrorCode) {
+        self.lock().reset(id, error);
+        self.changed.notify_all();
+    }
+
+    fn close(&self) {
+        let mut output = self.lock();
+        output.closed = true;
+        let _ = output.stream.shutdown(Shutdown::Both);
+        self.changed.notify_all();
+    }
+
+    /// Take the client's settings, and acknowledge them unless they came
+    /// with the `Upgrade: h2c` request.
+    fn apply_settings(&self, params: &[(u16, u32)], ack: bool) -> Result<(), Error> {
+        let mut output = self.lock();
+        let before = output.settings.initial_window_size;
+        output.settings.apply(params)?;
+
+        // A new initial window size moves the windows of open streams along
+        // (RFC 9113 section 6.9.2).
+        let delta = i64::from(output.settings.initial_window_size) - i64::from(before);
+        for window in output.streams.values_mut() {
+            *window += delta;
+            if *window > i64::from(MAX_WINDOW_SIZE) {
+                return Err(Error::Connection(
+                    ErrorCode::FlowControlError,
+                    "window is larger than the maximum",
+                ));
+            }
+        }
+        let table_size = output.settings.header_table_size as usize;
+        output.encoder.set_max_table_size(table_size);
+        if ack {
+            output.write(&[Frame::Settings {
+                ack: true,
+                settings: Vec::new(),
+            }]);
+        }
+        self.changed.notify_all();
+        Ok(())
+    }
+
+    fn window_update(&self, id: u32, increment: u32) -> Result<(), Error> {
+        let mut output = self.lock();
+        let too_large = match id {
+            0 => {
+                output.window += i64::from(increment);
+                output.window > i64::from(MAX_WINDOW_SIZE)
+            }
+            id => match output.streams.get_mut(&id) {
+                Some(window) => {
+                    *window += i64::from(increment);
+                    *window > i64::from(MAX_WINDOW_SIZE)
+                }
+                None => false,
+            },
+        };
+        self.changed.notify_all();
+
+        let reason = "window is larger than the maximum";
+        match (too_large, id) {
+            (false, _) => Ok(()),
+            (true, 0) => Err(Error::Connection(ErrorCode::FlowControlError, reason)),
+            (true, id) => Err(Error::Stream(id, ErrorCode::FlowControlError, reason)),
+        }
+    }
+
+    /// Send a response head on stream `id`, in as many frames as the
+    /// client's maximum frame size takes.
+    fn send_headers(&self, id: u32, fields: &[(String, String)], end_stream: bool) -> bool {
+        let mut output = self.lock();
+        if output.closed || !output.streams.contains_key(&id) {
+            return false;
+        }
+        let block = output
+            .encoder
+            .encode(fields.iter().map(|(name, value)| (name, value)));
+        let max_size = output.settings.max_frame_size as usize;
+
+        let fragments: Vec<&[u8]> = block.chunks(max_size).collect();
+        let last = fragments.len() - 1;
+        let frames: Vec<Frame> = fragments
+            .iter()
+            .enumerate()
+            .map(|(i, fragment)| match i {
+                0 => Frame::Headers {
+                    stream: id,
+                    block: fragment.to_vec(),
+                    end_stream,
+                    end_headers: i == last,
+                    priority: None,
+                },
+                _ => Frame::Continuation {
+                    stream: id,
+                    block: fragment.to_vec(),
+                    end_headers: i == last,
+                },
+            })
+            .collect();
+        output.write(&frames)
+    }
+
+    /// Send `data` on stream `id`, waiting for the client's windows to make
+    /// room for it. Returns `false` if the stream or connection closed, or
+    /// if the client kept its windows shut for the write timeout.
+    fn send_data(&self, id: u32, mut data: &[u8], end_stream: bool) -> bool {
+        let mut output = self.lock();
+        loop {
+            if output.closed {
+                return false;
+            }
+            let Some(&stream_window) = output.streams.get(&id) else {
+                return false;
+            };
+            let room = output
+                .window
+                .min(stream_window)
+                .min(i64::from(output.settings.max_frame_size));
+
+            if room <= 0 && !data.is_empty() {
+                output = match self.write_timeout {
+                    Some(timeout) => {
+                        let (output, waited) = self
+                            .changed
+                            .wait_timeout(output, timeout)
+                            .unwrap_or_else(|poisoned| poisoned.into_inner());
+                        if waited.timed_out() {
+                            let mut output = output;
+                            output.reset(id, ErrorCode::Cancel);
+                            return false;
+                        }
+                        output
+                    }
+                    None => self
+                        .changed
+                        .wait(output)
+                        .unwrap_or_else(|poisoned| poisoned.into_inner()),
+                };
+                continue;
+            }
+
+            let (chunk, rest) = data.split_at(data.len().min(room.max(0) as usize));
+            let frame = Frame::Data {
+                stream: id,
+                data: chunk.to_vec(),
+                end_stream: end_stream && rest.is_empty(),
+                padding: None,
+            };
+            if !output.write(&[frame]) {
+                return false;
+            }
+            let sent = chunk.len() as i64;
+            output.window -= sent;
+            if let Some(window) = output.streams.get_mut(&id) {
+                *window -= sent;
+            }
+            if rest.is_empty() {
+                return true;
+            }
+            data = rest;
+        }
+    }
+}
+
+fn is_timeout(e: &io::Error) -> bool {
+    matches!(
+        e.kind(),
+        io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn prepares_responses() {
+        let (response, stream) = prepare(Response::text(200, "hello"), false);
+        assert_eq!(response.headers.get("Content-Length"), Some("5"));
+        assert!(stream.is_none());
+
+        let (response, _) = prepare(Response::text(200, "hello"), true);
+        assert_eq!(response.headers.get("Content-Length"), Some("5"));
+        assert!(response.body.is_empty());
+
+        let (response, _) = prepare(Response::text(304, "hello"), false);
+        assert!(response.body.is_empty());
+        assert_eq!(prepare(Response::new(101), false).0.status, 500);
+
+        let (response, stream) = prepare(Response::new(200).with_stream(&b"abc"[..]), false);
+        assert!(!response.headers.contains("Content-Length"));
+        assert!(stream.is_some());
+
+        // A known length is kept, and holds the stream to it.
+        let streamed = Response::new(200)
+            .with_header("Content-Length", "2")
+            .with_stream(&b"abc"[..]);
+        let (response, stream) = prepare(streamed, false);
+        assert_eq!(response.headers.get("Content-Length"), Some("2"));
+        let mut body = Vec::new();
+        stream.unwrap().read_to_end(&mut body).unwrap();
+        assert_eq!(body, b"ab");
+
+        let short = Response::new(200)
+            .with_header("Content-Length", "4")
+            .with_stream(&b"abc"[..]);
+        let mut body = Vec::new();
+        assert!(prepare(short, false)
+            .1
+            .unwrap()
+            .read_to_end(&mut body)
+            .is_err());
+    }
+}
@@ -0,0 +1,352 @@
+//! The sockets a server accepts connections on: TCP, or Unix domain sockets
+//! for a server behind a local reverse proxy.
+
+use std::{
+    fmt,
+    io::{self, Read, Write},
+    net::{IpAddr, Shutdown, SocketAddr, TcpListener, TcpStream},
+    time::Duration,
+};
+
+#[cfg(unix)]
+use std::{
+    fs,
+    os::fd::{AsRawFd, RawFd},
+    os::unix::{
+        fs::{FileTypeExt, PermissionsExt},
+        net::{UnixListener, UnixStream},
+    },
+    path::{Path, PathBuf},
+};
+
+/// A listening socket. `Server::serve` takes anything that converts into
+/// one, like a `TcpListener`.
+#[derive(Debug)]
+pub enum Listener {
+    Tcp(TcpListener),
+    #[cfg(unix)]
+    Unix(UnixListener),
+}
+
+impl Listener {
+    /// Listen on a Unix domain socket at `path`, readable and writable as
+    /// `mode` says, like `0o660`, or as the umask has it if `None`.
+    ///
+    /// A socket file left behind by a server that's gone is removed first.
+    /// If a server still answers on it, binding fails with `AddrInUse`.
+    ///
+    /// On Linux the socket is created with `mode` already, under a umask
+    /// set for the while, so that no one can connect before it applies.
+    #[cfg(unix)]
+    pub fn bind_unix(path: impl AsRef<Path>, mode: Option<u32>) -> io::Result<Listener> {
+        let path = path.as_ref();
+        remove_stale_socket(path)?;
+        #[cfg(target_os = "linux")]
+        let umask = mode.map(|mode| super::sys::Umask::set(0o777 & !mode));
+        let listener = UnixListener::bind(path);
+        #[cfg(target_os = "linux")]
+        drop(umask);
+        let listener = listener?;
+        // Elsewhere, the mode applies from here on.
+        if let Some(mode) = mode {
+            fs::set_permissions(path, fs::Permissions::from_mode(mode))?;
+        }
+        Ok(Listener::Unix(listener))
+    }
+
+    /// The sockets passed by the service manager, systemd style: `LISTEN_FDS`
+    /// descriptors from 3 on, if `LISTEN_PID` is this process. Returns an
+    /// empty list if the server wasn't socket-activated.
+    ///
+    /// The variables are removed, so that child processes such as CGI
+    /// scripts don't take the sockets for theirs.
+    #[cfg(target_os = "linux")]
+    pub fn from_env() -> io::Result<Vec<Listener>> {
+        use std::{env, os::fd::FromRawFd, os::fd::OwnedFd, process};
+
+        const FIRST_FD: RawFd = 3;
+
+        let pid = env::var("LISTEN_PID").ok();
+        let fds = env::var("LISTEN_FDS").ok();
+        for name in ["LISTEN_PID", "LISTEN_FDS", "LISTEN_FDNAMES"] {
+            env::remove_var(name);
+        }
+        if pid.and_then(|pid| pid.parse().ok()) != Some(process::id()) {
+            return Ok(Vec::new());
+        }
+        let count: RawFd = match fds.map(|fds| fds.parse()) {
+            Some(Ok(count)) => count,
+            Some(Err(_)) => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    "LISTEN_FDS isn't a number",
+                ))
+            }
+            None => return Ok(Vec::new()),
+        };
+
+        (FIRST_FD..FIRST_FD + count)
+            .map(|fd| {
+                super::sys::set_cloexec(fd)?;
+                // SAFETY: the service manager handed the descriptor to this
+                // process, and it's taken only once as the variables are gone.
+                let fd = unsafe { OwnedFd::from_raw_fd(fd) };
+                // A Unix socket has no IP address, which is how the two
+                // kinds are told apart.
+                let tcp = TcpListener::from(fd);
+                if tcp.local_addr().is_ok() {
+                    return Ok(Listener::Tcp(tcp));
+                }
+                let unix = UnixListener::from(OwnedFd::from(tcp));
+                unix.local_addr()?;
+                Ok(Listener::Unix(unix))
+            })
+            .collect()
+    }
+
+    /// Accept a connection, and the IP address of the client if it has one.
+    pub fn accept(&self) -> io::Result<(Stream, Option<IpAddr>)> {
+        match self {
+            Listener::Tcp(listener) => {
+                let (stream, peer) = listener.accept()?;
+                Ok((Stream::Tcp(stream), Some(peer.ip())))
+            }
+            #[cfg(unix)]
+            Listener::Unix(listener) => {
+                let (stream, _) = listener.accept()?;
+                Ok((Stream::Unix(stream), None))
+            }
+        }
+    }
+
+    pub fn set_nonblocking(&self, nonblocking: bool) -> io::Result<()> {
+        match self {
+            Listener::Tcp(listener) => listener.set_nonblocking(nonblocking),
+            #[cfg(unix)]
+            Listener::Unix(listener) => listener.set_nonblocking(nonblocking),
+        }
+    }
+}
+
+/// A socket file at `path` that nobody listens on anymore.
+#[cfg(unix)]
+fn remove_stale_socket(path: &Path) -> io::Result<()> {
+    match fs::symlink_metadata(path) {
+        Ok(metadata) if metadata.file_type().is_socket() => {}
+        // Nothing there, or something that binding will refuse to replace.
+        _ => return Ok(()),
+    }
+    match UnixStream::connect(path) {
+        Ok(_) => Err(io::Error::new(
+            io::ErrorKind::AddrInUse,
+            format!("a server is listening on {} already", path.display()),
+        )),
+        Err(e) if e.kind() == io::ErrorKind::ConnectionRefused => fs::remove_file(path),
+        Err(_) => Ok(()),
+    }
+}
+
+impl From<TcpListener> for Listener {
+    fn from(listener: TcpListener) -> Listener {
+        Listener::Tcp(listener)
+    }
+}
+
+#[cfg(unix)]
+impl From<UnixListener> for Listener {
+    fn from(listener: UnixListener) -> Listener {
+        Listener::Unix(listener)
+    }
+}
+
+#[cfg(unix)]
+impl AsRawFd for Listener {
+    fn as_raw_fd(&self) -> RawFd {
+        match self {
+            Listener::Tcp(listener) => listener.as_raw_fd(),
+            Listener::Unix(listener) => listener.as_raw_fd(),
+        }
+    }
+}
+
+/// The local address, like `127.0.0.1:7878` or `unix:/run/echo.sock`.
+impl fmt::Display for Listener {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Listener::Tcp(listener) => match listener.local_addr() {
+                Ok(addr) => write!(f, "{addr}"),
+                Err(_) => write!(f, "a TCP socket"),
+            },
+            #[cfg(unix)]
+            Listener::Unix(listener) => {
+                let path = listener
+                    .local_addr()
+                    .ok()
+                    .and_then(|addr| addr.as_pathname().map(PathBuf::from));
+                match path {
+                    Some(path) => write!(f, "unix:{}", path.display()),
+                    None => write!(f, "an unnamed Unix socket"),
+                }
+            }
+        }
+    }
+}
+
+/// A client connection accepted by a `Listener`.
+#[derive(Debug)]
+pub enum Stream {
+    Tcp(TcpStream),
+    #[cfg(unix)]
+    Unix(UnixStream),
+}
+
+/// Forward a method to the socket, whichever kind it is.
+macro_rules! forward {
+    ($self:ident, $stream:ident => $call:expr) => {
+        match $self {
+            Stream::Tcp($stream) => $call,
+            #[cfg(unix)]
+            Stream::Unix($stream) => $call,
+        }
+    };
+}
+
+impl Stream {
+    /// The address of the client. Clients on a Unix socket have none.
+    pub fn peer_addr(&self) -> io::Result<SocketAddr> {
+        match self {
+            Stream::Tcp(stream) => stream.peer_addr(),
+            #[cfg(unix)]
+            Stream::Unix(_) => Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                "a Unix socket has no IP address",
+            )),
+        }
+    }
+
+    pub fn try_clone(&self) -> io::Result<Stream> {
+        forward!(self, stream => stream.try_clone().map(Stream::from))
+    }
+
+    pub fn set_read_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        forward!(self, stream => stream.set_read_timeout(timeout))
+    }
+
+    pub fn set_write_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        forward!(self, stream => stream.set_write_timeout(timeout))
+    }
+
+    pub fn set_nonblocking(&self, nonblocking: bool) -> io::Result<()> {
+        forward!(self, stream => stream.set_nonblocking(nonblocking))
+    }
+
+    pub fn shutdown(&self, how: Shutdown) -> io::Result<()> {
+        forward!(self, stream => stream.shutdown(how))
+    }
+
+    /// Send small writes right away rather than coalesce them, on TCP.
+    pub fn set_nodelay(&self, nodelay: bool) -> io::Result<()> {
+        match self {
+            Stream::Tcp(stream) => stream.set_nodelay(nodelay),
+            #[cfg(unix)]
+            Stream::Unix(_) => Ok(()),
+        }
+    }
+}
+
+impl Read for Stream {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        (&*self).read(buf)
+    }
+}
+
+impl Read for &Stream {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        forward!(self, stream => (&*stream).read(buf))
+    }
+}
+
+impl Write for Stream {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        (&*self).write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        (&*self).flush()
+    }
+}
+
+impl Write for &Stream {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        forward!(self, stream => (&*stream).write(buf))
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        forward!(self, stream => (&*stream).flush())
+    }
+}
+
+impl From<TcpStream> for Stream {
+    fn from(stream: TcpStream) -> Stream {
+        Stream::Tcp(stream)
+    }
+}
+
+#[cfg(unix)]
+impl From<UnixStream> for Stream {
+    fn from(stream: UnixStream) -> Stream {
+        Stream::Unix(stream)
+    }
+}
+
+#[cfg(unix)]
+impl AsRawFd for Stream {
+    fn as_raw_fd(&self) -> RawFd {
+        forward!(self, stream => stream.as_raw_fd())
+    }
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+    use crate::testing::TempDir;
+
+    #[test]
+    fn unix_sockets() {
+        let dir = TempDir::new("listener");
+        let path = dir.join("echo.sock");
+
+        let listener = Listener::bind_unix(&path, Some(0o600)).unwrap();
+        assert_eq!(listener.to_string(), format!("unix:{}", path.display()));
+        let mode = fs::metadata(&path).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o600);
+
+        let mut client = UnixStream::connect(&path).unwrap();
+        let (mut stream, peer) = listener.accept().unwrap();
+        assert_eq!(peer, None);
+        assert!(stream.peer_addr().is_err());
+        client.write_all(b"ping").unwrap();
+        let mut buf = [0; 4];
+        stream.read_exact(&mut buf).unwrap();
+        assert_eq!(&buf, b"ping");
+
+        // The socket is in use.
+        let error = Listener::bind_unix(&path, None).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::AddrInUse);
+
+        // Once the server is gone, its socket file is stale.
+        drop(listener);
+        assert!(path.exists());
+        Listener::bind_unix(&path, None).unwrap();
+    }
+
+    #[test]
+    fn other_files_are_left_alone() {
+        let dir = TempDir::new("listener-file");
+        let path = dir.join("echo.sock");
+        fs::write(&path, "not a socket").unwrap();
+        let error = Listener::bind_unix(&path, None).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::AddrInUse);
+        assert_eq!(fs::read_to_string(&path).unwrap(), "not a socket");
+    }
+}
@@ -0,0 +1,649 @@
+#[cfg(target_os = "linux")]
+mod event_loop;
+mod http2;
+mod listener;
+mod peers;
+#[cfg(target_os = "linux")]
+mod sys;
+mod threaded;
+
+use std::{
+    io::{self, Read, Write},
+    net::IpAddr,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+    thread,
+    time::{Duration, Instant, SystemTime},
+};
+
+use crate::{
+    access_log::{AccessLog, Deferred, Entry},
+    http::{self, BodyStream, Exact, Limits, Request, Response, Upgrade, Upgraded, Version},
+    metrics::ServerMetrics,
+    pool::{ExecuteError, ThreadPool},
+};
+pub use listener::{Listener, Stream};
+use peers::{PeerCounts, PeerSlot};
+use threaded::Plain;
+
+/// Something that turns requests into responses.
+///
+/// Handlers run on the server's `ThreadPool`, so they are free to block.
+/// Any `Fn(&Request) -> Response` closure is a handler.
+pub trait Handler: Send + Sync + 'static {
+    fn handle(&self, request: &Request) -> Response;
+}
+
+impl<F> Handler for F
+where
+    F: Fn(&Request) -> Response + Send + Sync + 'static,
+{
+    fn handle(&self, request: &Request) -> Response {
+        self(request)
+    }
+}
+
+/// How the server waits on its connections.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Backend {
+    /// A pool worker handles each connection from start to finish, including
+    /// waiting for the client to send its request.
+    #[default]
+    Threaded,
+    /// A single thread waits on every connection with `epoll` and only hands
+    /// complete requests to the pool. Slow clients don't hold up a worker,
+    /// and connections are kept alive between requests.
+    #[cfg(target_os = "linux")]
+    EventLoop,
+}
+
+/// Configures and creates a `Server`.
+pub struct Builder {
+    backend: Backend,
+    pool: Option<ThreadPool>,
+    access_log: Option<AccessLog>,
+    timeouts: Timeouts,
+    limits: Limits,
+    max_connections_per_ip: Option<usize>,
+    max_long_lived: usize,
+    metrics: Option<ServerMetrics>,
+}
+
+impl Builder {
+    pub fn new() -> Builder {
+        Builder {
+            backend: Backend::default(),
+            pool: None,
+            access_log: None,
+            metrics: None,
+            timeouts: Timeouts::default(),
+            limits: Limits::default(),
+            max_connections_per_ip: None,
+            max_long_lived: DEFAULT_MAX_LONG_LIVED_STREAMS,
+        }
+    }
+
+    pub fn backend(mut self, backend: Backend) -> Builder {
+        self.backend = backend;
+        self
+    }
+
+    /// The pool handlers run on. Defaults to `ThreadPool::new(4)`.
+    pub fn pool(mut self, pool: ThreadPool) -> Builder {
+        self.pool = Some(pool);
+        self
+    }
+
+    /// Log every answered request to `log`. There's no access log by
+    /// default.
+    pub fn access_log(mut self, log: AccessLog) -> Builder {
+        self.access_log = Some(log);
+        self
+    }
+
+    /// Keep metrics about the requests, connections and pool. There are none
+    /// by default.
+    pub fn metrics(mut self, metrics: ServerMetrics) -> Builder {
+        self.metrics = Some(metrics);
+        self
+    }
+
+    /// Close connections on which the client sent nothing for `timeout`,
+    /// including idle kept-alive connections. There's no timeout by default.
+    pub fn read_timeout(mut self, timeout: Duration) -> Builder {
+        self.timeouts.read = Some(timeout);
+        self
+    }
+
+    /// Close connections on which the client accepted none of the response
+    /// for `timeout`. There's no timeout by default.
+    pub fn write_timeout(mut self, timeout: Duration) -> Builder {
+        self.timeouts.write = Some(timeout);
+        self
+    }
+
+    /// Answer 408 and close connections on which the client takes longer
+    /// than `timeout` to send the head of a request. Unlike the read
+    /// timeout, it catches clients that trickle in a byte now and then to
+    /// hold on to a connection (and a worker, with the threaded backend).
+    /// There's no deadline by default.
+    pub fn header_timeout(mut self, timeout: Duration) -> Builder {
+        self.timeouts.header = Some(timeout);
+        self
+    }
+
+    /// The largest requests to accept. Larger ones get a 413, 414 or 431
+    /// response as soon as that's clear, and the connection is closed.
+    /// Defaults to `Limits::default()`.
+    pub fn limits(mut self, limits: Limits) -> Builder {
+        self.limits = limits;
+        self
+    }
+
+    /// Answer 429 to clients that already have `max` connections open, and
+    /// close the new one. There's no cap by default.
+    pub fn max_connections_per_ip(mut self, max: usize) -> Builder {
+        self.max_connections_per_ip = Some(max);
+        self
+    }
+
+    /// The most long-lived bodies, like event streams, to send at once. Each
+    /// takes a thread of its own; past the cap, HTTP/1 requests for one get
+    /// a 503 response and HTTP/2 streams are refused. Defaults to
+    /// `DEFAULT_MAX_LONG_LIVED_STREAMS`.
+    pub fn max_long_lived_streams(mut self, max: usize) -> Builder {
+        self.max_long_lived = max;
+        self
+    }
+
+    pub fn build<H: Handler>(self, handler: H) -> Server {
+        let pool = self.pool.unwrap_or_else(|| ThreadPool::new(4));
+        if let Some(metrics) = &self.metrics {
+            metrics.watch_pool(pool.stats_fn());
+        }
+        Server {
+            service: Arc::new(Service {
+                handler: Box::new(handler),
+                access_log: self.access_log,
+                metrics: self.metrics,
+                limits: self.limits,
+                timeouts: self.timeouts,
+                spawn: Box::new(pool.execute_fn()),
+                long_lived: Arc::new(LongLived::new(self.max_long_lived)),
+            }),
+            pool,
+            backend: self.backend,
+            peers: self
+                .max_connections_per_ip
+                .map(|max| Arc::new(PeerCounts::new(max))),
+        }
+    }
+}
+
+impl Default for Builder {
+    fn default() -> Builder {
+        Builder::new()
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+struct Timeouts {
+    read: Option<Duration>,
+    write: Option<Duration>,
+    /// How long after the server starts waiting for a request its head must
+    /// be in.
+    header: Option<Duration>,
+}
+
+pub struct Server {
+    service: Arc<Service>,
+    pool: ThreadPool,
+    backend: Backend,
+    /// `None` if connections per client aren't capped.
+    peers: Option<Arc<PeerCounts>>,
+}
+
+impl Server {
+    /// Create a server with the default backend and pool.
+    pub fn new<H: Handler>(handler: H) -> Server {
+        Builder::new().build(handler)
+    }
+
+    pub fn builder() -> Builder {
+        Builder::new()
+    }
+
+    /// Accept connections on `listener`, a `TcpListener` or any other
+    /// `Listener`, and answer their requests, until accepting fails for good.
+    pub fn serve(&self, listener: impl Into<Listener>) -> io::Result<()> {
+        let listener = listener.into();
+        match self.backend {
+            Backend::Threaded => threaded::serve(self, listener),
+            #[cfg(target_os = "linux")]
+            Backend::EventLoop => event_loop::serve(self, listener),
+        }
+    }
+
+    /// Serve every listener on a thread of its own, sharing the handler and
+    /// the pool. Returns the first error, once every listener has stopped.
+    pub fn serve_all<L: Into<Listener> + Send>(&self, listeners: Vec<L>) -> io::Result<()> {
+        thread::scope(|scope| {
+            let threads: Vec<_> = listeners
+                .into_iter()
+                .map(|listener| scope.spawn(|| self.serve(listener)))
+                .collect();
+
+            threads
+                .into_iter()
+                .map(|thread| thread.join().unwrap())
+                .fold(Ok(()), Result::and)
+        })
+    }
+
+    /// Answer one request read from `stream`, on the calling thread, the
+    /// way the threaded backend answers a socket. Meant for streams that
+    /// aren't sockets, like in-memory ones in tests: there are no timeouts,
+    /// and upgrades fail once the `101` response is written.
+    pub fn handle_connection<S: Read + Write>(&self, stream: S) {
+        threaded::handle_connection(Plain(stream), &self.service, PeerSlot::uncounted());
+    }
+
+    /// Count a new connection from `peer`, or return `None` if the client
+    /// has too many open already.
+    fn admit(&self, peer: Option<IpAddr>) -> Option<PeerSlot> {
+        let slot = match (&self.peers, peer) {
+            (Some(peers), Some(ip)) => peers.acquire(ip)?,
+            _ => PeerSlot::uncounted(),
+        };
+        match &self.service.metrics {
+            Some(metrics) => Some(slot.gauged(metrics.connections())),
+            None => Some(slot),
+        }
+    }
+}
+
+type Job = Box<dyn FnOnce() + Send>;
+
+/// What the workers need to answer requests.
+struct Service {
+    handler: Box<dyn Handler>,
+    access_log: Option<AccessLog>,
+    metrics: Option<ServerMetrics>,
+    limits: Limits,
+    timeouts: Timeouts,
+    /// Queue a job on the server's pool, like answering a stream of an
+    /// HTTP/2 connection.
+    spawn: Box<dyn Fn(Job) -> Result<(), ExecuteError> + Send + Sync>,
+    long_lived: Arc<LongLived>,
+}
+
+/// A serialized response, and what becomes of the connection after it.
+struct Reply {
+    bytes: Vec<u8>,
+    keep_alive: bool,
+    /// Hand the connection over once the response is sent.
+    upgrade: Option<Upgrade>,
+    /// The rest of the body, to send after `bytes`.
+    stream: Option<StreamedBody>,
+}
+
+impl Service {
+    /// Run the handler, log the request and serialize the response.
+    ///
+    /// A request to switch to HTTP/2 is answered with a `101` response
+    /// instead, and then on the new connection.
+    fn respond(self: &Arc<Self>, request: &Request, received: Instant, keep_alive: bool) -> Reply {
+        if let Some(settings) = crate::http2::upgrade_settings(request) {
+            let switched = http2::Switched::new(request, settings, received);
+            return Reply {
+                bytes: SWITCHING_TO_HTTP2.to_vec(),
+                keep_alive: false,
+                upgrade: Some(http2::upgrade(Arc::clone(self), Some(switched))),
+                stream: None,
+            };
+        }
+
+        let mut response = self.handler.handle(request);
+        let long_lived_slot = match &response.stream {
+            Some(stream) if stream.is_long_lived() => {
+                let slot = self.long_lived.acquire();
+                if slot.is_none() {
+                    response = busy();
+                }
+                slot
+            }
+            _ => None,
+        };
+
+        // A 101 response keeps its `Connection: upgrade`, and the connection
+        // is no longer HTTP's to keep alive or close.
+        let upgrade = match response.upgrade.take() {
+            Some(upgrade) if response.status == 101 => Some(upgrade),
+            _ => None,
+        };
+        let mut keep_alive = keep_alive
+            && upgrade.is_none()
+            && request.keep_alive()
+            && !response.headers.has_token("Connection", "close");
+
+        // These responses never have a body (RFC 9110 section 6.4.1).
+        let bodiless = response.status < 200 || response.status == 204 || response.status == 304;
+        let stream = response.stream.take().filter(|_| !bodiless);
+        let long_lived = stream.as_ref().is_some_and(BodyStream::is_long_lived);
+        keep_alive &= !long_lived;
+        // A streamed body of a known length is framed by it, other ones are
+        // chunked.
+        let length = stream
+            .as_ref()
+            .and_then(|_| response.headers.get("Content-Length"))
+            .and_then(|length| length.parse::<u64>().ok());
+        if stream.is_some() && length.is_none() {
+            response.headers.insert("Transfer-Encoding", "chunked");
+        }
+
+        // HTTP/1.0 clients don't know chunked transfer coding, they get the
+        // body delimited by its length instead, or by the end of the
+        // connection if it's streamed.
+        let mut chunked = response.headers.has_token("Transfer-Encoding", "chunked");
+        if chunked && request.version == Version::Http10 {
+            response.headers.remove("Transfer-Encoding");
+            chunked = false;
+            keep_alive &= stream.is_none();
+        }
+
+        if bodiless {
+            response.body.clear();
+            response.headers.remove("Transfer-Encoding");
+            chunked = false;
+        } else if chunked || (stream.is_some() && length.is_none()) {
+            response.headers.remove("Content-Length");
+        } else if !response.headers.contains("Content-Length") {
+            let length = response.body.len();
+            response
+                .headers
+                .insert("Content-Length", length.to_string());
+        }
+        if upgrade.is_none() {
+            if !keep_alive {
+                response.headers.insert("Connection", "close");
+            } else if request.version == Version::Http10 {
+                response.headers.insert("Connection", "keep-alive");
+            }
+        }
+
+        let head = request.method == "HEAD";
+        if head {
+            response.body.clear();
+        }
+        let body_len = response.body.len();
+        if chunked && !head {
+            response.body = encode_chunks(&response.body);
+            if stream.is_none() {
+                response.body.extend_from_slice(LAST_CHUNK);
+            }
+        }
+
+        let reader = stream
+            .filter(|_| !head)
+            .and_then(|stream| stream.take())
+            .map(|reader| match length {
+                Some(length) => {
+                    Box::new(Exact::new(reader, length.saturating_sub(body_len as u64))) as _
+                }
+                None => reader,
+            });
+        let reader = self.record(request, response.status, body_len, reader, received);
+        let stream = reader.map(|reader| StreamedBody { reader, chunked });
+        // A long-lived body is sent once the connection is off the pool.
+        let (stream, upgrade) = match stream {
+            Some(body) if long_lived => {
+                let slot = long_lived_slot.expect("long-lived bodies have a slot");
+                (None, Some(send_on_own_thread(body, slot)))
+            }
+            stream => (stream, upgrade),
+        };
+        Reply {
+            bytes: response.to_bytes(),
+            keep_alive,
+            upgrade,
+            stream,
+        }
+    }
+
+    /// Count the answered request in the metrics and log it, with the
+    /// `bytes` of its body and what's read of `stream`, the rest of it. The
+    /// entry of a streamed body is logged once that's read, so the stream
+    /// comes back counted, to be sent in place of `stream`.
+    fn record(
+        &self,
+        request: &Request,
+        status: u16,
+        bytes: usize,
+        stream: Option<Box<dyn Read + Send>>,
+        received: Instant,
+    ) -> Option<Box<dyn Read + Send>> {
+        if let Some(metrics) = &self.metrics {
+            metrics.observe(request, status, received.elapsed());
+        }
+        let Some(log) = &self.access_log else {
+            return stream;
+        };
+        let duration = received.elapsed();
+        let entry = Entry {
+            peer: request.peer,
+            time: SystemTime::now() - duration,
+            request,
+            status,
+            bytes,
+            duration,
+        };
+        match stream {
+            Some(reader) => Some(Box::new(Counted {
+                reader,
+                bytes,
+                entry: Some(log.defer(&entry)),
+            })),
+            None => {
+                log.log(&entry);
+                None
+            }
+        }
+    }
+}
+
+/// A streamed body that logs its request once it's done with, along with
+/// how much of it was read.
+struct Counted {
+    reader: Box<dyn Read + Send>,
+    bytes: usize,
+    entry: Option<Deferred>,
+}
+
+impl Read for Counted {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.reader.read(buf)?;
+        self.bytes += n;
+        Ok(n)
+    }
+}
+
+impl Drop for Counted {
+    fn drop(&mut self) {
+        if let Some(entry) = self.entry.take() {
+            entry.log(self.bytes);
+        }
+    }
+}
+
+/// The rest of a body that's read while it's sent.
+struct StreamedBody {
+    reader: Box<dyn Read + Send>,
+    /// Whether to frame it in chunks, rather than send it as it is until
+    /// the end of the connection.
+    chunked: bool,
+}
+
+impl StreamedBody {
+    /// Read the next piece of the body, framed for the wire. Returns whether
+    /// that was the end of the body.
+    fn next_chunk(&mut self) -> io::Result<(Vec<u8>, bool)> {
+        let mut buf = vec![0; CHUNK_SIZE];
+        let n = loop {
+            match self.reader.read(&mut buf) {
+                Ok(n) => break n,
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+                Err(e) => return Err(e),
+            }
+        };
+
+        match (n, self.chunked) {
+            (0, true) => Ok((LAST_CHUNK.to_vec(), true)),
+            (0, false) => Ok((Vec::new(), true)),
+            (n, true) => Ok((encode_chunks(&buf[..n]), false)),
+            (n, false) => {
+                buf.truncate(n);
+                Ok((buf, false))
+            }
+        }
+    }
+}
+
+/// Take over the connection to send the rest of a long-lived body, which
+/// ends with the connection. The body keeps its `slot` until then.
+fn send_on_own_thread(mut body: StreamedBody, slot: LongLivedSlot) -> Upgrade {
+    Upgrade::new(move |mut connection| {
+        let _slot = slot;
+        while let Ok((bytes, done)) = body.next_chunk() {
+            if connection.write_all(&bytes).is_err() || done {
+                break;
+            }
+        }
+    })
+}
+
+/// The long-lived bodies being sent, counted to cap them. Bodies are read
+/// with blocking calls, so each takes an OS thread while it's sent, on
+/// either backend: the event loop only spares the pool's workers, not the
+/// threads.
+struct LongLived {
+    max: usize,
+    count: AtomicUsize,
+}
+
+impl LongLived {
+    fn new(max: usize) -> LongLived {
+        LongLived {
+            max,
+            count: AtomicUsize::new(0),
+        }
+    }
+
+    /// Count a new long-lived body, or return `None` if there are as many
+    /// as there may be.
+    fn acquire(self: &Arc<LongLived>) -> Option<LongLivedSlot> {
+        self.count
+            .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |count| {
+                (count < self.max).then_some(count + 1)
+            })
+            .ok()?;
+        Some(LongLivedSlot(Arc::clone(self)))
+    }
+}
+
+/// A long-lived body's place in the count, given back when it's dropped.
+struct LongLivedSlot(Arc<LongLived>);
+
+impl Drop for LongLivedSlot {
+    fn drop(&mut self) {
+        self.0.count.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+/// Give an upgraded connection to its new owner, on a thread of its own.
+/// `buffered` is what the client sent after its request. The connection
+/// keeps its `slot` until the new owner is done with it.
+fn hand_over(
+    upgrade: Upgrade,
+    stream: Stream,
+    buffered: Vec<u8>,
+    timeouts: Timeouts,
+    slot: PeerSlot,
+) -> io::Result<()> {
+    // The new protocol decides how long the client may stay quiet.
+    stream.set_nonblocking(false)?;
+    stream.set_read_timeout(None)?;
+    stream.set_write_timeout(timeouts.write)?;
+
+    thread::Builder::new()
+        .name("echo-upgraded".to_string())
+        .spawn(move || {
+            upgrade.run(Upgraded::new(stream, buffered));
+            drop(slot);
+        })?;
+    Ok(())
+}
+
+/// The most long-lived bodies a server sends at once, by default. Each one
+/// is an OS thread, blocked reading its body most of the time. Its 2 MiB
+/// stack is only reserved, not used, so the cost is mostly in threads: this
+/// stays well within the usual per-user limits (`ulimit -u`, in the
+/// thousands) while serving a good many clients.
+pub const DEFAULT_MAX_LONG_LIVED_STREAMS: usize = 1024;
+
+const CHUNK_SIZE: usize = 16 * 1024;
+const LAST_CHUNK: &[u8] = b"0\r\n\r\n";
+
+/// The answer to `Upgrade: h2c`, after which the server speaks HTTP/2.
+const SWITCHING_TO_HTTP2: &[u8] =
+    b"HTTP/1.1 101 Switching Protocols\r\nConnection: Upgrade\r\nUpgrade: h2c\r\n\r\n";
+
+/// Frame a body in chunks of at most `CHUNK_SIZE` bytes (RFC 9112 section
+/// 7.1). The last, empty chunk is up to the caller.
+fn encode_chunks(body: &[u8]) -> Vec<u8> {
+    let mut out = Vec::with_capacity(body.len() + body.len() / CHUNK_SIZE * 10 + 10);
+    for chunk in body.chunks(CHUNK_SIZE) {
+        out.extend_from_slice(format!("{:x}\r\n", chunk.len()).as_bytes());
+        out.extend_from_slice(chunk);
+        out.extend_from_slice(b"\r\n");
+    }
+    out
+}
+
+/// The response to a request that couldn't be parsed.
+fn parse_error(error: &http::Error) -> Vec<u8> {
+    closing(Response::text(error.status(), format!("{error}\n")))
+}
+
+/// The response when the pool has no room for another job.
+fn unavailable() -> Vec<u8> {
+    closing(busy())
+}
+
+/// A 503 response asking the client to try again shortly.
+fn busy() -> Response {
+    Response::text(503, "Server is busy, please try again later.\n").with_header("Retry-After", "1")
+}
+
+/// The response when the client took too long to send its request.
+fn request_timeout() -> Vec<u8> {
+    closing(Response::text(408, "Request Timeout\n"))
+}
+
+/// The response when the client has too many connections open.
+fn too_many_connections() -> Vec<u8> {
+    let response = Response::text(429, "Too many connections, please try again later.\n")
+        .with_header("Retry-After", "1");
+    closing(response)
+}
+
+/// Serialize a response after which the server closes the connection.
+fn closing(mut response: Response) -> Vec<u8> {
+    let length = response.body.len();
+    response
+        .headers
+        .insert("Content-Length", length.to_string());
+    response.headers.insert("Connection", "close");
+    response.to_bytes()
+}
@@ -0,0 +1,83 @@
+//! Counting the open connections of each client, to cap them.
+
+use std::{
+    collections::HashMap,
+    net::IpAddr,
+    sync::{Arc, Mutex},
+};
+
+use crate::metrics::Gauge;
+
+/// The open connections of each client address.
+#[derive(Debug)]
+pub(super) struct PeerCounts {
+    max: usize,
+    counts: Mutex<HashMap<IpAddr, usize>>,
+}
+
+impl PeerCounts {
+    pub(super) fn new(max: usize) -> PeerCounts {
+        PeerCounts {
+            max,
+            counts: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Count a new connection from `ip`, or return `None` if it has as many
+    /// open as it may.
+    pub(super) fn acquire(self: &Arc<PeerCounts>, ip: IpAddr) -> Option<PeerSlot> {
+        let mut counts = self.counts.lock().unwrap();
+        let count = counts.entry(ip).or_insert(0);
+        if *count >= self.max {
+            return None;
+        }
+        *count += 1;
+        Some(PeerSlot {
+            counted: Some((Arc::clone(self), ip)),
+            open: None,
+        })
+    }
+}
+
+/// A connection's place among those of its client, and in the gauge of open
+/// connections, given back when it's dropped.
+#[derive(Debug)]
+pub(super) struct PeerSlot {
+    counted: Option<(Arc<PeerCounts>, IpAddr)>,
+    open: Option<Gauge>,
+}
+
+impl PeerSlot {
+    /// A slot for a connection that isn't counted.
+    pub(super) fn uncounted() -> PeerSlot {
+        PeerSlot {
+            counted: None,
+            open: None,
+        }
+    }
+
+    /// Count the connection in `open` too, while the slot lives.
+    pub(super) fn gauged(mut self, open: &Gauge) -> PeerSlot {
+        open.inc();
+        self.open = Some(open.clone());
+        self
+    }
+}
+
+impl Drop for PeerSlot {
+    fn drop(&mut self) {
+        if let Some(open) = &self.open {
+            open.dec();
+        }
+        let Some((peers, ip)) = &self.counted else {
+            return;
+        };
+        let mut counts = peers.counts.lock().unwrap();
+        if let Some(count) = counts.get_mut(ip) {
+            *count -= 1;
+            if *count == 0 {
+                counts.remove(ip);
+            }
+        }
+    }
+}
@@ -0,0 +1,215 @@
+//! Just enough of the Linux `epoll` and `eventfd` APIs for the event loop,
+//! of `fcntl` for socket activation and of `umask` for Unix sockets,
+//! declared by hand instead of pulling in the `libc` crate.
+
+use std::{
+    fs::File,
+    io::{self, Read, Write},
+    os::fd::{AsRawFd, FromRawFd, OwnedFd, RawFd},
+    time::Duration,
+};
+
+use std::ffi::{c_int, c_uint};
+
+const EPOLL_CLOEXEC: c_int = 0o2000000;
+const EPOLL_CTL_ADD: c_int = 1;
+const EPOLL_CTL_DEL: c_int = 2;
+const EPOLL_CTL_MOD: c_int = 3;
+
+pub const EPOLLIN: u32 = 0x001;
+pub const EPOLLOUT: u32 = 0x004;
+pub const EPOLLERR: u32 = 0x008;
+pub const EPOLLHUP: u32 = 0x010;
+pub const EPOLLRDHUP: u32 = 0x2000;
+
+const EFD_CLOEXEC: c_int = 0o2000000;
+const EFD_NONBLOCK: c_int = 0o4000;
+
+const F_GETFD: c_int = 1;
+const F_SETFD: c_int = 2;
+const FD_CLOEXEC: c_int = 1;
+
+/// `struct epoll_event`, which the kernel packs on x86-64.
+#[repr(C)]
+#[cfg_attr(target_arch = "x86_64", repr(packed))]
+#[derive(Clone, Copy)]
+struct EpollEvent {
+    events: u32,
+    data: u64,
+}
+
+extern "C" {
+    fn epoll_create1(flags: c_int) -> c_int;
+    fn epoll_ctl(epfd: c_int, op: c_int, fd: c_int, event: *mut EpollEvent) -> c_int;
+    fn epoll_wait(epfd: c_int, events: *mut EpollEvent, maxevents: c_int, timeout: c_int) -> c_int;
+    fn eventfd(initval: c_uint, flags: c_int) -> c_int;
+    fn fcntl(fd: c_int, cmd: c_int, ...) -> c_int;
+    fn umask(mask: c_uint) -> c_uint;
+}
+
+fn check(ret: c_int) -> io::Result<c_int> {
+    if ret < 0 {
+        Err(io::Error::last_os_error())
+    } else {
+        Ok(ret)
+    }
+}
+
+/// Keep `fd` from being inherited by child processes, like descriptors
+/// opened by the standard library are.
+pub fn set_cloexec(fd: RawFd) -> io::Result<()> {
+    // SAFETY: fcntl has no memory safety requirements; a bad descriptor
+    // makes it fail.
+    let flags = check(unsafe { fcntl(fd, F_GETFD) })?;
+    check(unsafe { fcntl(fd, F_SETFD, flags | FD_CLOEXEC) })?;
+    Ok(())
+}
+
+/// The process's umask set to `mask` until it's dropped, when the previous
+/// one is back. It's the whole process's, so this is for startup.
+pub struct Umask(c_uint);
+
+impl Umask {
+    pub fn set(mask: u32) -> Umask {
+        // SAFETY: umask can't fail and touches no memory.
+        Umask(unsafe { umask(mask) })
+    }
+}
+
+impl Drop for Umask {
+    fn drop(&mut self) {
+        // SAFETY: as above.
+        unsafe { umask(self.0) };
+    }
+}
+
+/// A readiness event: the token a file descriptor was registered with and
+/// the `EPOLL*` flags that are set.
+#[derive(Debug, Clone, Copy)]
+pub struct Event {
+    pub token: u64,
+    pub events: u32,
+}
+
+impl Event {
+    pub fn is_readable(&self) -> bool {
+        self.events & (EPOLLIN | EPOLLHUP | EPOLLRDHUP | EPOLLERR) != 0
+    }
+
+    pub fn is_writable(&self) -> bool {
+        self.events & (EPOLLOUT | EPOLLHUP | EPOLLERR) != 0
+    }
+}
+
+pub struct Epoll {
+    fd: OwnedFd,
+    buffer: Vec<EpollEvent>,
+}
+
+impl Epoll {
+    pub fn new() -> io::Result<Epoll> {
+        // SAFETY: epoll_create1 has no memory safety requirements, and the
+        // returned descriptor is owned by nobody else.
+        let fd = unsafe { OwnedFd::from_raw_fd(check(epoll_create1(EPOLL_CLOEXEC))?) };
+        Ok(Epoll {
+            fd,
+            buffer: vec![EpollEvent { events: 0, data: 0 }; 256],
+        })
+    }
+
+    fn ctl(&self, op: c_int, fd: RawFd, token: u64, events: u32) -> io::Result<()> {
+        let mut event = EpollEvent {
+            events,
+            data: token,
+        };
+        // SAFETY: `event` is a valid epoll_event for the duration of the call.
+        check(unsafe { epoll_ctl(self.fd.as_raw_fd(), op, fd, &mut event) })?;
+        Ok(())
+    }
+
+    /// Start watching `fd` for `events`, reporting them with `token`.
+    pub fn add(&self, fd: &impl AsRawFd, token: u64, events: u32) -> io::Result<()> {
+        self.ctl(EPOLL_CTL_ADD, fd.as_raw_fd(), token, events)
+    }
+
+    pub fn modify(&self, fd: &impl AsRawFd, token: u64, events: u32) -> io::Result<()> {
+        self.ctl(EPOLL_CTL_MOD, fd.as_raw_fd(), token, events)
+    }
+
+    pub fn delete(&self, fd: &impl AsRawFd) -> io::Result<()> {
+        self.ctl(EPOLL_CTL_DEL, fd.as_raw_fd(), 0, 0)
+    }
+
+    /// Wait for events, up to `timeout` or forever if `None`, and replace the
+    /// contents of `events` with them.
+    pub fn wait(&mut self, events: &mut Vec<Event>, timeout: Option<Duration>) -> io::Result<()> {
+        let timeout = match timeout {
+            // Round up, so that we don't wake up just before the deadline.
+            Some(timeout) => timeout
+                .as_nanos()
+                .div_ceil(1_000_000)
+                .min(c_int::MAX as u128) as c_int,
+            None => -1,
+        };
+
+        events.clear();
+        // SAFETY: the buffer holds `buffer.len()` initialized epoll_events.
+        let n = unsafe {
+            epoll_wait(
+                self.fd.as_raw_fd(),
+                self.buffer.as_mut_ptr(),
+                self.buffer.len() as c_int,
+                timeout,
+            )
+        };
+        let n = match check(n) {
+            Ok(n) => n as usize,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => 0,
+            Err(e) => return Err(e),
+        };
+
+        events.extend(self.buffer[..n].iter().map(|event| Event {
+            token: event.data,
+            events: event.events,
+        }));
+        Ok(())
+    }
+}
+
+/// An `eventfd` used to wake up a thread blocked in `Epoll::wait`.
+pub struct Waker {
+    file: File,
+}
+
+impl Waker {
+    pub fn new() -> io::Result<Waker> {
+        // SAFETY: eventfd has no memory safety requirements, and the
+        // returned descriptor is owned by nobody else.
+        let fd = check(unsafe { eventfd(0, EFD_CLOEXEC | EFD_NONBLOCK) })?;
+        let file = unsafe { File::from_raw_fd(fd) };
+        Ok(Waker { file })
+    }
+
+    pub fn wake(&self) -> io::Result<()> {
+        match (&self.file).write(&1u64.to_ne_bytes()) {
+            // The counter is about to overflow, so a wake-up is pending anyway.
+            Err(e) if e.kind() == io::ErrorKind::WouldBlock => Ok(()),
+            result => result.map(|_| ()),
+        }
+    }
+
+    /// Reset the counter after a wake-up.
+    pub fn drain(&self) -> io::Result<()> {
+        let mut buf = [0; 8];
+        match (&self.file).read(&mut buf) {
+            Err(e) if e.kind() == io::ErrorKind::WouldBlock => Ok(()),
+            result => result.map(|_| ()),
+        }
+    }
+}
+
+impl AsRawFd for Waker {
+    fn as_raw_fd(&self) -> RawFd {
+        self.file.as_raw_fd()
+    }
+}
@@ -0,0 +1,225 @@
+use std::{
+    io::{self, Read, Write},
+    net::IpAddr,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use super::{
+    hand_over, http2, parse_error, request_timeout, too_many_connections, unavailable, Listener,
+    PeerSlot, Server, Service, Stream, Timeouts,
+};
+use crate::{
+    http::{RequestParser, Upgrade},
+    http2::{preface, Preface},
+};
+
+pub(super) fn serve(server: &Server, listener: Listener) -> io::Result<()> {
+    loop {
+        let (stream, peer) = match listener.accept() {
+            Ok(accepted) => accepted,
+            Err(e) => {
+                eprintln!("Failed to accept a connection: {e}");
+                continue;
+            }
+        };
+
+        if let Err(e) = stream
+            .set_read_timeout(server.service.timeouts.read)
+            .and_then(|()| stream.set_write_timeout(server.service.timeouts.write))
+        {
+            eprintln!("Failed to set the connection's timeouts: {e}");
+            continue;
+        }
+
+        let Some(slot) = server.admit(peer) else {
+            let _ = (&stream).write_all(&too_many_connections());
+            continue;
+        };
+
+        // Keep a handle to the socket so that we can still answer the client
+        // if the pool refuses the job (and drops the stream along with it).
+        let mut overloaded = match stream.try_clone() {
+            Ok(overloaded) => overloaded,
+            Err(e) => {
+                eprintln!("Failed to clone a connection: {e}");
+                continue;
+            }
+        };
+
+        let service = Arc::clone(&server.service);
+        let result = server.pool.execute(move || {
+            handle_connection(stream, &service, slot);
+        });
+
+        if result.is_err() {
+            let _ = overloaded.write_all(&unavailable());
+        }
+    }
+}
+
+/// A client connection as far as answering it goes: a socket, or any other
+/// stream, like an in-memory one in tests.
+pub(super) trait Connection: Read + Write + Sized {
+    /// Bound the next reads; `None` lets them wait forever.
+    fn set_read_timeout(&mut self, timeout: Option<Duration>) -> io::Result<()>;
+
+    fn peer(&self) -> Option<IpAddr>;
+
+    /// Give the connection to the protocol it's upgraded to.
+    fn hand_over(
+        self,
+        upgrade: Upgrade,
+        buffered: Vec<u8>,
+        timeouts: Timeouts,
+        slot: PeerSlot,
+    ) -> io::Result<()>;
+}
+
+impl Connection for Stream {
+    fn set_read_timeout(&mut self, timeout: Option<Duration>) -> io::Result<()> {
+        Stream::set_read_timeout(self, timeout)
+    }
+
+    fn peer(&self) -> Option<IpAddr> {
+        self.peer_addr().ok().map(|addr| addr.ip())
+    }
+
+    fn hand_over(
+        self,
+        upgrade: Upgrade,
+        buffered: Vec<u8>,
+        timeouts: Timeouts,
+        slot: PeerSlot,
+    ) -> io::Result<()> {
+        hand_over(upgrade, self, buffered, timeouts, slot)
+    }
+}
+
+/// A stream that isn't a socket: it has no timeouts, no peer and can't be
+/// upgraded.
+pub(super) struct Plain<S>(pub(super) S);
+
+impl<S: Read> Read for Plain<S> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.0.read(buf)
+    }
+}
+
+impl<S: Write> Write for Plain<S> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.0.flush()
+    }
+}
+
+impl<S: Read + Write> Connection for Plain<S> {
+    fn set_read_timeout(&mut self, _: Option<Duration>) -> io::Result<()> {
+        Ok(())
+    }
+
+    fn peer(&self) -> Option<IpAddr> {
+        None
+    }
+
+    fn hand_over(self, _: Upgrade, _: Vec<u8>, _: Timeouts, _: PeerSlot) -> io::Result<()> {
+        Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "only sockets can be upgraded",
+        ))
+    }
+}
+
+/// Read one request, answer it and close the connection, unless the
+/// response hands it over to another protocol. Clients that open with the
+/// HTTP/2 preface are handed over to HTTP/2 right away.
+pub(super) fn handle_connection<C: Connection>(
+    mut stream: C,
+    service: &Arc<Service>,
+    slot: PeerSlot,
+) {
+    let mut buf = Vec::new();
+    let mut parser = RequestParser::default();
+    let mut chunk = [0; 4096];
+    let timeouts = service.timeouts;
+    let header_deadline = timeouts.header.map(|timeout| Instant::now() + timeout);
+
+    let (request, len) = loop {
+        match preface(&buf) {
+            Preface::Complete => {
+                let upgrade = http2::upgrade(Arc::clone(service), None);
+                if let Err(e) = stream.hand_over(upgrade, buf, timeouts, slot) {
+                    eprintln!("Failed to hand over an HTTP/2 connection: {e}");
+                }
+                return;
+            }
+            Preface::Partial => {}
+            Preface::Absent => match parser.parse(&buf, &service.limits) {
+                Ok(Some(parsed)) => break parsed,
+                Ok(None) => {}
+                Err(e) => {
+                    let _ = stream.write_all(&parse_error(&e));
+                    return;
+                }
+            },
+        }
+
+        // The header deadline holds however steadily the head trickles in;
+        // the read timeout only bounds each wait.
+        let mut timeout = timeouts.read;
+        if let Some(deadline) = header_deadline.filter(|_| !parser.has_head()) {
+            let left = deadline.saturating_duration_since(Instant::now());
+            if left.is_zero() {
+                let _ = stream.write_all(&request_timeout());
+                return;
+            }
+            timeout = Some(timeout.map_or(left, |timeout| timeout.min(left)));
+        }
+        if stream.set_read_timeout(timeout).is_err() {
+            return;
+        }
+
+        match stream.read(&mut chunk) {
+            Ok(0) => return,
+            Ok(n) => buf.extend_from_slice(&chunk[..n]),
+            // A client that sent part of a request learns why it's cut off.
+            Err(e) if is_timeout(&e) && !buf.is_empty() => {
+                let _ = stream.write_all(&request_timeout());
+                return;
+            }
+            Err(_) => return,
+        }
+    };
+
+    let mut request = request;
+    request.peer = stream.peer();
+    let reply = service.respond(&request, Instant::now(), false);
+
+    // The client may already be gone, there's nobody to report errors to.
+    if stream.write_all(&reply.bytes).is_err() {
+        return;
+    }
+    if let Some(mut body) = reply.stream {
+        while let Ok((bytes, done)) = body.next_chunk() {
+            if stream.write_all(&bytes).is_err() || done {
+                break;
+            }
+        }
+    }
+    if let Some(upgrade) = reply.upgrade {
+        buf.drain(..len);
+        if let Err(e) = stream.hand_over(upgrade, buf, timeouts, slot) {
+            eprintln!("Failed to hand over an upgraded connection: {e}");
+        }
+    }
+}
+
+fn is_timeout(e: &io::Error) -> bool {
+    matches!(
+        e.kind(),
+        io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut
+    )
+}
@@ -0,0 +1,245 @@
+//! Server-sent events: a `text/event-stream` response that stays open and
+//! carries events as they happen, which browsers read with `EventSource`.
+//!
+//! Each open stream costs the server an OS thread, which waits for events
+//! on its channel, whatever the backend; the server caps them with
+//! `Builder::max_long_lived_streams`.
+//!
+//! ```no_run
+//! use echo::{sse::{Event, EventStream}, Server};
+//! use std::{net::TcpListener, sync::mpsc, thread, time::Duration};
+//!
+//! let server = Server::new(EventStream::new(|_request, last_id| {
+//!     // A client that reconnects carries on after the last event it got.
+//!     let start = last_id.and_then(|id| id.parse::<u64>().ok()).map_or(0, |id| id + 1);
+//!     let (events, receiver) = mpsc::channel();
+//!     thread::spawn(move || {
+//!         for n in start.. {
+//!             let event = Event::new(format!("tick {n}")).id(n.to_string());
+//!             // Sending fails once the client is gone.
+//!             if events.send(event).is_err() {
+//!                 break;
+//!             }
+//!             thread::sleep(Duration::from_secs(1));
+//!         }
+//!     });
+//!     receiver
+//! }));
+//! server.serve(TcpListener::bind("127.0.0.1:7878").unwrap()).unwrap();
+//! ```
+
+use std::{
+    io::{self, Read},
+    sync::mpsc::{Receiver, RecvTimeoutError},
+    time::Duration,
+};
+
+use crate::{
+    http::{BodyStream, Request, Response},
+    server::Handler,
+};
+
+/// How long a stream stays quiet before a heartbeat comment, by default.
+pub const DEFAULT_HEARTBEAT: Duration = Duration::from_secs(15);
+
+/// A comment, which clients ignore, sent to show the stream is alive.
+const HEARTBEAT: &[u8] = b": heartbeat\n\n";
+
+/// One event of a stream.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Event {
+    /// The event's type, `message` unless given.
+    pub event: Option<String>,
+    pub data: String,
+    /// What the client sends back as `Last-Event-ID` when it reconnects.
+    pub id: Option<String>,
+    /// How long the client waits before reconnecting.
+    pub retry: Option<Duration>,
+}
+
+impl Event {
+    pub fn new(data: impl Into<String>) -> Event {
+        Event {
+            data: data.into(),
+            ..Event::default()
+        }
+    }
+
+    pub fn event(mut self, event: impl Into<String>) -> Event {
+        self.event = Some(event.into());
+        self
+    }
+
+    pub fn id(mut self, id: impl Into<String>) -> Event {
+        self.id = Some(id.into());
+        self
+    }
+
+    pub fn retry(mut self, retry: Duration) -> Event {
+        self.retry = Some(retry);
+        self
+    }
+
+    /// The event in the `text/event-stream` format. Line breaks in the data
+    /// span several `data:` lines, and are dropped from the other fields,
+    /// which hold a single line.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let single_line = |value: &str| value.replace(['\r', '\n'], "");
+
+        let mut out = String::new();
+        if let Some(event) = &self.event {
+            out.push_str(&format!("event: {}\n", single_line(event)));
+        }
+        if let Some(id) = &self.id {
+            // Clients ignore ids holding NUL.
+            out.push_str(&format!("id: {}\n", single_line(id).replace('\0', "")));
+        }
+        if let Some(retry) = self.retry {
+            out.push_str(&format!("retry: {}\n", retry.as_millis()));
+        }
+        let data = self.data.replace("\r\n", "\n").replace('\r', "\n");
+        for line in data.split('\n') {
+            out.push_str(&format!("data: {line}\n"));
+        }
+        out.push('\n');
+        out.into_bytes()
+    }
+}
+
+/// The `Last-Event-ID` a reconnecting client sent: the id of the last event
+/// it got.
+pub fn last_event_id(request: &Request) -> Option<&str> {
+    request.header("Last-Event-ID")
+}
+
+/// A `text/event-stream` response sending the events from `events` as they
+/// come, with a heartbeat comment whenever none came for `heartbeat`. The
+/// response ends once every sender is dropped, and the receiver is dropped
+/// once the client is gone.
+///
+/// Waiting for events doesn't hold up a worker of the server's pool: the
+/// stream is sent from a thread of its own, one per client.
+pub fn response(events: Receiver<Event>, heartbeat: Duration) -> Response {
+    let mut response = Response::new(200)
+        .with_header("Content-Type", "text/event-stream")
+        .with_header("Cache-Control", "no-cache");
+    response.stream = Some(BodyStream::long_lived(Events {
+        events,
+        heartbeat,
+        pending: Vec::new(),
+        pos: 0,
+    }));
+    response
+}
+
+/// A handler answering `GET` requests with a stream of events.
+///
+/// `on_connect` gets each request along with the `Last-Event-ID` of a
+/// reconnecting client, and returns the channel its events come from.
+pub struct EventStream<F> {
+    on_connect: F,
+    heartbeat: Duration,
+}
+
+impl<F> EventStream<F>
+where
+    F: Fn(&Request, Option<&str>) -> Receiver<Event> + Send + Sync + 'static,
+{
+    pub fn new(on_connect: F) -> EventStream<F> {
+        EventStream {
+            on_connect,
+            heartbeat: DEFAULT_HEARTBEAT,
+        }
+    }
+
+    /// Send a heartbeat comment after `interval` without events, so that
+    /// proxies keep the connection open and clients that are gone are
+    /// noticed. Defaults to `DEFAULT_HEARTBEAT`.
+    pub fn heartbeat(mut self, interval: Duration) -> EventStream<F> {
+        self.heartbeat = interval;
+        self
+    }
+}
+
+impl<F> Handler for EventStream<F>
+where
+    F: Fn(&Request, Option<&str>) -> Receiver<Event> + Send + Sync + 'static,
+{
+    fn handle(&self, request: &Request) -> Response {
+        if request.method != "GET" {
+            return Response::text(405, "Method Not Allowed\n").with_header("Allow", "GET");
+        }
+        let events = (self.on_connect)(request, last_event_id(request));
+        response(events, self.heartbeat)
+    }
+}
+
+/// The body of an event stream, read as the events come.
+struct Events {
+    events: Receiver<Event>,
+    heartbeat: Duration,
+    /// What's left to read of the last event.
+    pending: Vec<u8>,
+    pos: usize,
+}
+
+impl Read for Events {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.pos == self.pending.len() {
+            self.pending = match self.events.recv_timeout(self.heartbeat) {
+                Ok(event) => event.to_bytes(),
+                Err(RecvTimeoutError::Timeout) => HEARTBEAT.to_vec(),
+                Err(RecvTimeoutError::Disconnected) => return Ok(0),
+            };
+            self.pos = 0;
+        }
+        let n = (&self.pending[self.pos..]).read(buf)?;
+        self.pos += n;
+        Ok(n)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::mpsc;
+
+    #[test]
+    fn encodes_events() {
+        assert_eq!(Event::new("hello").to_bytes(), b"data: hello\n\n");
+        assert_eq!(Event::new("").to_bytes(), b"data: \n\n");
+
+        let event = Event::new("one\ntwo\r\nthree")
+            .event("update")
+            .id("7\n")
+            .retry(Duration::from_secs(3));
+        assert_eq!(
+            String::from_utf8(event.to_bytes()).unwrap(),
+            "event: update\nid: 7\nretry: 3000\ndata: one\ndata: two\ndata: three\n\n"
+        );
+    }
+
+    #[test]
+    fn reads_events_and_heartbeats() {
+        let (events, receiver) = mpsc::channel();
+        let response = response(receiver, Duration::from_millis(10));
+        assert_eq!(
+            response.headers.get("Content-Type"),
+            Some("text/event-stream")
+        );
+        let stream = response.stream.unwrap();
+        assert!(stream.is_long_lived());
+        let mut reader = stream.take().unwrap();
+
+        events.send(Event::new("first").id("1")).unwrap();
+        let mut buf = [0; 64];
+        let n = reader.read(&mut buf).unwrap();
+        assert_eq!(&buf[..n], b"id: 1\ndata: first\n\n");
+
+        let n = reader.read(&mut buf).unwrap();
+        assert_eq!(&buf[..n], HEARTBEAT);
+
+        drop(events);
+        assert_eq!(reader.read(&mut buf).unwrap(), 0);
+    }
+}
@@ -0,0 +1,664 @@
+//! HTML templates, loaded from a directory, compiled once and reloaded when
+//! their file changes.
+//!
+//! ```text
+//! {% extends "layout.html" %}
+//! {% block content %}
+//!   <h1>Hello, {{ user.name }}!</h1>
+//!   {% if items %}
+//!     <ul>{% for item in items %}<li>{{ loop.index }}. {{ item }}</li>{% endfor %}</ul>
+//!   {% else %}
+//!     {% include "empty.html" %}
+//!   {% endif %}
+//! {% endblock %}
+//! ```
+//!
+//! Output is HTML-escaped unless its last filter is `raw`. The other filters
+//! are `upper`, `lower` and `length`. Conditions can use `and`, `or`, `not`,
+//! `==` and `!=`. Variables that aren't defined are empty.
+
+mod parse;
+
+use std::{
+    collections::{BTreeMap, HashMap},
+    error, fmt, fs, io,
+    path::{Component, Path, PathBuf},
+    sync::{Arc, Mutex},
+    time::SystemTime,
+};
+
+use crate::http::{Request, Response};
+use parse::{Expr, Filter, Node, SyntaxError, Template};
+
+/// How deep includes and layouts may nest, which catches cycles.
+const MAX_DEPTH: usize = 16;
+
+/// A value a template can show.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub enum Value {
+    #[default]
+    Null,
+    Bool(bool),
+    Int(i64),
+    Str(String),
+    List(Vec<Value>),
+    Map(BTreeMap<String, Value>),
+}
+
+impl Value {
+    /// Whether the value counts as true in a condition: anything but null,
+    /// `false`, zero and empty strings, lists and maps.
+    pub fn is_truthy(&self) -> bool {
+        match self {
+            Value::Null => false,
+            Value::Bool(b) => *b,
+            Value::Int(n) => *n != 0,
+            Value::Str(s) => !s.is_empty(),
+            Value::List(list) => !list.is_empty(),
+            Value::Map(map) => !map.is_empty(),
+        }
+    }
+
+    /// The field or element called `key`.
+    fn get(&self, key: &str) -> Option<&Value> {
+        match self {
+            Value::Map(map) => map.get(key),
+            Value::List(list) => list.get(key.parse::<usize>().ok()?),
+            _ => None,
+        }
+    }
+}
+
+impl From<&str> for Value {
+    fn from(s: &str) -> Value {
+        Value::Str(s.to_string())
+    }
+}
+
+impl From<String> for Value {
+    fn from(s: String) -> Value {
+        Value::Str(s)
+    }
+}
+
+impl From<bool> for Value {
+    fn from(b: bool) -> Value {
+        Value::Bool(b)
+    }
+}
+
+impl From<i64> for Value {
+    fn from(n: i64) -> Value {
+        Value::Int(n)
+    }
+}
+
+impl From<u16> for Value {
+    fn from(n: u16) -> Value {
+        Value::Int(n.into())
+    }
+}
+
+impl From<usize> for Value {
+    fn from(n: usize) -> Value {
+        Value::Int(i64::try_from(n).unwrap_or(i64::MAX))
+    }
+}
+
+impl<T: Into<Value>> From<Option<T>> for Value {
+    fn from(value: Option<T>) -> Value {
+        value.map_or(Value::Null, Into::into)
+    }
+}
+
+impl<T: Into<Value>> From<Vec<T>> for Value {
+    fn from(values: Vec<T>) -> Value {
+        Value::List(values.into_iter().map(Into::into).collect())
+    }
+}
+
+/// A map from `(key, value)` pairs.
+impl<K: Into<String>, V: Into<Value>> FromIterator<(K, V)> for Value {
+    fn from_iter<I: IntoIterator<Item = (K, V)>>(pairs: I) -> Value {
+        Value::Map(
+            pairs
+                .into_iter()
+                .map(|(key, value)| (key.into(), value.into()))
+                .collect(),
+        )
+    }
+}
+
+/// The variables a template is rendered with.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Context {
+    values: BTreeMap<String, Value>,
+}
+
+impl Context {
+    pub fn new() -> Context {
+        Context::default()
+    }
+
+    /// The context for a page about `request`, with `request.method`,
+    /// `request.path`, `request.query` and `request.host`.
+    pub fn for_request(request: &Request) -> Context {
+        let request: Value = [
+            ("method", Value::from(request.method.as_str())),
+            ("path", request.path().into()),
+            ("query", request.query().into()),
+            ("host", request.header("Host").into()),
+        ]
+        .into_iter()
+        .collect();
+        Context::new().with("request", request)
+    }
+
+    pub fn with(mut self, name: impl Into<String>, value: impl Into<Value>) -> Context {
+        self.insert(name, value);
+        self
+    }
+
+    pub fn insert(&mut self, name: impl Into<String>, value: impl Into<Value>) {
+        self.values.insert(name.into(), value.into());
+    }
+}
+
+/// Why a template couldn't be rendered.
+#[derive(Debug)]
+pub enum Error {
+    /// The template file couldn't be read.
+    Io { name: String, error: io::Error },
+    /// The template is malformed.
+    Syntax {
+        name: String,
+        line: usize,
+        message: String,
+    },
+    /// The template doesn't fit its context, e.g. it loops over a string.
+    Render {
+        name: String,
+        line: usize,
+        message: String,
+    },
+    /// A template name that isn't a relative path inside the directory.
+    InvalidName(String),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Io { name, error } => write!(f, "{name}: {error}"),
+            Error::Syntax {
+                name,
+                line,
+                message,
+            }
+            | Error::Render {
+                name,
+                line,
+                message,
+            } => write!(f, "{name}, line {line}: {message}"),
+            Error::InvalidName(name) => write!(f, "invalid template name `{name}`"),
+        }
+    }
+}
+
+impl error::Error for Error {}
+
+struct Cached {
+    template: Arc<Template>,
+    modified: Option<SystemTime>,
+}
+
+/// The templates in a directory, named by their path in it, like
+/// `errors/404.html`.
+pub struct Templates {
+    dir: PathBuf,
+    reload: bool,
+    cache: Mutex<HashMap<String, Cached>>,
+}
+
+impl Templates {
+    pub fn new(dir: impl Into<PathBuf>) -> Templates {
+        Templates {
+            dir: dir.into(),
+            reload: true,
+            cache: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Whether to check if a template's file changed before each use, and
+    /// compile it again if so. On by default; turning it off saves a
+    /// `stat` per template and render.
+    pub fn reload(mut self, reload: bool) -> Templates {
+        self.reload = reload;
+        self
+    }
+
+    /// Render the template called `name`.
+    ///
+    /// # Errors
+    ///
+    /// If the template, or one it includes or extends, can't be read or
+    /// parsed, or doesn't fit `context`.
+    pub fn render(&self, name: &str, context: &Context) -> Result<String, Error> {
+        let mut renderer = Renderer {
+            templates: self,
+            scopes: vec![context.values.clone()],
+            out: String::new(),
+        };
+        renderer.template(name, 0)?;
+        Ok(renderer.out)
+    }
+
+    /// An HTML response rendered from the template called `name`, or a bare
+    /// 500 response if it fails to render.
+    pub fn response(&self, status: u16, name: &str, context: &Context) -> Response {
+        match self.render(name, context) {
+            Ok(page) => Response::html(status, page),
+            Err(e) => {
+                eprintln!("Failed to render {name}: {e}");
+                Response::text(500, "Internal Server Error\n")
+            }
+        }
+    }
+
+    /// Whether there's a template called `name`.
+    pub fn exists(&self, name: &str) -> bool {
+        self.path(name).is_ok_and(|path| path.is_file())
+    }
+
+    fn path(&self, name: &str) -> Result<PathBuf, Error> {
+        let relative = Path::new(name);
+        let normal = relative
+            .components()
+            .all(|component| matches!(component, Component::Normal(_)));
+        if name.is_empty() || !normal {
+            return Err(Error::InvalidName(name.to_string()));
+        }
+        Ok(self.dir.join(relative))
+    }
+
+    /// The compiled template called `name`, from the cache unless its file
+    /// changed.
+    fn load(&self, name: &str) -> Result<Arc<Template>, Error> {
+        let path = self.path(name)?;
+        let io_error = |error| Error::Io {
+            name: name.to_string(),
+            error,
+        };
+
+        // The lock is only held to look up and to insert, so that a slow
+        // disk or a big template doesn't hold up every other render.
+        let cached = self
+            .cache
+            .lock()
+            .unwrap()
+            .get(name)
+            .map(|cached| (Arc::clone(&cached.template), cached.modified));
+        let modified = match (cached, self.reload) {
+            (Some((template, _)), false) => return Ok(template),
+            (cached, true) => {
+                let modified = fs::metadata(&path).map_err(io_error)?.modified().ok();
+                if let Some((template, _)) =
+                    cached.filter(|(_, cached)| *cached == modified && modified.is_some())
+                {
+                    return Ok(template);
+                }
+                modified
+            }
+            (None, false) => None,
+        };
+
+        let source = fs::read_to_string(&path).map_err(io_error)?;
+        let template =
+            parse::parse(&source).map_err(|SyntaxError { line, message }| Error::Syntax {
+                name: name.to_string(),
+                line,
+                message,
+            })?;
+        let template = Arc::new(template);
+        self.cache.lock().unwrap().insert(
+            name.to_string(),
+            Cached {
+                template: Arc::clone(&template),
+                modified,
+            },
+        );
+        Ok(template)
+    }
+}
+
+struct Renderer<'a> {
+    templates: &'a Templates,
+    /// The variables, innermost loop last.
+    scopes: Vec<BTreeMap<String, Value>>,
+    out: String,
+}
+
+/// A template being rendered: its name for errors, and its layouts, the
+/// template itself first, whose blocks override those of the layouts.
+struct Chain<'a> {
+    name: &'a str,
+    templates: &'a [Arc<Template>],
+}
+
+impl Renderer<'_> {
+    fn template(&mut self, name: &str, depth: usize) -> Result<(), Error> {
+        let mut templates = vec![self.templates.load(name)?];
+        let mut root = name.to_string();
+        while let Some(layout) = templates.last().unwrap().extends.clone() {
+            if templates.len() + depth > MAX_DEPTH {
+                return Err(too_deep(name, 0));
+            }
+            templates.push(self.templates.load(&layout)?);
+            root = layout;
+        }
+
+        let chain = Chain {
+            name: &root,
+            templates: &templates,
+        };
+        let nodes = &templates.last().unwrap().nodes;
+        self.nodes(nodes, &chain, depth + templates.len())
+    }
+
+    fn nodes(&mut self, nodes: &[Node], chain: &Chain, depth: usize) -> Result<(), Error> {
+        for node in nodes {
+            self.node(node, chain, depth)?;
+        }
+        Ok(())
+    }
+
+    fn node(&mut self, node: &Node, chain: &Chain, depth: usize) -> Result<(), Error> {
+        match node {
+            Node::Text(text) => self.out.push_str(text),
+            Node::Output { expr, escape, line } => {
+                let value = self.eval(expr);
+                let text = match &value {
+                    Value::Null => String::new(),
+                    Value::Bool(b) => b.to_string(),
+                    Value::Int(n) => n.to_string(),
+                    Value::Str(s) => s.clone(),
+                    Value::List(_) | Value::Map(_) => {
+                        return Err(render_error(
+                            chain.name,
+                            *line,
+                            "can't show a list or a map",
+                        ))
+                    }
+                };
+                if *escape {
+                    escape_html(&text, &mut self.out);
+                } else {
+                    self.out.push_str(&text);
+                }
+            }
+            Node::If {
+                branches,
+                otherwise,
+            } => {
+                let body = branches
+                    .iter()
+                    .find(|(condition, _)| self.eval(condition).is_truthy())
+                    .map_or(otherwise, |(_, body)| body);
+                self.nodes(body, chain, depth)?;
+            }
+            Node::For {
+                name,
+                list,
+                body,
+                otherwise,
+                line,
+            } => {
+                let items = match self.eval(list) {
+                    Value::List(items) => items,
+                    Value::Null => Vec::new(),
+                    _ => return Err(render_error(chain.name, *line, "can only loop over a list")),
+                };
+                if items.is_empty() {
+                    return self.nodes(otherwise, chain, depth);
+                }
+
+                let length = items.len();
+                for (i, item) in items.into_iter().enumerate() {
+                    let info: Value = [
+                        ("index", Value::from(i + 1)),
+                        ("index0", i.into()),
+                        ("first", (i == 0).into()),
+                        ("last", (i + 1 == length).into()),
+                        ("length", length.into()),
+                    ]
+                    .into_iter()
+                    .collect();
+                    self.scopes.push(BTreeMap::from([
+                        (name.clone(), item),
+                        ("loop".to_string(), info),
+                    ]));
+                    let result = self.nodes(body, chain, depth);
+                    self.scopes.pop();
+                    result?;
+                }
+            }
+            Node::Include { name, line } => {
+                if depth >= MAX_DEPTH {
+                    return Err(too_deep(chain.name, *line));
+                }
+                self.template(name, depth + 1)?;
+            }
+            Node::Block { name, body } => {
+                // The most derived template that fills in the block wins.
+                let body = chain
+                    .templates
+                    .iter()
+                    .find_map(|template| template.blocks.get(name))
+                    .unwrap_or(body);
+                self.nodes(body, chain, depth)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn eval(&self, expr: &Expr) -> Value {
+        match expr {
+            Expr::Path(path) => self.lookup(path).cloned().unwrap_or_default(),
+            Expr::Str(s) => Value::Str(s.clone()),
+            Expr::Int(n) => Value::Int(*n),
+            Expr::Bool(b) => Value::Bool(*b),
+            Expr::Not(expr) => Value::Bool(!self.eval(expr).is_truthy()),
+            Expr::And(a, b) => Value::Bool(self.eval(a).is_truthy() && self.eval(b).is_truthy()),
+            Expr::Or(a, b) => Value::Bool(self.eval(a).is_truthy() || self.eval(b).is_truthy()),
+            Expr::Eq(a, b) => Value::Bool(self.eval(a) == self.eval(b)),
+            Expr::Ne(a, b) => Value::Bool(self.eval(a) != self.eval(b)),
+            Expr::Filter(expr, filter) => {
+                let value = self.eval(expr);
+                match (filter, value) {
+                    (Filter::Raw, value) => value,
+                    (Filter::Upper, Value::Str(s)) => Value::Str(s.to_uppercase()),
+                    (Filter::Lower, Value::Str(s)) => Value::Str(s.to_lowercase()),
+                    (Filter::Upper | Filter::Lower, value) => value,
+                    (Filter::Length, Value::Str(s)) => s.chars().count().into(),
+                    (Filter::Length, Value::List(list)) => list.len().into(),
+                    (Filter::Length, Value::Map(map)) => map.len().into(),
+                    (Filter::Length, _) => Value::Int(0),
+                }
+            }
+        }
+    }
+
+    fn lookup(&self, path: &[String]) -> Option<&Value> {
+        let (first, rest) = path.split_first()?;
+        let mut value = self
+            .scopes
+            .iter()
+            .rev()
+            .find_map(|scope| scope.get(first))?;
+        for key in rest {
+            value = value.get(key)?;
+        }
+        Some(value)
+    }
+}
+
+fn render_error(name: &str, line: usize, message: &str) -> Error {
+    Error::Render {
+        name: name.to_string(),
+        line,
+        message: message.to_string(),
+    }
+}
+
+fn too_deep(name: &str, line: usize) -> Error {
+    render_error(
+        name,
+        line,
+        "includes and layouts nest too deep, is there a cycle?",
+    )
+}
+
+/// Append `text` to `out`, with the characters that mean something in HTML
+/// replaced by entities.
+fn escape_html(text: &str, out: &mut String) {
+    for c in text.chars() {
+        match c {
+            '&' => out.push_str("&amp;"),
+            '<' => out.push_str("&lt;"),
+            '>' => out.push_str("&gt;"),
+            '"' => out.push_str("&quot;"),
+            '\'' => out.push_str("&#39;"),
+            c => out.push(c),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::TempDir;
+    use std::{thread, time::Duration};
+
+    struct Fixture {
+        dir: TempDir,
+        templates: Templates,
+    }
+
+    impl Fixture {
+        fn new(name: &str, files: &[(&str, &str)]) -> Fixture {
+            let dir = TempDir::new(&format!("templates-{name}"));
+            for (name, source) in files {
+                fs::write(dir.join(name), source).unwrap();
+            }
+            Fixture {
+                templates: Templates::new(dir.path()),
+                dir,
+            }
+        }
+    }
+
+    #[test]
+    fn interpolation_and_blocks() {
+        let fixture = Fixture::new(
+            "blocks",
+            &[(
+                "page.html",
+                "<h1>{{ title | upper }}</h1>{{ html }}|{{ html | raw }}|{{ missing }}\n\
+                 {%- if user.admin and not banned %} admin{% elif user %} user{% else %} guest{% endif %}\n\
+                 {%- for item in items %} {{ loop.index }}/{{ loop.length }}:{{ item.name }}\
+                 {%- if not loop.last %},{% endif %}{% else %} none{% endfor %}\n\
+                 {{- items.1.name }} {{ items | length }} {{ kind == 'a' }}",
+            )],
+        );
+        let items: Vec<Value> = ["x", "y"]
+            .into_iter()
+            .map(|name| [("name", name)].into_iter().collect())
+            .collect();
+        let context = Context::new()
+            .with("title", "Hi")
+            .with("html", "<b>&\"'</b>")
+            .with("user", [("admin", true)].into_iter().collect::<Value>())
+            .with("items", items)
+            .with("kind", "a");
+
+        assert_eq!(
+            fixture.templates.render("page.html", &context).unwrap(),
+            "<h1>HI</h1>&lt;b&gt;&amp;&quot;&#39;&lt;/b&gt;|<b>&\"'</b>| admin 1/2:x, 2/2:yy 2 true"
+        );
+
+        let context = Context::new()
+            .with("user", "someone")
+            .with("items", Vec::<Value>::new());
+        let page = fixture.templates.render("page.html", &context).unwrap();
+        assert!(page.ends_with(" user none 0 false"), "{page}");
+    }
+
+    #[test]
+    fn layouts_and_includes() {
+        let fixture = Fixture::new(
+            "layouts",
+            &[
+                (
+                    "base.html",
+                    "<title>{% block title %}Site{% endblock %}</title>{% include \"nav.html\" %}\
+                     <main>{% block content %}{% endblock %}</main>",
+                ),
+                ("nav.html", "<nav>{{ request.path }}</nav>"),
+                (
+                    "section.html",
+                    "{% extends \"base.html\" %}{% block content %}[{% block inner %}section{% endblock %}]{% endblock %}",
+                ),
+                (
+                    "page.html",
+                    "{% extends \"section.html\" %}ignored{% block title %}Page{% endblock %}\
+                     {% block inner %}{{ n }}{% endblock %}",
+                ),
+                ("loop.html", "{% include \"loop.html\" %}"),
+            ],
+        );
+        let context = Context::for_request(&Request::new("GET", "/a?b")).with("n", 1_i64);
+
+        assert_eq!(
+            fixture.templates.render("page.html", &context).unwrap(),
+            "<title>Page</title><nav>/a</nav><main>[1]</main>"
+        );
+        assert!(matches!(
+            fixture.templates.render("loop.html", &context),
+            Err(Error::Render { .. })
+        ));
+        assert!(matches!(
+            fixture.templates.render("../etc/passwd", &context),
+            Err(Error::InvalidName(_))
+        ));
+        assert!(matches!(
+            fixture.templates.render("missing.html", &context),
+            Err(Error::Io { .. })
+        ));
+    }
+
+    #[test]
+    fn reloads_changed_files() {
+        let fixture = Fixture::new("reload", &[("page.html", "one")]);
+        let context = Context::new();
+        assert_eq!(
+            fixture.templates.render("page.html", &context).unwrap(),
+            "one"
+        );
+
+        // Make sure the modification time changes, however coarse it is.
+        thread::sleep(Duration::from_millis(20));
+        let path = fixture.dir.join("page.html");
+        fs::write(&path, "two {{").unwrap();
+        let file = fs::File::options().write(true).open(&path).unwrap();
+        file.set_modified(SystemTime::now() + Duration::from_secs(1))
+            .unwrap();
+
+        let error = fixture.templates.render("page.html", &context).unwrap_err();
+        assert_eq!(error.to_string(), "page.html, line 1: `{{` is never closed");
+
+        let fixed = Templates::new(fixture.dir.path()).reload(false);
+        fs::write(&path, "three").unwrap();
+        assert_eq!(fixed.render("page.html", &context).unwrap(), "three");
+        fs::write(&path, "four").unwrap();
+        assert_eq!(fixed.render("page.html", &context).unwrap(), "three");
+    }
+}
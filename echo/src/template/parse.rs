@@ -0,0 +1,603 @@
+//! Turning template source into a tree of nodes.
+
+use std::collections::HashMap;
+
+/// A parsed template.
+#[derive(Debug)]
+pub(super) struct Template {
+    /// The layout this template fills in, from `{% extends "name" %}`.
+    pub extends: Option<String>,
+    pub nodes: Vec<Node>,
+    /// The contents of every block, by name.
+    pub blocks: HashMap<String, Vec<Node>>,
+}
+
+#[derive(Debug, Clone)]
+pub(super) enum Node {
+    Text(String),
+    /// `{{ expr }}`, escaped unless its last filter is `raw`.
+    Output {
+        expr: Expr,
+        escape: bool,
+        line: usize,
+    },
+    If {
+        branches: Vec<(Expr, Vec<Node>)>,
+        otherwise: Vec<Node>,
+    },
+    For {
+        name: String,
+        list: Expr,
+        body: Vec<Node>,
+        /// Rendered instead of the body if the list is empty.
+        otherwise: Vec<Node>,
+        line: usize,
+    },
+    Include {
+        name: String,
+        line: usize,
+    },
+    Block {
+        name: String,
+        body: Vec<Node>,
+    },
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub(super) enum Expr {
+    /// A variable, or a field of one: `user.name`, `items.0`.
+    Path(Vec<String>),
+    Str(String),
+    Int(i64),
+    Bool(bool),
+    Not(Box<Expr>),
+    And(Box<Expr>, Box<Expr>),
+    Or(Box<Expr>, Box<Expr>),
+    Eq(Box<Expr>, Box<Expr>),
+    Ne(Box<Expr>, Box<Expr>),
+    Filter(Box<Expr>, Filter),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(super) enum Filter {
+    /// Output the value as it is, without escaping it.
+    Raw,
+    Upper,
+    Lower,
+    Length,
+}
+
+/// A syntax error, on a line of the template.
+#[derive(Debug, PartialEq, Eq)]
+pub(super) struct SyntaxError {
+    pub line: usize,
+    pub message: String,
+}
+
+fn error<T>(line: usize, message: impl Into<String>) -> Result<T, SyntaxError> {
+    Err(SyntaxError {
+        line,
+        message: message.into(),
+    })
+}
+
+pub(super) fn parse(source: &str) -> Result<Template, SyntaxError> {
+    let tokens = lex(source)?;
+    let mut parser = Parser {
+        tokens: tokens.into_iter().peekable(),
+        blocks: HashMap::new(),
+        extends: None,
+    };
+
+    let (nodes, end) = parser.nodes(&[])?;
+    if let Some((tag, line)) = end {
+        return error(line, format!("unexpected `{{% {tag} %}}`"));
+    }
+    Ok(Template {
+        extends: parser.extends,
+        nodes,
+        blocks: parser.blocks,
+    })
+}
+
+#[derive(Debug)]
+enum Token {
+    Text(String),
+    /// The contents of `{{ ... }}`.
+    Output(String, usize),
+    /// The contents of `{% ... %}`.
+    Tag(String, usize),
+}
+
+/// Split the source into text and tags. A `-` just inside a tag's
+/// delimiters, as in `{%- ... -%}`, trims the whitespace on that side of
+/// it.
+fn lex(source: &str) -> Result<Vec<Token>, SyntaxError> {
+    let mut tokens = Vec::new();
+    let mut rest = source;
+    let mut line = 1;
+    let mut trim_next = false;
+
+    while !rest.is_empty() {
+        let start = ["{{", "{%", "{#"]
+            .iter()
+            .filter_map(|open| rest.find(open))
+            .min();
+        let Some(start) = start else {
+            push_text(&mut tokens, rest, trim_next, false);
+            break;
+        };
+
+        let (open, close) = match &rest[start..start + 2] {
+            "{{" => ("{{", "}}"),
+            "{%" => ("{%", "%}"),
+            _ => ("{#", "#}"),
+        };
+        let inner_start = start + open.len();
+        let Some(len) = rest[inner_start..].find(close) else {
+            let line = line + rest[..start].matches('\n').count();
+            return error(line, format!("`{open}` is never closed"));
+        };
+        let mut inner = &rest[inner_start..inner_start + len];
+
+        let trim_before = inner.starts_with('-');
+        let trim_after = inner.ends_with('-');
+        inner = inner.strip_prefix('-').unwrap_or(inner);
+        inner = inner.strip_suffix('-').unwrap_or(inner);
+
+        push_text(&mut tokens, &rest[..start], trim_next, trim_before);
+        let tag_line = line + rest[..start].matches('\n').count();
+        match open {
+            "{{" => tokens.push(Token::Output(inner.trim().to_string(), tag_line)),
+            "{%" => tokens.push(Token::Tag(inner.trim().to_string(), tag_line)),
+            _ => {}
+        }
+
+        let end = inner_start + len + close.len();
+        line += rest[..end].matches('\n').count();
+        rest = &rest[end..];
+        trim_next = trim_after;
+    }
+
+    Ok(tokens)
+}
+
+fn push_text(tokens: &mut Vec<Token>, text: &str, trim_start: bool, trim_end: bool) {
+    let text = if trim_start { text.trim_start() } else { text };
+    let text = if trim_end { text.trim_end() } else { text };
+    if !text.is_empty() {
+        tokens.push(Token::Text(text.to_string()));
+    }
+}
+
+struct Parser {
+    tokens: std::iter::Peekable<std::vec::IntoIter<Token>>,
+    blocks: HashMap<String, Vec<Node>>,
+    extends: Option<String>,
+}
+
+/// The tag that ended a list of nodes, and its line.
+type End = Option<(String, usize)>;
+
+impl Parser {
+    /// Parse nodes up to one of the `ends` tags, or the end of the template.
+    fn nodes(&mut self, ends: &[&str]) -> Result<(Vec<Node>, End), SyntaxError> {
+        let mut nodes = Vec::new();
+
+        while let Some(token) = self.tokens.next() {
+            match token {
+                Token::Text(text) => nodes.push(Node::Text(text)),
+                Token::Output(source, line) => {
+                    let expr = parse_expr(&source, line)?;
+                    let escape = !matches!(expr, Expr::Filter(_, Filter::Raw));
+                    nodes.push(Node::Output { expr, escape, line });
+                }
+                Token::Tag(source, line) => {
+                    let (keyword, args) = source
+                        .split_once(char::is_whitespace)
+                        .map_or((source.as_str(), ""), |(k, a)| (k, a.trim()));
+                    if ends.contains(&keyword) {
+                        return Ok((nodes, Some((source.clone(), line))));
+                    }
+                    match keyword {
+                        "if" => nodes.push(self.if_node(args, line)?),
+                        "for" => nodes.push(self.for_node(args, line)?),
+                        "include" => nodes.push(Node::Include {
+                            name: parse_name(args, line)?,
+                            line,
+                        }),
+                        "extends" => {
+                            if self.extends.is_some() {
+                                return error(line, "a template can only extend one layout");
+                            }
+                            self.extends = Some(parse_name(args, line)?);
+                        }
+                        "block" => nodes.push(self.block_node(args, line)?),
+                        _ => return error(line, format!("unexpected `{{% {source} %}}`")),
+                    }
+                }
+            }
+        }
+
+        Ok((nodes, None))
+    }
+
+    /// Parse nodes up to one of the `ends` tags, which must be there.
+    fn nodes_until(
+        &mut self,
+        ends: &[&str],
+        opening: &str,
+        line: usize,
+    ) -> Result<(Vec<Node>, String, usize), SyntaxError> {
+        match self.nodes(ends)? {
+            (nodes, Some((tag, end_line))) => Ok((nodes, tag, end_line)),
+            (_, None) => error(line, format!("`{{% {opening} %}}` is never closed")),
+        }
+    }
+
+    fn if_node(&mut self, condition: &str, line: usize) -> Result<Node, SyntaxError> {
+        let mut branches = Vec::new();
+        let mut condition = parse_expr(condition, line)?;
+        loop {
+            let (body, tag, tag_line) = self.nodes_until(&["elif", "else", "endif"], "if", line)?;
+            branches.push((condition, body));
+            match tag.split_once(char::is_whitespace) {
+                Some(("elif", next)) => condition = parse_expr(next.trim(), tag_line)?,
+                _ if tag == "else" => {
+                    let (otherwise, _, _) = self.nodes_until(&["endif"], "if", line)?;
+                    return Ok(Node::If {
+                        branches,
+                        otherwise,
+                    });
+                }
+                _ if tag == "endif" => {
+                    return Ok(Node::If {
+                        branches,
+                        otherwise: Vec::new(),
+                    })
+                }
+                _ => return error(tag_line, format!("unexpected `{{% {tag} %}}`")),
+            }
+        }
+    }
+
+    fn for_node(&mut self, args: &str, line: usize) -> Result<Node, SyntaxError> {
+        let Some((name, list)) = args.split_once(" in ") else {
+            return error(line, "expected `{% for name in list %}`");
+        };
+        let name = name.trim();
+        if !is_identifier(name) {
+            return error(line, format!("invalid loop variable `{name}`"));
+        }
+        let list = parse_expr(list.trim(), line)?;
+
+        let (body, tag, _) = self.nodes_until(&["else", "endfor"], "for", line)?;
+        let otherwise = if tag == "else" {
+            self.nodes_until(&["endfor"], "for", line)?.0
+        } else {
+            Vec::new()
+        };
+        Ok(Node::For {
+            name: name.to_string(),
+            list,
+            body,
+            otherwise,
+            line,
+        })
+    }
+
+    fn block_node(&mut self, name: &str, line: usize) -> Result<Node, SyntaxError> {
+        if !is_identifier(name) {
+            return error(line, format!("invalid block name `{name}`"));
+        }
+        let (body, tag, tag_line) = self.nodes_until(&["endblock"], "block", line)?;
+        if tag != "endblock" && tag != format!("endblock {name}") {
+            return error(tag_line, format!("`{{% {tag} %}}` doesn't close `{name}`"));
+        }
+        if self.blocks.insert(name.to_string(), body.clone()).is_some() {
+            return error(line, format!("block `{name}` is defined twice"));
+        }
+        Ok(Node::Block {
+            name: name.to_string(),
+            body,
+        })
+    }
+}
+
+/// A quoted template name, as in `{% include "header.html" %}`.
+fn parse_name(args: &str, line: usize) -> Result<String, SyntaxError> {
+    match parse_expr(args, line)? {
+        Expr::Str(name) => Ok(name),
+        _ => error(
+            line,
+            format!("expected a quoted template name, not `{args}`"),
+        ),
+    }
+}
+
+fn is_identifier(s: &str) -> bool {
+    s.chars()
+        .next()
+        .is_some_and(|c| c.is_alphabetic() || c == '_')
+        && s.chars().all(|c| c.is_alphanumeric() || c == '_')
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum ExprToken {
+    Word(String),
+    Str(String),
+    Int(i64),
+    Symbol(&'static str),
+}
+
+fn lex_expr(source: &str, line: usize) -> Result<Vec<ExprToken>, SyntaxError> {
+    let mut tokens = Vec::new();
+    let mut chars = source.char_indices().peekable();
+
+    while let Some(&(i, c)) = chars.peek() {
+        if c.is_whitespace() {
+            chars.next();
+        } else if c == '"' || c == '\'' {
+            chars.next();
+            let mut s = String::new();
+            loop {
+                match chars.next() {
+                    Some((_, '\\')) => match chars.next() {
+                        Some((_, escaped)) => s.push(escaped),
+                        None => return error(line, "unterminated string"),
+                    },
+                    Some((_, q)) if q == c => break,
+                    Some((_, other)) => s.push(other),
+                    None => return error(line, "unterminated string"),
+                }
+            }
+            tokens.push(ExprToken::Str(s));
+        } else if c.is_ascii_digit()
+            || (c == '-' && source[i + 1..].starts_with(|c: char| c.is_ascii_digit()))
+        {
+            chars.next();
+            let mut end = i + c.len_utf8();
+            while let Some(&(j, d)) = chars.peek() {
+                if !d.is_ascii_digit() {
+                    break;
+                }
+                end = j + 1;
+                chars.next();
+            }
+            let n = source[i..end]
+                .parse()
+                .or_else(|_| error(line, format!("invalid number `{}`", &source[i..end])))?;
+            tokens.push(ExprToken::Int(n));
+        } else if c.is_alphanumeric() || c == '_' {
+            let mut end = i;
+            while let Some(&(j, d)) = chars.peek() {
+                if !(d.is_alphanumeric() || d == '_' || d == '.') {
+                    break;
+                }
+                end = j + d.len_utf8();
+                chars.next();
+            }
+            tokens.push(ExprToken::Word(source[i..end].to_string()));
+        } else {
+            let symbol = ["==", "!=", "|", "(", ")"]
+                .into_iter()
+                .find(|symbol| source[i..].starts_with(symbol));
+            let Some(symbol) = symbol else {
+                return error(line, format!("unexpected `{c}` in `{source}`"));
+            };
+            for _ in 0..symbol.len() {
+                chars.next();
+            }
+            tokens.push(ExprToken::Symbol(symbol));
+        }
+    }
+
+    Ok(tokens)
+}
+
+/// Parse an expression: `or` binds loosest, then `and`, `not`, the
+/// comparisons and the filters.
+pub(super) fn parse_expr(source: &str, line: usize) -> Result<Expr, SyntaxError> {
+    let tokens = lex_expr(source, line)?;
+    if tokens.is_empty() {
+        return error(line, "expected an expression");
+    }
+    let mut parser = ExprParser {
+        tokens: &tokens,
+        pos: 0,
+        source,
+        line,
+    };
+    let expr = parser.or()?;
+    if parser.pos < tokens.len() {
+        return parser.unexpected();
+    }
+    Ok(expr)
+}
+
+struct ExprParser<'a> {
+    tokens: &'a [ExprToken],
+    pos: usize,
+    source: &'a str,
+    line: usize,
+}
+
+impl ExprParser<'_> {
+    fn peek(&self) -> Option<&ExprToken> {
+        self.tokens.get(self.pos)
+    }
+
+    fn eat_word(&mut self, word: &str) -> bool {
+        if matches!(self.peek(), Some(ExprToken::Word(w)) if w == word) {
+            self.pos += 1;
+            return true;
+        }
+        false
+    }
+
+    fn eat_symbol(&mut self, symbol: &'static str) -> bool {
+        if self.peek() == Some(&ExprToken::Symbol(symbol)) {
+            self.pos += 1;
+            return true;
+        }
+        false
+    }
+
+    fn unexpected<T>(&self) -> Result<T, SyntaxError> {
+        error(self.line, format!("invalid expression `{}`", self.source))
+    }
+
+    fn or(&mut self) -> Result<Expr, SyntaxError> {
+        let mut expr = self.and()?;
+        while self.eat_word("or") {
+            expr = Expr::Or(Box::new(expr), Box::new(self.and()?));
+        }
+        Ok(expr)
+    }
+
+    fn and(&mut self) -> Result<Expr, SyntaxError> {
+        let mut expr = self.not()?;
+        while self.eat_word("and") {
+            expr = Expr::And(Box::new(expr), Box::new(self.not()?));
+        }
+        Ok(expr)
+    }
+
+    fn not(&mut self) -> Result<Expr, SyntaxError> {
+        if self.eat_word("not") {
+            return Ok(Expr::Not(Box::new(self.not()?)));
+        }
+        self.comparison()
+    }
+
+    fn comparison(&mut self) -> Result<Expr, SyntaxError> {
+        let left = self.filtered()?;
+        if self.eat_symbol("==") {
+            return Ok(Expr::Eq(Box::new(left), Box::new(self.filtered()?)));
+        }
+        if self.eat_symbol("!=") {
+            return Ok(Expr::Ne(Box::new(left), Box::new(self.filtered()?)));
+        }
+        Ok(left)
+    }
+
+    fn filtered(&mut self) -> Result<Expr, SyntaxError> {
+        let mut expr = self.primary()?;
+        while self.eat_symbol("|") {
+            let filter = match self.peek() {
+                Some(ExprToken::Word(name)) => match name.as_str() {
+                    "raw" => Filter::Raw,
+                    "upper" => Filter::Upper,
+                    "lower" => Filter::Lower,
+                    "length" => Filter::Length,
+                    _ => return error(self.line, format!("unknown filter `{name}`")),
+                },
+                _ => return self.unexpected(),
+            };
+            self.pos += 1;
+            expr = Expr::Filter(Box::new(expr), filter);
+        }
+        Ok(expr)
+    }
+
+    fn primary(&mut self) -> Result<Expr, SyntaxError> {
+        let Some(token) = self.peek().cloned() else {
+            return self.unexpected();
+        };
+        self.pos += 1;
+
+        match token {
+            ExprToken::Str(s) => Ok(Expr::Str(s)),
+            ExprToken::Int(n) => Ok(Expr::Int(n)),
+            ExprToken::Symbol("(") => {
+                let expr = self.or()?;
+                if !self.eat_symbol(")") {
+                    return self.unexpected();
+                }
+                Ok(expr)
+            }
+            ExprToken::Word(word) => match word.as_str() {
+                "true" => Ok(Expr::Bool(true)),
+                "false" => Ok(Expr::Bool(false)),
+                "and" | "or" | "not" => self.unexpected(),
+                _ => {
+                    let path: Vec<String> = word.split('.').map(str::to_string).collect();
+                    if path.iter().any(String::is_empty) || !is_identifier(&path[0]) {
+                        return self.unexpected();
+                    }
+                    Ok(Expr::Path(path))
+                }
+            },
+            ExprToken::Symbol(_) => self.unexpected(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn path(s: &str) -> Expr {
+        Expr::Path(s.split('.').map(str::to_string).collect())
+    }
+
+    #[test]
+    fn expressions() {
+        assert_eq!(parse_expr("user.name", 1), Ok(path("user.name")));
+        assert_eq!(
+            parse_expr("not a or b and c == 'x'", 1),
+            Ok(Expr::Or(
+                Box::new(Expr::Not(Box::new(path("a")))),
+                Box::new(Expr::And(
+                    Box::new(path("b")),
+                    Box::new(Expr::Eq(
+                        Box::new(path("c")),
+                        Box::new(Expr::Str("x".to_string()))
+                    ))
+                ))
+            ))
+        );
+        assert_eq!(
+            parse_expr("items | length != -1", 1),
+            Ok(Expr::Ne(
+                Box::new(Expr::Filter(Box::new(path("items")), Filter::Length)),
+                Box::new(Expr::Int(-1))
+            ))
+        );
+
+        for bad in ["", "a ==", "a b", "(a", "a | shout", "a..b", "\"open"] {
+            assert!(parse_expr(bad, 1).is_err(), "{bad}");
+        }
+    }
+
+    #[test]
+    fn syntax_errors() {
+        let error = |source| parse(source).unwrap_err();
+
+        assert_eq!(error("a\n{{ name").message, "`{{` is never closed");
+        assert_eq!(error("a\n{{ name").line, 2);
+        assert_eq!(
+            error("{% if a %}\n{% endfor %}").message,
+            "unexpected `{% endfor %}`"
+        );
+        assert_eq!(error("\n\n{% for x in xs %}").line, 3);
+        assert_eq!(
+            error("{% frobnicate %}").message,
+            "unexpected `{% frobnicate %}`"
+        );
+        assert_eq!(
+            error("{% block a %}{% endblock b %}").message,
+            "`{% endblock b %}` doesn't close `a`"
+        );
+    }
+
+    #[test]
+    fn whitespace_control() {
+        let template = parse("<ul>\n  {%- for x in xs -%}\n  <li>\n{%- endfor %}</ul>").unwrap();
+        let Node::For { body, .. } = &template.nodes[1] else {
+            panic!("expected a for loop, got {:?}", template.nodes);
+        };
+        assert!(matches!(&template.nodes[0], Node::Text(t) if t == "<ul>"));
+        assert!(matches!(&body[0], Node::Text(t) if t == "<li>"));
+    }
+}
@@ -0,0 +1,167 @@
+//! Running a server in tests, over a socket or in memory, and giving tests
+//! a directory of their own.
+//!
+//! ```
+//! use echo::{testing::TestServer, Response};
+//!
+//! let server = TestServer::new(|_: &echo::Request| Response::text(200, "hi"));
+//! let response = server.get("/").unwrap();
+//! assert_eq!(response.body, b"hi");
+//! ```
+
+use std::{
+    env, fs,
+    io::{self, Cursor, Read, Write},
+    net::{SocketAddr, TcpListener},
+    path::{Path, PathBuf},
+    process,
+    sync::atomic::{AtomicU64, Ordering},
+    thread,
+};
+
+use crate::{
+    client::Client,
+    http::{self, parse_response, Response},
+    server::{Handler, Server},
+};
+
+/// A server on an ephemeral port of the loopback interface, running on a
+/// thread of its own until the test process exits.
+#[derive(Debug)]
+pub struct TestServer {
+    addr: SocketAddr,
+}
+
+impl TestServer {
+    /// Serve `handler` with the default settings.
+    ///
+    /// # Panics
+    ///
+    /// If no port can be bound.
+    pub fn new<H: Handler>(handler: H) -> TestServer {
+        TestServer::start(Server::new(handler)).expect("failed to start a test server")
+    }
+
+    /// Run `server`, as built with `Server::builder()` for instance.
+    pub fn start(server: Server) -> io::Result<TestServer> {
+        let listener = TcpListener::bind(("127.0.0.1", 0))?;
+        let addr = listener.local_addr()?;
+        thread::Builder::new()
+            .name(format!("echo-test-{}", addr.port()))
+            .spawn(move || {
+                if let Err(e) = server.serve(listener) {
+                    eprintln!("Test server error: {e}");
+                }
+            })?;
+        Ok(TestServer { addr })
+    }
+
+    pub fn addr(&self) -> SocketAddr {
+        self.addr
+    }
+
+    /// The URL of `target` on the server, like `http://127.0.0.1:49152/a`.
+    pub fn url(&self, target: &str) -> String {
+        format!("http://{}{target}", self.addr)
+    }
+
+    /// A client of the server, which keeps its connection alive between
+    /// requests when the server does.
+    pub fn client(&self) -> Client {
+        Client::new(self.addr)
+    }
+
+    /// Send a `GET` request for `target` on a connection of its own.
+    pub fn get(&self, target: &str) -> io::Result<Response> {
+        self.client().get(target)
+    }
+}
+
+/// A connection for `Server::handle_connection` that reads what the client
+/// sent from memory, and keeps what the server writes.
+#[derive(Debug, Default)]
+pub struct MemoryStream {
+    input: Cursor<Vec<u8>>,
+    output: Vec<u8>,
+}
+
+impl MemoryStream {
+    /// A connection on which the client sent `input`, then stopped.
+    pub fn new(input: impl Into<Vec<u8>>) -> MemoryStream {
+        MemoryStream {
+            input: Cursor::new(input.into()),
+            output: Vec::new(),
+        }
+    }
+
+    /// What the server wrote.
+    pub fn output(&self) -> &[u8] {
+        &self.output
+    }
+
+    /// The response the server wrote, to a request with `method`.
+    pub fn response(&self, method: &str) -> Result<Response, http::Error> {
+        parse_response(&self.output, method)
+    }
+}
+
+impl Read for MemoryStream {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.input.read(buf)
+    }
+}
+
+impl Write for MemoryStream {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.output.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// A new, empty directory under the system's temporary directory, removed
+/// along with its contents when dropped.
+#[derive(Debug)]
+pub struct TempDir {
+    path: PathBuf,
+}
+
+impl TempDir {
+    /// A directory named after `name`, the process and a counter, so that
+    /// tests running at once each get their own.
+    ///
+    /// # Panics
+    ///
+    /// If the directory can't be created.
+    pub fn new(name: &str) -> TempDir {
+        static COUNTER: AtomicU64 = AtomicU64::new(0);
+        let count = COUNTER.fetch_add(1, Ordering::Relaxed);
+        let path = env::temp_dir().join(format!("echo-{name}-{}-{count}", process::id()));
+        let _ = fs::remove_dir_all(&path);
+        fs::create_dir_all(&path).expect("failed to create a temporary directory");
+        TempDir { path }
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// The path of `name` in the directory.
+    pub fn join(&self, name: impl AsRef<Path>) -> PathBuf {
+        self.path.join(name)
+    }
+}
+
+impl AsRef<Path> for TempDir {
+    fn as_ref(&self) -> &Path {
+        &self.path
+    }
+}
+
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.path);
+    }
+}
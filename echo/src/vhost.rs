@@ -0,0 +1,182 @@
+//! Serving several sites from one server, told apart by the `Host` header.
+
+use std::collections::HashMap;
+
+use crate::{
+    http::{Request, Response, Version},
+    server::Handler,
+};
+
+/// A handler choosing another handler by the host name a request is for.
+///
+/// Host names match exactly, ignoring case and the port, or by a wildcard
+/// like `*.example.com`, which matches the names below `example.com` at any
+/// depth but not `example.com` itself. The most specific wildcard wins.
+/// Requests for other hosts, and HTTP/1.0 requests without a `Host`, go to
+/// the default host, or get a 421 response if there's none.
+///
+/// HTTP/1.1 requests must have exactly one `Host` header (RFC 9112 section
+/// 3.2); others get a 400 response.
+///
+/// ```
+/// use echo::{vhost::VirtualHosts, Request, Response};
+///
+/// let hosts = VirtualHosts::new()
+///     .host("docs.example.com", |_: &Request| Response::text(200, "docs"))
+///     .host("*.example.com", |_: &Request| Response::text(200, "any"))
+///     .default_host(|_: &Request| Response::text(404, "no such site"));
+/// ```
+#[derive(Default)]
+pub struct VirtualHosts {
+    sites: Vec<Box<dyn Handler>>,
+    /// The site of each exact host name.
+    exact: HashMap<String, usize>,
+    /// The sites of wildcards, by the suffix they match, like
+    /// `.example.com`, the longest first.
+    wildcards: Vec<(String, usize)>,
+    default: Option<Box<dyn Handler>>,
+}
+
+impl VirtualHosts {
+    pub fn new() -> VirtualHosts {
+        VirtualHosts::default()
+    }
+
+    /// Answer the requests for `pattern`, a host name or a wildcard, with
+    /// `handler`.
+    pub fn host<H: Handler>(self, pattern: &str, handler: H) -> VirtualHosts {
+        self.hosts(&[pattern], handler)
+    }
+
+    /// Answer the requests for any of `patterns` with `handler`.
+    ///
+    /// # Panics
+    ///
+    /// If a pattern was given before.
+    pub fn hosts<H: Handler>(mut self, patterns: &[&str], handler: H) -> VirtualHosts {
+        let site = self.sites.len();
+        self.sites.push(Box::new(handler));
+        for pattern in patterns {
+            let pattern = normalize(pattern);
+            let added = match pattern.strip_prefix('*') {
+                Some(suffix) => {
+                    let exists = self.wildcards.iter().any(|(s, _)| s == suffix);
+                    self.wildcards.push((suffix.to_string(), site));
+                    !exists
+                }
+                None => self.exact.insert(pattern.clone(), site).is_none(),
+            };
+            assert!(added, "host `{pattern}` given twice");
+        }
+        self.wildcards
+            .sort_by_key(|(suffix, _)| std::cmp::Reverse(suffix.len()));
+        self
+    }
+
+    /// Answer requests for any other host with `handler`.
+    pub fn default_host<H: Handler>(mut self, handler: H) -> VirtualHosts {
+        self.default = Some(Box::new(handler));
+        self
+    }
+
+    /// The handler for the host `name`, normalized.
+    fn site(&self, name: &str) -> Option<&dyn Handler> {
+        let site = self.exact.get(name).copied().or_else(|| {
+            self.wildcards
+                .iter()
+                .find(|(suffix, _)| name.len() > suffix.len() && name.ends_with(suffix.as_str()))
+                .map(|(_, site)| *site)
+        });
+        match site {
+            Some(site) => Some(&*self.sites[site]),
+            None => self.default.as_deref(),
+        }
+    }
+}
+
+impl Handler for VirtualHosts {
+    fn handle(&self, request: &Request) -> Response {
+        let mut hosts = request.headers.get_all("Host");
+        let site = match (hosts.next(), hosts.next()) {
+            (Some(host), None) => self.site(&host_name(host)),
+            (None, None) if request.version == Version::Http10 => self.default.as_deref(),
+            _ => return Response::text(400, "Bad Request: expected one Host header\n"),
+        };
+        match site {
+            Some(site) => site.handle(request),
+            None => Response::text(421, "Misdirected Request: unknown host\n"),
+        }
+    }
+}
+
+/// The host name of a `Host` value, without the port.
+fn host_name(host: &str) -> String {
+    let host = host.trim();
+    let name = match host.rfind(':') {
+        // An IPv6 address has colons of its own, in brackets.
+        Some(colon) if !host[colon..].contains(']') => &host[..colon],
+        _ => host,
+    };
+    normalize(name)
+}
+
+/// Host names are case-insensitive, and may end in a dot.
+fn normalize(name: &str) -> String {
+    name.trim_end_matches('.').to_ascii_lowercase()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn site(name: &'static str) -> impl Handler {
+        move |_: &Request| Response::text(200, name)
+    }
+
+    fn body(hosts: &VirtualHosts, host: Option<&str>) -> (u16, String) {
+        let mut request = Request::new("GET", "/");
+        if let Some(host) = host {
+            request.headers.insert("Host", host);
+        }
+        let response = hosts.handle(&request);
+        (response.status, String::from_utf8(response.body).unwrap())
+    }
+
+    #[test]
+    fn chooses_sites() {
+        let hosts = VirtualHosts::new()
+            .hosts(&["docs.example.com", "DOCS.example.org"], site("docs"))
+            .host("*.example.com", site("any"))
+            .host("*.api.example.com", site("api"))
+            .host("[::1]", site("loopback"));
+        let host = |name| body(&hosts, Some(name));
+
+        assert_eq!(host("docs.example.com:8080"), (200, "docs".into()));
+        assert_eq!(host("Docs.Example.Org."), (200, "docs".into()));
+        assert_eq!(host("blog.example.com"), (200, "any".into()));
+        assert_eq!(host("a.b.example.com"), (200, "any".into()));
+        assert_eq!(host("v1.api.example.com"), (200, "api".into()));
+        assert_eq!(host("[::1]:7878"), (200, "loopback".into()));
+        assert_eq!(host("example.com").0, 421);
+        assert_eq!(body(&hosts, None).0, 400);
+
+        let hosts = hosts.default_host(site("default"));
+        assert_eq!(body(&hosts, Some("example.com")).1, "default");
+        assert_eq!(body(&hosts, None).0, 400);
+
+        let mut request = Request::new("GET", "/");
+        request.version = Version::Http10;
+        assert_eq!(hosts.handle(&request).body, b"default");
+        request.headers.append("Host", "docs.example.com");
+        request.headers.append("Host", "blog.example.com");
+        assert_eq!(hosts.handle(&request).status, 400);
+    }
+
+    #[test]
+    #[should_panic(expected = "given twice")]
+    fn hosts_are_unique() {
+        let _ = VirtualHosts::new()
+            .host("*.example.com", site("a"))
+            .host("*.Example.com", site("b"));
+    }
+}
@@ -0,0 +1,233 @@
+//! The WebSocket framing (RFC 6455 section 5).
+
+use std::io::{self, Read};
+
+use super::{MESSAGE_TOO_BIG, PROTOCOL_ERROR};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(super) enum Opcode {
+    Continuation,
+    Text,
+    Binary,
+    Close,
+    Ping,
+    Pong,
+}
+
+impl Opcode {
+    fn from_u8(n: u8) -> Option<Opcode> {
+        match n {
+            0x0 => Some(Opcode::Continuation),
+            0x1 => Some(Opcode::Text),
+            0x2 => Some(Opcode::Binary),
+            0x8 => Some(Opcode::Close),
+            0x9 => Some(Opcode::Ping),
+            0xa => Some(Opcode::Pong),
+            _ => None,
+        }
+    }
+
+    fn to_u8(self) -> u8 {
+        match self {
+            Opcode::Continuation => 0x0,
+            Opcode::Text => 0x1,
+            Opcode::Binary => 0x2,
+            Opcode::Close => 0x8,
+            Opcode::Ping => 0x9,
+            Opcode::Pong => 0xa,
+        }
+    }
+
+    pub fn is_control(self) -> bool {
+        matches!(self, Opcode::Close | Opcode::Ping | Opcode::Pong)
+    }
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub(super) struct Frame {
+    pub fin: bool,
+    pub opcode: Opcode,
+    /// Unmasked.
+    pub payload: Vec<u8>,
+}
+
+/// Why a frame couldn't be read.
+#[derive(Debug)]
+pub(super) enum ReadError {
+    Io(io::Error),
+    /// The client broke the protocol, and the connection has to be closed
+    /// with the given status code.
+    Violation(u16, &'static str),
+}
+
+impl From<io::Error> for ReadError {
+    fn from(error: io::Error) -> ReadError {
+        ReadError::Io(error)
+    }
+}
+
+/// Read a frame sent by a client, which has to be masked. Payloads longer
+/// than `max_len` are refused before they're read.
+pub(super) fn read_frame<R: Read>(reader: &mut R, max_len: usize) -> Result<Frame, ReadError> {
+    let mut head = [0; 2];
+    reader.read_exact(&mut head)?;
+
+    let fin = head[0] & 0x80 != 0;
+    // No extension is negotiated, so the reserved bits have to be clear.
+    if head[0] & 0x70 != 0 {
+        return Err(ReadError::Violation(
+            PROTOCOL_ERROR,
+            "reserved bits are set",
+        ));
+    }
+    let opcode = Opcode::from_u8(head[0] & 0x0f)
+        .ok_or(ReadError::Violation(PROTOCOL_ERROR, "unknown opcode"))?;
+    if head[1] & 0x80 == 0 {
+        return Err(ReadError::Violation(
+            PROTOCOL_ERROR,
+            "client frames must be masked",
+        ));
+    }
+
+    let len = match head[1] & 0x7f {
+        126 => {
+            let mut len = [0; 2];
+            reader.read_exact(&mut len)?;
+            u64::from(u16::from_be_bytes(len))
+        }
+        127 => {
+            let mut len = [0; 8];
+            reader.read_exact(&mut len)?;
+            u64::from_be_bytes(len)
+        }
+        len => u64::from(len),
+    };
+    if opcode.is_control() && (len > 125 || !fin) {
+        return Err(ReadError::Violation(
+            PROTOCOL_ERROR,
+            "control frames must be short and unfragmented",
+        ));
+    }
+    if len > max_len as u64 {
+        return Err(ReadError::Violation(MESSAGE_TOO_BIG, "message is too big"));
+    }
+
+    let mut mask = [0; 4];
+    reader.read_exact(&mut mask)?;
+    let mut payload = vec![0; len as usize];
+    reader.read_exact(&mut payload)?;
+    for (i, byte) in payload.iter_mut().enumerate() {
+        *byte ^= mask[i % 4];
+    }
+
+    Ok(Frame {
+        fin,
+        opcode,
+        payload,
+    })
+}
+
+/// Serialize an unmasked frame, as servers send them.
+pub(super) fn encode_frame(opcode: Opcode, payload: &[u8]) -> Vec<u8> {
+    let mut out = Vec::with_capacity(payload.len() + 10);
+    out.push(0x80 | opcode.to_u8());
+    match payload.len() {
+        len @ 0..=125 => out.push(len as u8),
+        len @ 126..=0xffff => {
+            out.push(126);
+            out.extend_from_slice(&(len as u16).to_be_bytes());
+        }
+        len => {
+            out.push(127);
+            out.extend_from_slice(&(len as u64).to_be_bytes());
+        }
+    }
+    out.extend_from_slice(payload);
+    out
+}
+
+#[cfg(test)]
+pub(super) mod tests {
+    use super::*;
+
+    /// A frame the way a client sends it.
+    pub fn masked(fin: bool, opcode: u8, payload: &[u8]) -> Vec<u8> {
+        let mask = [0x37, 0xfa, 0x21, 0x3d];
+        let mut out = vec![u8::from(fin) << 7 | opcode];
+        match payload.len() {
+            len @ 0..=125 => out.push(0x80 | len as u8),
+            len @ 126..=0xffff => {
+                out.push(0x80 | 126);
+                out.extend_from_slice(&(len as u16).to_be_bytes());
+            }
+            len => {
+                out.push(0x80 | 127);
+                out.extend_from_slice(&(len as u64).to_be_bytes());
+            }
+        }
+        out.extend_from_slice(&mask);
+        out.extend(payload.iter().enumerate().map(|(i, b)| b ^ mask[i % 4]));
+        out
+    }
+
+    fn read(bytes: &[u8]) -> Result<Frame, ReadError> {
+        read_frame(&mut &bytes[..], 1 << 20)
+    }
+
+    fn violation(bytes: &[u8]) -> u16 {
+        match read(bytes) {
+            Err(ReadError::Violation(code, _)) => code,
+            other => panic!("expected a violation, got {other:?}"),
+        }
+    }
+
+    #[test]
+    fn masked_frames() {
+        // The example from RFC 6455 section 5.7.
+        let frame = read(&[
+            0x81, 0x85, 0x37, 0xfa, 0x21, 0x3d, 0x7f, 0x9f, 0x4d, 0x51, 0x58,
+        ])
+        .unwrap();
+        assert_eq!(
+            frame,
+            Frame {
+                fin: true,
+                opcode: Opcode::Text,
+                payload: b"Hello".to_vec(),
+            }
+        );
+
+        for len in [125, 126, 0xffff, 0x10000] {
+            let payload = vec![b'x'; len];
+            let frame = read(&masked(false, 0x2, &payload)).unwrap();
+            assert!(!frame.fin);
+            assert_eq!(frame.payload, payload);
+        }
+    }
+
+    #[test]
+    fn protocol_violations() {
+        assert_eq!(
+            violation(&[0x81, 0x05, b'H', b'e', b'l', b'l', b'o']),
+            PROTOCOL_ERROR
+        );
+        assert_eq!(violation(&masked(true, 0x3, b"")), PROTOCOL_ERROR);
+        assert_eq!(violation(&masked(true, 0x1 | 0x40, b"")), PROTOCOL_ERROR);
+        assert_eq!(violation(&masked(false, 0x9, b"")), PROTOCOL_ERROR);
+        assert_eq!(violation(&masked(true, 0x9, &[0; 126])), PROTOCOL_ERROR);
+        assert_eq!(
+            violation(&masked(true, 0x2, &vec![0; (1 << 20) + 1])),
+            MESSAGE_TOO_BIG
+        );
+        assert!(matches!(read(&[0x81]), Err(ReadError::Io(_))));
+    }
+
+    #[test]
+    fn unmasked_server_frames() {
+        assert_eq!(encode_frame(Opcode::Text, b"Hello"), b"\x81\x05Hello");
+        let frame = encode_frame(Opcode::Binary, &[0; 300]);
+        assert_eq!(frame[..4], [0x82, 126, 0x01, 0x2c]);
+        let frame = encode_frame(Opcode::Binary, &[0; 70_000]);
+        assert_eq!(frame[..10], [0x82, 127, 0, 0, 0, 0, 0, 0x01, 0x11, 0x70]);
+    }
+}
@@ -0,0 +1,513 @@
+//! WebSocket connections (RFC 6455): the upgrade handshake, then messages
+//! in both directions until either side closes.
+//!
+//! ```no_run
+//! use echo::{websocket::{self, Message}, Request, Response, Server};
+//! use std::net::TcpListener;
+//!
+//! let server = Server::new(|request: &Request| {
+//!     websocket::upgrade(request, |mut socket| {
+//!         while let Ok(message) = socket.recv() {
+//!             if let Message::Text(_) | Message::Binary(_) = message {
+//!                 let _ = socket.send(&message);
+//!             }
+//!         }
+//!     })
+//! });
+//! server.serve(TcpListener::bind("127.0.0.1:7878").unwrap()).unwrap();
+//! ```
+
+mod frame;
+
+use std::{
+    io::{self, Write},
+    net::SocketAddr,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex,
+    },
+    time::Duration,
+};
+
+use crate::{
+    base64,
+    http::{Request, Response, Upgraded, Version},
+    server::Stream,
+    sha1::sha1,
+};
+use frame::{encode_frame, read_frame, Opcode, ReadError};
+
+/// Close status codes (RFC 6455 section 7.4.1).
+pub const NORMAL_CLOSURE: u16 = 1000;
+pub const GOING_AWAY: u16 = 1001;
+pub const PROTOCOL_ERROR: u16 = 1002;
+pub const UNSUPPORTED_DATA: u16 = 1003;
+pub const INVALID_PAYLOAD: u16 = 1007;
+pub const POLICY_VIOLATION: u16 = 1008;
+pub const MESSAGE_TOO_BIG: u16 = 1009;
+pub const INTERNAL_ERROR: u16 = 1011;
+
+/// The largest message `WebSocket::recv` accepts by default.
+pub const DEFAULT_MAX_MESSAGE_SIZE: usize = 16 * 1024 * 1024;
+
+/// Appended to the client's key to prove that the server speaks WebSocket.
+const ACCEPT_GUID: &str = "258EAFA5-E914-47DA-95CA-C5AB0DC85B11";
+
+/// Whether the request asks to switch to WebSocket.
+pub fn is_upgrade(request: &Request) -> bool {
+    request.headers.has_token("Upgrade", "websocket")
+}
+
+/// Answer a WebSocket handshake: a `101 Switching Protocols` response after
+/// which `on_open` gets the connection, on a thread of its own. Requests
+/// that aren't a valid handshake get a 400 response, or a 426 one if they
+/// ask for another version of the protocol.
+pub fn upgrade<F>(request: &Request, on_open: F) -> Response
+where
+    F: FnOnce(WebSocket) + Send + 'static,
+{
+    let accept = match accept_key(request) {
+        Ok(accept) => accept,
+        Err(response) => return response,
+    };
+
+    Response::new(101)
+        .with_header("Upgrade", "websocket")
+        .with_header("Connection", "Upgrade")
+        .with_header("Sec-WebSocket-Accept", accept)
+        .with_upgrade(move |connection| match WebSocket::new(connection) {
+            Ok(socket) => on_open(socket),
+            Err(e) => eprintln!("Failed to set up a WebSocket: {e}"),
+        })
+}
+
+/// Check the handshake (RFC 6455 section 4.2.1) and compute the
+/// `Sec-WebSocket-Accept` value, or the response refusing it.
+fn accept_key(request: &Request) -> Result<String, Response> {
+    let bad_request = |reason: &str| Err(Response::text(400, format!("{reason}\n")));
+
+    if request.method != "GET" || request.version < Version::Http11 {
+        return bad_request("WebSocket handshakes are HTTP/1.1 GET requests");
+    }
+    if !is_upgrade(request) || !request.headers.has_token("Connection", "upgrade") {
+        return bad_request("Expected `Upgrade: websocket` and `Connection: Upgrade`");
+    }
+    if request.header("Sec-WebSocket-Version") != Some("13") {
+        return Err(
+            Response::text(426, "Only WebSocket version 13 is supported\n")
+                .with_header("Sec-WebSocket-Version", "13"),
+        );
+    }
+
+    let key = request.header("Sec-WebSocket-Key").unwrap_or("").trim();
+    match base64::decode(key) {
+        Some(nonce) if nonce.len() == 16 => {}
+        _ => return bad_request("Invalid `Sec-WebSocket-Key`"),
+    }
+
+    Ok(base64::encode(&sha1(
+        format!("{key}{ACCEPT_GUID}").as_bytes(),
+    )))
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Message {
+    Text(String),
+    Binary(Vec<u8>),
+    Ping(Vec<u8>),
+    Pong(Vec<u8>),
+    /// The closing handshake, with the status code and reason if there's
+    /// one.
+    Close(Option<CloseFrame>),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CloseFrame {
+    pub code: u16,
+    pub reason: String,
+}
+
+/// The server's side of a WebSocket connection.
+///
+/// Pings are answered as they arrive, and a close message from the client
+/// is answered before `recv` returns it. Protocol violations close the
+/// connection with the matching status code and make `recv` fail.
+#[derive(Debug)]
+pub struct WebSocket {
+    connection: Upgraded,
+    sender: Sender,
+    max_message_size: usize,
+    /// The opcode and the payload so far of a fragmented message. Control
+    /// frames can arrive between the fragments.
+    fragments: Option<(Opcode, Vec<u8>)>,
+    /// The closing handshake is over, or the connection is unusable.
+    closed: bool,
+}
+
+impl WebSocket {
+    fn new(connection: Upgraded) -> io::Result<WebSocket> {
+        let stream = connection.stream().try_clone()?;
+        Ok(WebSocket {
+            connection,
+            sender: Sender {
+                stream: Arc::new(Mutex::new(stream)),
+                closing: Arc::new(AtomicBool::new(false)),
+            },
+            max_message_size: DEFAULT_MAX_MESSAGE_SIZE,
+            fragments: None,
+            closed: false,
+        })
+    }
+
+    pub fn peer_addr(&self) -> io::Result<SocketAddr> {
+        self.connection.stream().peer_addr()
+    }
+
+    /// Refuse messages longer than `bytes`, closing the connection with
+    /// `MESSAGE_TOO_BIG`. Defaults to `DEFAULT_MAX_MESSAGE_SIZE`.
+    pub fn set_max_message_size(&mut self, bytes: usize) {
+        self.max_message_size = bytes;
+    }
+
+    /// A handle to send messages from other threads.
+    pub fn sender(&self) -> Sender {
+        self.sender.clone()
+    }
+
+    pub fn send(&self, message: &Message) -> io::Result<()> {
+        self.sender.send(message)
+    }
+
+    /// Wait for the next message, putting fragmented messages back together.
+    ///
+    /// # Errors
+    ///
+    /// Fails if the connection breaks, if the client violates the protocol,
+    /// and once the connection is closed.
+    pub fn recv(&mut self) -> io::Result<Message> {
+        loop {
+            if self.closed {
+                return Err(io::Error::new(
+                    io::ErrorKind::NotConnected,
+                    "the WebSocket is closed",
+                ));
+            }
+
+            let limit = self.max_message_size
+                - self
+                    .fragments
+                    .as_ref()
+                    .map_or(0, |(_, payload)| payload.len());
+            let frame = match read_frame(&mut self.connection, limit) {
+                Ok(frame) => frame,
+                Err(ReadError::Io(e)) => {
+                    self.closed = true;
+                    return Err(e);
+                }
+                Err(ReadError::Violation(code, reason)) => return Err(self.fail(code, reason)),
+            };
+
+            match (frame.opcode, &mut self.fragments) {
+                (Opcode::Text | Opcode::Binary, Some(_)) => {
+                    return Err(self.fail(PROTOCOL_ERROR, "expected a continuation frame"));
+                }
+                (Opcode::Continuation, None) => {
+                    return Err(self.fail(PROTOCOL_ERROR, "unexpected continuation frame"));
+                }
+                (Opcode::Text | Opcode::Binary, None) if !frame.fin => {
+                    self.fragments = Some((frame.opcode, frame.payload));
+                }
+                (Opcode::Text | Opcode::Binary, None) => {
+                    return self.message(frame.opcode, frame.payload);
+                }
+                (Opcode::Continuation, Some((_, payload))) => {
+                    payload.extend_from_slice(&frame.payload);
+                    if frame.fin {
+                        let (opcode, payload) = self.fragments.take().unwrap();
+                        return self.message(opcode, payload);
+                    }
+                }
+                (Opcode::Ping, _) => {
+                    if !self.sender.closing.load(Ordering::SeqCst) {
+                        self.sender.send_frame(Opcode::Pong, &frame.payload)?;
+                    }
+                    return Ok(Message::Ping(frame.payload));
+                }
+                (Opcode::Pong, _) => return Ok(Message::Pong(frame.payload)),
+                (Opcode::Close, _) => {
+                    let close = match parse_close(&frame.payload) {
+                        Ok(close) => close,
+                        Err((code, reason)) => return Err(self.fail(code, reason)),
+                    };
+                    // Echo the status code, unless this answers our close.
+                    let code = close.as_ref().map(|close| close.code);
+                    self.sender.send_close(code, "")?;
+                    self.closed = true;
+                    return Ok(Message::Close(close));
+                }
+            }
+        }
+    }
+
+    /// Start the closing handshake and wait for the client's answer, for up
+    /// to five seconds. Messages that arrive in the meantime are dropped.
+    pub fn close(&mut self, code: u16, reason: &str) -> io::Result<()> {
+        self.sender.send_close(Some(code), reason)?;
+        self.connection
+            .stream()
+            .set_read_timeout(Some(Duration::from_secs(5)))?;
+        while !self.closed {
+            if let Err(e) = self.recv() {
+                return match e.kind() {
+                    io::ErrorKind::NotConnected => Ok(()),
+                    _ => Err(e),
+                };
+            }
+        }
+        Ok(())
+    }
+
+    fn message(&mut self, opcode: Opcode, payload: Vec<u8>) -> io::Result<Message> {
+        match opcode {
+            Opcode::Text => match String::from_utf8(payload) {
+                Ok(text) => Ok(Message::Text(text)),
+                Err(_) => Err(self.fail(INVALID_PAYLOAD, "text message isn't UTF-8")),
+            },
+            _ => Ok(Message::Binary(payload)),
+        }
+    }
+
+    /// Close the connection because the client broke the protocol.
+    fn fail(&mut self, code: u16, reason: &'static str) -> io::Error {
+        self.closed = true;
+        let _ = self.sender.send_close(Some(code), reason);
+        io::Error::new(io::ErrorKind::InvalidData, reason)
+    }
+}
+
+/// Parse the payload of a close frame, or return the status code to fail
+/// the connection with.
+fn parse_close(payload: &[u8]) -> Result<Option<CloseFrame>, (u16, &'static str)> {
+    let (code, reason) = match payload {
+        [] => return Ok(None),
+        [_] => return Err((PROTOCOL_ERROR, "close frame with a truncated status code")),
+        [high, low, reason @ ..] => (u16::from_be_bytes([*high, *low]), reason),
+    };
+
+    // Codes that can't be sent, like 1005 and 1006, are invalid; 3000 to
+    // 4999 are for libraries and applications.
+    if !matches!(code, 1000..=1003 | 1007..=1014 | 3000..=4999) {
+        return Err((PROTOCOL_ERROR, "invalid close status code"));
+    }
+    let reason = String::from_utf8(reason.to_vec())
+        .map_err(|_| (INVALID_PAYLOAD, "close reason isn't UTF-8"))?;
+    Ok(Some(CloseFrame { code, reason }))
+}
+
+/// Sends messages on a WebSocket. Clones share the connection, so that
+/// several threads can write to it, e.g. to broadcast to a room.
+#[derive(Debug, Clone)]
+pub struct Sender {
+    stream: Arc<Mutex<Stream>>,
+    /// A close frame was sent, after which nothing else may be.
+    closing: Arc<AtomicBool>,
+}
+
+impl Sender {
+    /// Send a message. Sending `Message::Close` starts the closing
+    /// handshake.
+    ///
+    /// # Errors
+    ///
+    /// Fails if the connection breaks, and after a close message was sent.
+    pub fn send(&self, message: &Message) -> io::Result<()> {
+        match message {
+            Message::Text(text) => self.send_frame(Opcode::Text, text.as_bytes()),
+            Message::Binary(data) => self.send_frame(Opcode::Binary, data),
+            Message::Ping(data) => self.send_frame(Opcode::Ping, data),
+            Message::Pong(data) => self.send_frame(Opcode::Pong, data),
+            Message::Close(None) => self.send_close(None, ""),
+            Message::Close(Some(close)) => self.send_close(Some(close.code), &close.reason),
+        }
+    }
+
+    fn send_frame(&self, opcode: Opcode, payload: &[u8]) -> io::Result<()> {
+        let frame = encode_frame(opcode, payload);
+        let mut stream = self.stream.lock().unwrap();
+        if self.closing.load(Ordering::SeqCst) {
+            return Err(io::Error::new(
+                io::ErrorKind::NotConnected,
+                "the WebSocket is closing",
+            ));
+        }
+        stream.write_all(&frame)
+    }
+
+    /// Send a close frame, unless one was sent already.
+    fn send_close(&self, code: Option<u16>, reason: &str) -> io::Result<()> {
+        let mut payload = Vec::new();
+        if let Some(code) = code {
+            payload.extend_from_slice(&code.to_be_bytes());
+            // Control frames are limited to 125 bytes.
+            let mut end = reason.len().min(123);
+            while !reason.is_char_boundary(end) {
+                end -= 1;
+            }
+            payload.extend_from_slice(&reason.as_bytes()[..end]);
+        }
+
+        let mut stream = self.stream.lock().unwrap();
+        if self.closing.swap(true, Ordering::SeqCst) {
+            return Ok(());
+        }
+        stream.write_all(&encode_frame(Opcode::Close, &payload))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use frame::tests::masked;
+    use std::{
+        io::Read,
+        net::{TcpListener, TcpStream},
+    };
+
+    fn handshake(headers: &[(&str, &str)]) -> Response {
+        let mut request = Request::new("GET", "/ws");
+        for (name, value) in headers {
+            request.headers.append(*name, *value);
+        }
+        upgrade(&request, |_| {})
+    }
+
+    /// A WebSocket and the client's end of its connection.
+    fn connected() -> (WebSocket, TcpStream) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let (server, _) = listener.accept().unwrap();
+        let socket = WebSocket::new(Upgraded::new(server.into(), Vec::new())).unwrap();
+        (socket, client)
+    }
+
+    /// Read an unmasked frame from the server: its first byte and payload.
+    fn read_server_frame(client: &mut TcpStream) -> (u8, Vec<u8>) {
+        let mut head = [0; 2];
+        client.read_exact(&mut head).unwrap();
+        assert_eq!(head[1] & 0x80, 0, "server frames aren't masked");
+        let mut payload = vec![0; usize::from(head[1] & 0x7f)];
+        client.read_exact(&mut payload).unwrap();
+        (head[0], payload)
+    }
+
+    #[test]
+    fn handshakes() {
+        // The example from RFC 6455 section 1.3.
+        let valid = [
+            ("Upgrade", "websocket"),
+            ("Connection", "keep-alive, Upgrade"),
+            ("Sec-WebSocket-Key", "dGhlIHNhbXBsZSBub25jZQ=="),
+            ("Sec-WebSocket-Version", "13"),
+        ];
+        let response = handshake(&valid);
+        assert_eq!(response.status, 101);
+        assert_eq!(
+            response.headers.get("Sec-WebSocket-Accept"),
+            Some("s3pPLMBiTxaQ9kYGzzhZRbK+xOo=")
+        );
+        assert!(response.upgrade.is_some());
+
+        let response = handshake(&[valid[0], valid[1], valid[2], ("Sec-WebSocket-Version", "8")]);
+        assert_eq!(response.status, 426);
+        assert_eq!(response.headers.get("Sec-WebSocket-Version"), Some("13"));
+
+        assert_eq!(handshake(&[valid[0], valid[1], valid[3]]).status, 400);
+        assert_eq!(
+            handshake(&[
+                valid[0],
+                valid[1],
+                ("Sec-WebSocket-Key", "c2hvcnQ="),
+                valid[3]
+            ])
+            .status,
+            400
+        );
+        assert_eq!(handshake(&[valid[1], valid[2], valid[3]]).status, 400);
+    }
+
+    #[test]
+    fn messages_pings_and_closing() {
+        let (mut socket, mut client) = connected();
+
+        // A fragmented text message with a ping in the middle.
+        client.write_all(&masked(false, 0x1, b"Hel")).unwrap();
+        client
+            .write_all(&masked(true, 0x9, b"are you there?"))
+            .unwrap();
+        client.write_all(&masked(true, 0x0, b"lo")).unwrap();
+        client.write_all(&masked(true, 0x2, &[1, 2, 3])).unwrap();
+
+        assert_eq!(
+            socket.recv().unwrap(),
+            Message::Ping(b"are you there?".to_vec())
+        );
+        assert_eq!(
+            read_server_frame(&mut client),
+            (0x8a, b"are you there?".to_vec())
+        );
+        assert_eq!(socket.recv().unwrap(), Message::Text("Hello".to_string()));
+        assert_eq!(socket.recv().unwrap(), Message::Binary(vec![1, 2, 3]));
+
+        socket
+            .sender()
+            .send(&Message::Text("Hi".to_string()))
+            .unwrap();
+        assert_eq!(read_server_frame(&mut client), (0x81, b"Hi".to_vec()));
+
+        // The client's close is echoed, and nothing can be sent afterwards.
+        client
+            .write_all(&masked(true, 0x8, b"\x03\xe8bye"))
+            .unwrap();
+        assert_eq!(
+            socket.recv().unwrap(),
+            Message::Close(Some(CloseFrame {
+                code: NORMAL_CLOSURE,
+                reason: "bye".to_string(),
+            }))
+        );
+        assert_eq!(read_server_frame(&mut client), (0x88, b"\x03\xe8".to_vec()));
+        assert!(socket.send(&Message::Text("late".to_string())).is_err());
+        assert!(socket.recv().is_err());
+    }
+
+    #[test]
+    fn violations_close_the_connection() {
+        let (mut socket, mut client) = connected();
+        client.write_all(&masked(true, 0x1, b"\xff\xfe")).unwrap();
+        assert_eq!(
+            socket.recv().unwrap_err().kind(),
+            io::ErrorKind::InvalidData
+        );
+        let (first, payload) = read_server_frame(&mut client);
+        assert_eq!(first, 0x88);
+        assert_eq!(payload[..2], INVALID_PAYLOAD.to_be_bytes());
+
+        let (mut socket, mut client) = connected();
+        socket.set_max_message_size(4);
+        client.write_all(&masked(false, 0x2, b"abc")).unwrap();
+        client.write_all(&masked(true, 0x0, b"de")).unwrap();
+        assert!(socket.recv().is_err());
+        assert_eq!(
+            read_server_frame(&mut client).1[..2],
+            MESSAGE_TOO_BIG.to_be_bytes()
+        );
+
+        let (mut socket, mut client) = connected();
+        client.write_all(&masked(true, 0x8, b"\x03\xed")).unwrap();
+        assert!(socket.recv().is_err());
+        assert_eq!(
+            read_server_frame(&mut client).1[..2],
+            PROTOCOL_ERROR.to_be_bytes()
+        );
+    }
+}
@@ -0,0 +1,1169 @@
+use std::{
+    collections::HashMap,
+    fs,
+    io::{Read, Write},
+    net::TcpStream,
+    sync::{Arc, Barrier},
+    thread,
+    time::{Duration, Instant},
+};
+
+use echo::{
+    client::Client,
+    files::StaticFiles,
+    json,
+    metrics::{Registry, ServerMetrics},
+    middleware::{BasicAuth, Cors, Pipeline, Recover, RequestId},
+    pool::ThreadPool,
+    server::Backend,
+    testing::{MemoryStream, TempDir, TestServer},
+    Request, Response, Server,
+};
+
+fn route(request: &Request) -> Response {
+    match (request.method.as_str(), request.path()) {
+        ("GET" | "HEAD", "/") => Response::text(200, "home\n"),
+        ("GET", "/hello") => {
+            let query = request.query_params();
+            let name = query.get("name").unwrap_or("world");
+            Response::text(200, format!("Hello, {name}!\n"))
+        }
+        ("POST", "/echo") => Response::text(200, request.body.clone()),
+        ("POST", "/upload") => {
+            let form = match request.multipart() {
+                Ok(form) => form,
+                Err(e) => return e.into(),
+            };
+            let file = form
+                .file("file")
+                .map(|file| (file.filename.as_str(), file.size));
+            Response::text(200, format!("{:?} {:?}", form.fields.get("title"), file))
+        }
+        ("POST", "/sum") => {
+            let numbers = match request.json() {
+                Ok(numbers) => numbers,
+                Err(e) => return e.into(),
+            };
+            let sum: f64 = numbers
+                .as_array()
+                .unwrap_or_default()
+                .iter()
+                .filter_map(json::Value::as_f64)
+                .sum();
+            let body: json::Value = [("sum", sum)].into_iter().collect();
+            Response::json(200, &body)
+        }
+        (_, "/" | "/hello" | "/echo") => Response::text(405, "Method Not Allowed\n"),
+        _ => Response::text(404, "Not Found\n"),
+    }
+}
+
+/// Every backend this platform has.
+fn backends() -> Vec<Backend> {
+    vec![
+        Backend::Threaded,
+        #[cfg(target_os = "linux")]
+        Backend::EventLoop,
+    ]
+}
+
+fn start(backend: Backend) -> TestServer {
+    TestServer::start(Server::builder().backend(backend).build(route)).unwrap()
+}
+
+#[test]
+fn routing() {
+    for backend in backends() {
+        let server = start(backend);
+
+        let response = server.get("/").unwrap();
+        assert_eq!(response.status, 200, "{backend:?}");
+        assert_eq!(response.body, b"home\n");
+        assert_eq!(response.headers.get("Content-Length"), Some("5"));
+
+        let response = server.get("/hello?name=echo").unwrap();
+        assert_eq!(response.body, b"Hello, echo!\n");
+
+        let mut post = Request::new("POST", "/echo");
+        post.body = b"ping".to_vec().into();
+        let response = server.client().send(&post).unwrap();
+        assert_eq!(response.body, b"ping");
+
+        let response = server.client().send(&Request::new("HEAD", "/")).unwrap();
+        assert_eq!(response.status, 200);
+        assert_eq!(response.headers.get("Content-Length"), Some("5"));
+        assert!(response.body.is_empty());
+
+        let response = server.client().send(&Request::new("DELETE", "/")).unwrap();
+        assert_eq!(response.status, 405);
+    }
+}
+
+#[test]
+fn not_found() {
+    let root = TempDir::new("it-files");
+    fs::write(root.join("index.html"), "<h1>index</h1>").unwrap();
+    let files = StaticFiles::new(root.path());
+
+    for backend in backends() {
+        let server = start(backend);
+        let response = server.get("/nothing/here").unwrap();
+        assert_eq!(response.status, 404, "{backend:?}");
+        assert_eq!(response.body, b"Not Found\n");
+
+        let files = files.clone();
+        let server = TestServer::start(
+            Server::builder()
+                .backend(backend)
+                .build(move |request: &Request| files.serve(request)),
+        )
+        .unwrap();
+        assert_eq!(server.get("/").unwrap().body, b"<h1>index</h1>");
+        assert_eq!(server.get("/missing.html").unwrap().status, 404);
+        assert_eq!(server.get("/../etc/passwd").unwrap().status, 404);
+    }
+}
+
+#[test]
+fn logs_bytes_sent() {
+    use echo::access_log::AccessLog;
+
+    let root = TempDir::new("it-log");
+    fs::write(root.join("big.bin"), vec![b'x'; 100_000]).unwrap();
+    let files = StaticFiles::new(root.path());
+
+    for backend in backends() {
+        let path = root.join(format!("{backend:?}.log"));
+        let files = files.clone();
+        let server = TestServer::start(
+            Server::builder()
+                .backend(backend)
+                .access_log(AccessLog::builder().path(&path).build().unwrap())
+                .build(move |request: &Request| files.serve(request)),
+        )
+        .unwrap();
+
+        assert_eq!(server.get("/big.bin").unwrap().body.len(), 100_000);
+        let mut range = Request::new("GET", "/big.bin");
+        range.headers.insert("Range", "bytes=10-19");
+        assert_eq!(server.client().send(&range).unwrap().status, 206);
+        let head = Request::new("HEAD", "/big.bin");
+        assert_eq!(server.client().send(&head).unwrap().status, 200);
+
+        // Streamed bodies are logged once they're sent, so the lines may
+        // take a moment.
+        let deadline = Instant::now() + Duration::from_secs(2);
+        let bytes = loop {
+            let log = fs::read_to_string(&path).unwrap_or_default();
+            let mut bytes: Vec<_> = log
+                .lines()
+                .map(|line| {
+                    let fields: Vec<_> = line.split(' ').collect();
+                    (fields[5].to_string(), fields[fields.len() - 2].to_string())
+                })
+                .collect();
+            if bytes.len() == 3 || Instant::now() > deadline {
+                bytes.sort();
+                break bytes;
+            }
+            thread::sleep(Duration::from_millis(10));
+        };
+        let expected = [("\"GET", "10"), ("\"GET", "100000"), ("\"HEAD", "-")]
+            .map(|(method, bytes)| (method.to_string(), bytes.to_string()));
+        assert_eq!(bytes, expected, "{backend:?}");
+    }
+}
+
+#[test]
+fn protected_files() {
+    let root = TempDir::new("it-protected");
+    fs::create_dir_all(root.join("admin")).unwrap();
+    fs::write(root.join("admin/secret.txt"), "secret").unwrap();
+    fs::write(root.join("public.txt"), "public").unwrap();
+    let auth = BasicAuth::parse("bob:{SHA}5en6G6MezRroT3XKqkdPOmY/BfQ=\n")
+        .unwrap()
+        .prefix("/admin");
+    let server = TestServer::new(Pipeline::new(StaticFiles::new(root.path())).with(auth));
+
+    assert_eq!(server.get("/public.txt").unwrap().status, 200);
+    // However the path is spelled, the files under the prefix are behind
+    // the login.
+    for target in [
+        "/admin/secret.txt",
+        "//admin/secret.txt",
+        "/%61dmin/secret.txt",
+        "/./admin/secret.txt",
+    ] {
+        assert_eq!(server.get(target).unwrap().status, 401, "{target}");
+    }
+}
+
+#[test]
+fn malformed_requests() {
+    for backend in backends() {
+        let server = start(backend);
+        let mut request = Request::new("GET", "/");
+        request.headers.insert("Content-Length", "nope");
+        let response = server.client().send(&request).unwrap();
+        assert_eq!(response.status, 400, "{backend:?}");
+        assert!(response.headers.has_token("Connection", "close"));
+    }
+}
+
+#[test]
+fn endless_heads() {
+    use echo::http::Limits;
+
+    for backend in backends() {
+        let limits = Limits {
+            head: 1024,
+            body: 1024,
+            ..Limits::default()
+        };
+        let server = TestServer::start(
+            Server::builder()
+                .backend(backend)
+                .limits(limits)
+                .build(route),
+        )
+        .unwrap();
+
+        // A head that never ends is answered once it's past the limit,
+        // however much more the client has sent by then.
+        let mut stream = TcpStream::connect(server.addr()).unwrap();
+        stream
+            .set_read_timeout(Some(Duration::from_secs(5)))
+            .unwrap();
+        let mut writer = stream.try_clone().unwrap();
+        let sending = thread::spawn(move || {
+            writer.write_all(b"GET / HTTP/1.1\r\nX-Filler: ").unwrap();
+            let filler = [b'a'; 64 * 1024];
+            // The server closes the connection before it's all sent.
+            for _ in 0..64 {
+                if writer.write_all(&filler).is_err() {
+                    break;
+                }
+            }
+        });
+        let output = read_until_closed(&mut stream);
+        assert!(output.starts_with(b"HTTP/1.1 431 "), "{backend:?}");
+        sending.join().unwrap();
+    }
+}
+
+#[test]
+fn middleware_pipeline() {
+    let users = BasicAuth::parse("admin:{SHA}5en6G6MezRroT3XKqkdPOmY/BfQ=")
+        .unwrap()
+        .prefix("/admin");
+    let pipeline = Pipeline::new(|request: &Request| match request.path() {
+        "/panic" => panic!("handler bug"),
+        "/admin/stats" => Response::text(200, "stats\n"),
+        _ => route(request),
+    })
+    .with(RequestId::new())
+    .with(Recover::new())
+    .with(Cors::new().allow_origin("https://app.example"))
+    .with(users);
+    let server = TestServer::new(pipeline);
+
+    let response = server.get("/panic").unwrap();
+    assert_eq!(response.status, 500);
+    assert_eq!(response.headers.get("X-Request-Id").map(str::len), Some(32));
+    // The worker survived the panic.
+    assert_eq!(server.get("/").unwrap().status, 200);
+
+    let mut request = Request::new("GET", "/admin/stats");
+    assert_eq!(server.client().send(&request).unwrap().status, 401);
+    request
+        .headers
+        .insert("Authorization", "Basic YWRtaW46c2VjcmV0");
+    let response = server.client().send(&request).unwrap();
+    assert_eq!(response.body, b"stats\n");
+
+    let mut preflight = Request::new("OPTIONS", "/echo");
+    preflight.headers.insert("Origin", "https://app.example");
+    preflight
+        .headers
+        .insert("Access-Control-Request-Method", "POST");
+    let response = server.client().send(&preflight).unwrap();
+    assert_eq!(response.status, 204);
+    assert_eq!(
+        response.headers.get("Access-Control-Allow-Origin"),
+        Some("https://app.example")
+    );
+}
+
+#[test]
+fn metrics() {
+    for backend in backends() {
+        let registry = Registry::new();
+        let metrics = registry.clone();
+        let server = TestServer::start(
+            Server::builder()
+                .backend(backend)
+                .metrics(ServerMetrics::new(&registry))
+                .build(move |request: &Request| match request.path() {
+                    "/metrics" => metrics.response(),
+                    _ => route(request),
+                }),
+        )
+        .unwrap();
+
+        server.get("/hello?name=a").unwrap();
+        server.get("/hello?name=b").unwrap();
+        server.get("/missing").unwrap();
+        let delete = Request::new("DELETE", "/echo");
+        assert_eq!(server.client().send(&delete).unwrap().status, 405);
+        let response = server.get("/metrics").unwrap();
+        assert_eq!(
+            response.headers.get("Content-Type"),
+            Some("text/plain; version=0.0.4; charset=utf-8")
+        );
+
+        let body = String::from_utf8(response.body).unwrap();
+        for line in [
+            "echo_http_requests_total{route=\"/hello\",status=\"200\"} 2",
+            "echo_http_requests_total{route=\"unmatched\",status=\"404\"} 1",
+            "echo_http_requests_total{route=\"unmatched\",status=\"405\"} 1",
+            "echo_http_request_duration_seconds_count{route=\"/hello\"} 2",
+            "echo_pool_workers 4",
+            "echo_pool_queued_jobs 0",
+        ] {
+            assert!(
+                body.lines().any(|l| l == line),
+                "{backend:?}: no {line} in\n{body}"
+            );
+        }
+    }
+}
+
+#[test]
+fn json_api() {
+    let server = start(Backend::Threaded);
+
+    let mut request = Request::new("POST", "/sum");
+    request.body = b"[1, 2.5, 3]".to_vec().into();
+    let response = server.client().send(&request).unwrap();
+    assert_eq!(
+        response.headers.get("Content-Type"),
+        Some("application/json")
+    );
+    let body = json::from_slice(&response.body).unwrap();
+    assert_eq!(body.get("sum").and_then(json::Value::as_f64), Some(6.5));
+
+    request.body = b"[1, 2,\n 3".to_vec().into();
+    let response = server.client().send(&request).unwrap();
+    assert_eq!(response.status, 400);
+    assert_eq!(
+        json::from_slice(&response.body).unwrap().get("error"),
+        Some(&json::Value::from(
+            "invalid JSON: line 2, column 3: the array is never closed"
+        ))
+    );
+}
+
+#[test]
+fn forms() {
+    let server = start(Backend::Threaded);
+
+    let response = server.get("/hello?name=a%20b&x=1").unwrap();
+    assert_eq!(response.body, b"Hello, a b!\n");
+
+    let mut request = Request::new("POST", "/upload");
+    request
+        .headers
+        .insert("Content-Type", "multipart/form-data; boundary=----abc");
+    request.body = b"------abc\r\n\
+        Content-Disposition: form-data; name=\"title\"\r\n\r\n\
+        Holiday\r\n\
+        ------abc\r\n\
+        Content-Disposition: form-data; name=\"file\"; filename=\"beach.jpg\"\r\n\
+        Content-Type: image/jpeg\r\n\r\n\
+        \xff\xd8\xff\xe0\r\n\
+        ------abc--\r\n"
+        .to_vec()
+        .into();
+    let response = server.client().send(&request).unwrap();
+    assert_eq!(response.body, b"Some(\"Holiday\") Some((\"beach.jpg\", 4))");
+
+    request.body.truncate(40);
+    let response = server.client().send(&request).unwrap();
+    assert_eq!(response.status, 400);
+    assert_eq!(
+        response.body,
+        b"malformed form: unexpected end of the body\n"
+    );
+
+    request.headers.insert("Content-Type", "text/plain");
+    assert_eq!(server.client().send(&request).unwrap().status, 415);
+}
+
+/// Read what the server sends until it closes the connection, as far as
+/// it gets before a reset.
+fn read_until_closed(stream: &mut TcpStream) -> Vec<u8> {
+    let mut output = Vec::new();
+    let mut chunk = [0; 64 * 1024];
+    while let Ok(n @ 1..) = stream.read(&mut chunk) {
+        output.extend_from_slice(&chunk[..n]);
+    }
+    output
+}
+
+#[test]
+fn timeouts() {
+    for backend in backends() {
+        let connect = |server: &TestServer| {
+            let stream = TcpStream::connect(server.addr()).unwrap();
+            stream
+                .set_read_timeout(Some(Duration::from_secs(5)))
+                .unwrap();
+            stream
+        };
+
+        let server = TestServer::start(
+            Server::builder()
+                .backend(backend)
+                .read_timeout(Duration::from_millis(200))
+                .build(route),
+        )
+        .unwrap();
+        // An idle connection is closed without a word.
+        let mut stream = connect(&server);
+        assert!(read_until_closed(&mut stream).is_empty(), "{backend:?}");
+        // A client that goes quiet halfway through a request gets a 408.
+        let mut stream = connect(&server);
+        stream.write_all(b"GET / HTTP/1.1\r\nHost: x\r\n").unwrap();
+        let output = read_until_closed(&mut stream);
+        assert!(output.starts_with(b"HTTP/1.1 408 "), "{backend:?}");
+
+        // The header deadline holds however steadily the head trickles in.
+        let server = TestServer::start(
+            Server::builder()
+                .backend(backend)
+                .read_timeout(Duration::from_secs(2))
+                .header_timeout(Duration::from_millis(300))
+                .build(route),
+        )
+        .unwrap();
+        let mut stream = connect(&server);
+        let started = Instant::now();
+        stream.write_all(b"GET / HTTP/1.1\r\n").unwrap();
+        for _ in 0..2 {
+            thread::sleep(Duration::from_millis(100));
+            stream.write_all(b"X-Trickle: 1\r\n").unwrap();
+        }
+        let output = read_until_closed(&mut stream);
+        assert!(output.starts_with(b"HTTP/1.1 408 "), "{backend:?}");
+        assert!(started.elapsed() < Duration::from_secs(1), "{backend:?}");
+
+        // A client that doesn't take its response is cut off.
+        let size = 64 << 20;
+        let server = TestServer::start(
+            Server::builder()
+                .backend(backend)
+                .write_timeout(Duration::from_millis(200))
+                .build(move |request: &Request| match request.path() {
+                    "/big" => Response::new(200).with_body(vec![b'x'; size]),
+                    _ => route(request),
+                }),
+        )
+        .unwrap();
+        let mut stream = connect(&server);
+        stream
+            .write_all(b"GET /big HTTP/1.1\r\nHost: x\r\n\r\n")
+            .unwrap();
+        thread::sleep(Duration::from_secs(1));
+        let output = read_until_closed(&mut stream);
+        assert!(output.len() < size, "{backend:?}");
+    }
+}
+
+#[test]
+fn connections_per_ip() {
+    for backend in backends() {
+        let server = TestServer::start(
+            Server::builder()
+                .backend(backend)
+                .max_connections_per_ip(1)
+                .build(route),
+        )
+        .unwrap();
+
+        // A connection in the middle of its request holds the only slot.
+        let mut held = TcpStream::connect(server.addr()).unwrap();
+        held.write_all(b"GET / HTTP/1.1\r\n").unwrap();
+        let response = server.get("/").unwrap();
+        assert_eq!(response.status, 429, "{backend:?}");
+        assert_eq!(response.headers.get("Retry-After"), Some("1"));
+
+        held.write_all(b"Host: x\r\nConnection: close\r\n\r\n")
+            .unwrap();
+        assert!(read_until_closed(&mut held).starts_with(b"HTTP/1.1 200 "));
+        // The slot is free again once that connection is closed.
+        let deadline = Instant::now() + Duration::from_secs(2);
+        while server.get("/").unwrap().status == 429 {
+            assert!(Instant::now() < deadline, "{backend:?}");
+            thread::sleep(Duration::from_millis(10));
+        }
+    }
+}
+
+#[test]
+fn threaded_backend_closes_connections() {
+    let server = start(Backend::Threaded);
+    let mut client = server.client();
+
+    let response = client.get("/").unwrap();
+    assert!(response.headers.has_token("Connection", "close"));
+    assert!(!client.is_connected());
+    assert_eq!(client.get("/hello").unwrap().status, 200);
+}
+
+#[cfg(target_os = "linux")]
+#[test]
+fn event_loop_keeps_connections_alive() {
+    let server = TestServer::start(
+        Server::builder()
+            .backend(Backend::EventLoop)
+            .max_connections_per_ip(1)
+            .build(route),
+    )
+    .unwrap();
+    let mut client = server.client();
+
+    let response = client.get("/").unwrap();
+    assert_eq!(response.headers.get("Connection"), None);
+    assert!(client.is_connected());
+
+    // The kept connection is the only one allowed, so another client is
+    // turned away while this one carries on.
+    assert_eq!(server.get("/").unwrap().status, 429);
+    for _ in 0..3 {
+        assert_eq!(client.get("/hello").unwrap().body, b"Hello, world!\n");
+        assert!(client.is_connected());
+    }
+
+    let mut close = Request::new("GET", "/");
+    close.headers.insert("Connection", "close");
+    let response = client.send(&close).unwrap();
+    assert!(response.headers.has_token("Connection", "close"));
+    assert!(!client.is_connected());
+
+    // HTTP/1.0 clients have to ask for it.
+    let mut old = Request::new("GET", "/");
+    old.version = echo::http::Version::Http10;
+    client.send(&old).unwrap();
+    assert!(!client.is_connected());
+    old.headers.insert("Connection", "keep-alive");
+    let response = client.send(&old).unwrap();
+    assert_eq!(response.headers.get("Connection"), Some("keep-alive"));
+    assert!(client.is_connected());
+}
+
+#[test]
+fn concurrent_requests() {
+    for backend in backends() {
+        let server = TestServer::start(
+            Server::builder()
+                .backend(backend)
+                .pool(ThreadPool::new(4))
+                .build(|request: &Request| {
+                    thread::sleep(Duration::from_millis(200));
+                    Response::text(200, request.target.clone())
+                }),
+        )
+        .unwrap();
+        let addr = server.addr();
+
+        // Eight requests on four workers take two rounds, not eight.
+        let start = Instant::now();
+        let barrier = Arc::new(Barrier::new(8));
+        let clients: Vec<_> = (0..8)
+            .map(|i| {
+                let barrier = Arc::clone(&barrier);
+                thread::spawn(move || {
+                    barrier.wait();
+                    let target = format!("/{i}");
+                    let response = Client::new(addr).get(&target).unwrap();
+                    assert_eq!(response.status, 200);
+                    assert_eq!(response.body, target.as_bytes());
+                })
+            })
+            .collect();
+        for client in clients {
+            client.join().unwrap();
+        }
+        let elapsed = start.elapsed();
+        assert!(
+            elapsed < Duration::from_millis(1200),
+            "{backend:?}: {elapsed:?}"
+        );
+    }
+}
+
+#[cfg(unix)]
+#[test]
+fn unix_sockets() {
+    use echo::{http::parse_response, server::Listener};
+    use std::os::unix::net::UnixStream;
+
+    let dir = TempDir::new("it-unix");
+    for backend in backends() {
+        let path = dir.join(format!("{backend:?}.sock"));
+        let listener = Listener::bind_unix(&path, Some(0o600)).unwrap();
+        let server = Server::builder()
+            .backend(backend)
+            .build(|request: &Request| {
+                // Clients on a Unix socket have no address.
+                Response::text(200, format!("{:?}", request.peer))
+            });
+        thread::spawn(move || server.serve(listener));
+
+        let mut stream = UnixStream::connect(&path).unwrap();
+        stream
+            .write_all(b"GET / HTTP/1.1\r\nHost: x\r\nConnection: close\r\n\r\n")
+            .unwrap();
+        let mut output = Vec::new();
+        stream.read_to_end(&mut output).unwrap();
+        let response = parse_response(&output, "GET").unwrap();
+        assert_eq!(response.status, 200, "{backend:?}");
+        assert_eq!(response.body, b"None");
+    }
+}
+
+#[cfg(unix)]
+#[test]
+fn rate_limits() {
+    use echo::{http::parse_response, middleware::RateLimit, server::Listener};
+    use std::os::unix::net::UnixStream;
+
+    let limited = || {
+        Pipeline::new(route).with(
+            RateLimit::new(1, Duration::from_secs(60))
+                .key_header("X-Api-Key")
+                .max_clients(2),
+        )
+    };
+
+    // Clients on a Unix socket have no address, so they share a bucket.
+    let dir = TempDir::new("it-rate-limit");
+    let path = dir.join("echo.sock");
+    let listener = Listener::bind_unix(&path, Some(0o600)).unwrap();
+    let server = Server::new(limited());
+    thread::spawn(move || server.serve(listener));
+    let statuses: Vec<_> = (0..2)
+        .map(|_| {
+            let mut stream = UnixStream::connect(&path).unwrap();
+            stream
+                .write_all(b"GET / HTTP/1.1\r\nHost: x\r\nConnection: close\r\n\r\n")
+                .unwrap();
+            let mut output = Vec::new();
+            stream.read_to_end(&mut output).unwrap();
+            parse_response(&output, "GET").unwrap().status
+        })
+        .collect();
+    assert_eq!(statuses, [200, 429]);
+
+    // Making up keys gets a client fresh buckets only until there are as
+    // many as allowed, after which new keys share one.
+    let server = TestServer::new(limited());
+    let get = |key: &str| {
+        let mut request = Request::new("GET", "/");
+        request.headers.insert("X-Api-Key", key);
+        server.client().send(&request).unwrap().status
+    };
+    let statuses: Vec<_> = ["a", "b", "c", "d", "e"].map(get).into();
+    assert_eq!(statuses, [200, 200, 200, 429, 429]);
+    assert_eq!(get("a"), 429);
+}
+
+#[test]
+fn in_memory_connections() {
+    let server = Server::new(route);
+
+    let mut stream = MemoryStream::new("GET /hello?name=memory HTTP/1.1\r\nHost: x\r\n\r\n");
+    server.handle_connection(&mut stream);
+    let response = stream.response("GET").unwrap();
+    assert_eq!(response.status, 200);
+    assert_eq!(response.body, b"Hello, memory!\n");
+
+    let mut stream = MemoryStream::new("GET / HTTP/1.1\r\nHost: x\r\nContent-Length: x\r\n\r\n");
+    server.handle_connection(&mut stream);
+    assert_eq!(stream.response("GET").unwrap().status, 400);
+
+    // A client that gives up halfway gets no answer.
+    let mut stream = MemoryStream::new("GET / HTTP/1.1\r\nHo");
+    server.handle_connection(&mut stream);
+    assert!(stream.output().is_empty());
+}
+
+/// Read frames off an HTTP/2 connection until `streams` have all ended, and
+/// collect each one's status and body. `buf` is what was read already.
+fn http2_responses(
+    stream: &mut TcpStream,
+    mut buf: Vec<u8>,
+    streams: &[u32],
+) -> HashMap<u32, (String, Vec<u8>)> {
+    use echo::http2::{
+        frame::{parse_frame, Frame},
+        hpack::Decoder,
+    };
+
+    let mut decoder = Decoder::new();
+    let mut responses = HashMap::new();
+    let mut ended = Vec::new();
+    while !streams.iter().all(|id| ended.contains(id)) {
+        let Some((frame, len)) = parse_frame(&buf, 1 << 14).unwrap() else {
+            let mut chunk = [0; 4096];
+            let n = stream.read(&mut chunk).unwrap();
+            assert!(n > 0, "the connection closed early");
+            buf.extend_from_slice(&chunk[..n]);
+            continue;
+        };
+        buf.drain(..len);
+        match frame {
+            Frame::Headers {
+                stream,
+                block,
+                end_stream,
+                ..
+            } => {
+                let fields = decoder.decode(&block).unwrap();
+                let status = String::from_utf8(fields[0].1.clone()).unwrap();
+                assert_eq!(fields[0].0, b":status");
+                responses.insert(stream, (status, Vec::new()));
+                if end_stream {
+                    ended.push(stream);
+                }
+            }
+            Frame::Data {
+                stream,
+                data,
+                end_stream,
+                ..
+            } => {
+                responses.get_mut(&stream).unwrap().1.extend(data);
+                if end_stream {
+                    ended.push(stream);
+                }
+            }
+            Frame::GoAway { error, .. } => panic!("the server went away: {error:?}"),
+            _ => {}
+        }
+    }
+    responses
+}
+
+#[test]
+fn http2() {
+    use echo::http2::{frame::Frame, hpack::Encoder, PREFACE};
+
+    let settings = Frame::Settings {
+        ack: false,
+        settings: Vec::new(),
+    };
+    for backend in backends() {
+        let server = start(backend);
+
+        // With prior knowledge, two streams at once.
+        let mut stream = TcpStream::connect(server.addr()).unwrap();
+        let mut encoder = Encoder::new();
+        let mut output = PREFACE.to_vec();
+        output.extend(settings.encode());
+        let get = [
+            (":method", "GET"),
+            (":scheme", "http"),
+            (":path", "/hello?name=h2"),
+            (":authority", "localhost"),
+        ];
+        output.extend(
+            Frame::Headers {
+                stream: 1,
+                block: encoder.encode(get),
+                end_stream: true,
+                end_headers: true,
+                priority: None,
+            }
+            .encode(),
+        );
+        let post = [
+            (":method", "POST"),
+            (":scheme", "http"),
+            (":path", "/echo"),
+            (":authority", "localhost"),
+        ];
+        output.extend(
+            Frame::Headers {
+                stream: 3,
+                block: encoder.encode(post),
+                end_stream: false,
+                end_headers: true,
+                priority: None,
+            }
+            .encode(),
+        );
+        for (data, end_stream) in [("ping ", false), ("pong", true)] {
+            output.extend(
+                Frame::Data {
+                    stream: 3,
+                    data: data.into(),
+                    end_stream,
+                    padding: None,
+                }
+                .encode(),
+            );
+        }
+        stream.write_all(&output).unwrap();
+
+        let responses = http2_responses(&mut stream, Vec::new(), &[1, 3]);
+        assert_eq!(responses[&1], ("200".into(), b"Hello, h2!\n".to_vec()));
+        assert_eq!(responses[&3], ("200".into(), b"ping pong".to_vec()));
+
+        // Upgraded from HTTP/1.1, the request is answered on stream 1.
+        let mut stream = TcpStream::connect(server.addr()).unwrap();
+        stream
+            .write_all(
+                b"GET /hello HTTP/1.1\r\nHost: x\r\nConnection: Upgrade, HTTP2-Settings\r\n\
+                  Upgrade: h2c\r\nHTTP2-Settings: AAMAAABk\r\n\r\n",
+            )
+            .unwrap();
+        let mut output = PREFACE.to_vec();
+        output.extend(settings.encode());
+        stream.write_all(&output).unwrap();
+
+        let mut buf = Vec::new();
+        while !buf.windows(4).any(|window| window == b"\r\n\r\n") {
+            let mut byte = [0];
+            stream.read_exact(&mut byte).unwrap();
+            buf.push(byte[0]);
+        }
+        assert!(buf.starts_with(b"HTTP/1.1 101 "), "{backend:?}");
+        let responses = http2_responses(&mut stream, Vec::new(), &[1]);
+        assert_eq!(responses[&1], ("200".into(), b"Hello, world!\n".to_vec()));
+    }
+}
+
+/// Read the next frame off an HTTP/2 connection.
+fn next_frame(stream: &mut TcpStream, buf: &mut Vec<u8>) -> echo::http2::frame::Frame {
+    use echo::http2::frame::parse_frame;
+
+    loop {
+        if let Some((frame, len)) = parse_frame(buf, 1 << 14).unwrap() {
+            buf.drain(..len);
+            return frame;
+        }
+        let mut chunk = [0; 4096];
+        let n = stream.read(&mut chunk).unwrap();
+        assert!(n > 0, "the connection closed early");
+        buf.extend_from_slice(&chunk[..n]);
+    }
+}
+
+#[test]
+fn http2_abuse() {
+    use echo::http2::{
+        frame::{ErrorCode, Frame},
+        hpack::Encoder,
+        PREFACE,
+    };
+    use std::sync::atomic::{AtomicBool, Ordering};
+
+    let released = Arc::new(AtomicBool::new(false));
+    let handler = {
+        let released = Arc::clone(&released);
+        move |request: &Request| {
+            if request.path() == "/wait" {
+                while !released.load(Ordering::SeqCst) {
+                    thread::sleep(Duration::from_millis(5));
+                }
+            }
+            route(request)
+        }
+    };
+    let headers = |encoder: &mut Encoder, stream, method, path, end_stream| {
+        let fields = [
+            (":method", method),
+            (":scheme", "http"),
+            (":path", path),
+            (":authority", "localhost"),
+        ];
+        Frame::Headers {
+            stream,
+            block: encoder.encode(fields),
+            end_stream,
+            end_headers: true,
+            priority: None,
+        }
+        .encode()
+    };
+    let reset = |stream| {
+        Frame::RstStream {
+            stream,
+            error: ErrorCode::Cancel,
+        }
+        .encode()
+    };
+    let mut preface = PREFACE.to_vec();
+    preface.extend(
+        Frame::Settings {
+            ack: false,
+            settings: Vec::new(),
+        }
+        .encode(),
+    );
+
+    for backend in backends() {
+        released.store(false, Ordering::SeqCst);
+        let server = TestServer::start(
+            Server::builder()
+                .backend(backend)
+                .read_timeout(Duration::from_millis(300))
+                .build(handler.clone()),
+        )
+        .unwrap();
+
+        // A request whose body never comes gets a 408 once the read timeout
+        // is up, even though the client keeps the connection busy.
+        let mut stream = TcpStream::connect(server.addr()).unwrap();
+        stream
+            .set_read_timeout(Some(Duration::from_secs(5)))
+            .unwrap();
+        let mut encoder = Encoder::new();
+        let mut output = preface.clone();
+        output.extend(headers(&mut encoder, 1, "POST", "/echo", false));
+        stream.write_all(&output).unwrap();
+        for _ in 0..5 {
+            thread::sleep(Duration::from_millis(100));
+            let ping = Frame::Ping {
+                ack: false,
+                data: [0; 8],
+            };
+            stream.write_all(&ping.encode()).unwrap();
+        }
+        let responses = http2_responses(&mut stream, Vec::new(), &[1]);
+        assert_eq!(responses[&1].0, "408", "{backend:?}");
+
+        // Streams the client reset count as open until they're answered, so
+        // they can't be used to start more requests than the limit allows.
+        let mut stream = TcpStream::connect(server.addr()).unwrap();
+        stream
+            .set_read_timeout(Some(Duration::from_secs(5)))
+            .unwrap();
+        let mut encoder = Encoder::new();
+        let mut output = preface.clone();
+        for id in (1..200).step_by(2) {
+            output.extend(headers(&mut encoder, id, "GET", "/wait", true));
+            output.extend(reset(id));
+        }
+        output.extend(headers(&mut encoder, 201, "GET", "/", true));
+        stream.write_all(&output).unwrap();
+        let mut buf = Vec::new();
+        let error = loop {
+            match next_frame(&mut stream, &mut buf) {
+                Frame::RstStream { stream: 201, error } => break error,
+                Frame::Headers { stream: 201, .. } => panic!("stream 201 was answered"),
+                _ => {}
+            }
+        };
+        assert_eq!(error, ErrorCode::RefusedStream, "{backend:?}");
+        released.store(true, Ordering::SeqCst);
+
+        // Resetting streams at that rate ends the connection.
+        let mut output = Vec::new();
+        for id in (203..600).step_by(2) {
+            output.extend(headers(&mut encoder, id, "GET", "/", true));
+            output.extend(reset(id));
+        }
+        // The server may close before it reads all of it.
+        let _ = stream.write_all(&output);
+        let error = loop {
+            if let Frame::GoAway { error, .. } = next_frame(&mut stream, &mut buf) {
+                break error;
+            }
+        };
+        assert_eq!(error, ErrorCode::EnhanceYourCalm, "{backend:?}");
+    }
+}
+
+#[test]
+fn server_sent_events() {
+    use echo::{
+        server::Handler,
+        sse::{Event, EventStream},
+    };
+    use std::sync::{mpsc, Mutex};
+
+    /// Read from `stream` until what it sent holds `expected`.
+    fn read_until(stream: &mut TcpStream, output: &mut Vec<u8>, expected: &str) {
+        while !String::from_utf8_lossy(output).contains(expected) {
+            let mut chunk = [0; 1024];
+            let n = stream.read(&mut chunk).unwrap();
+            assert!(n > 0, "the stream ended early");
+            output.extend_from_slice(&chunk[..n]);
+        }
+    }
+
+    for backend in backends() {
+        let senders = Arc::new(Mutex::new(Vec::new()));
+        let events = EventStream::new({
+            let senders = Arc::clone(&senders);
+            move |_: &Request, last_id: Option<&str>| {
+                let (sender, receiver) = mpsc::channel();
+                let event = Event::new(format!("after {}", last_id.unwrap_or("-")));
+                sender.send(event).unwrap();
+                senders.lock().unwrap().push(sender);
+                receiver
+            }
+        });
+        let server = TestServer::start(
+            Server::builder()
+                .backend(backend)
+                .pool(ThreadPool::new(1))
+                .build(move |request: &Request| match request.path() {
+                    "/events" => events.handle(request),
+                    _ => route(request),
+                }),
+        )
+        .unwrap();
+
+        let mut clients: Vec<_> = ["", "Last-Event-ID: 41\r\n"]
+            .into_iter()
+            .map(|header| {
+                let mut stream = TcpStream::connect(server.addr()).unwrap();
+                stream
+                    .set_read_timeout(Some(Duration::from_secs(5)))
+                    .unwrap();
+                let request = format!("GET /events HTTP/1.1\r\nHost: x\r\n{header}\r\n");
+                stream.write_all(request.as_bytes()).unwrap();
+                (stream, Vec::new())
+            })
+            .collect();
+        for ((stream, output), last_id) in clients.iter_mut().zip(["-", "41"]) {
+            read_until(stream, output, &format!("data: after {last_id}\n\n"));
+            assert!(String::from_utf8_lossy(output).contains("text/event-stream"));
+        }
+
+        // The clients waiting for events leave the only worker free.
+        assert_eq!(server.get("/").unwrap().status, 200, "{backend:?}");
+
+        for sender in senders.lock().unwrap().iter() {
+            sender.send(Event::new("bye").event("end")).unwrap();
+        }
+        for (stream, output) in &mut clients {
+            read_until(stream, output, "event: end\ndata: bye\n\n");
+        }
+
+        // Once the senders are gone, the streams end.
+        senders.lock().unwrap().clear();
+        for (stream, output) in &mut clients {
+            stream.read_to_end(output).unwrap();
+            assert!(output.ends_with(b"0\r\n\r\n"));
+        }
+    }
+}
+
+#[test]
+fn long_lived_stream_cap() {
+    use echo::{
+        http2::{
+            frame::{ErrorCode, Frame},
+            hpack::Encoder,
+            PREFACE,
+        },
+        server::Handler,
+        sse::{Event, EventStream},
+    };
+    use std::sync::{mpsc, Mutex};
+
+    let open_stream = |server: &TestServer| {
+        let mut stream = TcpStream::connect(server.addr()).unwrap();
+        stream
+            .set_read_timeout(Some(Duration::from_secs(5)))
+            .unwrap();
+        stream
+            .write_all(b"GET /events HTTP/1.1\r\nHost: x\r\n\r\n")
+            .unwrap();
+        let mut head = [0; 12];
+        stream.read_exact(&mut head).unwrap();
+        (stream, head)
+    };
+
+    for backend in backends() {
+        let senders = Arc::new(Mutex::new(Vec::new()));
+        let events = EventStream::new({
+            let senders = Arc::clone(&senders);
+            move |_: &Request, _: Option<&str>| {
+                let (sender, receiver) = mpsc::channel::<Event>();
+                senders.lock().unwrap().push(sender);
+                receiver
+            }
+        });
+        let server = TestServer::start(
+            Server::builder()
+                .backend(backend)
+                .max_long_lived_streams(1)
+                .build(move |request: &Request| match request.path() {
+                    "/events" => events.handle(request),
+                    _ => route(request),
+                }),
+        )
+        .unwrap();
+
+        let (mut held, head) = open_stream(&server);
+        assert_eq!(&head, b"HTTP/1.1 200", "{backend:?}");
+
+        // With the only slot taken, another stream is turned away, while
+        // other requests are still answered.
+        let response = server.get("/events").unwrap();
+        assert_eq!(response.status, 503, "{backend:?}");
+        assert_eq!(response.headers.get("Retry-After"), Some("1"));
+        assert_eq!(server.get("/").unwrap().status, 200);
+
+        // Over HTTP/2 the stream is refused, so the client may retry it.
+        let mut stream = TcpStream::connect(server.addr()).unwrap();
+        stream
+            .set_read_timeout(Some(Duration::from_secs(5)))
+            .unwrap();
+        let mut output = PREFACE.to_vec();
+        let settings = Frame::Settings {
+            ack: false,
+            settings: Vec::new(),
+        };
+        output.extend(settings.encode());
+        let fields = [
+            (":method", "GET"),
+            (":scheme", "http"),
+            (":path", "/events"),
+            (":authority", "localhost"),
+        ];
+        let headers = Frame::Headers {
+            stream: 1,
+            block: Encoder::new().encode(fields),
+            end_stream: true,
+            end_headers: true,
+            priority: None,
+        };
+        output.extend(headers.encode());
+        stream.write_all(&output).unwrap();
+        let mut buf = Vec::new();
+        let error = loop {
+            match next_frame(&mut stream, &mut buf) {
+                Frame::RstStream { stream: 1, error } => break error,
+                Frame::Headers { stream: 1, .. } => panic!("the stream was answered"),
+                _ => {}
+            }
+        };
+        assert_eq!(error, ErrorCode::RefusedStream, "{backend:?}");
+
+        // The slot is free again once the stream ends.
+        senders.lock().unwrap().clear();
+        read_until_closed(&mut held);
+        let deadline = Instant::now() + Duration::from_secs(2);
+        while open_stream(&server).1 != *b"HTTP/1.1 200" {
+            assert!(Instant::now() < deadline, "{backend:?}");
+            thread::sleep(Duration::from_millis(10));
+        }
+        senders.lock().unwrap().clear();
+    }
+}